@@ -0,0 +1,2 @@
+-- An empty catch-all was previously stored as an empty string instead of NULL
+UPDATE domains SET catch_all = NULL WHERE catch_all = '';
@@ -0,0 +1,3 @@
+-- Maximum number of mails an alias may forward per day, NULL means unlimited.
+-- This is only stored here, enforcement is up to the mailserver.
+ALTER TABLE aliases ADD COLUMN daily_limit INTEGER DEFAULT NULL;
@@ -0,0 +1,10 @@
+-- Remembers which alias was created for a quick-alias link nonce,
+-- so that repeated requests (prefetching, reloads) don't create new aliases.
+CREATE TABLE IF NOT EXISTS quick_alias_nonces (
+	mailbox    TEXT NOT NULL,
+	nonce      TEXT NOT NULL,
+	address    TEXT NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (mailbox, nonce)
+	-- FOREIGN KEY (mailbox) REFERENCES mailboxes (address) ON DELETE CASCADE
+) WITHOUT ROWID;
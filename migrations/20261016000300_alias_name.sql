@@ -0,0 +1,2 @@
+-- An optional human-friendly name for an alias, NULL means the address is shown instead.
+ALTER TABLE aliases ADD COLUMN name TEXT DEFAULT NULL;
@@ -0,0 +1,4 @@
+-- Whether the mailbox wants a weekly digest of its alias activity,
+-- and when the last one was sent to avoid duplicates.
+ALTER TABLE mailboxes ADD COLUMN digest BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE mailboxes ADD COLUMN digest_last_sent TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS domain_routes (
+	id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	domain     TEXT NOT NULL,
+	-- localpart pattern, where '*' matches any sequence of characters (compatible with GLOB)
+	pattern    TEXT NOT NULL,
+	target     TEXT NOT NULL,
+	-- routes with a lower priority are matched first
+	priority   INTEGER NOT NULL DEFAULT 0,
+	active     BOOL NOT NULL DEFAULT TRUE,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	UNIQUE (domain, pattern)
+	-- FOREIGN KEY (domain) REFERENCES domains (domain) ON DELETE CASCADE
+);
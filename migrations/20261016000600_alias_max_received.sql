@@ -0,0 +1,11 @@
+-- Number of received mails after which an alias deactivates itself,
+-- useful for single-use aliases. NULL means the alias never expires.
+ALTER TABLE aliases ADD COLUMN max_received INTEGER DEFAULT NULL;
+
+-- The mailserver increments n_recv, so the limit is enforced right here
+CREATE TRIGGER IF NOT EXISTS aliases_max_received
+AFTER UPDATE OF n_recv ON aliases
+WHEN NEW.max_received IS NOT NULL AND NEW.n_recv >= NEW.max_received AND NEW.active
+BEGIN
+	UPDATE aliases SET active = FALSE WHERE address = NEW.address;
+END;
@@ -0,0 +1,4 @@
+-- Optional TOTP secret (base32) for two-factor authentication on login.
+-- NULL means that two-factor authentication is disabled for the account.
+ALTER TABLE users ADD COLUMN totp_secret TEXT DEFAULT NULL;
+ALTER TABLE mailboxes ADD COLUMN totp_secret TEXT DEFAULT NULL;
@@ -0,0 +1,4 @@
+-- Whether aliases created by this account start out active,
+-- which allows a create-then-enable workflow.
+ALTER TABLE users ADD COLUMN new_aliases_active BOOL NOT NULL DEFAULT TRUE;
+ALTER TABLE mailboxes ADD COLUMN new_aliases_active BOOL NOT NULL DEFAULT TRUE;
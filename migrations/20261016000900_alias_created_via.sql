@@ -0,0 +1,3 @@
+-- How an alias was created, e.g. `ui` or the name of the API integration.
+-- Unknown (NULL) for aliases created before this was recorded.
+ALTER TABLE aliases ADD COLUMN created_via TEXT DEFAULT NULL;
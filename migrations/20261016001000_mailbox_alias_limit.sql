@@ -0,0 +1,2 @@
+-- The maximum number of aliases a mailbox may own, or NULL for unlimited.
+ALTER TABLE mailboxes ADD COLUMN alias_limit INTEGER DEFAULT NULL;
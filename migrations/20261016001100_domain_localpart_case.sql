@@ -0,0 +1,3 @@
+-- Whether the mailserver treats localparts of this domain as case-sensitive.
+-- Otherwise idmail lowercases new localparts and detects duplicates regardless of case.
+ALTER TABLE domains ADD COLUMN localpart_case_sensitive BOOL NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- When an alias is deactivated automatically, or NULL to never expire.
+ALTER TABLE aliases ADD COLUMN expires_at TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,2 @@
+-- Display name the mail server uses in the From header when forwarding, or NULL for none.
+ALTER TABLE aliases ADD COLUMN from_name TEXT DEFAULT NULL;
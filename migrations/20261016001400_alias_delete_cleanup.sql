@@ -0,0 +1,8 @@
+-- Removes rows that refer to an alias once it is deleted, regardless of whether
+-- that happens in the UI, via the API or by provisioning. Tables that refer to
+-- aliases by their address must be cleaned up here, there are no foreign keys.
+CREATE TRIGGER IF NOT EXISTS aliases_delete_cleanup
+AFTER DELETE ON aliases
+BEGIN
+	DELETE FROM quick_alias_nonces WHERE address = OLD.address;
+END;
@@ -0,0 +1,22 @@
+-- External addresses that a mailbox may use as the target of its aliases.
+-- Mailboxes can only add recipients, which must then be verified by the owner of the mailbox.
+CREATE TABLE IF NOT EXISTS recipients (
+	address    TEXT NOT NULL,
+	mailbox    TEXT NOT NULL,
+	verified   BOOL NOT NULL DEFAULT FALSE,
+	created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY (mailbox, address)
+	-- FOREIGN KEY (mailbox) REFERENCES mailboxes (address) ON DELETE CASCADE
+) WITHOUT ROWID;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_rename_recipients
+AFTER UPDATE OF address ON mailboxes
+BEGIN
+	UPDATE recipients SET mailbox = NEW.address WHERE mailbox = OLD.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_delete_recipients
+AFTER DELETE ON mailboxes
+BEGIN
+	DELETE FROM recipients WHERE mailbox = OLD.address;
+END;
@@ -0,0 +1,44 @@
+-- Recently deleted aliases, which can be restored until they are purged after
+-- IDMAIL_ALIAS_TRASH_DAYS. Provisioned aliases are not kept, since the
+-- provisioning file would recreate them anyway.
+CREATE TABLE IF NOT EXISTS deleted_aliases (
+	address      TEXT NOT NULL PRIMARY KEY,
+	domain       TEXT NOT NULL,
+	target       TEXT NOT NULL,
+	comment      TEXT NOT NULL,
+	n_recv       INTEGER NOT NULL DEFAULT 0,
+	n_sent       INTEGER NOT NULL DEFAULT 0,
+	active       BOOLEAN NOT NULL DEFAULT TRUE,
+	owner        TEXT NOT NULL,
+	created_at   TIMESTAMP NOT NULL,
+	daily_limit  INTEGER DEFAULT NULL,
+	name         TEXT DEFAULT NULL,
+	max_received INTEGER DEFAULT NULL,
+	created_via  TEXT DEFAULT NULL,
+	expires_at   TIMESTAMP DEFAULT NULL,
+	from_name    TEXT DEFAULT NULL,
+	deleted_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+) WITHOUT ROWID;
+
+CREATE TRIGGER IF NOT EXISTS aliases_delete_trash
+AFTER DELETE ON aliases
+WHEN NOT OLD.provisioned
+BEGIN
+	INSERT OR REPLACE INTO deleted_aliases (address, domain, target, comment, n_recv, n_sent, active, owner,
+		created_at, daily_limit, name, max_received, created_via, expires_at, from_name)
+	VALUES (OLD.address, OLD.domain, OLD.target, OLD.comment, OLD.n_recv, OLD.n_sent, OLD.active, OLD.owner,
+		OLD.created_at, OLD.daily_limit, OLD.name, OLD.max_received, OLD.created_via, OLD.expires_at, OLD.from_name);
+END;
+
+-- Restoring needs the address, so a new alias or mailbox with it supersedes the deleted one
+CREATE TRIGGER IF NOT EXISTS aliases_insert_trash
+AFTER INSERT ON aliases
+BEGIN
+	DELETE FROM deleted_aliases WHERE address = NEW.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_insert_trash
+AFTER INSERT ON mailboxes
+BEGIN
+	DELETE FROM deleted_aliases WHERE address = NEW.address;
+END;
@@ -0,0 +1,37 @@
+-- Named API tokens, so that each integration can use its own token and be revoked independently.
+-- The account is either the username of a user or the address of a mailbox, as told by account_kind.
+-- Only the hash of each token is stored, the plaintext is shown once when the token is created.
+-- The tokens that were stored on mailboxes before are moved here (and hashed) on startup.
+CREATE TABLE IF NOT EXISTS api_tokens (
+	id           INTEGER PRIMARY KEY AUTOINCREMENT,
+	account      TEXT NOT NULL,
+	account_kind TEXT NOT NULL CHECK (account_kind IN ('user', 'mailbox')),
+	name         TEXT NOT NULL,
+	token_hash   TEXT NOT NULL UNIQUE,
+	-- Comma separated scopes, e.g. `alias:create,alias:read`. NULL grants all scopes.
+	scopes       TEXT DEFAULT NULL,
+	created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	last_used_at TIMESTAMP DEFAULT NULL
+	-- FOREIGN KEY (account) REFERENCES mailboxes (address) ON DELETE CASCADE
+	-- FOREIGN KEY (account) REFERENCES users (username) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_account ON api_tokens (account_kind, account);
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_rename_api_tokens
+AFTER UPDATE OF address ON mailboxes
+BEGIN
+	UPDATE api_tokens SET account = NEW.address WHERE account_kind = 'mailbox' AND account = OLD.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_delete_api_tokens
+AFTER DELETE ON mailboxes
+BEGIN
+	DELETE FROM api_tokens WHERE account_kind = 'mailbox' AND account = OLD.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_delete_api_tokens
+AFTER DELETE ON users
+BEGIN
+	DELETE FROM api_tokens WHERE account_kind = 'user' AND account = OLD.username;
+END;
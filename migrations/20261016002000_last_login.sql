@@ -0,0 +1,3 @@
+-- When each account last logged in to the web interface, for auditing. NULL if it never did.
+ALTER TABLE users ADD COLUMN last_login_at TIMESTAMP DEFAULT NULL;
+ALTER TABLE mailboxes ADD COLUMN last_login_at TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,15 @@
+-- Mailboxes are always owned by users, never by other mailboxes. Logins of mailboxes resolve
+-- their owner exactly once (e.g. for the domains they may use), so chains must not exist.
+CREATE TRIGGER IF NOT EXISTS mailboxes_owner_insert_user
+BEFORE INSERT ON mailboxes
+WHEN NOT EXISTS (SELECT 1 FROM users WHERE username = NEW.owner)
+BEGIN
+	SELECT RAISE(ABORT, 'mailbox owner must be a user');
+END;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_owner_update_user
+BEFORE UPDATE OF owner ON mailboxes
+WHEN NEW.owner != OLD.owner AND NOT EXISTS (SELECT 1 FROM users WHERE username = NEW.owner)
+BEGIN
+	SELECT RAISE(ABORT, 'mailbox owner must be a user');
+END;
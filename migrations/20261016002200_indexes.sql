@@ -0,0 +1,9 @@
+-- Indexes for the columns that listing and counting filter on, so these don't scan whole tables
+-- on large installations. For non-admins, the alias owner check also looks up the mailboxes of
+-- the user by owner, see push_owner_scope.
+CREATE INDEX IF NOT EXISTS aliases_owner ON aliases (owner);
+CREATE INDEX IF NOT EXISTS aliases_domain ON aliases (domain);
+CREATE INDEX IF NOT EXISTS aliases_active ON aliases (active);
+CREATE INDEX IF NOT EXISTS mailboxes_owner ON mailboxes (owner);
+CREATE INDEX IF NOT EXISTS domains_owner ON domains (owner);
+CREATE INDEX IF NOT EXISTS domains_active_public ON domains (active, public);
@@ -0,0 +1,3 @@
+-- The alias table is sorted by creation date by default, which this index serves
+-- without sorting all aliases, including when continuing after the last loaded row.
+CREATE INDEX IF NOT EXISTS aliases_created_at ON aliases (created_at);
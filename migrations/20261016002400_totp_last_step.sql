@@ -0,0 +1,5 @@
+-- The time step of the last accepted TOTP code, so an observed code can't be
+-- used a second time. Secrets are now stored encrypted with a key that is kept
+-- outside of the database, existing plaintext secrets are encrypted on startup.
+ALTER TABLE users ADD COLUMN totp_last_step INTEGER DEFAULT NULL;
+ALTER TABLE mailboxes ADD COLUMN totp_last_step INTEGER DEFAULT NULL;
@@ -0,0 +1,25 @@
+-- The `mailbox` column of quick_alias_nonces holds the account that used the nonce,
+-- which is either the address of a mailbox or the username of a user. A foreign key
+-- can only reference one of the two tables, so the nonces follow their account here.
+CREATE TRIGGER IF NOT EXISTS mailboxes_rename_quick_alias_nonces
+AFTER UPDATE OF address ON mailboxes
+BEGIN
+	UPDATE quick_alias_nonces SET mailbox = NEW.address WHERE mailbox = OLD.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS mailboxes_delete_quick_alias_nonces
+AFTER DELETE ON mailboxes
+BEGIN
+	DELETE FROM quick_alias_nonces WHERE mailbox = OLD.address;
+END;
+
+CREATE TRIGGER IF NOT EXISTS users_delete_quick_alias_nonces
+AFTER DELETE ON users
+BEGIN
+	DELETE FROM quick_alias_nonces WHERE mailbox = OLD.username;
+END;
+
+-- Nonces of accounts that were already deleted
+DELETE FROM quick_alias_nonces
+WHERE mailbox NOT IN (SELECT address FROM mailboxes)
+AND mailbox NOT IN (SELECT username FROM users);
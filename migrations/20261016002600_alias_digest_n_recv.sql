@@ -0,0 +1,5 @@
+-- The received counter of each alias as of the last digest of its target, so a digest
+-- reports the mails received in its period instead of all time. Existing counts are
+-- treated as already reported.
+ALTER TABLE aliases ADD COLUMN digest_n_recv INTEGER NOT NULL DEFAULT 0;
+UPDATE aliases SET digest_n_recv = n_recv;
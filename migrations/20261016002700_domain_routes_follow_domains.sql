@@ -0,0 +1,16 @@
+-- Foreign keys are not enforced in this schema, so the routes follow their domain through
+-- triggers, like recipients, api_tokens and quick_alias_nonces follow their account.
+CREATE TRIGGER IF NOT EXISTS domains_rename_domain_routes
+AFTER UPDATE OF domain ON domains
+BEGIN
+	UPDATE domain_routes SET domain = NEW.domain WHERE domain = OLD.domain;
+END;
+
+CREATE TRIGGER IF NOT EXISTS domains_delete_domain_routes
+AFTER DELETE ON domains
+BEGIN
+	DELETE FROM domain_routes WHERE domain = OLD.domain;
+END;
+
+-- Routes of domains that were already deleted or renamed
+DELETE FROM domain_routes WHERE domain NOT IN (SELECT domain FROM domains);
@@ -1,30 +1,49 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::ops::Range;
 use std::str::FromStr;
 
-use crate::auth::User;
-use crate::utils::{DeleteModal, EditModal, Select};
-use crate::utils::{SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer};
+use crate::auth::{get_capabilities, User};
+use crate::users::OwnerInput;
+use crate::utils::{download_file, DeleteModal, EditModal, FieldErrorMessage, Modal, Select, ValidationErrors};
+use crate::utils::{
+    CopyableRenderer, OptionTextRenderer, OptionTimediffRenderer, SliderRenderer, THeadCellRenderer,
+    TailwindClassesPreset, TimediffRenderer,
+};
+use crate::utils::{ProvisionedBadge, ProvisionedFilter, PROVISIONED_TOOLTIP};
 
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{
+    ensure_not_provisioned, push_order_by, push_own_aliases_scope, push_owner_scope, LogQuery, OwnerScope, Provisioned,
+};
+#[cfg(feature = "ssr")]
+use crate::mailboxes::{alias_limit_reached, push_alias_limit_not_reached};
+#[cfg(feature = "ssr")]
+use crate::utils::map_unique_violation;
 use anyhow::bail;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Months, NaiveDate, Utc};
 use faker_rand::en_us::internet::Username;
 use leptos::leptos_dom::is_browser;
 use leptos::{ev::MouseEvent, logging::error, *};
 use leptos_icons::Icon;
 use leptos_struct_table::*;
-use leptos_use::use_debounce_fn_with_arg;
+use leptos_use::{use_debounce_fn, use_debounce_fn_with_arg};
 use rand::rngs::OsRng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
 use sqlx::QueryBuilder;
 
+/// The maximum length of the display name used in the From header
+pub(crate) const MAX_FROM_NAME_LENGTH: usize = 64;
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, TableRow)]
 #[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
 #[table(sortable, classes_provider = TailwindClassesPreset, thead_cell_renderer = THeadCellRenderer)]
 pub struct Alias {
-    #[table(class = "w-40")]
+    /// The name of the alias, or the address if it has none. Computed by the query
+    #[table(class = "w-40", title = "Name")]
+    pub display_name: String,
+    #[table(class = "w-40", renderer = "CopyableRenderer")]
     pub address: String,
     #[table(class = "w-40")]
     pub target: String,
@@ -33,12 +52,34 @@ pub struct Alias {
     pub n_recv: i64,
     #[table(class = "w-1", title = "Sent")]
     pub n_sent: i64,
+    /// Received plus sent mails, computed by the query
+    #[table(class = "w-1", title = "Activity")]
+    pub activity: i64,
     #[table(class = "w-1", renderer = "SliderRenderer")]
     pub active: bool,
     #[table(class = "w-1")]
     pub owner: String,
     #[table(class = "w-1", title = "Created", renderer = "TimediffRenderer")]
     pub created_at: DateTime<Utc>,
+    /// How the alias was created, either `ui`, `import` or the name of the API token. Unknown for older aliases
+    #[table(class = "w-1", title = "Source", renderer = "OptionTextRenderer")]
+    pub created_via: Option<String>,
+    /// When the alias is deactivated automatically
+    #[table(class = "w-1", title = "Expires", renderer = "OptionTimediffRenderer")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[table(skip)]
+    pub daily_limit: Option<i64>,
+    /// Number of received mails after which the alias deactivates itself
+    #[table(skip)]
+    pub max_received: Option<i64>,
+    #[table(skip)]
+    pub name: Option<String>,
+    /// Display name used in the From header of forwarded mails
+    #[table(skip)]
+    pub from_name: Option<String>,
+    /// Whether the entry is managed by the provisioning file
+    #[table(skip)]
+    pub provisioned: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -47,9 +88,37 @@ pub struct AliasQuery {
     sort: VecDeque<(usize, ColumnSort)>,
     range: Range<usize>,
     search: String,
+    #[serde(default)]
+    provisioned: Option<bool>,
+    /// The creation date and address of the last row before the range. When sorting by [`NEWEST_FIRST`], the rows
+    /// continue after it instead of skipping `range.start` rows, which stays fast deep into large tables.
+    #[serde(default)]
+    after: Option<(DateTime<Utc>, String)>,
+}
+
+/// The index of the `activity` column in the alias table
+const ACTIVITY_COLUMN: usize = 6;
+
+/// The index of the `created_at` column in the alias table
+const CREATED_AT_COLUMN: usize = 9;
+
+/// The default sorting of the alias table, which supports keyset pagination
+const NEWEST_FIRST: (usize, ColumnSort) = (CREATED_AT_COLUMN, ColumnSort::Descending);
+
+/// Brings a domain into the canonical form the mailserver sees, which is lowercase
+/// and without a trailing dot. With `reject_noncanonical` (see `IDMAIL_REJECT_NONCANONICAL_DOMAINS`),
+/// non-canonical domains are rejected instead.
+pub(crate) fn normalize_domain(domain: &str, reject_noncanonical: bool) -> anyhow::Result<String> {
+    let normalized = domain.strip_suffix('.').unwrap_or(domain).to_lowercase();
+    if normalized != domain && reject_noncanonical {
+        bail!("domain '{domain}' must be lowercase and must not end with a dot");
+    }
+
+    Ok(normalized)
 }
 
 pub(crate) fn validate_address(localpart: &str, domain: &str, allow_reserved: bool) -> anyhow::Result<String> {
+    let domain = normalize_domain(domain, false)?;
     let address = format!("{localpart}@{domain}");
     if !allow_reserved
         && matches!(
@@ -71,31 +140,124 @@ pub(crate) fn validate_address(localpart: &str, domain: &str, allow_reserved: bo
     Ok(email_address::EmailAddress::from_str(&address).map(|x| x.to_string())?)
 }
 
+/// Rejects localparts that are reserved on all domains via `IDMAIL_RESERVED_LOCALPARTS`.
+/// Unlike the builtin reserved addresses, these can't even be used by domain owners, only by admins.
 #[cfg(feature = "ssr")]
-pub(crate) fn push_and_check_aliases_owner(query: &mut QueryBuilder<'_, sqlx::Sqlite>, username: String) {
-    query.push(" AND ( owner = ");
-    query.push_bind(username.clone());
-    query.push(
-        " OR owner IN ( \
-        SELECT address from mailboxes WHERE owner = ",
-    );
-    query.push_bind(username.clone());
-    query.push(" ) )");
+pub(crate) fn check_globally_reserved(
+    config: &crate::config::Config,
+    localpart: &str,
+    is_admin: bool,
+) -> anyhow::Result<()> {
+    if is_admin {
+        return Ok(());
+    }
+
+    if config
+        .reserved_localparts
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(localpart))
+    {
+        bail!("'{localpart}' is reserved on all domains and can only be used by admins");
+    }
+    Ok(())
+}
+
+/// Parses the daily limit of an alias as entered by the user. An empty
+/// input means that the alias is unlimited.
+pub(crate) fn parse_daily_limit(daily_limit: &str) -> anyhow::Result<Option<i64>> {
+    let daily_limit = daily_limit.trim();
+    if daily_limit.is_empty() {
+        return Ok(None);
+    }
+
+    match daily_limit.parse::<i64>() {
+        Ok(x) if x >= 0 => Ok(Some(x)),
+        _ => bail!("daily limit must be a non-negative integer"),
+    }
+}
+
+/// Parses the number of received mails after which an alias deactivates
+/// itself, as entered by the user. An empty input means never.
+pub(crate) fn parse_max_received(max_received: &str) -> anyhow::Result<Option<i64>> {
+    let max_received = max_received.trim();
+    if max_received.is_empty() {
+        return Ok(None);
+    }
+
+    match max_received.parse::<i64>() {
+        Ok(x) if x > 0 => Ok(Some(x)),
+        _ => bail!("deactivation limit must be a positive integer"),
+    }
+}
+
+/// Parses the expiry date of an alias as entered by the user. The alias expires at the start
+/// of the given day in the local timezone. An empty input means never.
+pub(crate) fn parse_expires_at(expires_at: &str) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let expires_at = expires_at.trim();
+    if expires_at.is_empty() {
+        return Ok(None);
+    }
+
+    let Ok(date) = chrono::NaiveDate::parse_from_str(expires_at, "%Y-%m-%d") else {
+        bail!("expiry date must be a valid date");
+    };
+    match date.and_time(chrono::NaiveTime::MIN).and_local_timezone(chrono::Local) {
+        chrono::LocalResult::Single(x) | chrono::LocalResult::Ambiguous(x, _) => Ok(Some(x.with_timezone(&Utc))),
+        chrono::LocalResult::None => bail!("expiry date must be a valid date"),
+    }
+}
+
+/// Parses the display name used in the From header of forwarded mails.
+/// An empty input means no display name.
+pub(crate) fn parse_from_name(from_name: &str) -> anyhow::Result<Option<String>> {
+    let from_name = from_name.trim();
+    if from_name.is_empty() {
+        return Ok(None);
+    }
+
+    if from_name.chars().count() > MAX_FROM_NAME_LENGTH {
+        bail!("sender name must be at most {MAX_FROM_NAME_LENGTH} characters");
+    }
+    // The name ends up in a mail header, so reject anything that could break out of it
+    if from_name.chars().any(char::is_control) {
+        bail!("sender name must not contain control characters");
+    }
+    Ok(Some(from_name.to_string()))
+}
+
+/// Formats an expiry date for the date input, the inverse of [`parse_expires_at`]
+fn format_expires_at(expires_at: Option<DateTime<Utc>>) -> String {
+    expires_at
+        .map(|x| x.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
 }
 
 #[server]
 pub async fn list_aliases(query: AliasQuery) -> Result<Vec<Alias>, ServerFnError> {
     let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    Ok(query_aliases(&pool, &user, query).await?)
+}
 
-    let AliasQuery { sort, range, search } = query;
-
-    let mut query = QueryBuilder::new("SELECT * FROM aliases WHERE 1=1");
-    if !user.admin {
-        push_and_check_aliases_owner(&mut query, user.username.clone());
-    }
+#[cfg(feature = "ssr")]
+async fn query_aliases(pool: &sqlx::SqlitePool, user: &User, query: AliasQuery) -> Result<Vec<Alias>, sqlx::Error> {
+    let AliasQuery {
+        sort,
+        range,
+        search,
+        provisioned,
+        after,
+    } = query;
+
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE 1=1",
+    );
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
     if !search.is_empty() {
         query.push(" AND ( address LIKE concat('%', ");
         query.push_bind(&search);
+        query.push(", '%') OR name LIKE concat('%', ");
+        query.push_bind(&search);
         query.push(", '%') OR comment LIKE concat('%', ");
         query.push_bind(&search);
         query.push(", '%') OR owner LIKE concat('%', ");
@@ -103,28 +265,335 @@ pub async fn list_aliases(query: AliasQuery) -> Result<Vec<Alias>, ServerFnError
         query.push(", '%') )");
     }
 
-    if let Some(order) = Alias::sorting_to_sql(&sort) {
-        query.push(" ");
-        query.push(order);
+    if let Some(provisioned) = provisioned {
+        query.push(" AND provisioned = ");
+        query.push_bind(provisioned);
+    }
+
+    // The address is the last sort key, so it decides the order between aliases created at the same time
+    let after = after.filter(|_| sort.len() == 1 && sort[0] == NEWEST_FIRST);
+    if let Some((created_at, address)) = &after {
+        query.push(" AND ( created_at < datetime(");
+        query.push_bind(created_at);
+        query.push(") OR ( created_at = datetime(");
+        query.push_bind(created_at);
+        query.push(") AND address > ");
+        query.push_bind(address);
+        query.push(" ) )");
     }
 
+    push_order_by(&mut query, Alias::sorting_to_sql(&sort), "address");
+
     query.push(" LIMIT ");
     query.push_bind(range.len() as i64);
-    query.push(" OFFSET ");
-    query.push_bind(range.start as i64);
+    if after.is_none() {
+        query.push(" OFFSET ");
+        query.push_bind(range.start as i64);
+    }
+
+    query.logged().build_query_as::<Alias>().fetch_all(pool).await
+}
+
+/// Exports all aliases the user is allowed to see as CSV, including a header row.
+#[server]
+pub async fn export_aliases_csv() -> Result<String, ServerFnError> {
+    use crate::utils::csv_field;
+    let user = crate::auth::auth_any().await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE 1=1",
+    );
+    push_owner_scope(&mut query, &user, OwnerScope::Aliases);
+    query.push(" ORDER BY address");
+
+    let pool = crate::database::ssr::pool()?;
+    let aliases = query.logged().build_query_as::<Alias>().fetch_all(&pool).await?;
+
+    let mut csv = "address,target,comment,active,owner,n_recv,n_sent,created_at\n".to_string();
+    for alias in aliases {
+        let row = [
+            csv_field(&alias.address),
+            csv_field(&alias.target),
+            csv_field(&alias.comment),
+            alias.active.to_string(),
+            csv_field(&alias.owner),
+            alias.n_recv.to_string(),
+            alias.n_sent.to_string(),
+            alias.created_at.to_rfc3339(),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    Ok(csv)
+}
+
+/// The maximum number of rows that can be imported at once
+#[cfg(feature = "ssr")]
+const MAX_IMPORT_ROWS: usize = 10000;
+
+/// The format of the data given to [`import_aliases`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportFormat {
+    /// CSV with a header row, like the one from [`export_aliases_csv`]
+    #[default]
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// What happened to a single row of an import
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportOutcome {
+    Created,
+    /// An alias with this address already exists, so the row was skipped
+    Duplicate,
+    Failed(String),
+}
+
+/// The outcome of a single row of an import
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedRow {
+    /// The row in the input, starting at 1. For CSV the header is row 1
+    pub row: usize,
+    pub address: String,
+    pub outcome: ImportOutcome,
+}
+
+/// An alias as given in an import
+#[cfg(feature = "ssr")]
+#[derive(Debug, Deserialize)]
+struct ImportRow {
+    address: String,
+    target: String,
+    #[serde(default)]
+    comment: String,
+    #[serde(default = "import_row_default_active")]
+    active: bool,
+}
+
+#[cfg(feature = "ssr")]
+fn import_row_default_active() -> bool {
+    true
+}
+
+/// Parses the rows of an import into their row number, address and content. Rows that
+/// cannot be parsed are returned with the reason, so they can be reported along with the others.
+#[cfg(feature = "ssr")]
+#[allow(clippy::type_complexity)]
+fn parse_import_rows(
+    data: &str,
+    format: ImportFormat,
+) -> anyhow::Result<Vec<(usize, String, Result<ImportRow, String>)>> {
+    let rows = match format {
+        ImportFormat::JsonLines => data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| match serde_json::from_str::<ImportRow>(line) {
+                Ok(row) => (i + 1, row.address.trim().to_string(), Ok(row)),
+                Err(e) => {
+                    // Still report the address if there is one
+                    let address = serde_json::from_str::<serde_json::Value>(line)
+                        .ok()
+                        .and_then(|x| x.get("address")?.as_str().map(|x| x.trim().to_string()));
+                    (i + 1, address.unwrap_or_default(), Err(e.to_string()))
+                }
+            })
+            .collect::<Vec<_>>(),
+        ImportFormat::Csv => {
+            let mut records = crate::utils::parse_csv(data).into_iter().enumerate();
+            let Some((_, header)) = records.next() else {
+                return Ok(vec![]);
+            };
+            let column = |name: &str| header.iter().position(|x| x.trim().eq_ignore_ascii_case(name));
+            let (Some(address), Some(target)) = (column("address"), column("target")) else {
+                bail!("the CSV header must contain an address and a target column");
+            };
+            let (comment, active) = (column("comment"), column("active"));
+
+            records
+                .filter(|(_, record)| record.iter().any(|x| !x.trim().is_empty()))
+                .map(|(i, record)| {
+                    let field = |index: Option<usize>| index.and_then(|x| record.get(x)).map(|x| x.trim());
+                    let address = field(Some(address)).unwrap_or_default().to_string();
+                    let row = match field(active).unwrap_or_default() {
+                        "" | "true" | "1" => Ok(true),
+                        "false" | "0" => Ok(false),
+                        x => Err(format!("invalid value '{x}' for active")),
+                    }
+                    .map(|active| ImportRow {
+                        address: address.clone(),
+                        target: field(Some(target)).unwrap_or_default().to_string(),
+                        comment: field(comment).unwrap_or_default().to_string(),
+                        active,
+                    });
+                    (i + 1, address, row)
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    if rows.len() > MAX_IMPORT_ROWS {
+        bail!("at most {MAX_IMPORT_ROWS} aliases can be imported at once");
+    }
+    Ok(rows)
+}
 
+/// Imports the given aliases with the same validation as [`create_or_update_alias`].
+/// All aliases are inserted in a single transaction, but rows that fail don't prevent
+/// the others from being imported. Returns the outcome of each row.
+#[server]
+pub async fn import_aliases(data: String, format: ImportFormat) -> Result<Vec<ImportedRow>, ServerFnError> {
+    use crate::domains::localpart_case_sensitive;
+    let user = crate::auth::auth_admin().await?;
     let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_as::<Alias>().fetch_all(&pool).await?)
+
+    let rows = parse_import_rows(&data, format).map_err(|e| ServerFnError::new(e.to_string()))?;
+    let mut tx = pool.begin().await?;
+    let mut imported = Vec::new();
+    for (row, address, parsed) in rows {
+        let outcome = match parsed {
+            Err(e) => ImportOutcome::Failed(e),
+            Ok(parsed) => {
+                let (alias, domain) = address.rsplit_once('@').unwrap_or((&address, ""));
+                match check_alias(&user, &pool, None, alias, domain, parsed.target.trim(), None, "").await {
+                    Err(e) => match ValidationErrors::from_server_error(&e) {
+                        Some(errors) if errors.get("alias").is_some_and(|x| x.ends_with("already exists!")) => {
+                            ImportOutcome::Duplicate
+                        }
+                        Some(errors) => ImportOutcome::Failed(
+                            errors.0.into_iter().map(|x| x.message).collect::<Vec<_>>().join(", "),
+                        ),
+                        None => ImportOutcome::Failed(e.to_string()),
+                    },
+                    Ok(checked) => {
+                        let mut query = QueryBuilder::new(
+                            "INSERT INTO aliases (address, domain, target, comment, active, owner, created_via)",
+                        );
+                        query.push("SELECT ");
+                        query.push_bind(&checked.address);
+                        query.push(", ");
+                        query.push_bind(&checked.domain);
+                        query.push(", ");
+                        query.push_bind(checked.target);
+                        query.push(", ");
+                        query.push_bind(parsed.comment);
+                        query.push(", ");
+                        query.push_bind(parsed.active);
+                        query.push(", ");
+                        query.push_bind(&checked.owner);
+                        query.push(", 'import'");
+                        // make sure that no mailbox exists with that address
+                        query.push(" WHERE NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
+                        query.push_bind(&checked.address);
+                        query.push(") AND ");
+                        push_alias_limit_not_reached(&mut query, &checked.owner);
+                        // Earlier rows of this import aren't visible to the checks outside of the transaction
+                        if !localpart_case_sensitive(&pool, &checked.domain).await? {
+                            query.push(" AND NOT EXISTS (SELECT * FROM aliases WHERE address = ");
+                            query.push_bind(&checked.address);
+                            query.push(" COLLATE NOCASE)");
+                        }
+
+                        match query.logged().build().execute(&mut *tx).await {
+                            Ok(result) if result.rows_affected() > 0 => ImportOutcome::Created,
+                            Ok(_) if alias_limit_reached(&mut *tx, &checked.owner).await? => {
+                                ImportOutcome::Failed("Alias limit reached".to_string())
+                            }
+                            Ok(_) => ImportOutcome::Duplicate,
+                            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => ImportOutcome::Duplicate,
+                            Err(e) => ImportOutcome::Failed(e.to_string()),
+                        }
+                    }
+                }
+            }
+        };
+        imported.push(ImportedRow { row, address, outcome });
+    }
+    tx.commit().await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+
+    let created = imported.iter().filter(|x| x.outcome == ImportOutcome::Created).count();
+    log::info!(
+        "imported {created} of {} aliases for '{}'",
+        imported.len(),
+        user.username
+    );
+    Ok(imported)
+}
+
+/// Returns all aliases forwarding to the given target that the user is allowed to see.
+#[cfg(feature = "ssr")]
+pub async fn aliases_by_target(pool: &sqlx::SqlitePool, user: &User, target: &str) -> Result<Vec<Alias>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE target = ",
+    );
+    query.push_bind(target);
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    query.push(" ORDER BY address");
+
+    query.logged().build_query_as::<Alias>().fetch_all(pool).await
+}
+
+/// Whether the alias list should be ordered by activity instead of creation date by default.
+#[server]
+pub async fn sort_aliases_by_activity() -> Result<bool, ServerFnError> {
+    crate::auth::auth_any().await?;
+    Ok(crate::database::ssr::config()?.sort_aliases_by_activity)
+}
+
+/// Rejects the queries of the statistics cards if they are disabled, so that
+/// they aren't issued even by clients that still show the cards.
+#[cfg(feature = "ssr")]
+fn ensure_stats_enabled() -> Result<(), ServerFnError> {
+    if !crate::database::ssr::config()?.stats {
+        return Err(ServerFnError::new(STATS_DISABLED_ERROR));
+    }
+    Ok(())
 }
 
+/// The error of the statistics queries if the statistics are disabled
+pub const STATS_DISABLED_ERROR: &str = "Statistics are disabled";
+
 /// Count all aliases, or just active/inactive ones if specified.
+/// Optionally only counts aliases of the given domain.
 #[server]
-pub async fn alias_count(active: Option<bool>, since: Option<DateTime<Utc>>) -> Result<usize, ServerFnError> {
+pub async fn alias_count(
+    active: Option<bool>,
+    since: Option<DateTime<Utc>>,
+    domain: Option<String>,
+) -> Result<usize, ServerFnError> {
+    // The plain count is also the row count of the alias table, the others are statistics
+    if active.is_some() || since.is_some() {
+        ensure_stats_enabled()?;
+    }
     let user = crate::auth::auth_any().await?;
+    let filter = format!("{active:?}/{domain:?}");
 
+    let pool = crate::database::ssr::pool()?;
+    let load = async { Ok::<_, ServerFnError>(count_aliases(&pool, &user, active, since, domain).await? as usize) };
+    // The start of a time range usually differs on every call, so such counts aren't cached
+    if since.is_some() {
+        return load.await;
+    }
+    crate::database::ssr::count_cache()?
+        .get_or_load(crate::count_cache::CountTable::Aliases, &user.username, filter, load)
+        .await
+}
+
+#[cfg(feature = "ssr")]
+async fn count_aliases(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    active: Option<bool>,
+    since: Option<DateTime<Utc>>,
+    domain: Option<String>,
+) -> Result<i64, sqlx::Error> {
     let mut query = QueryBuilder::new("SELECT COUNT(*) FROM aliases WHERE 1=1");
-    if !user.admin {
-        push_and_check_aliases_owner(&mut query, user.username.clone());
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    if let Some(domain) = domain {
+        query.push(" AND domain = ");
+        query.push_bind(domain);
     }
     if let Some(active) = active {
         query.push(" AND active = ");
@@ -135,75 +604,327 @@ pub async fn alias_count(active: Option<bool>, since: Option<DateTime<Utc>>) ->
         query.push_bind(since);
     }
 
+    query.logged().build_query_scalar::<i64>().fetch_one(pool).await
+}
+
+/// The maximum number of aliases returned by [`recent_aliases`]
+const MAX_RECENT_ALIASES: usize = 50;
+
+/// Returns the most recently created aliases of the user, newest first.
+#[server]
+pub async fn recent_aliases(limit: usize) -> Result<Vec<Alias>, ServerFnError> {
+    ensure_stats_enabled()?;
+    let user = crate::auth::auth_any().await?;
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
+    Ok(recent_aliases_of(&pool, &user, limit).await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn recent_aliases_of(pool: &sqlx::SqlitePool, user: &User, limit: usize) -> Result<Vec<Alias>, sqlx::Error> {
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE 1=1",
+    );
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    query.push(" ORDER BY created_at DESC, address LIMIT ");
+    query.push_bind(limit.min(MAX_RECENT_ALIASES) as i64);
 
-    Ok(count as usize)
+    query.logged().build_query_as::<Alias>().fetch_all(pool).await
 }
 
-/// Sum message counts on all aliases
+/// Count the aliases created per day since the given point in time. Days are
+/// bucketed in the timezone given by the offset to UTC in minutes, so the
+/// client can pass its local offset. Admins see all aliases unless they pass an
+/// owner, which allows showing the timeline of a single user or mailbox.
 #[server]
-pub async fn count_sent_or_received(sent: bool) -> Result<usize, ServerFnError> {
+pub async fn alias_creation_timeline(
+    since: DateTime<Utc>,
+    utc_offset_minutes: i32,
+    owner: Option<String>,
+) -> Result<Vec<(NaiveDate, usize)>, ServerFnError> {
     let user = crate::auth::auth_any().await?;
 
-    let col_name = if sent { "n_sent" } else { "n_recv" };
-    let mut query = QueryBuilder::new(format!("SELECT SUM({col_name}) FROM aliases WHERE 1=1"));
-    if !user.admin {
-        push_and_check_aliases_owner(&mut query, user.username.clone());
+    // Real-world offsets are within [-12:00, +14:00]
+    if !(-12 * 60..=14 * 60).contains(&utc_offset_minutes) {
+        return Err(ServerFnError::new("invalid utc offset"));
+    }
+
+    let pool = crate::database::ssr::pool()?;
+    Ok(creation_timeline(&pool, &user, since, utc_offset_minutes, owner.as_deref()).await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn creation_timeline(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    since: DateTime<Utc>,
+    utc_offset_minutes: i32,
+    owner: Option<&str>,
+) -> Result<Vec<(NaiveDate, usize)>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT date(created_at, ");
+    query.push_bind(format!("{utc_offset_minutes:+} minutes"));
+    // Compared in SQLite, so the format matches the CURRENT_TIMESTAMP of created_at
+    query.push(") AS day, COUNT(*) FROM aliases WHERE datetime(created_at) >= datetime(");
+    query.push_bind(since);
+    query.push(")");
+    if let Some(owner) = owner {
+        query.push(" AND owner = ");
+        query.push_bind(owner);
     }
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    query.push(" GROUP BY day ORDER BY day");
+
+    let days = query
+        .logged()
+        .build_query_as::<(NaiveDate, i64)>()
+        .fetch_all(pool)
+        .await?;
+
+    Ok(days.into_iter().map(|(day, count)| (day, count as usize)).collect())
+}
+
+/// Sum message counts on all aliases, or just on the aliases of the given domain.
+#[server]
+pub async fn count_sent_or_received(sent: bool, domain: Option<String>) -> Result<usize, ServerFnError> {
+    ensure_stats_enabled()?;
+    let user = crate::auth::auth_any().await?;
 
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
+    Ok(sum_sent_or_received(&pool, &user, sent, domain).await? as usize)
+}
+
+#[cfg(feature = "ssr")]
+async fn sum_sent_or_received(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    sent: bool,
+    domain: Option<String>,
+) -> Result<i64, sqlx::Error> {
+    let col_name = if sent { "n_sent" } else { "n_recv" };
+    let mut query = QueryBuilder::new(format!("SELECT COALESCE(SUM({col_name}), 0) FROM aliases WHERE 1=1"));
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    if let Some(domain) = domain {
+        query.push(" AND domain = ");
+        query.push_bind(domain);
+    }
 
-    Ok(count as usize)
+    query.logged().build_query_scalar::<i64>().fetch_one(pool).await
 }
 
 #[server]
 pub async fn delete_alias(address: String) -> Result<(), ServerFnError> {
     let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Aliases, &address).await?;
 
     let mut query = QueryBuilder::new("DELETE FROM aliases WHERE address = ");
     query.push_bind(address);
 
     // Non-admins can only delete their own aliases
-    if !user.admin {
-        push_and_check_aliases_owner(&mut query, user.username.clone());
-    }
+    push_owner_scope(&mut query, &user, OwnerScope::Aliases);
+
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+    Ok(())
+}
+
+/// A deleted alias that can still be restored
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct DeletedAlias {
+    pub address: String,
+    pub target: String,
+    pub owner: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Lists the aliases that were deleted within the last `IDMAIL_ALIAS_TRASH_DAYS`, most recent first.
+#[server]
+pub async fn list_deleted_aliases() -> Result<Vec<DeletedAlias>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT address, target, owner, deleted_at FROM deleted_aliases \
+        WHERE deleted_at > datetime('now', '-' || ",
+    );
+    query.push_bind(crate::database::ssr::config()?.alias_trash_days);
+    query.push(" || ' days')");
+    push_owner_scope(&mut query, &user, OwnerScope::Aliases);
+    query.push(" ORDER BY deleted_at DESC, address");
 
     let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    Ok(query.logged().build_query_as::<DeletedAlias>().fetch_all(&pool).await?)
+}
+
+/// Restores an alias from the trash, as long as its grace period isn't over.
+#[server]
+pub async fn restore_alias(address: String) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    let trash_days = crate::database::ssr::config()?.alias_trash_days;
+    restore(&pool, &user, &address, trash_days).await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+
+    Ok(())
+}
+
+/// Moves an alias of the user from the trash back to the aliases, if it was deleted within the given days
+#[cfg(feature = "ssr")]
+async fn restore(pool: &sqlx::SqlitePool, user: &User, address: &str, trash_days: u32) -> Result<(), ServerFnError> {
+    let mut query = QueryBuilder::new("SELECT domain, owner FROM deleted_aliases WHERE address = ");
+    query.push_bind(address);
+    query.push(" AND deleted_at > datetime('now', '-' || ");
+    query.push_bind(trash_days);
+    query.push(" || ' days')");
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+    let Some((domain, owner)) = query
+        .logged()
+        .build_query_as::<(String, String)>()
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Err(ServerFnError::new(format!("'{address}' cannot be restored anymore")));
+    };
+
+    // The domain may have been deleted or become unavailable in the meantime
+    if crate::domains::allowed_domain_owner_for(pool, user, &domain)
+        .await?
+        .is_none()
+    {
+        return Err(ServerFnError::new(format!("'{domain}' is not available anymore")));
+    }
+    // Creating the alias removes it from the trash. Mails it received before are not reported by digests again
+    let mut query = QueryBuilder::new(
+        "INSERT INTO aliases (address, domain, target, comment, n_recv, n_sent, active, owner, created_at, \
+        daily_limit, name, max_received, created_via, expires_at, from_name, digest_n_recv) \
+        SELECT address, domain, target, comment, n_recv, n_sent, active, owner, created_at, \
+        daily_limit, name, max_received, created_via, expires_at, from_name, n_recv \
+        FROM deleted_aliases WHERE address = ",
+    );
+    query.push_bind(address);
+    // make sure that no mailbox was renamed to that address in the meantime
+    query.push(" AND NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
+    query.push_bind(address);
+    query.push(") AND ");
+    push_alias_limit_not_reached(&mut query, &owner);
+    let result = query
+        .logged()
+        .build()
+        .execute(pool)
+        .await
+        .map_err(map_unique_violation("alias", address))?;
+    if result.rows_affected() == 0 {
+        if alias_limit_reached(pool, &owner).await? {
+            return Err(ServerFnError::new("Alias limit reached"));
+        }
+        return Err(ValidationErrors::field("alias", "This address is already in use by a mailbox!").into());
+    }
+
     Ok(())
 }
 
+/// Lists the caller's own aliases that never received any mail and were created before the given
+/// point in time. Provisioned aliases are excluded, since they are managed elsewhere. Admins only
+/// see their own aliases here as well, so pruning never touches other users.
 #[server]
-pub async fn create_or_update_alias(
-    old_address: Option<String>,
-    alias: String,
+pub async fn list_unused_aliases(older_than: DateTime<Utc>) -> Result<Vec<Alias>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases \
+        WHERE n_recv = 0 AND provisioned = FALSE AND created_at < ",
+    );
+    query.push_bind(older_than);
+    push_own_aliases_scope(&mut query, &user);
+    query.push(" ORDER BY created_at");
+
+    let pool = crate::database::ssr::pool()?;
+    Ok(query.logged().build_query_as::<Alias>().fetch_all(&pool).await?)
+}
+
+/// Deletes all aliases that [`list_unused_aliases`] returns for the same point in time.
+/// Returns the number of deleted aliases.
+#[server]
+pub async fn delete_unused_aliases(older_than: DateTime<Utc>) -> Result<usize, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    let deleted = delete_unused_aliases_of(&pool, &user, older_than, false).await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+    Ok(deleted)
+}
+
+/// Deletes the user's own aliases that never received mail and are older than the given cutoff.
+/// Provisioned aliases and aliases of other users are never touched, even for admins.
+/// With `api_only`, only aliases created through the API are deleted, which always have a random
+/// address. Returns the number of deleted aliases.
+#[cfg(feature = "ssr")]
+pub async fn delete_unused_aliases_of(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    older_than: DateTime<Utc>,
+    api_only: bool,
+) -> Result<usize, sqlx::Error> {
+    let mut query = QueryBuilder::new("DELETE FROM aliases WHERE n_recv = 0 AND provisioned = FALSE AND created_at < ");
+    query.push_bind(older_than);
+    push_own_aliases_scope(&mut query, user);
+    if api_only {
+        query.push(" AND created_via NOT IN ('ui', 'import')");
+    }
+
+    Ok(query.logged().build().execute(pool).await?.rows_affected() as usize)
+}
+
+/// The normalized and validated fields of an alias that is about to be created or updated
+#[cfg(feature = "ssr")]
+struct CheckedAlias {
+    address: String,
     domain: String,
     target: String,
-    comment: String,
-    active: bool,
     owner: String,
-) -> Result<(), ServerFnError> {
-    use crate::domains::allowed_domains;
+}
+
+/// Validates the inputs of [`create_or_update_alias`] without changing anything,
+/// so the same rules apply to the dry run in [`validate_alias`].
+#[cfg(feature = "ssr")]
+#[allow(clippy::too_many_arguments)]
+async fn check_alias(
+    user: &User,
+    pool: &sqlx::SqlitePool,
+    old_address: Option<&str>,
+    alias: &str,
+    domain: &str,
+    target: &str,
+    daily_limit: Option<i64>,
+    owner: &str,
+) -> Result<CheckedAlias, ServerFnError> {
+    use crate::domains::{allowed_domain_owner_for, localpart_case_sensitive};
     use crate::mailboxes::allowed_targets;
 
-    let user = crate::auth::auth_any().await?;
-    let pool = crate::database::ssr::pool()?;
+    if daily_limit.is_some_and(|x| x < 0) {
+        return Err(ValidationErrors::field("daily_limit", "daily limit must be a non-negative integer").into());
+    }
 
-    let target = if target.is_empty() || !user.admin {
-        if user.mailbox_owner.is_some() {
-            &user.username
+    let target = if user.admin {
+        // Admins may target any address, which is validated below
+        target
+    } else if user.mailbox_owner.is_some() {
+        // Mailboxes may forward to their verified recipients, otherwise they target themselves
+        if !target.is_empty() && allowed_targets().await?.iter().any(|x| x == target) {
+            target
         } else {
-            if !allowed_targets().await?.contains(&target) {
-                return Err(ServerFnError::new("target must be set to a valid email address"));
-            }
-            &target
+            &user.username
         }
     } else {
-        &target
+        if !allowed_targets().await?.iter().any(|x| x == target) {
+            return Err(ValidationErrors::field("target", "target must be set to a valid email address").into());
+        }
+        target
     };
 
+    // Whatever the role, the resolved target must be a valid address
+    if target.is_empty() || email_address::EmailAddress::from_str(target).is_err() {
+        return Err(ValidationErrors::field("target", "target must be set to a valid email address").into());
+    }
+
     let owner = if user.admin {
         // Only admins can assign other owners
         owner.trim()
@@ -217,46 +938,239 @@ pub async fn create_or_update_alias(
 
     // Empty owner -> self owned
     let owner = if owner.is_empty() { &user.username } else { owner };
+    if user.admin {
+        crate::users::check_owner_exists(pool, owner, true).await?;
+    }
 
     // Check if address is valid
-    let allowed_domains = allowed_domains().await?;
-    let Some((_, domain_owner)) = allowed_domains.iter().find(|x| x.0 == domain) else {
-        return Err(ServerFnError::new("domain must be set to a valid domain"));
+    let config = crate::database::ssr::config()?;
+    let domain = normalize_domain(domain, config.reject_noncanonical_domains)
+        .map_err(|e| ValidationErrors::field("domain", e))?;
+    let Some(domain_owner) = allowed_domain_owner_for(pool, user, &domain).await? else {
+        return Err(ValidationErrors::field("domain", "domain must be set to a valid domain").into());
     };
 
+    let case_sensitive = localpart_case_sensitive(pool, &domain).await?;
+    let alias = if case_sensitive {
+        alias.to_string()
+    } else {
+        alias.to_lowercase()
+    };
     let address = validate_address(
         &alias,
         &domain,
-        user.admin || *domain_owner == user.username || user.mailbox_owner.is_some_and(|x| x == *domain_owner),
+        user.admin || domain_owner == user.username || user.mailbox_owner.as_ref() == Some(&domain_owner),
     )
-    .map_err(ServerFnError::new)?;
+    .map_err(|e| ValidationErrors::field("alias", e))?;
+    check_globally_reserved(&config, &alias, user.admin).map_err(|e| ValidationErrors::field("alias", e))?;
+
+    // Aliases must never shadow existing mailboxes
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM mailboxes WHERE address = ");
+    query.push_bind(&address);
+    if !case_sensitive {
+        query.push(" COLLATE NOCASE");
+    }
+    if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? > 0 {
+        return Err(ValidationErrors::field("alias", format!("'{address}' is already in use by a mailbox!")).into());
+    }
 
-    let mut query = if let Some(old_address) = old_address {
-        let mut query = QueryBuilder::new("UPDATE aliases SET address = ");
-        query.push_bind(&address);
-        query.push(", domain = ");
-        query.push_bind(domain);
-        query.push(", target = ");
-        query.push_bind(target);
-        query.push(", comment = ");
-        query.push_bind(comment);
-        query.push(", active = ");
-        query.push_bind(active);
-        query.push(", owner = ");
-        query.push_bind(owner);
-        query.push(" WHERE address = ");
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM aliases WHERE address = ");
+    query.push_bind(&address);
+    if !case_sensitive {
+        query.push(" COLLATE NOCASE");
+    }
+    if let Some(old_address) = old_address {
+        // Renaming an alias must not conflict with itself
+        query.push(" AND address != ");
         query.push_bind(old_address);
-        if !user.admin {
-            push_and_check_aliases_owner(&mut query, user.username.clone());
-        }
-        // make sure that no mailbox exists with that address
-        query.push(" AND NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
-        query.push_bind(&address);
+    }
+    if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? > 0 {
+        return Err(ValidationErrors::field("alias", format!("'{address}' already exists!")).into());
+    }
+
+    // Optionally, each owner may only point a single alias at a target
+    if config.unique_targets {
+        let mut query = QueryBuilder::new("SELECT address FROM aliases WHERE owner = ");
+        query.push_bind(owner);
+        query.push(" AND target = ");
+        query.push_bind(target);
+        if let Some(old_address) = old_address {
+            query.push(" AND address != ");
+            query.push_bind(old_address);
+        }
+        query.push(" LIMIT 1");
+        if let Some(existing) = query
+            .logged()
+            .build_query_scalar::<String>()
+            .fetch_optional(pool)
+            .await?
+        {
+            return Err(
+                ValidationErrors::field("target", format!("'{existing}' already forwards to '{target}'!")).into(),
+            );
+        }
+    }
+
+    Ok(CheckedAlias {
+        address,
+        domain,
+        target: target.to_string(),
+        owner: owner.to_string(),
+    })
+}
+
+/// Runs the validation of [`create_or_update_alias`] without creating or updating
+/// anything, and returns the errors that saving would currently result in.
+#[server]
+pub async fn validate_alias(
+    old_address: Option<String>,
+    alias: String,
+    domain: String,
+    target: String,
+    daily_limit: Option<i64>,
+    owner: String,
+) -> Result<ValidationErrors, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    match check_alias(
+        &user,
+        &pool,
+        old_address.as_deref(),
+        &alias,
+        &domain,
+        &target,
+        daily_limit,
+        &owner,
+    )
+    .await
+    {
+        Ok(_) => Ok(ValidationErrors::default()),
+        Err(e) => ValidationErrors::from_server_error(&e).ok_or(e),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[server]
+pub async fn create_or_update_alias(
+    old_address: Option<String>,
+    alias: String,
+    domain: String,
+    target: String,
+    name: String,
+    comment: String,
+    daily_limit: Option<i64>,
+    max_received: Option<i64>,
+    expires_at: Option<DateTime<Utc>>,
+    from_name: String,
+    active: bool,
+    owner: String,
+) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    save_alias(
+        &pool,
+        &user,
+        old_address,
+        alias,
+        domain,
+        target,
+        name,
+        comment,
+        daily_limit,
+        max_received,
+        expires_at,
+        from_name,
+        active,
+        owner,
+    )
+    .await
+}
+
+/// Creates or updates an alias on behalf of the given user, see [`create_or_update_alias`]
+#[cfg(feature = "ssr")]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn save_alias(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    old_address: Option<String>,
+    alias: String,
+    domain: String,
+    target: String,
+    name: String,
+    comment: String,
+    daily_limit: Option<i64>,
+    max_received: Option<i64>,
+    expires_at: Option<DateTime<Utc>>,
+    from_name: String,
+    active: bool,
+    owner: String,
+) -> Result<(), ServerFnError> {
+    let CheckedAlias {
+        address,
+        domain,
+        target,
+        owner,
+    } = check_alias(
+        user,
+        pool,
+        old_address.as_deref(),
+        &alias,
+        &domain,
+        &target,
+        daily_limit,
+        &owner,
+    )
+    .await?;
+    if max_received.is_some_and(|x| x <= 0) {
+        return Err(ValidationErrors::field("max_received", "deactivation limit must be a positive integer").into());
+    }
+    // Empty name -> display the address
+    let name = Some(name.trim()).filter(|x| !x.is_empty());
+    let from_name = parse_from_name(&from_name).map_err(|e| ValidationErrors::field("from_name", e.to_string()))?;
+
+    let mut query = if let Some(old_address) = &old_address {
+        ensure_not_provisioned(pool, user, Provisioned::Aliases, old_address).await?;
+        let mut query = QueryBuilder::new("UPDATE aliases SET address = ");
+        query.push_bind(&address);
+        query.push(", domain = ");
+        query.push_bind(domain);
+        query.push(", target = ");
+        query.push_bind(target);
+        query.push(", name = ");
+        query.push_bind(name);
+        query.push(", comment = ");
+        query.push_bind(comment);
+        query.push(", daily_limit = ");
+        query.push_bind(daily_limit);
+        query.push(", max_received = ");
+        query.push_bind(max_received);
+        query.push(", expires_at = ");
+        query.push_bind(expires_at);
+        query.push(", from_name = ");
+        query.push_bind(from_name);
+        query.push(", active = ");
+        query.push_bind(active);
+        query.push(", owner = ");
+        query.push_bind(&owner);
+        query.push(" WHERE address = ");
+        query.push_bind(old_address);
+        push_owner_scope(&mut query, user, OwnerScope::Aliases);
+        // make sure that no mailbox exists with that address
+        query.push(" AND NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
+        query.push_bind(&address);
+        query.push(")");
+        // a new owner must have room for another alias
+        query.push(" AND (owner = ");
+        query.push_bind(&owner);
+        query.push(" OR ");
+        push_alias_limit_not_reached(&mut query, &owner);
         query.push(")");
 
         query
     } else {
-        let mut query = QueryBuilder::new("INSERT INTO aliases (address, domain, target, comment, active, owner)");
+        let mut query = QueryBuilder::new(
+            "INSERT INTO aliases (address, domain, target, name, comment, daily_limit, max_received, expires_at, from_name, active, owner, created_via)",
+        );
         query.push("SELECT ");
         query.push_bind(&address);
         query.push(", ");
@@ -264,82 +1178,329 @@ pub async fn create_or_update_alias(
         query.push(", ");
         query.push_bind(target);
         query.push(", ");
+        query.push_bind(name);
+        query.push(", ");
         query.push_bind(comment);
         query.push(", ");
+        query.push_bind(daily_limit);
+        query.push(", ");
+        query.push_bind(max_received);
+        query.push(", ");
+        query.push_bind(expires_at);
+        query.push(", ");
+        query.push_bind(from_name);
+        query.push(", ");
         query.push_bind(active);
         query.push(", ");
-        query.push_bind(owner);
+        query.push_bind(&owner);
+        query.push(", 'ui'");
         // make sure that no mailbox exists with that address
         query.push(" WHERE NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
         query.push_bind(&address);
-        query.push(")");
+        query.push(") AND ");
+        push_alias_limit_not_reached(&mut query, &owner);
 
         query
     };
 
-    if query.build().execute(&pool).await?.rows_affected() == 0 {
-        return Err(ServerFnError::new("This address is already in use by a mailbox!"));
+    let result = query
+        .logged()
+        .build()
+        .execute(pool)
+        .await
+        .map_err(map_unique_violation("alias", &address))?;
+    if result.rows_affected() == 0 {
+        return Err(unwritten_alias_error(pool, user, old_address.as_deref(), &address, &owner).await?);
     }
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
 
     Ok(())
 }
 
+/// Explains why creating or updating an alias affected no rows: the alias to update doesn't exist
+/// or isn't accessible, a mailbox already uses the address or the owner reached their alias limit.
+#[cfg(feature = "ssr")]
+async fn unwritten_alias_error(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    old_address: Option<&str>,
+    address: &str,
+    owner: &str,
+) -> Result<ServerFnError, ServerFnError> {
+    if let Some(old_address) = old_address {
+        let mut query = QueryBuilder::new("SELECT COUNT(*) FROM aliases WHERE address = ");
+        query.push_bind(old_address);
+        push_owner_scope(&mut query, user, OwnerScope::Aliases);
+        if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? == 0 {
+            return Ok(ServerFnError::new(format!("'{old_address}' does not exist")));
+        }
+    }
+
+    let mailbox_exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM mailboxes WHERE address = ?")
+        .bind(address)
+        .fetch_one(pool)
+        .await?
+        > 0;
+    if mailbox_exists {
+        return Ok(ValidationErrors::field("alias", "This address is already in use by a mailbox!").into());
+    }
+    if alias_limit_reached(pool, owner).await? {
+        return Ok(ServerFnError::new("Alias limit reached"));
+    }
+    // Whatever prevented the change is gone by now
+    Ok(ServerFnError::new(
+        "The alias was changed concurrently, please try again",
+    ))
+}
+
 #[server]
 pub async fn update_alias_active(address: String, active: bool) -> Result<(), ServerFnError> {
     let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Aliases, &address).await?;
     let mut query = QueryBuilder::new("UPDATE aliases SET active = ");
     query.push_bind(active);
     query.push(" WHERE address = ");
     query.push_bind(address);
 
     // Non-admins can only change their own aliases
-    if !user.admin {
-        push_and_check_aliases_owner(&mut query, user.username.clone());
+    push_owner_scope(&mut query, &user, OwnerScope::Aliases);
+
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+    Ok(())
+}
+
+/// An action that is applied to several selected aliases at once
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BulkAction {
+    Delete,
+    Activate,
+    Deactivate,
+}
+
+/// Applies the given action to all of the given aliases in a single transaction.
+/// Aliases that the user may not access are skipped. Returns the number of affected aliases.
+#[server]
+pub async fn bulk_update_aliases(addresses: Vec<String>, action: BulkAction) -> Result<usize, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    for address in &addresses {
+        ensure_not_provisioned(&pool, &user, Provisioned::Aliases, address).await?;
     }
 
+    let mut tx = pool.begin().await?;
+    let mut affected = 0;
+    for address in addresses {
+        let mut query = match action {
+            BulkAction::Delete => QueryBuilder::new("DELETE FROM aliases"),
+            BulkAction::Activate | BulkAction::Deactivate => {
+                let mut query = QueryBuilder::new("UPDATE aliases SET active = ");
+                query.push_bind(action == BulkAction::Activate);
+                query
+            }
+        };
+        query.push(" WHERE address = ");
+        query.push_bind(address);
+
+        // Non-admins can only change their own aliases
+        push_owner_scope(&mut query, &user, OwnerScope::Aliases);
+        affected += query.logged().build().execute(&mut *tx).await?.rows_affected() as usize;
+    }
+    tx.commit().await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+
+    Ok(affected)
+}
+
+/// Aliases whose addresses only differ in case. Such aliases can exist in databases that were
+/// filled before addresses were normalized, and which of them receives mail depends on the lookup.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaseDuplicates {
+    /// The lowercased address shared by all aliases in the group
+    pub address: String,
+    /// The addresses of the colliding aliases, oldest first
+    pub aliases: Vec<String>,
+}
+
+/// Finds all aliases whose addresses only differ in case, which is only allowed for admins.
+/// Domains with case sensitive localparts are skipped, since their aliases are distinct.
+#[server]
+pub async fn find_case_duplicates() -> Result<Vec<CaseDuplicates>, ServerFnError> {
+    crate::auth::auth_admin().await?;
     let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
-    Ok(())
+    Ok(case_duplicates(&pool).await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn case_duplicates(pool: &sqlx::SqlitePool) -> Result<Vec<CaseDuplicates>, sqlx::Error> {
+    let addresses = sqlx::query_scalar::<_, String>(
+        "SELECT address FROM aliases WHERE lower(address) IN ( \
+        SELECT lower(a.address) FROM aliases a LEFT JOIN domains d ON d.domain = a.domain \
+        WHERE NOT COALESCE(d.localpart_case_sensitive, FALSE) \
+        GROUP BY lower(a.address) HAVING COUNT(*) > 1) \
+        ORDER BY lower(address), created_at, address",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // SQLite's lower() only folds ASCII, so the groups must be formed the same way
+    let mut groups: Vec<CaseDuplicates> = Vec::new();
+    for alias in addresses {
+        let address = alias.to_ascii_lowercase();
+        match groups.last_mut() {
+            Some(group) if group.address == address => group.aliases.push(alias),
+            _ => groups.push(CaseDuplicates {
+                address,
+                aliases: vec![alias],
+            }),
+        }
+    }
+    Ok(groups)
+}
+
+/// Merges all aliases whose addresses only differ in case from the given one into it, which is
+/// only allowed for admins. The mail counters of the other aliases are added to the kept one
+/// before they are removed. Returns the number of removed aliases.
+#[server]
+pub async fn merge_case_duplicates(keep: String) -> Result<usize, ServerFnError> {
+    let user = crate::auth::auth_admin().await?;
+    let pool = crate::database::ssr::pool()?;
+    let removed = merge_into(&pool, &user, &keep).await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+
+    log::info!("merged {removed} aliases that only differ in case into '{keep}'");
+    Ok(removed)
+}
+
+#[cfg(feature = "ssr")]
+async fn merge_into(pool: &sqlx::SqlitePool, user: &User, keep: &str) -> Result<usize, ServerFnError> {
+    let Some(domain) = sqlx::query_scalar::<_, String>("SELECT domain FROM aliases WHERE address = ?")
+        .bind(keep)
+        .fetch_optional(pool)
+        .await?
+    else {
+        return Err(ServerFnError::new(format!("'{keep}' does not exist")));
+    };
+    if crate::domains::localpart_case_sensitive(pool, &domain).await? {
+        return Err(ServerFnError::new(format!(
+            "'{domain}' has case sensitive localparts, so its aliases are not duplicates"
+        )));
+    }
+
+    let duplicates = sqlx::query_scalar::<_, String>(
+        "SELECT address FROM aliases WHERE lower(address) = lower(?1) AND address != ?1",
+    )
+    .bind(keep)
+    .fetch_all(pool)
+    .await?;
+    for address in &duplicates {
+        ensure_not_provisioned(pool, user, Provisioned::Aliases, address).await?;
+    }
+
+    let mut tx = pool.begin().await?;
+    let (n_recv, n_sent) = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT COALESCE(SUM(n_recv), 0), COALESCE(SUM(n_sent), 0) FROM aliases \
+        WHERE lower(address) = lower(?1) AND address != ?1",
+    )
+    .bind(keep)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE aliases SET n_recv = n_recv + ?, n_sent = n_sent + ? WHERE address = ?")
+        .bind(n_recv)
+        .bind(n_sent)
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+    let removed = sqlx::query("DELETE FROM aliases WHERE lower(address) = lower(?1) AND address != ?1")
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() as usize;
+    // Restoring a merged alias from the trash would bring back the duplicate and count its mails twice
+    sqlx::query("DELETE FROM deleted_aliases WHERE lower(address) = lower(?1) AND address != ?1")
+        .bind(keep)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(removed)
+}
+
+/// The last row of a loaded range, from which the next range of the same query can continue
+#[derive(Clone)]
+struct LastRow {
+    search: String,
+    provisioned: Option<bool>,
+    /// The end of the loaded range
+    end: usize,
+    created_at: DateTime<Utc>,
+    address: String,
 }
 
 #[derive(Default)]
 pub struct AliasTableDataProvider {
     sort: VecDeque<(usize, ColumnSort)>,
     pub search: RwSignal<String>,
+    pub provisioned: RwSignal<Option<bool>>,
+    last_row: RwSignal<Option<LastRow>>,
 }
 
 impl TableDataProvider<Alias> for AliasTableDataProvider {
     async fn get_rows(&self, range: Range<usize>) -> Result<(Vec<Alias>, Range<usize>), String> {
+        let search = self.search.get_untracked().trim().to_string();
+        let provisioned = self.provisioned.get_untracked();
+        let after = self
+            .last_row
+            .get_untracked()
+            .filter(|x| x.end == range.start && x.search == search && x.provisioned == provisioned)
+            .map(|x| (x.created_at, x.address));
         list_aliases(AliasQuery {
-            search: self.search.get_untracked().trim().to_string(),
+            search: search.clone(),
+            provisioned,
             sort: self.sort.clone(),
             range: range.clone(),
+            after,
         })
         .await
         .map(|rows| {
             let len = rows.len();
+            self.last_row.set(rows.last().map(|x| LastRow {
+                search,
+                provisioned,
+                end: range.start + len,
+                created_at: x.created_at,
+                address: x.address.clone(),
+            }));
             (rows, range.start..range.start + len)
         })
         .map_err(|e| format!("{e:?}"))
     }
 
     async fn row_count(&self) -> Option<usize> {
-        alias_count(None, None).await.ok()
+        alias_count(None, None, None).await.ok()
     }
 
     fn set_sorting(&mut self, sorting: &VecDeque<(usize, ColumnSort)>) {
         self.sort.clone_from(sorting);
+        self.last_row.set(None);
     }
 
     fn track(&self) {
         self.search.track();
+        self.provisioned.track();
     }
 }
 
+/// The catch-all that an alias with the given state leaks mail to, if the user should be warned about it.
+/// Mail to inactive aliases is still delivered to the catch-all of the domain.
+fn catch_all_warning(active: bool, domain_catch_all: Option<String>) -> Option<String> {
+    domain_catch_all.filter(|_| !active)
+}
+
 #[component]
 pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
     let mut rows = AliasTableDataProvider::default();
-    let default_sorting = VecDeque::from([(7, ColumnSort::Descending)]);
+    let default_sorting = VecDeque::from([NEWEST_FIRST]);
     rows.set_sorting(&default_sorting);
     let sorting = create_rw_signal(default_sorting);
 
@@ -353,6 +1514,9 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
 
     let on_input = use_debounce_fn_with_arg(move |value| rows.search.set(value), 300.0);
     let (count, set_count) = create_signal(0);
+    let import_modal_open = create_rw_signal(false);
+    let case_duplicates_modal_open = create_rw_signal(false);
+    let user_admin = user.admin;
 
     let (allowed_domains, set_allowed_domains) = create_signal(vec![]);
     let refresh_domains = move || {
@@ -379,15 +1543,60 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
     if is_browser() {
         refresh_domains();
         refresh_targets();
+        spawn_local(async move {
+            match sort_aliases_by_activity().await {
+                Err(e) => error!("Failed to load the default alias sorting: {}", e),
+                Ok(true) => sorting.set(VecDeque::from([(ACTIVITY_COLUMN, ColumnSort::Descending)])),
+                Ok(false) => {}
+            }
+        });
     }
 
     let delete_modal_alias = create_rw_signal(None);
+
+    let selected_aliases = create_rw_signal(HashSet::<String>::new());
+    let bulk_delete_modal = create_rw_signal(None);
+    let run_bulk_action = move |action: BulkAction| {
+        let addresses = selected_aliases.get_untracked().into_iter().collect::<Vec<_>>();
+        spawn_local(async move {
+            if let Err(e) = bulk_update_aliases(addresses, action).await {
+                error!("Failed to update selected aliases: {}", e);
+            } else {
+                selected_aliases.set(HashSet::new());
+            }
+            bulk_delete_modal.set(None);
+            reload.notify();
+        });
+    };
+
+    // Aliases older than this that never received mail are offered for pruning
+    let prune_modal = create_rw_signal(None);
+    let (prune_cutoff, set_prune_cutoff) = create_signal(Utc::now());
+    let (prune_count, set_prune_count) = create_signal(0);
+    let open_prune_modal = move || {
+        let cutoff = Utc::now() - Months::new(3);
+        spawn_local(async move {
+            match list_unused_aliases(cutoff).await {
+                Err(e) => error!("Failed to load unused aliases: {}", e),
+                Ok(aliases) => {
+                    set_prune_cutoff(cutoff);
+                    set_prune_count(aliases.len());
+                    prune_modal.set(Some("unused aliases".to_string()));
+                }
+            }
+        });
+    };
     let edit_modal_alias = create_rw_signal(None);
 
     let (edit_modal_input_alias, set_edit_modal_input_alias) = create_signal("".to_string());
     let (edit_modal_input_domain, set_edit_modal_input_domain) = create_signal("".to_string());
     let (edit_modal_input_target, set_edit_modal_input_target) = create_signal("".to_string());
+    let (edit_modal_input_name, set_edit_modal_input_name) = create_signal("".to_string());
     let (edit_modal_input_comment, set_edit_modal_input_comment) = create_signal("".to_string());
+    let (edit_modal_input_daily_limit, set_edit_modal_input_daily_limit) = create_signal("".to_string());
+    let (edit_modal_input_max_received, set_edit_modal_input_max_received) = create_signal("".to_string());
+    let (edit_modal_input_expires_at, set_edit_modal_input_expires_at) = create_signal("".to_string());
+    let (edit_modal_input_from_name, set_edit_modal_input_from_name) = create_signal("".to_string());
     let (edit_modal_input_active, set_edit_modal_input_active) = create_signal(true);
     let (edit_modal_input_owner, set_edit_modal_input_owner) = create_signal("".to_string());
 
@@ -399,6 +1608,33 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
         });
     }
 
+    // Mail to inactive aliases is still delivered to the catch-all of the domain,
+    // so we warn the user about this when the selected domain has one.
+    let (domain_catch_all, set_domain_catch_all) = create_signal(None);
+    create_effect(move |_| {
+        let domain = edit_modal_input_domain();
+        spawn_local(async move {
+            use crate::domains::domain_catch_all;
+            match domain_catch_all(domain).await {
+                Err(e) => error!("Failed to load catch-all of domain: {}", e),
+                Ok(catch_all) => set_domain_catch_all(catch_all),
+            }
+        });
+    });
+    let catch_all_warning = move || catch_all_warning(edit_modal_input_active(), domain_catch_all());
+
+    // The initial state of the active checkbox for new aliases, as configured in the account settings
+    let (new_aliases_active, set_new_aliases_active) = create_signal(true);
+    if leptos::leptos_dom::is_browser() {
+        spawn_local(async move {
+            use crate::users::new_aliases_active;
+            match new_aliases_active().await {
+                Err(e) => error!("Failed to load new alias setting: {}", e),
+                Ok(active) => set_new_aliases_active(active),
+            }
+        });
+    }
+
     let username = user.username.clone();
     let is_mailbox = user.mailbox_owner.is_some();
     let edit_modal_open_with = Callback::new(move |edit_alias: Option<Alias>| {
@@ -420,7 +1656,12 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                 set_edit_modal_input_domain(domain);
             }
             set_edit_modal_input_target(edit_alias.target.clone());
+            set_edit_modal_input_name(edit_alias.name.clone().unwrap_or_default());
             set_edit_modal_input_comment(edit_alias.comment.clone());
+            set_edit_modal_input_daily_limit(edit_alias.daily_limit.map(|x| x.to_string()).unwrap_or_default());
+            set_edit_modal_input_max_received(edit_alias.max_received.map(|x| x.to_string()).unwrap_or_default());
+            set_edit_modal_input_expires_at(format_expires_at(edit_alias.expires_at));
+            set_edit_modal_input_from_name(edit_alias.from_name.clone().unwrap_or_default());
             set_edit_modal_input_active(edit_alias.active);
             set_edit_modal_input_owner(edit_alias.owner.clone());
         } else {
@@ -440,25 +1681,35 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
             } else if !allowed_targets.contains(&edit_modal_input_target()) {
                 set_edit_modal_input_target(allowed_targets.first().cloned().unwrap_or("".to_string()));
             }
+            set_edit_modal_input_name("".to_string());
             set_edit_modal_input_comment("".to_string());
-            set_edit_modal_input_active(true);
+            set_edit_modal_input_daily_limit("".to_string());
+            set_edit_modal_input_max_received("".to_string());
+            set_edit_modal_input_expires_at("".to_string());
+            set_edit_modal_input_from_name("".to_string());
+            set_edit_modal_input_active(new_aliases_active.get_untracked());
         }
     });
 
-    let on_edit = move |(data, on_error): (Option<Alias>, Callback<String>)| {
+    let on_edit = move |(data, on_error): (Option<Alias>, Callback<ServerFnError>)| {
         spawn_local(async move {
             if let Err(e) = create_or_update_alias(
                 data.map(|x| x.address),
                 edit_modal_input_alias.get_untracked(),
                 edit_modal_input_domain.get_untracked(),
                 edit_modal_input_target.get_untracked(),
+                edit_modal_input_name.get_untracked(),
                 edit_modal_input_comment.get_untracked(),
+                parse_daily_limit(&edit_modal_input_daily_limit.get_untracked()).unwrap_or_default(),
+                parse_max_received(&edit_modal_input_max_received.get_untracked()).unwrap_or_default(),
+                parse_expires_at(&edit_modal_input_expires_at.get_untracked()).unwrap_or_default(),
+                edit_modal_input_from_name.get_untracked(),
                 edit_modal_input_active.get_untracked(),
                 edit_modal_input_owner.get_untracked(),
             )
             .await
             {
-                on_error(e.to_string())
+                on_error(e)
             } else {
                 reload.notify();
                 edit_modal_alias.set(None);
@@ -475,6 +1726,28 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
         });
     };
 
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let edit_provisioned = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|x| x.edit_provisioned)
+    };
+    let trash_days = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .map_or(0, |x| x.alias_trash_days)
+    };
+    let delete_warning = move || {
+        if trash_days() > 0 {
+            format!("It can be restored from the trash within {} days.", trash_days())
+        } else {
+            "This action cannot be undone.".to_string()
+        }
+    };
+    let trash_modal_open = create_rw_signal(false);
+
     #[allow(unused_variables, non_snake_case)]
     let alias_row_renderer = move |class: Signal<String>,
                                    row: Alias,
@@ -483,23 +1756,55 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                                    on_select: EventHandler<MouseEvent>,
                                    on_change: EventHandler<ChangeEvent<Alias>>| {
         let delete_address = row.address.clone();
+        let select_address = row.address.clone();
+        let is_selected = {
+            let address = row.address.clone();
+            move || selected_aliases.with(|x| x.contains(&address))
+        };
         let edit_alias = row.clone();
+        let provisioned = row.provisioned;
+        let locked = move || provisioned && !edit_provisioned();
         view! {
             <tr class=class on:click=move |mouse_event| on_select.run(mouse_event)>
                 {row.render_row(index, on_change)}
                 <td class="w-1 px-4 py-2 whitespace-nowrap text-ellipsis">
-                    <div class="inline-flex items-center rounded-md">
+                    <input
+                        class="w-4 h-4 me-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all disabled:opacity-50"
+                        type="checkbox"
+                        title="Select"
+                        prop:checked=is_selected
+                        disabled=locked
+                        on:change=move |ev| {
+                            let checked = event_target_checked(&ev);
+                            let address = select_address.clone();
+                            selected_aliases
+                                .update(|x| {
+                                    if checked {
+                                        x.insert(address);
+                                    } else {
+                                        x.remove(&address);
+                                    }
+                                });
+                        }
+                    />
+                    {provisioned.then(|| view! { <ProvisionedBadge/> })}
+                    <div
+                        class="inline-flex items-center rounded-md"
+                        title=move || locked().then_some(PROVISIONED_TOOLTIP)
+                    >
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| edit_modal_open_with(Some(edit_alias.clone()))
+                            disabled=locked
                         >
                             <Icon icon=icondata::FiEdit class="w-5 h-5"/>
                         </button>
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| {
                                 delete_modal_alias.set(Some(delete_address.clone()));
                             }
+                            disabled=locked
                         >
 
                             <Icon icon=icondata::FiTrash2 class="w-5 h-5"/>
@@ -523,6 +1828,11 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
             || (!user.admin && edit_modal_input_target().is_empty())
     });
 
+    let has_invalid_daily_limit = create_memo(move |_| parse_daily_limit(&edit_modal_input_daily_limit()).is_err());
+    let has_invalid_max_received = create_memo(move |_| parse_max_received(&edit_modal_input_max_received()).is_err());
+    let has_invalid_expires_at = create_memo(move |_| parse_expires_at(&edit_modal_input_expires_at()).is_err());
+    let has_invalid_from_name = create_memo(move |_| parse_from_name(&edit_modal_input_from_name()).is_err());
+
     let errors = create_memo(move |_| {
         let mut errors = Vec::<String>::new();
         if let Err(e) = validate_address(
@@ -535,9 +1845,62 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
         if let Err(e) = email_address::EmailAddress::from_str(&edit_modal_input_target()) {
             errors.push(format!("invalid target address: {}", e));
         }
+        if let Err(e) = parse_daily_limit(&edit_modal_input_daily_limit()) {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = parse_max_received(&edit_modal_input_max_received()) {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = parse_expires_at(&edit_modal_input_expires_at()) {
+            errors.push(e.to_string());
+        }
+        if let Err(e) = parse_from_name(&edit_modal_input_from_name()) {
+            errors.push(e.to_string());
+        }
         errors
     });
 
+    // Validate the inputs on the server while typing, so problems show up before saving
+    let field_errors = create_rw_signal(ValidationErrors::default());
+    let validate = use_debounce_fn(
+        move || {
+            let Some(data) = edit_modal_alias.get_untracked() else {
+                return;
+            };
+            if !errors.get_untracked().is_empty() {
+                field_errors.set(ValidationErrors::default());
+                return;
+            }
+            spawn_local(async move {
+                let result = validate_alias(
+                    data.map(|x| x.address),
+                    edit_modal_input_alias.get_untracked(),
+                    edit_modal_input_domain.get_untracked(),
+                    edit_modal_input_target.get_untracked(),
+                    parse_daily_limit(&edit_modal_input_daily_limit.get_untracked()).unwrap_or_default(),
+                    edit_modal_input_owner.get_untracked(),
+                )
+                .await;
+                match result {
+                    Ok(errors) if edit_modal_alias.with_untracked(|x| x.is_some()) => field_errors.set(errors),
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to validate alias: {}", e),
+                }
+            });
+        },
+        500.0,
+    );
+    create_effect(move |_| {
+        edit_modal_input_alias.track();
+        edit_modal_input_domain.track();
+        edit_modal_input_target.track();
+        edit_modal_input_daily_limit.track();
+        edit_modal_input_owner.track();
+        if edit_modal_alias.with(|x| x.is_some()) {
+            validate();
+        }
+    });
+
     view! {
         <div class="h-full flex-1 flex-col mt-12">
             <div class="flex items-center justify-between space-y-2 mb-4">
@@ -554,6 +1917,44 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                             on_input(event_target_value(&e));
                         }
                     />
+                    <ProvisionedFilter value=rows.provisioned/>
+                    <button
+                        type="button"
+                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 dark:focus:ring-zinc-800"
+                        on:click=move |_| {
+                            spawn_local(async move {
+                                match export_aliases_csv().await {
+                                    Ok(csv) => {
+                                        if let Err(e) = download_file("aliases.csv", "text/csv", &csv) {
+                                            error!("Failed to download aliases: {:?}", e);
+                                        }
+                                    }
+                                    Err(e) => error!("Failed to export aliases: {}", e),
+                                }
+                            });
+                        }
+                    >
+                        <Icon icon=icondata::FiDownload class="w-6 h-6 me-2"/>
+                        "Export CSV"
+                    </button>
+                    <Show when=move || user_admin>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 dark:focus:ring-zinc-800"
+                            on:click=move |_| import_modal_open.set(true)
+                        >
+                            <Icon icon=icondata::FiUpload class="w-6 h-6 me-2"/>
+                            "Import"
+                        </button>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 dark:focus:ring-zinc-800"
+                            on:click=move |_| case_duplicates_modal_open.set(true)
+                        >
+                            <Icon icon=icondata::FiCopy class="w-6 h-6 me-2"/>
+                            "Case duplicates"
+                        </button>
+                    </Show>
 
                     <button
                         type="button"
@@ -576,6 +1977,51 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                         <Icon icon=icondata::FaDiceSolid class="w-6 h-6 me-2"/>
                         "New Random"
                     </button>
+                    <button
+                        type="button"
+                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-red-600 dark:hover:bg-red-500 hover:text-white dark:hover:text-black focus:ring-red-300 dark:focus:ring-red-900"
+                        on:click=move |_| open_prune_modal()
+                    >
+                        <Icon icon=icondata::FiTrash2 class="w-6 h-6 me-2"/>
+                        "Prune unused"
+                    </button>
+                    <Show when=move || trash_days() != 0>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 dark:focus:ring-zinc-800"
+                            on:click=move |_| trash_modal_open.set(true)
+                        >
+                            <Icon icon=icondata::FiRotateCcw class="w-6 h-6 me-2"/>
+                            "Trash"
+                        </button>
+                    </Show>
+                    <Show when=move || selected_aliases.with(|x| !x.is_empty())>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-white dark:bg-black border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            on:click=move |_| run_bulk_action(BulkAction::Activate)
+                        >
+                            "Activate (" {move || selected_aliases.with(|x| x.len())} ")"
+                        </button>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-white dark:bg-black border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            on:click=move |_| run_bulk_action(BulkAction::Deactivate)
+                        >
+                            "Deactivate (" {move || selected_aliases.with(|x| x.len())} ")"
+                        </button>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-red-600 dark:hover:bg-red-500 hover:text-white dark:hover:text-black focus:ring-red-300 dark:focus:ring-red-900"
+                            on:click=move |_| {
+                                let n = selected_aliases.with_untracked(|x| x.len());
+                                bulk_delete_modal.set(Some(format!("{n} aliases")));
+                            }
+                        >
+                            <Icon icon=icondata::FiTrash2 class="w-6 h-6 me-2"/>
+                            "Delete (" {move || selected_aliases.with(|x| x.len())} ")"
+                        </button>
+                    </Show>
                     <div class="flex flex-1"></div>
                     <div class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-right px-4">
                         {count} " results"
@@ -588,7 +2034,7 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                             <TableContent
                                 rows
                                 sorting=sorting
-                                sorting_mode=SortingMode::SingleColumn
+                                sorting_mode=SortingMode::MultiColumn
                                 row_renderer=alias_row_renderer
                                 reload_controller=reload_controller
                                 loading_row_display_limit=0
@@ -601,9 +2047,13 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
             </div>
         </div>
 
+        <ImportAliasesModal open=import_modal_open on_import=move |_| reload.notify()/>
+        <TrashModal open=trash_modal_open on_restore=move |_| reload.notify()/>
+        <CaseDuplicatesModal open=case_duplicates_modal_open on_merge=move |_| reload.notify()/>
+
         <DeleteModal
             data=delete_modal_alias
-            text="Are you sure you want to delete this alias? This action cannot be undone.".into_view()
+            text=view! { "Are you sure you want to delete this alias? " {delete_warning} }.into_view()
             on_confirm=move |data| {
                 spawn_local(async move {
                     if let Err(e) = delete_alias(data).await {
@@ -616,12 +2066,42 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
             }
         />
 
+        <DeleteModal
+            data=bulk_delete_modal
+            text=view! { "Are you sure you want to delete all selected aliases? " {delete_warning} }.into_view()
+            on_confirm=move |_| run_bulk_action(BulkAction::Delete)
+        />
+
+        <DeleteModal
+            data=prune_modal
+            text=view! {
+                {move || {
+                    format!(
+                        "{} of your own aliases were created more than 3 months ago and never received any mail. Are you sure you want to delete all of them? {}",
+                        prune_count(),
+                        delete_warning(),
+                    )
+                }}
+            }
+                .into_view()
+            on_confirm=move |_| {
+                spawn_local(async move {
+                    match delete_unused_aliases(prune_cutoff.get_untracked()).await {
+                        Err(e) => error!("Failed to delete unused aliases: {}", e),
+                        Ok(_) => reload.notify(),
+                    }
+                    prune_modal.set(None);
+                });
+            }
+        />
+
         <EditModal
             data=edit_modal_alias
             what="Alias".to_string()
             get_title=move |x| { &x.address }
             on_confirm=on_edit
             errors
+            field_errors
         >
             <div class="flex flex-col sm:flex-row">
                 <div class="flex flex-1 flex-col gap-2">
@@ -659,6 +2139,8 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     />
                 </div>
             </div>
+            <FieldErrorMessage field="alias"/>
+            <FieldErrorMessage field="domain"/>
             <div class="flex flex-col gap-2">
                 <label
                     class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
@@ -667,22 +2149,16 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     Target
                 </label>
 
-                {if user.admin || user.mailbox_owner.is_some() {
+                {if user.admin {
                     view! {
                         <input
                             class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
                             class=("!ring-4", has_invalid_target)
                             class=("!ring-red-500", has_invalid_target)
                             type="email"
-                            placeholder=if user.mailbox_owner.is_some() {
-                                user.username.clone()
-                            } else {
-                                "target@example.com".to_string()
-                            }
-
+                            placeholder="target@example.com"
                             on:input=move |ev| set_edit_modal_input_target(event_target_value(&ev))
                             prop:value=edit_modal_input_target
-                            disabled=!user.admin
                         />
                     }
                         .into_view()
@@ -697,7 +2173,22 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     }
                         .into_view()
                 }}
-
+                <FieldErrorMessage field="target"/>
+            </div>
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="name"
+                >
+                    Name
+                </label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    type="text"
+                    placeholder="Name (optional)"
+                    on:input=move |ev| set_edit_modal_input_name(event_target_value(&ev))
+                    prop:value=edit_modal_input_name
+                />
             </div>
             <div class="flex flex-col gap-2">
                 <label
@@ -717,17 +2208,89 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
             <div class="flex flex-col gap-2">
                 <label
                     class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
-                    for="owner"
+                    for="daily_limit"
                 >
-                    Owner
+                    Daily Limit
+                </label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    class=("!ring-4", has_invalid_daily_limit)
+                    class=("!ring-red-500", has_invalid_daily_limit)
+                    type="number"
+                    min="0"
+                    placeholder="Unlimited"
+                    on:input=move |ev| set_edit_modal_input_daily_limit(event_target_value(&ev))
+                    prop:value=edit_modal_input_daily_limit
+                />
+                <FieldErrorMessage field="daily_limit"/>
+            </div>
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="max_received"
+                >
+                    Deactivate after received mails
+                </label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    class=("!ring-4", has_invalid_max_received)
+                    class=("!ring-red-500", has_invalid_max_received)
+                    type="number"
+                    min="1"
+                    placeholder="Never"
+                    on:input=move |ev| set_edit_modal_input_max_received(event_target_value(&ev))
+                    prop:value=edit_modal_input_max_received
+                />
+                <FieldErrorMessage field="max_received"/>
+            </div>
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="expires_at"
+                >
+                    Deactivate on date
                 </label>
                 <input
                     class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    class=("!ring-4", has_invalid_expires_at)
+                    class=("!ring-red-500", has_invalid_expires_at)
+                    type="date"
+                    on:input=move |ev| set_edit_modal_input_expires_at(event_target_value(&ev))
+                    prop:value=edit_modal_input_expires_at
+                />
+            </div>
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="from_name"
+                >
+                    Sender name
+                </label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    class=("!ring-4", has_invalid_from_name)
+                    class=("!ring-red-500", has_invalid_from_name)
                     type="text"
+                    placeholder="Shown in the From header when forwarding (optional)"
+                    on:input=move |ev| set_edit_modal_input_from_name(event_target_value(&ev))
+                    prop:value=edit_modal_input_from_name
+                />
+                <FieldErrorMessage field="from_name"/>
+            </div>
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="owner"
+                >
+                    Owner
+                </label>
+                <OwnerInput
+                    id="alias_owner"
+                    value=edit_modal_input_owner
+                    set_value=set_edit_modal_input_owner
                     placeholder=edit_modal_input_owner
-                    on:input=move |ev| set_edit_modal_input_owner(event_target_value(&ev))
-                    prop:value=edit_modal_input_owner
-                    disabled=!user.admin
+                    admin=user.admin
+                    allow_mailboxes=true
                 />
             </div>
             <div class="flex flex-row gap-2 mt-2 items-center">
@@ -745,6 +2308,1096 @@ pub fn Aliases(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     Active
                 </label>
             </div>
+            {move || {
+                catch_all_warning()
+                    .map(|catch_all| {
+                        view! {
+                            <div class="rounded-lg p-4 flex bg-yellow-100 dark:bg-yellow-900 mt-2">
+                                <div>
+                                    <Icon
+                                        icon=icondata::AiWarningFilled
+                                        class="w-5 h-5 text-yellow-500 dark:text-yellow-200"
+                                    />
+                                </div>
+                                <div class="ml-3 text-yellow-700 dark:text-yellow-200">
+                                    <p>
+                                        "This domain has a catch-all, so mail to inactive aliases will still be delivered to "
+                                        {catch_all}
+                                    </p>
+                                </div>
+                            </div>
+                        }
+                    })
+            }}
         </EditModal>
     }
 }
+
+/// Lists the recently deleted aliases and allows restoring them
+#[component]
+fn TrashModal(open: RwSignal<bool>, #[prop(into)] on_restore: Callback<()>) -> impl IntoView {
+    let modal_elem = create_node_ref::<html::Dialog>();
+    let reload = create_trigger();
+    let deleted = create_resource(
+        move || (open(), reload.track()),
+        |(open, _)| async move {
+            if open {
+                list_deleted_aliases().await
+            } else {
+                Ok(Vec::new())
+            }
+        },
+    );
+    let (error, set_error) = create_signal(None::<String>);
+    create_effect(move |_| {
+        if open() {
+            set_error(None);
+        }
+    });
+
+    let restore = move |address: String| {
+        spawn_local(async move {
+            match restore_alias(address).await {
+                Ok(()) => {
+                    set_error(None);
+                    on_restore(());
+                }
+                Err(e) => set_error(Some(
+                    ValidationErrors::from_server_error(&e)
+                        .and_then(|x| x.get("alias").map(str::to_string))
+                        .unwrap_or_else(|| e.to_string()),
+                )),
+            }
+            reload.notify();
+        });
+    };
+
+    let rows = move || {
+        deleted
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|alias| {
+                let address = alias.address.clone();
+                let deleted_ago = chrono_humanize::HumanTime::from(alias.deleted_at - Utc::now()).to_string();
+                view! {
+                    <li class="flex flex-row items-center gap-2 py-2">
+                        <div class="flex flex-1 flex-col min-w-0">
+                            <span class="font-medium truncate">{alias.address}</span>
+                            <span class="text-sm text-gray-500 dark:text-gray-400 truncate">
+                                "→ " {alias.target} ", deleted " {deleted_ago}
+                            </span>
+                        </div>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center rounded-lg transition-all px-3 py-2 text-sm font-semibold text-gray-900 dark:text-gray-200 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-4 dark:focus:ring-zinc-800"
+                            on:click=move |_| restore(address.clone())
+                        >
+                            "Restore"
+                        </button>
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+    let is_empty = move || deleted.get().and_then(Result::ok).is_some_and(|x| x.is_empty());
+
+    view! {
+        <Modal open dialog_el=modal_elem>
+            <div class="relative p-4 transform overflow-hidden rounded-lg bg-white dark:bg-black text-left transition-all sm:w-full sm:max-w-xl">
+                <h3 class="text-2xl tracking-tight mt-2 mb-2 font-semibold text-gray-900 dark:text-gray-200">
+                    "Trash"
+                </h3>
+                <div class="pb-3 space-y-3">
+                    <Show
+                        when=move || !is_empty()
+                        fallback=|| {
+                            view! { <p class="text-sm text-gray-500 dark:text-gray-400">"No recently deleted aliases"</p> }
+                        }
+                    >
+                        <ul class="divide-y divide-gray-200 dark:divide-zinc-800 max-h-96 overflow-auto">{rows}</ul>
+                    </Show>
+                    {move || error().map(|e| view! { <p class="text-sm text-red-500">{e}</p> })}
+                </div>
+                <div class="flex flex-col gap-3 sm:flex-row-reverse">
+                    <button
+                        type="button"
+                        class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
+                        on:click=move |_ev| open.set(false)
+                    >
+                        "Close"
+                    </button>
+                </div>
+            </div>
+        </Modal>
+    }
+}
+
+#[component]
+fn CaseDuplicatesModal(open: RwSignal<bool>, #[prop(into)] on_merge: Callback<()>) -> impl IntoView {
+    let modal_elem = create_node_ref::<html::Dialog>();
+    let reload = create_trigger();
+    let duplicates = create_resource(
+        move || (open(), reload.track()),
+        |(open, _)| async move {
+            if open {
+                find_case_duplicates().await
+            } else {
+                Ok(Vec::new())
+            }
+        },
+    );
+    let (error, set_error) = create_signal(None::<String>);
+    create_effect(move |_| {
+        if open() {
+            set_error(None);
+        }
+    });
+
+    let merge = move |keep: String| {
+        spawn_local(async move {
+            match merge_case_duplicates(keep).await {
+                Ok(_) => {
+                    set_error(None);
+                    on_merge(());
+                }
+                Err(e) => set_error(Some(e.to_string())),
+            }
+            reload.notify();
+        });
+    };
+
+    let rows = move || {
+        duplicates
+            .get()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|group| {
+                let aliases = group
+                    .aliases
+                    .into_iter()
+                    .map(|alias| {
+                        let keep = alias.clone();
+                        view! {
+                            <div class="flex flex-row items-center gap-2">
+                                <span class="flex-1 text-sm truncate">{alias}</span>
+                                <button
+                                    type="button"
+                                    class="inline-flex flex-none items-center rounded-lg transition-all px-3 py-1 text-sm font-semibold text-gray-900 dark:text-gray-200 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-4 dark:focus:ring-zinc-800"
+                                    on:click=move |_| merge(keep.clone())
+                                >
+                                    "Keep"
+                                </button>
+                            </div>
+                        }
+                    })
+                    .collect_view();
+                view! {
+                    <li class="flex flex-col gap-1 py-2">
+                        <span class="font-medium truncate">{group.address}</span>
+                        {aliases}
+                    </li>
+                }
+            })
+            .collect_view()
+    };
+    let is_empty = move || duplicates.get().and_then(Result::ok).is_some_and(|x| x.is_empty());
+
+    view! {
+        <Modal open dialog_el=modal_elem>
+            <div class="relative p-4 transform overflow-hidden rounded-lg bg-white dark:bg-black text-left transition-all sm:w-full sm:max-w-xl">
+                <h3 class="text-2xl tracking-tight mt-2 mb-2 font-semibold text-gray-900 dark:text-gray-200">
+                    "Case duplicates"
+                </h3>
+                <div class="pb-3 space-y-3">
+                    <p class="text-sm text-gray-500 dark:text-gray-400">
+                        "These aliases only differ in case. Keeping one of them adds the mail counters of the others to it and removes them permanently."
+                    </p>
+                    <Show
+                        when=move || !is_empty()
+                        fallback=|| {
+                            view! { <p class="text-sm text-gray-500 dark:text-gray-400">"No duplicates found"</p> }
+                        }
+                    >
+                        <ul class="divide-y divide-gray-200 dark:divide-zinc-800 max-h-96 overflow-auto">{rows}</ul>
+                    </Show>
+                    {move || error().map(|e| view! { <p class="text-sm text-red-500">{e}</p> })}
+                </div>
+                <div class="flex flex-col gap-3 sm:flex-row-reverse">
+                    <button
+                        type="button"
+                        class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
+                        on:click=move |_ev| open.set(false)
+                    >
+                        "Close"
+                    </button>
+                </div>
+            </div>
+        </Modal>
+    }
+}
+
+#[component]
+fn ImportAliasesModal(open: RwSignal<bool>, #[prop(into)] on_import: Callback<()>) -> impl IntoView {
+    let modal_elem = create_node_ref::<html::Dialog>();
+    let formats = vec!["CSV".to_string(), "JSON lines".to_string()];
+    let (choices, _) = create_signal(formats);
+    let (format, set_format) = create_signal("CSV".to_string());
+    let (data, set_data) = create_signal("".to_string());
+    let (waiting, set_waiting) = create_signal(false);
+    let (error, set_error) = create_signal(None::<String>);
+    let report = create_rw_signal(None::<Vec<ImportedRow>>);
+
+    create_effect(move |_| {
+        if open() {
+            set_data("".to_string());
+            set_waiting(false);
+            set_error(None);
+            report.set(None);
+        }
+    });
+
+    let on_confirm = move |_| {
+        if waiting.get_untracked() {
+            return;
+        }
+        set_waiting(true);
+        let format = match format.get_untracked().as_str() {
+            "JSON lines" => ImportFormat::JsonLines,
+            _ => ImportFormat::Csv,
+        };
+        spawn_local(async move {
+            match import_aliases(data.get_untracked(), format).await {
+                Ok(rows) => {
+                    report.set(Some(rows));
+                    on_import(());
+                }
+                Err(e) => set_error(Some(e.to_string())),
+            }
+            set_waiting(false);
+        });
+    };
+
+    let summary = move || {
+        report.with(|rows| {
+            let rows = rows.as_deref().unwrap_or_default();
+            let count = |outcome: fn(&ImportOutcome) -> bool| rows.iter().filter(|x| outcome(&x.outcome)).count();
+            format!(
+                "{} created, {} skipped as duplicates, {} failed",
+                count(|x| *x == ImportOutcome::Created),
+                count(|x| *x == ImportOutcome::Duplicate),
+                count(|x| matches!(x, ImportOutcome::Failed(_))),
+            )
+        })
+    };
+    let failures = move || {
+        report
+            .get()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|x| match x.outcome {
+                ImportOutcome::Failed(reason) => Some(view! {
+                    <li>"Row " {x.row} " (" {x.address} "): " {reason}</li>
+                }),
+                _ => None,
+            })
+            .collect_view()
+    };
+
+    view! {
+        <Modal open dialog_el=modal_elem>
+            <div class="relative p-4 transform overflow-hidden rounded-lg bg-white dark:bg-black text-left transition-all sm:w-full sm:max-w-xl">
+                <h3 class="text-2xl tracking-tight mt-2 mb-2 font-semibold text-gray-900 dark:text-gray-200">
+                    "Import aliases"
+                </h3>
+                <Show
+                    when=move || report.with(|x| x.is_none())
+                    fallback=move || {
+                        view! {
+                            <div class="pb-3 space-y-3">
+                                <p class="text-sm font-medium text-gray-900 dark:text-gray-200">{summary}</p>
+                                <ul class="text-sm text-red-500 max-h-64 overflow-auto">{failures}</ul>
+                            </div>
+                        }
+                    }
+                >
+                    <div class="pb-3 space-y-3">
+                        <p class="text-sm text-gray-500 dark:text-gray-400">
+                            "Paste CSV with a header row, or one JSON object per line. Each alias needs an address and a target, the comment and active columns are optional."
+                        </p>
+                        <Select
+                            class="w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            choices
+                            value=format
+                            set_value=set_format
+                        />
+                        <textarea
+                            class="flex w-full h-48 rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent font-mono text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring"
+                            placeholder="address,target,comment,active"
+                            on:input=move |ev| set_data(event_target_value(&ev))
+                            prop:value=data
+                        ></textarea>
+                        {move || error().map(|e| view! { <p class="text-sm text-red-500">{e}</p> })}
+                    </div>
+                </Show>
+                <div class="flex flex-col gap-3 sm:flex-row-reverse">
+                    <Show when=move || report.with(|x| x.is_none())>
+                        <button
+                            type="button"
+                            disabled=waiting
+                            class="inline-flex w-full min-w-20 justify-center items-center rounded-lg transition-all px-3 py-2 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white dark:text-zinc-100 focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto"
+                            on:click=on_confirm
+                        >
+                            <Show when=waiting>
+                                <Icon icon=icondata::CgSpinner class="inline w-5 h-5 me-2 text-blue-900 animate-spin"/>
+                            </Show>
+                            Import
+                        </button>
+                    </Show>
+                    <button
+                        type="button"
+                        class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
+                        on:click=move |_ev| open.set(false)
+                    >
+                        {move || if report.with(|x| x.is_some()) { "Dismiss" } else { "Cancel" }}
+                    </button>
+                </div>
+            </div>
+        </Modal>
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn domains_are_normalized_unless_rejected() {
+        assert_eq!(normalize_domain("Example.Com.", false).unwrap(), "example.com");
+        assert_eq!(normalize_domain("example.com.", false).unwrap(), "example.com");
+        assert_eq!(
+            validate_address("Foo", "Example.Com.", false).unwrap(),
+            "Foo@example.com"
+        );
+
+        assert!(normalize_domain("Example.com", true).is_err());
+        assert!(normalize_domain("example.com.", true).is_err());
+        assert_eq!(normalize_domain("example.com", true).unwrap(), "example.com");
+    }
+
+    #[test]
+    fn globally_reserved_localparts_are_left_to_admins() {
+        let config = crate::config::Config {
+            reserved_localparts: vec!["postmaster".to_string(), "billing".to_string()],
+            ..Default::default()
+        };
+        assert!(check_globally_reserved(&config, "billing", false).is_err());
+        assert!(check_globally_reserved(&config, "Billing", false).is_err());
+        assert!(check_globally_reserved(&config, "POSTMASTER", false).is_err());
+        assert!(check_globally_reserved(&config, "billing", true).is_ok());
+        assert!(check_globally_reserved(&config, "shop", false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn alias_duplicates_follow_the_case_setting_of_the_domain() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_domain(&db, "strict.com", "admin").await;
+        sqlx::query("UPDATE domains SET localpart_case_sensitive = TRUE WHERE domain = 'strict.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        insert_mailbox(&db, "Box@example.com", "admin").await;
+        insert_mailbox(&db, "box@strict.com", "admin").await;
+        insert_alias(&db, "Dup@example.com", "box@strict.com", "admin").await;
+        insert_alias(&db, "dup@strict.com", "box@strict.com", "admin").await;
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+
+        let admin = admin("admin");
+        let create = |alias: &'static str, domain: &'static str| {
+            let (db, admin) = (&db, &admin);
+            async move {
+                check_alias(admin, db, None, alias, domain, "someone@example.org", None, "")
+                    .await
+                    .map(|x| x.address)
+                    .map_err(|e| field_error(e, "alias").unwrap())
+            }
+        };
+        // Case-insensitive domains lowercase the localpart and find duplicates regardless of case
+        assert_eq!(create("New", "example.com").await.unwrap(), "new@example.com");
+        assert!(create("BOX", "example.com")
+            .await
+            .unwrap_err()
+            .contains("in use by a mailbox"));
+        assert_eq!(
+            create("DUP", "example.com").await.unwrap_err(),
+            "'dup@example.com' already exists!"
+        );
+        // Case-sensitive domains keep the localpart and only find exact duplicates
+        assert_eq!(create("Box", "strict.com").await.unwrap(), "Box@strict.com");
+        assert!(create("box", "strict.com")
+            .await
+            .unwrap_err()
+            .contains("in use by a mailbox"));
+        assert_eq!(create("Dup", "strict.com").await.unwrap(), "Dup@strict.com");
+        assert_eq!(
+            create("dup", "strict.com").await.unwrap_err(),
+            "'dup@strict.com' already exists!"
+        );
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn targets_are_unique_per_owner_if_configured() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_alias(&db, "shop@example.com", "someone@example.org", "admin").await;
+
+        let admin = admin("admin");
+        let check = |old_address: Option<&'static str>, alias: &'static str, owner: &'static str| {
+            let (db, admin) = (&db, &admin);
+            async move {
+                check_alias(
+                    admin,
+                    db,
+                    old_address,
+                    alias,
+                    "example.com",
+                    "someone@example.org",
+                    None,
+                    owner,
+                )
+                .await
+                .map(|x| x.address)
+                .map_err(|e| field_error(e, "target").unwrap())
+            }
+        };
+
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        assert_eq!(check(None, "news", "").await.unwrap(), "news@example.com");
+        runtime.dispose();
+
+        let config = crate::config::Config {
+            unique_targets: true,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+        assert_eq!(
+            check(None, "news", "").await.unwrap_err(),
+            "'shop@example.com' already forwards to 'someone@example.org'!"
+        );
+        // The alias itself may keep its target, and other owners are unaffected
+        assert_eq!(
+            check(Some("shop@example.com"), "store", "").await.unwrap(),
+            "store@example.com"
+        );
+        assert_eq!(check(None, "news", "alice").await.unwrap(), "news@example.com");
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn admins_cannot_create_aliases_without_a_target() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "admin").await;
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+
+        let admin = admin("admin");
+        for target in ["", "not an address"] {
+            let error = check_alias(&admin, &db, None, "new", "example.com", target, None, "")
+                .await
+                .map(|x| x.address)
+                .unwrap_err();
+            assert_eq!(
+                field_error(error, "target").as_deref(),
+                Some("target must be set to a valid email address")
+            );
+        }
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn aliases_can_be_sorted_by_two_columns() {
+        const OWNER_COLUMN: usize = 8;
+        let sort = VecDeque::from([(OWNER_COLUMN, ColumnSort::Ascending), NEWEST_FIRST]);
+
+        let mut query = QueryBuilder::new("SELECT * FROM aliases");
+        push_order_by(&mut query, Alias::sorting_to_sql(&sort), "address");
+        assert_eq!(
+            query.sql(),
+            "SELECT * FROM aliases ORDER BY owner ASC, created_at DESC, address"
+        );
+
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "admin").await;
+        for (address, owner, days_ago) in [
+            ("a@example.com", "bob", 2),
+            ("b@example.com", "alice", 2),
+            ("c@example.com", "alice", 1),
+            ("d@example.com", "bob", 2),
+        ] {
+            insert_alias(&db, address, "someone@example.org", owner).await;
+            sqlx::query("UPDATE aliases SET created_at = datetime('now', ?) WHERE address = ?")
+                .bind(format!("-{days_ago} days"))
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+        let query = AliasQuery {
+            sort,
+            range: 0..10,
+            search: String::new(),
+            provisioned: None,
+            after: None,
+        };
+        let addresses = query_aliases(&db, &admin("admin"), query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.address)
+            .collect::<Vec<_>>();
+        // Rows that are equal in both columns keep a stable order by their address
+        assert_eq!(
+            addresses,
+            ["c@example.com", "b@example.com", "a@example.com", "d@example.com"]
+        );
+    }
+
+    #[test]
+    fn only_inactive_aliases_warn_about_the_catch_all() {
+        let catch_all = || Some("all@example.org".to_string());
+        assert_eq!(catch_all_warning(false, catch_all()), catch_all());
+        assert_eq!(catch_all_warning(true, catch_all()), None);
+        assert_eq!(catch_all_warning(false, None), None);
+    }
+
+    #[tokio::test]
+    async fn creation_timeline_groups_by_local_day_and_owner() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_mailbox(&db, "alice@example.com", "alice").await;
+        for (address, owner, created_at) in [
+            ("a@example.com", "alice", "2026-10-01 22:30:00"),
+            ("b@example.com", "alice", "2026-10-01 23:30:00"),
+            ("c@example.com", "alice@example.com", "2026-10-02 00:30:00"),
+            ("d@example.com", "admin", "2026-10-02 10:00:00"),
+            ("old@example.com", "alice", "2026-09-01 12:00:00"),
+        ] {
+            insert_alias(&db, address, "alice@example.com", owner).await;
+            sqlx::query("UPDATE aliases SET created_at = ? WHERE address = ?")
+                .bind(created_at)
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        let since = "2026-10-01T00:00:00Z".parse().unwrap();
+        let day = |day: u32| NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+        let timeline = |user: User, offset, owner| {
+            let db = &db;
+            async move { creation_timeline(db, &user, since, offset, owner).await.unwrap() }
+        };
+
+        // In UTC+1, the alias created at 23:30 UTC belongs to the next day
+        assert_eq!(timeline(admin("admin"), 0, None).await, [(day(1), 2), (day(2), 2)]);
+        assert_eq!(timeline(admin("admin"), 60, None).await, [(day(1), 1), (day(2), 3)]);
+        // Admins can look at a single user or mailbox, everyone else only sees their own aliases
+        assert_eq!(timeline(admin("admin"), 0, Some("alice")).await, [(day(1), 2)]);
+        assert_eq!(
+            timeline(admin("admin"), 0, Some("alice@example.com")).await,
+            [(day(2), 1)]
+        );
+        assert!(timeline(user("alice"), 0, Some("admin")).await.is_empty());
+        assert_eq!(timeline(user("alice"), 0, None).await, [(day(1), 2), (day(2), 1)]);
+    }
+
+    #[tokio::test]
+    async fn delete_unused_aliases_only_touches_own_aliases() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_mailbox(&db, "alice@example.com", "alice").await;
+        insert_mailbox(&db, "bob@example.com", "bob").await;
+        insert_alias(&db, "admin1@example.com", "alice@example.com", "admin").await;
+        insert_alias(&db, "alice1@example.com", "alice@example.com", "alice").await;
+        insert_alias(&db, "alice2@example.com", "alice@example.com", "alice@example.com").await;
+        insert_alias(&db, "bob1@example.com", "bob@example.com", "bob").await;
+
+        let cutoff = Utc::now() + chrono::Duration::days(1);
+        let deleted = delete_unused_aliases_of(&db, &admin("admin"), cutoff, false)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+        let deleted = delete_unused_aliases_of(&db, &user("alice"), cutoff, false)
+            .await
+            .unwrap();
+        assert_eq!(deleted, 2);
+
+        let remaining: Vec<String> = sqlx::query_scalar("SELECT address FROM aliases")
+            .fetch_all(&*db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec!["bob1@example.com".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn case_duplicates_are_found_and_merged() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_domain(&db, "strict.com", "admin").await;
+        sqlx::query("UPDATE domains SET localpart_case_sensitive = TRUE WHERE domain = 'strict.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        for (address, n_recv, n_sent) in [
+            ("Foo@example.com", 3, 1),
+            ("foo@example.com", 5, 2),
+            ("FOO@example.com", 7, 4),
+            ("bar@example.com", 1, 1),
+            ("Bar@strict.com", 1, 0),
+            ("bar@strict.com", 1, 0),
+        ] {
+            insert_alias(&db, address, "admin@example.com", "admin").await;
+            sqlx::query("UPDATE aliases SET n_recv = ?, n_sent = ? WHERE address = ?")
+                .bind(n_recv)
+                .bind(n_sent)
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        // Case sensitive domains are skipped, both when finding and when merging
+        let groups = case_duplicates(&db).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].address, "foo@example.com");
+        let mut aliases = groups[0].aliases.clone();
+        aliases.sort();
+        assert_eq!(aliases, ["FOO@example.com", "Foo@example.com", "foo@example.com"]);
+
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        let admin = admin("admin");
+        assert!(merge_into(&db, &admin, "bar@strict.com").await.is_err());
+        assert_eq!(merge_into(&db, &admin, "foo@example.com").await.unwrap(), 2);
+        runtime.dispose();
+
+        let counters = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT n_recv, n_sent FROM aliases WHERE lower(address) = 'foo@example.com'",
+        )
+        .fetch_all(&*db)
+        .await
+        .unwrap();
+        assert_eq!(counters, [(15, 7)]);
+        assert!(case_duplicates(&db).await.unwrap().is_empty());
+
+        // The merged aliases can't be restored from the trash
+        let trashed = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM deleted_aliases")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(trashed, 0);
+    }
+
+    #[tokio::test]
+    async fn keyset_pages_match_offset_pages() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        // Several aliases share a creation date, so the address has to break the ties
+        for i in 0..23 {
+            let address = format!("alias{:02}@example.com", (i * 7) % 23);
+            insert_alias(&db, &address, "alice@example.com", "alice").await;
+            sqlx::query("UPDATE aliases SET created_at = datetime('2026-10-01', ?) WHERE address = ?")
+                .bind(format!("+{} hours", i / 4))
+                .bind(&address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        let alice = user("alice");
+        let page = |range: Range<usize>, after: Option<(DateTime<Utc>, String)>| AliasQuery {
+            sort: VecDeque::from([NEWEST_FIRST]),
+            range,
+            search: String::new(),
+            provisioned: None,
+            after,
+        };
+        let addresses = |rows: &[Alias]| rows.iter().map(|x| x.address.clone()).collect::<Vec<_>>();
+
+        let all = query_aliases(&db, &alice, page(0..100, None)).await.unwrap();
+        assert_eq!(all.len(), 23);
+        let mut after = None;
+        for start in (0..all.len()).step_by(5) {
+            let offset = query_aliases(&db, &alice, page(start..start + 5, None)).await.unwrap();
+            let keyset = query_aliases(&db, &alice, page(start..start + 5, after)).await.unwrap();
+            assert_eq!(addresses(&keyset), addresses(&offset), "page at {start}");
+            assert_eq!(addresses(&keyset), addresses(&all[start..(start + 5).min(all.len())]));
+            after = keyset.last().map(|x| (x.created_at, x.address.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn aliases_can_be_sorted_by_activity() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        for (address, n_recv, n_sent) in [
+            ("quiet@example.com", 0, 0),
+            ("busy@example.com", 10, 5),
+            ("sender@example.com", 0, 12),
+            ("tied@example.com", 12, 0),
+        ] {
+            insert_alias(&db, address, "alice@example.com", "alice").await;
+            sqlx::query("UPDATE aliases SET n_recv = ?, n_sent = ? WHERE address = ?")
+                .bind(n_recv)
+                .bind(n_sent)
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        let query = |sort: ColumnSort| AliasQuery {
+            sort: VecDeque::from([(ACTIVITY_COLUMN, sort)]),
+            range: 0..10,
+            search: String::new(),
+            provisioned: None,
+            after: None,
+        };
+        let alice = user("alice");
+        let rows = query_aliases(&db, &alice, query(ColumnSort::Descending)).await.unwrap();
+        let order = rows
+            .iter()
+            .map(|x| (x.address.as_str(), x.activity))
+            .collect::<Vec<_>>();
+        // Equal activity falls back to the address
+        assert_eq!(
+            order,
+            [
+                ("busy@example.com", 15),
+                ("sender@example.com", 12),
+                ("tied@example.com", 12),
+                ("quiet@example.com", 0)
+            ]
+        );
+        let rows = query_aliases(&db, &alice, query(ColumnSort::Ascending)).await.unwrap();
+        assert_eq!(rows[0].address, "quiet@example.com");
+        assert_eq!(rows[3].address, "busy@example.com");
+    }
+
+    #[tokio::test]
+    async fn recent_aliases_are_the_newest_of_the_user() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        for i in 0..MAX_RECENT_ALIASES + 2 {
+            let address = format!("alias{i:02}@example.com");
+            insert_alias(&db, &address, "alice@example.com", "alice").await;
+            sqlx::query("UPDATE aliases SET created_at = datetime('2026-10-01', ?) WHERE address = ?")
+                .bind(format!("+{i} hours"))
+                .bind(&address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+        // Bob's alias is the newest, but not visible to Alice
+        insert_alias(&db, "bob@example.com", "bob@example.com", "bob").await;
+
+        let alice = user("alice");
+        let addresses = |rows: Vec<Alias>| rows.into_iter().map(|x| x.address).collect::<Vec<_>>();
+        let recent = recent_aliases_of(&db, &alice, 3).await.unwrap();
+        let newest = MAX_RECENT_ALIASES + 1;
+        let expected = (newest - 2..=newest).rev().map(|i| format!("alias{i:02}@example.com"));
+        assert_eq!(addresses(recent), expected.collect::<Vec<_>>());
+        // The limit is capped
+        let all = recent_aliases_of(&db, &alice, usize::MAX).await.unwrap();
+        assert_eq!(all.len(), MAX_RECENT_ALIASES);
+    }
+
+    #[tokio::test]
+    async fn names_are_searched_and_fall_back_to_the_address() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_alias(&db, "x7k2@example.com", "alice@example.com", "alice").await;
+        insert_alias(&db, "unnamed@example.com", "alice@example.com", "alice").await;
+        sqlx::query("UPDATE aliases SET name = 'Shopping' WHERE address = 'x7k2@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let query = |search: &str, sort: VecDeque<(usize, ColumnSort)>| AliasQuery {
+            sort,
+            range: 0..10,
+            search: search.to_string(),
+            provisioned: None,
+            after: None,
+        };
+        let alice = user("alice");
+        let names = |rows: Vec<Alias>| {
+            rows.into_iter()
+                .map(|x| (x.display_name, x.address))
+                .collect::<Vec<_>>()
+        };
+
+        let rows = query_aliases(&db, &alice, query("shop", VecDeque::new()))
+            .await
+            .unwrap();
+        assert_eq!(names(rows), [("Shopping".to_string(), "x7k2@example.com".to_string())]);
+
+        // Sorting by name uses the address of unnamed aliases
+        let by_name = VecDeque::from([(0, ColumnSort::Ascending)]);
+        let rows = query_aliases(&db, &alice, query("", by_name)).await.unwrap();
+        assert_eq!(
+            names(rows),
+            [
+                ("Shopping".to_string(), "x7k2@example.com".to_string()),
+                ("unnamed@example.com".to_string(), "unnamed@example.com".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn statistics_can_be_limited_to_a_domain() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "other.com", "alice").await;
+        for (address, owner, active, n_recv, n_sent) in [
+            ("a@example.com", "alice", true, 3, 1),
+            ("b@example.com", "alice", false, 4, 0),
+            ("c@other.com", "alice", true, 5, 2),
+            ("d@example.com", "bob", true, 100, 100),
+        ] {
+            insert_alias(&db, address, "alice@example.com", owner).await;
+            sqlx::query("UPDATE aliases SET active = ?, n_recv = ?, n_sent = ? WHERE address = ?")
+                .bind(active)
+                .bind(n_recv)
+                .bind(n_sent)
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        let alice = user("alice");
+        let count = |active: Option<bool>, domain: Option<&str>| {
+            let (db, alice) = (&db, &alice);
+            let domain = domain.map(str::to_string);
+            async move { count_aliases(db, alice, active, None, domain).await.unwrap() }
+        };
+        assert_eq!(count(None, None).await, 3);
+        assert_eq!(count(None, Some("example.com")).await, 2);
+        assert_eq!(count(Some(true), Some("example.com")).await, 1);
+        assert_eq!(count(Some(false), Some("other.com")).await, 0);
+        assert_eq!(count(None, Some("missing.com")).await, 0);
+
+        let sum = |sent: bool, domain: Option<&str>| {
+            let (db, alice) = (&db, &alice);
+            let domain = domain.map(str::to_string);
+            async move { sum_sent_or_received(db, alice, sent, domain).await.unwrap() }
+        };
+        assert_eq!(sum(false, None).await, 12);
+        assert_eq!(sum(false, Some("example.com")).await, 7);
+        assert_eq!(sum(true, Some("other.com")).await, 2);
+        assert_eq!(sum(true, Some("missing.com")).await, 0);
+        // Admins see the aliases of all owners
+        let all = sum_sent_or_received(&db, &admin("admin"), true, Some("example.com".to_string())).await;
+        assert_eq!(all.unwrap(), 101);
+    }
+
+    #[tokio::test]
+    async fn aliases_can_be_filtered_by_provisioning() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_alias(&db, "manual@example.com", "alice@example.com", "alice").await;
+        insert_alias(&db, "provisioned@example.com", "alice@example.com", "alice").await;
+        sqlx::query("UPDATE aliases SET provisioned = TRUE WHERE address = 'provisioned@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let alice = user("alice");
+        for (provisioned, expected) in [
+            (None, vec!["manual@example.com", "provisioned@example.com"]),
+            (Some(true), vec!["provisioned@example.com"]),
+            (Some(false), vec!["manual@example.com"]),
+        ] {
+            let query = AliasQuery {
+                sort: VecDeque::new(),
+                range: 0..10,
+                search: String::new(),
+                provisioned,
+                after: None,
+            };
+            let rows = query_aliases(&db, &alice, query).await.unwrap();
+            let mut addresses = rows.iter().map(|x| x.address.as_str()).collect::<Vec<_>>();
+            addresses.sort();
+            assert_eq!(addresses, expected, "{provisioned:?}");
+            assert!(rows
+                .iter()
+                .all(|x| x.provisioned == (x.address == "provisioned@example.com")));
+        }
+    }
+
+    #[tokio::test]
+    async fn disabled_stats_issue_no_queries() {
+        let db = TestDb::new().await;
+        let config = crate::config::Config {
+            stats: false,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+        let disabled =
+            |result: Result<usize, ServerFnError>| result.is_err_and(|e| e.to_string().contains(STATS_DISABLED_ERROR));
+        assert!(disabled(alias_count(Some(true), None, None).await));
+        assert!(disabled(alias_count(None, Some(Utc::now()), None).await));
+        assert!(disabled(count_sent_or_received(false, None).await));
+        assert!(recent_aliases(5)
+            .await
+            .is_err_and(|e| e.to_string().contains(STATS_DISABLED_ERROR)));
+        // The row count of the alias table isn't a statistic, it only fails for lack of a session here
+        assert!(!disabled(alias_count(None, None, None).await));
+        runtime.dispose();
+
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        assert!(!disabled(count_sent_or_received(false, None).await));
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn trashed_aliases_can_be_restored_within_the_window() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "me@example.com", "alice").await;
+        insert_alias(&db, "a@example.com", "me@example.com", "alice").await;
+        let delete = || sqlx::query("DELETE FROM aliases WHERE address = 'a@example.com'").execute(&*db);
+        let restored = || async {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM aliases WHERE address = 'a@example.com'")
+                .fetch_one(&*db)
+                .await
+                .unwrap()
+                == 1
+        };
+
+        delete().await.unwrap();
+        restore(&db, &user("alice"), "a@example.com", 30).await.unwrap();
+        assert!(restored().await);
+
+        // Other users can't restore it
+        delete().await.unwrap();
+        let error = restore(&db, &user("bob"), "a@example.com", 30).await.unwrap_err();
+        assert!(error.to_string().contains("cannot be restored anymore"), "{error}");
+
+        // Neither can the owner once the window is over
+        sqlx::query("UPDATE deleted_aliases SET deleted_at = datetime('now', '-31 days')")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let error = restore(&db, &user("alice"), "a@example.com", 30).await.unwrap_err();
+        assert!(error.to_string().contains("cannot be restored anymore"), "{error}");
+        assert!(!restored().await);
+    }
+
+    #[tokio::test]
+    async fn restored_aliases_must_not_shadow_renamed_entries() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "me@example.com", "alice").await;
+        insert_mailbox(&db, "other@example.com", "alice").await;
+        insert_alias(&db, "a@example.com", "me@example.com", "alice").await;
+        insert_alias(&db, "b@example.com", "me@example.com", "alice").await;
+        sqlx::query("DELETE FROM aliases WHERE address = 'a@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let rename = |table: &'static str, from: &'static str, to: &'static str| {
+            let db = &db;
+            async move {
+                sqlx::query(&format!("UPDATE {table} SET address = ? WHERE address = ?"))
+                    .bind(to)
+                    .bind(from)
+                    .execute(&**db)
+                    .await
+            }
+        };
+
+        // Renames don't purge the trash, unlike inserts
+        rename("mailboxes", "other@example.com", "a@example.com").await.unwrap();
+        let error = restore(&db, &user("alice"), "a@example.com", 30).await.unwrap_err();
+        assert_eq!(
+            field_error(error, "alias").unwrap(),
+            "This address is already in use by a mailbox!"
+        );
+        rename("mailboxes", "a@example.com", "other@example.com").await.unwrap();
+
+        rename("aliases", "b@example.com", "a@example.com").await.unwrap();
+        let error = restore(&db, &user("alice"), "a@example.com", 30).await.unwrap_err();
+        assert_eq!(field_error(error, "alias").unwrap(), "'a@example.com' already exists!");
+    }
+
+    #[tokio::test]
+    async fn unwritten_aliases_report_the_actual_reason() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "me@example.com", "alice").await;
+        insert_alias(&db, "bobs@example.com", "me@example.com", "bob").await;
+        let alice = user("alice");
+
+        let reason = |old_address: Option<&'static str>, address: &'static str, owner: &'static str| {
+            let (db, alice) = (&db, &alice);
+            async move {
+                unwritten_alias_error(db, alice, old_address, address, owner)
+                    .await
+                    .unwrap()
+            }
+        };
+        let missing = reason(Some("missing@example.com"), "new@example.com", "alice").await;
+        assert!(
+            missing.to_string().contains("'missing@example.com' does not exist"),
+            "{missing}"
+        );
+        // Aliases of others are indistinguishable from missing ones
+        let foreign = reason(Some("bobs@example.com"), "new@example.com", "alice").await;
+        assert!(
+            foreign.to_string().contains("'bobs@example.com' does not exist"),
+            "{foreign}"
+        );
+
+        let mailbox = reason(None, "me@example.com", "alice").await;
+        assert_eq!(
+            field_error(mailbox, "alias").unwrap(),
+            "This address is already in use by a mailbox!"
+        );
+
+        sqlx::query("UPDATE mailboxes SET alias_limit = 0 WHERE address = 'me@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let limit = reason(None, "new@example.com", "me@example.com").await;
+        assert!(limit.to_string().contains("Alias limit reached"), "{limit}");
+    }
+}
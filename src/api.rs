@@ -1,4 +1,17 @@
-use crate::{aliases::validate_address, auth::User, state::AppState};
+use crate::{
+    aliases::{
+        aliases_by_target, check_globally_reserved, delete_unused_aliases_of, normalize_domain, parse_from_name,
+        validate_address, Alias,
+    },
+    auth::{ssr::AuthSession, ApiScope, Capabilities, User},
+    count_cache::CountTable,
+    database::ssr::{push_owner_scope, LogQuery, OwnerScope},
+    domains::{allowed_domain_owner_for, localpart_case_sensitive, random_allowed_domain_for},
+    mailboxes::{alias_limit_reached, push_alias_limit_not_reached},
+    state::AppState,
+    users::new_aliases_active_of,
+    utils::csv_field,
+};
 use axum::{
     extract::{self, rejection::JsonRejection, State},
     response::IntoResponse,
@@ -6,8 +19,7 @@ use axum::{
 };
 use axum_extra::extract::WithRejection;
 use faker_rand::en_us::internet::Username;
-use http::{HeaderMap, StatusCode};
-use rand::seq::SliceRandom;
+use http::{header, HeaderMap, StatusCode};
 use rand::{rngs::OsRng, Rng};
 use serde::Deserialize;
 use serde_json::json;
@@ -24,9 +36,15 @@ pub enum ApiError {
     /// Unauthorized
     #[error("Unauthorized")]
     Unauthorized(String),
+    /// Forbidden
+    #[error("Forbidden")]
+    Forbidden(String),
     /// Bad Request
     #[error("BadRequest")]
     BadRequest(String),
+    /// Not Found
+    #[error("NotFound")]
+    NotFound(String),
     /// Internal Server Error
     #[error("ServerError")]
     ServerError(String),
@@ -38,7 +56,9 @@ impl IntoResponse for ApiError {
         let (status, message) = match self {
             ApiError::JsonExtractorRejection(json_rejection) => (json_rejection.status(), json_rejection.body_text()),
             ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
             ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
             ApiError::ServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
         };
 
@@ -51,103 +71,203 @@ impl IntoResponse for ApiError {
     }
 }
 
-async fn login_with_api_token(app_state: &AppState, headers: &HeaderMap) -> Result<User, ApiError> {
+/// Authenticates the request by its API token, which must have the given scope, if any.
+/// Returns the account together with the name of the token.
+async fn login_with_api_token(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    scope: Option<ApiScope>,
+) -> Result<(User, String), ApiError> {
     let Some(api_token) = headers.get("Authorization").and_then(|x| x.to_str().ok()) else {
         return Err(ApiError::Unauthorized("Missing API token in request".to_string()));
     };
 
     let api_token = api_token.strip_prefix("Bearer").unwrap_or(api_token).trim_start();
-    let Some(user) = User::get_by_api_token(api_token, &app_state.pool).await else {
-        return Err(ApiError::Unauthorized("Invalid API token".to_string()));
-    };
-
-    log::info!("api token used successfully for user '{}'", user.username);
-    Ok(user)
+    login_with_api_token_str(app_state, api_token, scope).await
 }
 
-async fn allowed_domains(app_state: &AppState, user: &User) -> Result<Vec<String>, String> {
-    let mut query = QueryBuilder::new("SELECT domain FROM domains");
-    query.push(" WHERE active = TRUE AND (public = TRUE");
-    if let Some(mailbox_owner) = &user.mailbox_owner {
-        query.push(" OR owner = ");
-        query.push_bind(mailbox_owner.clone());
+async fn login_with_api_token_str(
+    app_state: &AppState,
+    api_token: &str,
+    scope: Option<ApiScope>,
+) -> Result<(User, String), ApiError> {
+    let Some((user, scopes, token_name)) = User::get_by_api_token(api_token, &app_state.pool).await else {
+        return Err(ApiError::Unauthorized("Invalid API token".to_string()));
+    };
+    if let Some(scope) = scope.filter(|x| !scopes.contains(x)) {
+        return Err(ApiError::Forbidden(format!(
+            "The API token lacks the '{}' scope",
+            scope.as_str()
+        )));
     }
-    query.push(")");
 
-    query
-        .build_query_scalar::<String>()
-        .fetch_all(&app_state.pool)
-        .await
-        .map_err(|e| e.to_string())
+    log::info!(
+        "api token '{token_name}' used successfully for user '{}'",
+        user.username
+    );
+    Ok((user, token_name))
 }
 
+/// The longest expiry that can be requested for a new alias, which keeps the date representable.
+/// Also bounds how far back unused aliases can be cleaned up.
+const MAX_EXPIRES_IN_DAYS: i64 = 100 * 365;
+
+#[allow(clippy::too_many_arguments)]
 async fn create_random_alias(
     app_state: &AppState,
     user: &User,
     domain: Option<String>,
     comment: &str,
-) -> Result<(String, String, String), ApiError> {
-    let target = &user.username;
+    max_received: Option<i64>,
+    expires_in_days: Option<i64>,
+    from_name: Option<&str>,
+    created_via: &str,
+) -> Result<(String, String, String, bool, Option<String>), ApiError> {
     let owner = &user.username;
-    let allowed_domains = allowed_domains(app_state, user).await.map_err(ApiError::BadRequest)?;
-
-    let Some(domain) = domain.or_else(|| allowed_domains.choose(&mut OsRng).cloned()) else {
-        return Err(ApiError::BadRequest("no usable domains are configured".to_string()));
+    let db_error = |e: sqlx::Error| {
+        log::error!("database error while selecting a domain via api token: {e}");
+        ApiError::ServerError("database error".to_string())
     };
 
-    let alias = OsRng.gen::<Username>().to_string();
+    // Mailboxes receive their own aliases, users forward them to the first mailbox they own
+    let target = if user.mailbox_owner.is_some() {
+        user.username.clone()
+    } else {
+        sqlx::query_scalar::<_, String>(
+            "SELECT address FROM mailboxes WHERE owner = ? AND active = TRUE ORDER BY address LIMIT 1",
+        )
+        .bind(&user.username)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| ApiError::BadRequest("Users need to own an active mailbox to create aliases".to_string()))?
+    };
 
-    // Check if resulting address is valid
-    if !allowed_domains.contains(&domain) {
-        return Err(ApiError::BadRequest(format!(
-            "Chosen domain '{}' does not exist or is not allowed to be used",
+    let domain = match domain {
+        Some(domain) => {
+            let domain = normalize_domain(&domain, app_state.config.reject_noncanonical_domains)
+                .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            if allowed_domain_owner_for(&app_state.pool, user, &domain)
+                .await
+                .map_err(db_error)?
+                .is_none()
+            {
+                return Err(ApiError::BadRequest(format!(
+                    "Chosen domain '{}' does not exist or is not allowed to be used",
+                    domain
+                )));
+            }
             domain
-        )));
+        }
+        // Let the database pick, so accounts with many usable domains don't need to load all of them
+        None => random_allowed_domain_for(&app_state.pool, user)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(|| ApiError::BadRequest("no usable domains are configured".to_string()))?,
+    };
+
+    let alias = OsRng.gen::<Username>().to_string();
+    let alias = if localpart_case_sensitive(&app_state.pool, &domain)
+        .await
+        .map_err(db_error)?
+    {
+        alias
+    } else {
+        alias.to_lowercase()
     };
 
     let address = validate_address(&alias, &domain, false /* never allow reserved */)
         .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    check_globally_reserved(&app_state.config, &alias, false).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    if max_received.is_some_and(|x| x <= 0) {
+        return Err(ApiError::BadRequest(
+            "max_received must be a positive integer".to_string(),
+        ));
+    }
+    let expires_at = match expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRES_IN_DAYS).contains(&days) => {
+            return Err(ApiError::BadRequest(format!(
+                "expires_in_days must be between 1 and {MAX_EXPIRES_IN_DAYS}"
+            )));
+        }
+        days => days.map(|x| chrono::Utc::now() + chrono::Duration::days(x)),
+    };
+    let from_name = parse_from_name(from_name.unwrap_or_default()).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    let active = new_aliases_active_of(&app_state.pool, &user.username)
+        .await
+        .map_err(|e| {
+            log::error!("database error while loading alias defaults via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
 
-    let mut query = QueryBuilder::new("INSERT INTO aliases (address, domain, target, comment, active, owner)");
+    let mut query = QueryBuilder::new(
+        "INSERT INTO aliases (address, domain, target, comment, max_received, expires_at, from_name, active, owner, created_via)",
+    );
     query.push("SELECT ");
     query.push_bind(&address);
     query.push(", ");
     query.push_bind(&domain);
     query.push(", ");
-    query.push_bind(target);
+    query.push_bind(&target);
     query.push(", ");
     query.push_bind(comment);
     query.push(", ");
-    query.push_bind(true);
+    query.push_bind(max_received);
+    query.push(", ");
+    query.push_bind(expires_at);
+    query.push(", ");
+    query.push_bind(&from_name);
+    query.push(", ");
+    query.push_bind(active);
     query.push(", ");
     query.push_bind(owner);
+    query.push(", ");
+    query.push_bind(created_via);
     // make sure that no mailbox exists with that address
     query.push(" WHERE NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
     query.push_bind(&address);
-    query.push(")");
+    query.push(") AND ");
+    push_alias_limit_not_reached(&mut query, owner);
 
     if query
+        .logged()
         .build()
         .execute(&app_state.pool)
         .await
-        .map_err(|e| {
-            log::error!("database error while creating alias via api token: {e}");
-            ApiError::ServerError("database error".to_string())
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => {
+                ApiError::BadRequest(format!("'{address}' already exists!"))
+            }
+            e => {
+                log::error!("database error while creating alias via api token: {e}");
+                ApiError::ServerError("database error".to_string())
+            }
         })?
         .rows_affected()
         == 0
     {
-        return Err(ApiError::ServerError(
-            "This address is already in use by a mailbox!".to_string(),
-        ));
+        if alias_limit_reached(&app_state.pool, owner).await.map_err(|e| {
+            log::error!("database error while checking the alias limit via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })? {
+            return Err(ApiError::BadRequest("Alias limit reached".to_string()));
+        }
+        return Err(ApiError::BadRequest(format!(
+            "'{address}' is already in use by a mailbox!"
+        )));
     }
+    app_state.count_cache.invalidate(CountTable::Aliases);
 
-    Ok((address, alias, domain))
+    Ok((address, alias, domain, active, from_name))
 }
 
 #[derive(Deserialize)]
 pub struct SimpleLoginRequest {
     note: String,
+    /// Not part of the SimpleLogin API: deactivate the alias after this many days
+    #[serde(default)]
+    expires_in_days: Option<i64>,
 }
 
 pub async fn create_simple_login(
@@ -155,8 +275,18 @@ pub async fn create_simple_login(
     headers: HeaderMap,
     WithRejection(extract::Json(body), _): WithRejection<extract::Json<SimpleLoginRequest>, ApiError>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let user = login_with_api_token(&app_state, &headers).await?;
-    let (address, _, _) = create_random_alias(&app_state, &user, None, &body.note).await?;
+    let (user, token_name) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasCreate)).await?;
+    let (address, ..) = create_random_alias(
+        &app_state,
+        &user,
+        None,
+        &body.note,
+        None,
+        body.expires_in_days,
+        None,
+        &token_name,
+    )
+    .await?;
 
     Ok((
         StatusCode::CREATED,
@@ -171,6 +301,12 @@ pub async fn create_simple_login(
 pub struct AddyIoRequest {
     domain: String,
     description: Option<String>,
+    /// Not part of the addy.io API: deactivate the alias after this many received mails
+    max_received: Option<i64>,
+    /// Not part of the addy.io API: deactivate the alias after this many days
+    expires_in_days: Option<i64>,
+    /// Only part of the addy.io update API, but accepted here so it can be set on creation
+    from_name: Option<String>,
 }
 
 pub async fn create_addy_io(
@@ -178,13 +314,17 @@ pub async fn create_addy_io(
     headers: HeaderMap,
     WithRejection(extract::Json(body), _): WithRejection<extract::Json<AddyIoRequest>, ApiError>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let user = login_with_api_token(&app_state, &headers).await?;
+    let (user, token_name) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasCreate)).await?;
     let description = body.description.unwrap_or("".to_string());
-    let (address, _, domain) = create_random_alias(
+    let (address, _, domain, active, from_name) = create_random_alias(
         &app_state,
         &user,
         (!body.domain.is_empty() && body.domain != "random").then_some(body.domain),
         &description,
+        body.max_received,
+        body.expires_in_days,
+        body.from_name.as_deref(),
+        &token_name,
     )
     .await?;
 
@@ -200,9 +340,9 @@ pub async fn create_addy_io(
                 "extension": null,
                 "domain": domain,
                 "email": address,
-                "active": true,
+                "active": active,
                 "description": description,
-                "from_name": null,
+                "from_name": from_name,
                 "emails_forwarded": 0,
                 "emails_blocked": 0,
                 "emails_replied": 0,
@@ -220,3 +360,1374 @@ pub async fn create_addy_io(
     )
         .into_response())
 }
+
+#[derive(Deserialize)]
+pub struct QuickAliasQuery {
+    token: Option<String>,
+    domain: Option<String>,
+    note: Option<String>,
+    nonce: Option<String>,
+    expires_in_days: Option<i64>,
+}
+
+/// Creates a random alias from a simple GET request, so it can be used from bookmarks
+/// or mobile shortcuts. The token is taken from the `Authorization` header if present,
+/// otherwise from the query, since bookmarks cannot set headers. Each request needs a nonce,
+/// and a request with a nonce that was already used returns the previously created alias,
+/// which prevents link prefetching or reloads from creating additional aliases.
+pub async fn quick_alias(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Query(query): extract::Query<QuickAliasQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, token_name) = match &query.token {
+        Some(token) if !headers.contains_key(header::AUTHORIZATION) => {
+            login_with_api_token_str(&app_state, token, Some(ApiScope::AliasCreate)).await?
+        }
+        _ => login_with_api_token(&app_state, &headers, Some(ApiScope::AliasCreate)).await?,
+    };
+
+    let Some(nonce) = query.nonce.filter(|x| !x.is_empty()) else {
+        return Err(ApiError::BadRequest("A nonce is required".to_string()));
+    };
+    let db_error = |e: sqlx::Error| {
+        log::error!("database error while handling quick alias nonce: {e}");
+        ApiError::ServerError("database error".to_string())
+    };
+    let existing_alias = || {
+        sqlx::query_scalar::<_, String>("SELECT address FROM quick_alias_nonces WHERE mailbox = ? AND nonce = ?")
+            .bind(&user.username)
+            .bind(&nonce)
+            .fetch_optional(&app_state.pool)
+    };
+    if let Some(address) = existing_alias().await.map_err(db_error)? {
+        return Ok((StatusCode::OK, address).into_response());
+    }
+
+    let domain = query.domain.filter(|x| !x.is_empty() && x != "random");
+    let note = query.note.unwrap_or_default();
+    let (address, ..) = create_random_alias(
+        &app_state,
+        &user,
+        domain,
+        &note,
+        None,
+        query.expires_in_days,
+        None,
+        &token_name,
+    )
+    .await?;
+
+    // A concurrent request with the same nonce may have stored its alias in the meantime,
+    // in which case that one is returned and the alias created here is removed again.
+    let stored = sqlx::query(
+        "INSERT INTO quick_alias_nonces (mailbox, nonce, address) VALUES (?, ?, ?) \
+        ON CONFLICT (mailbox, nonce) DO NOTHING",
+    )
+    .bind(&user.username)
+    .bind(&nonce)
+    .bind(&address)
+    .execute(&app_state.pool)
+    .await
+    .map_err(db_error)?
+    .rows_affected()
+        > 0;
+    if !stored {
+        sqlx::query("DELETE FROM aliases WHERE address = ?")
+            .bind(&address)
+            .execute(&app_state.pool)
+            .await
+            .map_err(db_error)?;
+        app_state.count_cache.invalidate(CountTable::Aliases);
+        let address = existing_alias().await.map_err(db_error)?.ok_or_else(|| {
+            log::error!("quick alias nonce of '{}' vanished after a conflict", user.username);
+            ApiError::ServerError("database error".to_string())
+        })?;
+        return Ok((StatusCode::OK, address).into_response());
+    }
+
+    Ok((StatusCode::CREATED, address).into_response())
+}
+
+/// Verifies the given API token without any side effects, so clients
+/// can check their configuration before relying on it.
+pub async fn ping(State(app_state): State<AppState>, headers: HeaderMap) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, None).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "username": user.username,
+            "mailbox": user.mailbox_owner.is_some().then_some(&user.username),
+            "owner": user.mailbox_owner,
+        })),
+    )
+        .into_response())
+}
+
+/// Returns the optional features enabled on this server. Doesn't require authentication.
+pub async fn capabilities(State(app_state): State<AppState>) -> impl IntoResponse {
+    Json(Capabilities::new(&app_state.config))
+}
+
+/// The maximum number of aliases returned by a single list request
+const MAX_LIST_ALIASES_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ListAliasesQuery {
+    active: Option<bool>,
+    search: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Lists the aliases of the token's owner, optionally filtered by status or a search term.
+pub async fn list_aliases(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Query(query): extract::Query<ListAliasesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasRead)).await?;
+
+    let limit = query
+        .limit
+        .unwrap_or(MAX_LIST_ALIASES_LIMIT)
+        .clamp(0, MAX_LIST_ALIASES_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let mut sql = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE 1=1",
+    );
+    push_owner_scope(&mut sql, &user, OwnerScope::Aliases);
+    if let Some(active) = query.active {
+        sql.push(" AND active = ");
+        sql.push_bind(active);
+    }
+    if let Some(search) = query.search.filter(|x| !x.is_empty()) {
+        sql.push(" AND ( address LIKE concat('%', ");
+        sql.push_bind(search.clone());
+        sql.push(", '%') OR name LIKE concat('%', ");
+        sql.push_bind(search.clone());
+        sql.push(", '%') OR comment LIKE concat('%', ");
+        sql.push_bind(search);
+        sql.push(", '%') )");
+    }
+    sql.push(" ORDER BY created_at DESC, address LIMIT ");
+    sql.push_bind(limit);
+    sql.push(" OFFSET ");
+    sql.push_bind(offset);
+
+    let aliases = sql
+        .logged()
+        .build_query_as::<Alias>()
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(|e| {
+            log::error!("database error while listing aliases: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+
+    let aliases = aliases.iter().map(alias_json).collect::<Vec<_>>();
+    Ok((StatusCode::OK, Json(aliases)).into_response())
+}
+
+/// The representation of an alias in API responses
+fn alias_json(alias: &Alias) -> serde_json::Value {
+    json!({
+        "address": alias.address,
+        "target": alias.target,
+        "comment": alias.comment,
+        "active": alias.active,
+        "n_recv": alias.n_recv,
+        "n_sent": alias.n_sent,
+        "created_at": alias.created_at,
+        "created_via": alias.created_via,
+        "expires_at": alias.expires_at,
+        "daily_limit": alias.daily_limit,
+        "from_name": alias.from_name,
+    })
+}
+
+/// Returns the given alias if it is visible to the user
+async fn visible_alias(app_state: &AppState, user: &User, address: &str) -> Result<Alias, ApiError> {
+    let mut query = QueryBuilder::new(
+        "SELECT *, COALESCE(name, address) AS display_name, n_recv + n_sent AS activity FROM aliases WHERE address = ",
+    );
+    query.push_bind(address);
+    push_owner_scope(&mut query, user, OwnerScope::Aliases);
+
+    let alias = query
+        .logged()
+        .build_query_as::<Alias>()
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(|e| {
+            log::error!("database error while fetching alias via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?
+        .ok_or_else(|| ApiError::NotFound(format!("Alias '{address}' not found")))?;
+    Ok(alias)
+}
+
+/// Returns the given alias if it is visible to the user and may be changed
+async fn owned_alias(app_state: &AppState, user: &User, address: &str) -> Result<Alias, ApiError> {
+    let alias = visible_alias(app_state, user, address).await?;
+    if alias.provisioned && !app_state.config.allow_editing_provisioned {
+        return Err(ApiError::BadRequest(format!(
+            "'{address}' is managed by the provisioning file, please change it there instead"
+        )));
+    }
+    Ok(alias)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatKind {
+    Recv,
+    Sent,
+}
+
+fn default_stat_count() -> i64 {
+    1
+}
+
+#[derive(Deserialize)]
+pub struct IncrementStatRequest {
+    kind: StatKind,
+    #[serde(default = "default_stat_count")]
+    count: i64,
+}
+
+/// Checks the token of the mailserver against `IDMAIL_STATS_TOKEN`.
+/// This token is separate from the API tokens of mailboxes, and the endpoint is disabled without it.
+fn check_stats_token(app_state: &AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    use sha2::{Digest, Sha256};
+
+    let Some(expected) = &app_state.config.stats_token else {
+        return Err(ApiError::Unauthorized("The stats endpoint is disabled".to_string()));
+    };
+    let Some(token) = headers.get("Authorization").and_then(|x| x.to_str().ok()) else {
+        return Err(ApiError::Unauthorized("Missing stats token in request".to_string()));
+    };
+    let token = token.strip_prefix("Bearer").unwrap_or(token).trim_start();
+
+    // Compare digests, so the comparison time doesn't depend on the token
+    if Sha256::digest(token.as_bytes()) != Sha256::digest(expected.as_bytes()) {
+        return Err(ApiError::Unauthorized("Invalid stats token".to_string()));
+    }
+    Ok(())
+}
+
+/// Increments the received or sent counter of an alias. Meant to be called by the mailserver.
+pub async fn increment_alias_stat(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(address): extract::Path<String>,
+    WithRejection(extract::Json(body), _): WithRejection<extract::Json<IncrementStatRequest>, ApiError>,
+) -> Result<impl IntoResponse, ApiError> {
+    check_stats_token(&app_state, &headers)?;
+    if body.count <= 0 {
+        return Err(ApiError::BadRequest("count must be a positive integer".to_string()));
+    }
+
+    // A single UPDATE increments atomically, so concurrent requests never lose counts
+    let query = match body.kind {
+        StatKind::Recv => "UPDATE aliases SET n_recv = n_recv + ? WHERE address = ?",
+        StatKind::Sent => "UPDATE aliases SET n_sent = n_sent + ? WHERE address = ?",
+    };
+    let result = sqlx::query(query)
+        .bind(body.count)
+        .bind(&address)
+        .execute(&app_state.pool)
+        .await
+        .map_err(|e| {
+            log::error!("database error while incrementing alias stats: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound(format!("Alias '{address}' not found")));
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteUnusedAliasesQuery {
+    older_than_days: u32,
+}
+
+/// Deletes the random aliases of the token owner that were created through the API, never received
+/// mail and are older than the given number of days. Admin tokens only clean up the admin's own
+/// aliases. Returns the number of deleted aliases.
+pub async fn delete_unused_aliases(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Query(query): extract::Query<DeleteUnusedAliasesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasDelete)).await?;
+    if i64::from(query.older_than_days) > MAX_EXPIRES_IN_DAYS {
+        return Err(ApiError::BadRequest(format!(
+            "older_than_days must be at most {MAX_EXPIRES_IN_DAYS}"
+        )));
+    }
+    let older_than = chrono::Utc::now() - chrono::Duration::days(query.older_than_days.into());
+
+    let deleted = delete_unused_aliases_of(&app_state.pool, &user, older_than, true)
+        .await
+        .map_err(|e| {
+            log::error!("database error while deleting unused aliases via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+    app_state.count_cache.invalidate(CountTable::Aliases);
+
+    Ok((StatusCode::OK, Json(json!({ "deleted": deleted }))).into_response())
+}
+
+/// Returns a single alias owned by the token's owner. Unlike the list endpoint,
+/// which returns an empty list if nothing matches, this is a `404` if it doesn't exist.
+pub async fn get_alias(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(address): extract::Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasRead)).await?;
+    // Provisioned aliases can't be changed here, but reading them is fine
+    let alias = visible_alias(&app_state, &user, &address).await?;
+    Ok((StatusCode::OK, Json(alias_json(&alias))).into_response())
+}
+
+/// Deletes an alias owned by the token's owner.
+pub async fn delete_alias(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(address): extract::Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasDelete)).await?;
+    let alias = owned_alias(&app_state, &user, &address).await?;
+
+    sqlx::query("DELETE FROM aliases WHERE address = ?")
+        .bind(&alias.address)
+        .execute(&app_state.pool)
+        .await
+        .map_err(|e| {
+            log::error!("database error while deleting alias via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+    app_state.count_cache.invalidate(CountTable::Aliases);
+
+    Ok((StatusCode::OK, Json(alias_json(&alias))).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct UpdateAliasRequest {
+    #[serde(default)]
+    active: Option<bool>,
+    /// The comment of the alias, named like addy.io's field. `null` clears it, leaving it out keeps it.
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    description: Option<Option<String>>,
+}
+
+/// Activates, deactivates or changes the description of an alias owned by the token's owner, like
+/// addy.io's endpoint of the same path. Aliases have no separate id, so the id is the address of the alias.
+pub async fn update_alias(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(address): extract::Path<String>,
+    WithRejection(extract::Json(body), _): WithRejection<extract::Json<UpdateAliasRequest>, ApiError>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (user, _) = login_with_api_token(&app_state, &headers, Some(ApiScope::AliasUpdate)).await?;
+    if body.active.is_none() && body.description.is_none() {
+        return Err(ApiError::BadRequest(
+            "Nothing to update, expected 'active' or 'description'".to_string(),
+        ));
+    }
+
+    let mut alias = owned_alias(&app_state, &user, &address).await?;
+    if let Some(active) = body.active {
+        alias.active = active;
+    }
+    if let Some(description) = body.description {
+        alias.comment = description.unwrap_or_default();
+    }
+
+    sqlx::query("UPDATE aliases SET active = ?, comment = ? WHERE address = ?")
+        .bind(alias.active)
+        .bind(&alias.comment)
+        .bind(&alias.address)
+        .execute(&app_state.pool)
+        .await
+        .map_err(|e| {
+            log::error!("database error while updating alias via api token: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+    app_state.count_cache.invalidate(CountTable::Aliases);
+
+    let (local_part, domain) = alias.address.rsplit_once('@').unwrap_or((&alias.address, ""));
+    let created_at = alias.created_at.format("%Y-%m-%d %H:%M:%S").to_string();
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "data": {
+                "id": alias.address,
+                "user_id": "00000000-0000-0000-0000-000000000000",
+                "aliasable_id": null,
+                "aliasable_type": null,
+                "local_part": local_part,
+                "extension": null,
+                "domain": domain,
+                "email": alias.address,
+                "active": alias.active,
+                "description": alias.comment,
+                "from_name": alias.from_name,
+                "emails_forwarded": alias.n_recv,
+                "emails_blocked": 0,
+                "emails_replied": 0,
+                "emails_sent": alias.n_sent,
+                "recipients": [],
+                "last_forwarded": null,
+                "last_blocked": null,
+                "last_replied": null,
+                "last_sent": null,
+                "created_at": created_at,
+                "updated_at": created_at,
+                "deleted_at": null
+            }
+        })),
+    )
+        .into_response())
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct ExportAliasesQuery {
+    target: String,
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Exports all aliases that forward to the given target mailbox, which helps
+/// to migrate them when a mailbox is decommissioned.
+pub async fn export_aliases_by_target(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+    extract::Query(query): extract::Query<ExportAliasesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(user) = auth_session.current_user.filter(|x| x.active) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+    export_aliases(&app_state, &user, query).await
+}
+
+async fn export_aliases(
+    app_state: &AppState,
+    user: &User,
+    query: ExportAliasesQuery,
+) -> Result<axum::response::Response, ApiError> {
+    let aliases = aliases_by_target(&app_state.pool, user, &query.target)
+        .await
+        .map_err(|e| {
+            log::error!("database error while exporting aliases: {e}");
+            ApiError::ServerError("database error".to_string())
+        })?;
+
+    let (content_type, extension, body) = match query.format {
+        ExportFormat::Json => (
+            "application/json",
+            "json",
+            serde_json::to_string_pretty(&aliases).map_err(|e| ApiError::ServerError(e.to_string()))?,
+        ),
+        ExportFormat::Csv => {
+            let mut csv = "address,target,name,comment,active,owner,daily_limit,n_recv,n_sent,created_at\n".to_string();
+            for alias in aliases {
+                let row = [
+                    csv_field(&alias.address),
+                    csv_field(&alias.target),
+                    csv_field(alias.name.as_deref().unwrap_or_default()),
+                    csv_field(&alias.comment),
+                    alias.active.to_string(),
+                    csv_field(&alias.owner),
+                    alias.daily_limit.map(|x| x.to_string()).unwrap_or_default(),
+                    alias.n_recv.to_string(),
+                    alias.n_sent.to_string(),
+                    alias.created_at.to_rfc3339(),
+                ];
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+            ("text/csv", "csv", csv)
+        }
+    };
+
+    let filename = format!("aliases-{}.{extension}", query.target.replace(['"', '/', '\\'], "_"));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Writes a consistent snapshot of the database into a temporary file in the given directory and
+/// returns it opened for reading, along with its size. The file is only readable by us and is
+/// removed as soon as it was reopened, so no copy is left behind, even if the download is aborted.
+async fn create_backup(pool: &sqlx::SqlitePool, dir: &std::path::Path) -> Result<(tokio::fs::File, u64), String> {
+    let file = tempfile::Builder::new()
+        .prefix("idmail-backup-")
+        .suffix(".db")
+        .tempfile_in(dir)
+        .map_err(|e| e.to_string())?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(file.path().to_string_lossy())
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let backup = file.reopen().map_err(|e| e.to_string())?;
+    let len = backup.metadata().map_err(|e| e.to_string())?.len();
+    Ok((tokio::fs::File::from_std(backup), len))
+}
+
+/// Creates a consistent snapshot of the database and streams it as a download.
+/// `VACUUM INTO` only needs a read transaction, so live traffic is not blocked.
+pub async fn download_backup(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(user) = auth_session.current_user.filter(|x| x.active && x.admin) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let backup = create_backup(&app_state.pool, &std::env::temp_dir()).await;
+    let (backup, len) = backup.map_err(|e| {
+        log::error!("failed to create database backup: {e}");
+        ApiError::ServerError("failed to create database backup".to_string())
+    })?;
+
+    log::info!("admin '{}' downloaded a database backup", user.username);
+    let filename = format!("idmail-{}.db", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/vnd.sqlite3".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        axum::body::Body::from_stream(tokio_util::io::ReaderStream::new(backup)),
+    )
+        .into_response())
+}
+
+/// Returns the current routing as a static stalwart directory, for mailservers
+/// that should load a snapshot instead of querying the database live.
+pub async fn export_stalwart_snapshot(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(user) = auth_session.current_user.filter(|x| x.active && x.admin) else {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    };
+
+    let (snapshot, skipped) = crate::stalwart::render_snapshot(&app_state.pool).await.map_err(|e| {
+        log::error!("failed to create stalwart snapshot: {e}");
+        ApiError::ServerError("failed to create stalwart snapshot".to_string())
+    })?;
+
+    log::info!(
+        "admin '{}' exported a stalwart snapshot ({skipped} routes skipped)",
+        user.username
+    );
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/toml".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"idmail-stalwart.toml\"".to_string(),
+            ),
+        ],
+        snapshot,
+    )
+        .into_response())
+}
+
+/// Returns the usage of each user for billing: their domains and mailboxes, and the aliases they own
+/// directly or through one of their mailboxes together with the mails received and sent via them.
+pub async fn owner_usage(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+) -> Result<impl IntoResponse, ApiError> {
+    if !auth_session.current_user.is_some_and(|x| x.active && x.admin) {
+        return Err(ApiError::Unauthorized("Unauthorized".to_string()));
+    }
+    Ok(Json(usage_per_owner(&app_state.pool).await?))
+}
+
+/// The usage of every user, including the aliases of their mailboxes
+async fn usage_per_owner(pool: &sqlx::SqlitePool) -> Result<Vec<serde_json::Value>, ApiError> {
+    let usage = sqlx::query_as::<_, (String, i64, i64, i64, i64, i64, i64)>(
+        "WITH alias_usage AS ( \
+            SELECT COALESCE(mailboxes.owner, aliases.owner) AS owner, COUNT(*) AS aliases, \
+            SUM(aliases.active) AS active_aliases, SUM(aliases.n_recv) AS n_recv, SUM(aliases.n_sent) AS n_sent \
+            FROM aliases LEFT JOIN mailboxes ON mailboxes.address = aliases.owner GROUP BY 1 \
+        ) \
+        SELECT users.username, \
+            (SELECT COUNT(*) FROM domains WHERE domains.owner = users.username), \
+            (SELECT COUNT(*) FROM mailboxes WHERE mailboxes.owner = users.username), \
+            COALESCE(alias_usage.aliases, 0), COALESCE(alias_usage.active_aliases, 0), \
+            COALESCE(alias_usage.n_recv, 0), COALESCE(alias_usage.n_sent, 0) \
+        FROM users LEFT JOIN alias_usage ON alias_usage.owner = users.username \
+        ORDER BY users.username",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        log::error!("database error while collecting owner usage: {e}");
+        ApiError::ServerError("database error".to_string())
+    })?;
+
+    let usage = usage
+        .into_iter()
+        .map(|(owner, domains, mailboxes, aliases, active_aliases, n_recv, n_sent)| {
+            json!({
+                "owner": owner,
+                "domains": domains,
+                "mailboxes": mailboxes,
+                "aliases": aliases,
+                "active_aliases": active_aliases,
+                "n_recv": n_recv,
+                "n_sent": n_sent,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, testing::*};
+
+    const ADMIN_TOKEN: &str = "admin-token-0123456789";
+    const ALICE_TOKEN: &str = "alice-token-0123456789";
+    const BOB_TOKEN: &str = "bob-token-0123456789";
+
+    /// Alice and Bob each own a mailbox and a token, Alice also owns the alias `shop@example.com`
+    async fn setup() -> (TestDb, AppState) {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_mailbox(&db, "alice@example.com", "alice").await;
+        insert_mailbox(&db, "bob@example.com", "bob").await;
+        insert_api_token(&db, "alice", "cli", ALICE_TOKEN).await;
+        insert_api_token(&db, "bob", "cli", BOB_TOKEN).await;
+        insert_alias(&db, "shop@example.com", "alice@example.com", "alice").await;
+        sqlx::query("UPDATE aliases SET comment = 'shop'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let state = app_state(&db, Config::default());
+        (db, state)
+    }
+
+    async fn alias_row(pool: &sqlx::SqlitePool, address: &str) -> (bool, String) {
+        sqlx::query_as("SELECT active, comment FROM aliases WHERE address = ?")
+            .bind(address)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    fn update_body(body: serde_json::Value) -> WithRejection<extract::Json<UpdateAliasRequest>, ApiError> {
+        WithRejection(extract::Json(serde_json::from_value(body).unwrap()), Default::default())
+    }
+
+    async fn set_created_via(pool: &sqlx::SqlitePool, address: &str, created_via: &str) {
+        sqlx::query("UPDATE aliases SET created_via = ? WHERE address = ?")
+            .bind(created_via)
+            .bind(address)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_unused_aliases_is_scoped_to_own_random_aliases() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_mailbox(&db, "admin@example.com", "admin").await;
+        insert_mailbox(&db, "bob@example.com", "bob").await;
+        insert_api_token(&db, "admin", "cli", ADMIN_TOKEN).await;
+        for (address, owner, created_via) in [
+            ("random1@example.com", "admin", "cli"),
+            ("random2@example.com", "admin@example.com", "addy.io"),
+            ("manual@example.com", "admin", "ui"),
+            ("imported@example.com", "admin", "import"),
+            ("bobs@example.com", "bob", "cli"),
+        ] {
+            insert_alias(&db, address, "admin@example.com", owner).await;
+            set_created_via(&db, address, created_via).await;
+        }
+        insert_alias(&db, "unknown@example.com", "admin@example.com", "admin").await;
+        sqlx::query("UPDATE aliases SET created_at = datetime('now', '-10 days')")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let state = app_state(&db, Config::default());
+        let query = |older_than_days| extract::Query(DeleteUnusedAliasesQuery { older_than_days });
+
+        // Nothing is old enough yet
+        let response = delete_unused_aliases(State(state.clone()), bearer(ADMIN_TOKEN), query(30)).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["deleted"], 0);
+
+        let response = delete_unused_aliases(State(state.clone()), bearer(ADMIN_TOKEN), query(7)).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["deleted"], 2);
+
+        let mut remaining: Vec<String> = sqlx::query_scalar("SELECT address FROM aliases")
+            .fetch_all(&*db)
+            .await
+            .unwrap();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "bobs@example.com",
+                "imported@example.com",
+                "manual@example.com",
+                "unknown@example.com"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_unused_aliases_rejects_huge_ages() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_api_token(&db, "admin", "cli", ADMIN_TOKEN).await;
+
+        let state = app_state(&db, Config::default());
+        let query = extract::Query(DeleteUnusedAliasesQuery {
+            older_than_days: u32::MAX,
+        });
+        let response = delete_unused_aliases(State(state), bearer(ADMIN_TOKEN), query).await;
+        assert_eq!(response_json(response).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn update_alias_keeps_fields_that_were_not_sent() {
+        let (db, state) = setup().await;
+        let path = || extract::Path("shop@example.com".to_string());
+
+        let response = update_alias(
+            State(state.clone()),
+            bearer(ALICE_TOKEN),
+            path(),
+            update_body(json!({ "active": false })),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["description"], "shop");
+        assert_eq!(alias_row(&db, "shop@example.com").await, (false, "shop".to_string()));
+
+        let response = update_alias(
+            State(state.clone()),
+            bearer(ALICE_TOKEN),
+            path(),
+            update_body(json!({ "description": null })),
+        )
+        .await;
+        assert_eq!(response_json(response).await.0, StatusCode::OK);
+        assert_eq!(alias_row(&db, "shop@example.com").await, (false, String::new()));
+
+        let response = update_alias(State(state), bearer(ALICE_TOKEN), path(), update_body(json!({}))).await;
+        assert_eq!(response_json(response).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn update_alias_rejects_aliases_of_others() {
+        let (db, state) = setup().await;
+
+        let response = update_alias(
+            State(state),
+            bearer(BOB_TOKEN),
+            extract::Path("shop@example.com".to_string()),
+            update_body(json!({ "active": false, "description": "mine now" })),
+        )
+        .await;
+        assert_eq!(response_json(response).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(alias_row(&db, "shop@example.com").await, (true, "shop".to_string()));
+    }
+
+    #[tokio::test]
+    async fn get_alias_is_not_found_unless_visible() {
+        let (db, state) = setup().await;
+        sqlx::query("UPDATE aliases SET provisioned = TRUE")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let path = |address: &str| extract::Path(address.to_string());
+
+        // Provisioned aliases can't be changed through the API, but they can be read
+        let response = get_alias(State(state.clone()), bearer(ALICE_TOKEN), path("shop@example.com")).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["address"], "shop@example.com");
+
+        let response = get_alias(State(state.clone()), bearer(BOB_TOKEN), path("shop@example.com")).await;
+        assert_eq!(response_json(response).await.0, StatusCode::NOT_FOUND);
+        let response = get_alias(State(state), bearer(ALICE_TOKEN), path("missing@example.com")).await;
+        assert_eq!(response_json(response).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn list_aliases_is_empty_if_nothing_matches() {
+        let (_db, state) = setup().await;
+        let query = |search: &str| {
+            extract::Query(ListAliasesQuery {
+                active: None,
+                search: Some(search.to_string()),
+                limit: None,
+                offset: None,
+            })
+        };
+
+        let response = list_aliases(State(state.clone()), bearer(ALICE_TOKEN), query("shop")).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+
+        let response = list_aliases(State(state.clone()), bearer(ALICE_TOKEN), query("nothing")).await;
+        assert_eq!(response_json(response).await, (StatusCode::OK, json!([])));
+        let response = list_aliases(State(state), bearer(BOB_TOKEN), query("shop")).await;
+        assert_eq!(response_json(response).await, (StatusCode::OK, json!([])));
+    }
+
+    #[tokio::test]
+    async fn backups_leave_no_file_behind() {
+        use tokio::io::AsyncReadExt;
+
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let (mut file, len) = create_backup(&db, dir.path()).await.unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        let mut backup = Vec::new();
+        file.read_to_end(&mut backup).await.unwrap();
+        assert_eq!(backup.len() as u64, len);
+        assert!(backup.starts_with(b"SQLite format 3\0"));
+    }
+
+    fn quick_alias_query(token: Option<&str>, nonce: Option<&str>) -> extract::Query<QuickAliasQuery> {
+        extract::Query(QuickAliasQuery {
+            token: token.map(str::to_string),
+            domain: Some("alice.test".to_string()),
+            note: None,
+            nonce: nonce.map(str::to_string),
+            expires_in_days: None,
+        })
+    }
+
+    async fn body_text(response: axum::response::Response) -> (StatusCode, String) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn quick_alias_requires_token_and_nonce() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+
+        let response = quick_alias(
+            State(state.clone()),
+            HeaderMap::new(),
+            quick_alias_query(None, Some("n1")),
+        )
+        .await;
+        assert_eq!(
+            response.err().unwrap().into_response().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let response = quick_alias(
+            State(state),
+            HeaderMap::new(),
+            quick_alias_query(Some(ALICE_TOKEN), None),
+        )
+        .await;
+        assert_eq!(
+            response.err().unwrap().into_response().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn quick_alias_nonces_are_only_used_once() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+
+        let create = |token: Option<&'static str>, headers: HeaderMap| {
+            let state = state.clone();
+            async move {
+                let response = quick_alias(State(state), headers, quick_alias_query(token, Some("n1"))).await;
+                body_text(response.unwrap().into_response()).await
+            }
+        };
+        let (status, address) = create(Some(ALICE_TOKEN), HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(address.ends_with("@alice.test"));
+        // The header takes precedence over the query
+        let (status, again) = create(Some("invalid-token-0123456789"), bearer(ALICE_TOKEN)).await;
+        assert_eq!((status, again), (StatusCode::OK, address));
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM aliases WHERE domain = 'alice.test'")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn new_aliases_honor_the_active_preference() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        let create_active = |nonce: &'static str| {
+            let (pool, state) = ((*db).clone(), state.clone());
+            async move {
+                let query = quick_alias_query(None, Some(nonce));
+                let response = quick_alias(State(state), bearer(ALICE_TOKEN), query).await.unwrap();
+                let (_, address) = body_text(response.into_response()).await;
+                sqlx::query_scalar::<_, bool>("SELECT active FROM aliases WHERE address = ?")
+                    .bind(address)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert!(create_active("n1").await);
+        sqlx::query("UPDATE users SET new_aliases_active = FALSE WHERE username = 'alice'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert!(!create_active("n2").await);
+    }
+
+    #[tokio::test]
+    async fn quick_alias_nonces_are_removed_with_their_account() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        let query = quick_alias_query(None, Some("n1"));
+        quick_alias(State(state), bearer(ALICE_TOKEN), query).await.unwrap();
+
+        sqlx::query("DELETE FROM users WHERE username = 'alice'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM quick_alias_nonces")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_random_aliases_respect_the_alias_limit() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        sqlx::query("UPDATE mailboxes SET alias_limit = 1 WHERE address = 'alice@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let mailbox = mailbox("alice@example.com", "alice");
+        let create = || create_random_alias(&state, &mailbox, None, "", None, None, None, "cli");
+        let (a, b) = tokio::join!(create(), create());
+        assert!(a.is_ok() != b.is_ok());
+        let err = a.err().or(b.err()).unwrap();
+        assert!(matches!(err, ApiError::BadRequest(message) if message == "Alias limit reached"));
+    }
+
+    #[tokio::test]
+    async fn api_aliases_record_the_name_of_their_token() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        let account_token = "mailbox-token-0123456789";
+        sqlx::query("UPDATE mailboxes SET api_token = ? WHERE address = 'alice@example.com'")
+            .bind(account_token)
+            .execute(&*db)
+            .await
+            .unwrap();
+        crate::auth::ssr::move_legacy_api_tokens(&db).await.unwrap();
+
+        for (token, nonce, created_via) in [(ALICE_TOKEN, "n1", "cli"), (account_token, "n2", "Account token")] {
+            let query = quick_alias_query(None, Some(nonce));
+            let response = quick_alias(State(state.clone()), bearer(token), query).await;
+            let (status, address) = body_text(response.unwrap().into_response()).await;
+            assert_eq!(status, StatusCode::CREATED);
+
+            let stored: Option<String> = sqlx::query_scalar("SELECT created_via FROM aliases WHERE address = ?")
+                .bind(&address)
+                .fetch_one(&*db)
+                .await
+                .unwrap();
+            assert_eq!(stored.as_deref(), Some(created_via));
+        }
+    }
+
+    #[tokio::test]
+    async fn aliases_deactivate_after_their_max_received() {
+        let (db, mut state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        state.config = std::sync::Arc::new(Config {
+            stats_token: Some("stats-token".to_string()),
+            ..Default::default()
+        });
+
+        let mailbox = mailbox("alice@example.com", "alice");
+        let create = |max_received| create_random_alias(&state, &mailbox, None, "", max_received, None, None, "cli");
+        for invalid in [0, -1] {
+            let err = create(Some(invalid)).await.unwrap_err();
+            assert!(matches!(err, ApiError::BadRequest(message) if message.contains("max_received")));
+        }
+        let (address, ..) = create(Some(2)).await.unwrap();
+
+        let receive = || {
+            let body = serde_json::from_value(json!({ "kind": "recv" })).unwrap();
+            increment_alias_stat(
+                State(state.clone()),
+                bearer("stats-token"),
+                extract::Path(address.clone()),
+                WithRejection(extract::Json(body), Default::default()),
+            )
+        };
+        receive().await.unwrap();
+        assert!(alias_row(&db, &address).await.0);
+        receive().await.unwrap();
+        assert!(!alias_row(&db, &address).await.0);
+
+        // Reactivating the alias doesn't reset the counter, so the next mail deactivates it again
+        sqlx::query("UPDATE aliases SET active = TRUE WHERE address = ?")
+            .bind(&address)
+            .execute(&*db)
+            .await
+            .unwrap();
+        receive().await.unwrap();
+        assert!(!alias_row(&db, &address).await.0);
+    }
+
+    #[tokio::test]
+    async fn user_tokens_act_with_the_permissions_of_the_user() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        insert_user(&db, "carol", false).await;
+        insert_alias(&db, "bob-shop@example.com", "bob@example.com", "bob").await;
+        for (username, token) in [
+            ("alice", "alice-account-token-0123"),
+            ("carol", "carol-account-token-0123"),
+        ] {
+            insert_api_token(&db, username, "cli", token).await;
+        }
+
+        let (status, body) = response_json(ping(State(state.clone()), bearer("alice-account-token-0123")).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "username": "alice", "mailbox": null, "owner": null }));
+
+        // Only the aliases of the user are visible, and new ones forward to a mailbox of the user
+        let (user, _) = login_with_api_token_str(&state, "alice-account-token-0123", None)
+            .await
+            .unwrap();
+        let (address, ..) = create_random_alias(&state, &user, None, "", None, None, None, "cli")
+            .await
+            .unwrap();
+        let target: String = sqlx::query_scalar("SELECT target FROM aliases WHERE address = ?")
+            .bind(&address)
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(target, "alice@example.com");
+        let query = extract::Query(ListAliasesQuery {
+            active: None,
+            search: None,
+            limit: None,
+            offset: None,
+        });
+        let response = list_aliases(State(state.clone()), bearer("alice-account-token-0123"), query).await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        let mut listed = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["address"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        let mut expected = vec![address, "shop@example.com".to_string()];
+        listed.sort();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        let path = extract::Path("bob-shop@example.com".to_string());
+        let response = get_alias(State(state.clone()), bearer("alice-account-token-0123"), path).await;
+        assert_eq!(response_json(response).await.0, StatusCode::NOT_FOUND);
+
+        // Users without a mailbox can authenticate, but have nowhere to forward new aliases to
+        let (user, _) = login_with_api_token_str(&state, "carol-account-token-0123", None)
+            .await
+            .unwrap();
+        assert_eq!((user.username.as_str(), user.mailbox_owner.is_none()), ("carol", true));
+        let err = create_random_alias(&state, &user, None, "", None, None, None, "cli")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiError::BadRequest(message) if message.contains("mailbox")));
+    }
+
+    fn export_query(target: &str, format: ExportFormat) -> ExportAliasesQuery {
+        ExportAliasesQuery {
+            target: target.to_string(),
+            format,
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_contain_the_visible_aliases_of_the_target() {
+        let (db, state) = setup().await;
+        insert_alias(&db, "gift@example.com", "alice@example.com", "bob").await;
+        insert_alias(&db, "other@example.com", "bob@example.com", "alice").await;
+
+        let response = export_aliases(
+            &state,
+            &user("alice"),
+            export_query("alice@example.com", ExportFormat::Csv),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+        let (status, csv) = body_text(response).await;
+        assert_eq!(status, StatusCode::OK);
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "{csv}");
+        assert!(lines[0].starts_with("address,target,name,comment,active,owner,"));
+        assert!(
+            lines[1].starts_with("shop@example.com,alice@example.com,,shop,true,alice,"),
+            "{csv}"
+        );
+
+        let response = export_aliases(
+            &state,
+            &admin("admin"),
+            export_query("alice@example.com", ExportFormat::Json),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let (status, json) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        let addresses = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| (x["address"].as_str().unwrap(), x["owner"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, [("gift@example.com", "bob"), ("shop@example.com", "alice")]);
+    }
+
+    #[tokio::test]
+    async fn ping_identifies_the_account_of_the_token() {
+        let (_db, state) = setup().await;
+        let (status, body) = response_json(ping(State(state.clone()), bearer(ALICE_TOKEN)).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["username"], "alice");
+
+        for headers in [bearer("not-a-token-0123456789"), HeaderMap::new()] {
+            let (status, _) = response_json(ping(State(state.clone()), headers).await).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    /// Creates or updates `limited@example.com` for Alice like the alias editor does
+    async fn save_limited_alias(
+        db: &TestDb,
+        old_address: Option<&str>,
+        daily_limit: Option<i64>,
+        from_name: &str,
+    ) -> Result<(), leptos::ServerFnError> {
+        crate::aliases::save_alias(
+            db,
+            &admin("admin"),
+            old_address.map(str::to_string),
+            "limited".to_string(),
+            "example.com".to_string(),
+            "alice@example.com".to_string(),
+            String::new(),
+            String::new(),
+            daily_limit,
+            None,
+            None,
+            from_name.to_string(),
+            true,
+            "alice".to_string(),
+        )
+        .await
+    }
+
+    async fn get_alias_json(state: &AppState, address: &str) -> serde_json::Value {
+        let response = get_alias(
+            State(state.clone()),
+            bearer(ALICE_TOKEN),
+            extract::Path(address.to_string()),
+        )
+        .await;
+        let (status, body) = response_json(response).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    #[tokio::test]
+    async fn daily_limits_are_stored_and_exposed() {
+        let (db, state) = setup().await;
+        let runtime = server_context(&state);
+
+        let error = save_limited_alias(&db, None, Some(-1), "").await.unwrap_err();
+        assert!(field_error(error, "daily_limit").is_some());
+
+        save_limited_alias(&db, None, Some(25), "").await.unwrap();
+        assert_eq!(get_alias_json(&state, "limited@example.com").await["daily_limit"], 25);
+        save_limited_alias(&db, Some("limited@example.com"), None, "")
+            .await
+            .unwrap();
+        assert!(get_alias_json(&state, "limited@example.com").await["daily_limit"].is_null());
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn from_names_are_stored_and_exposed() {
+        let (db, state) = setup().await;
+        insert_domain(&db, "alice.test", "alice").await;
+        let runtime = server_context(&state);
+
+        let error = save_limited_alias(&db, None, None, "Shop\r\nBcc: x").await.unwrap_err();
+        assert!(field_error(error, "from_name").is_some());
+        save_limited_alias(&db, None, None, "  The Shop ").await.unwrap();
+        assert_eq!(
+            get_alias_json(&state, "limited@example.com").await["from_name"],
+            "The Shop"
+        );
+        runtime.dispose();
+
+        let body = |from_name: &str| {
+            WithRejection(
+                extract::Json(AddyIoRequest {
+                    domain: "alice.test".to_string(),
+                    description: None,
+                    max_received: None,
+                    expires_in_days: None,
+                    from_name: Some(from_name.to_string()),
+                }),
+                Default::default(),
+            )
+        };
+        let response = create_addy_io(State(state.clone()), bearer(ALICE_TOKEN), body("Alice")).await;
+        let (status, created) = response_json(response.unwrap()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["data"]["from_name"], "Alice");
+        let address = created["data"]["email"].as_str().unwrap();
+        assert_eq!(get_alias_json(&state, address).await["from_name"], "Alice");
+
+        let too_long = "x".repeat(crate::aliases::MAX_FROM_NAME_LENGTH + 1);
+        let response = create_addy_io(State(state.clone()), bearer(ALICE_TOKEN), body(&too_long)).await;
+        assert_eq!(response_json(response).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn usage_is_aggregated_per_owner() {
+        let (db, _state) = setup().await;
+        insert_mailbox(&db, "alice2@example.com", "alice").await;
+        // Aliases of mailboxes count for the user owning the mailbox
+        insert_alias(&db, "box@example.com", "alice@example.com", "alice@example.com").await;
+        sqlx::query("UPDATE aliases SET n_recv = 3, n_sent = 1 WHERE owner = 'alice'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE aliases SET n_recv = 4, active = FALSE WHERE owner = 'alice@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let usage = usage_per_owner(&db).await.unwrap();
+        let owners = usage.iter().map(|x| x["owner"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(owners, ["admin", "alice", "bob"]);
+        let expected = |domains: i64, mailboxes: i64, aliases: i64, active_aliases: i64, n_recv: i64, n_sent: i64| {
+            serde_json::json!({
+                "domains": domains,
+                "mailboxes": mailboxes,
+                "aliases": aliases,
+                "active_aliases": active_aliases,
+                "n_recv": n_recv,
+                "n_sent": n_sent,
+            })
+        };
+        let counts = |x: &serde_json::Value| {
+            let mut x = x.clone();
+            x.as_object_mut().unwrap().remove("owner");
+            x
+        };
+        assert_eq!(counts(&usage[0]), expected(1, 0, 0, 0, 0, 0));
+        assert_eq!(counts(&usage[1]), expected(0, 2, 2, 1, 7, 1));
+        assert_eq!(counts(&usage[2]), expected(0, 1, 0, 0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn capabilities_reflect_the_configuration() {
+        let db = TestDb::new().await;
+        let (_, defaults) = response_json(capabilities(State(app_state(&db, Config::default()))).await).await;
+        assert_eq!(defaults["signup"], "disabled");
+        assert_eq!(defaults["user_mailbox_creation"], true);
+        assert_eq!(defaults["digests"], false);
+        assert_eq!(defaults["edit_provisioned"], false);
+        assert_eq!(defaults["oidc"], false);
+        assert_eq!(defaults["password_login"], true);
+
+        let config = Config {
+            signup: crate::auth::SignupMode::Mailbox,
+            user_mailbox_creation: false,
+            digest_from: Some("digest@example.com".to_string()),
+            allow_editing_provisioned: true,
+            stats: false,
+            alias_trash_days: 7,
+            ..Default::default()
+        };
+        let (status, configured) = response_json(capabilities(State(app_state(&db, config))).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(configured["signup"], "mailbox");
+        assert_eq!(configured["user_mailbox_creation"], false);
+        assert_eq!(configured["digests"], true);
+        assert_eq!(configured["edit_provisioned"], true);
+        assert_eq!(configured["stats"], false);
+        assert_eq!(configured["alias_trash_days"], 7);
+    }
+}
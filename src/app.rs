@@ -1,13 +1,13 @@
 use crate::{
-    aliases::{alias_count, count_sent_or_received, Aliases},
-    auth::{get_user, Login, LoginView, Logout},
-    domains::Domains,
+    aliases::{alias_count, count_sent_or_received, recent_aliases, Aliases},
+    auth::{get_capabilities, get_user, Login, LoginView, Logout, Signup},
+    domains::{allowed_domains, Domains},
     mailboxes::Mailboxes,
     users::{AccountSettings, Users},
-    utils::ColorModeToggle,
+    utils::{ColorModeToggle, Select},
 };
 use chrono::{Months, Utc};
-use leptos::{html::Div, *};
+use leptos::{html::Div, leptos_dom::is_browser, logging::error, *};
 use leptos_icons::Icon;
 use leptos_meta::{provide_meta_context, Body, Link, Stylesheet, Title};
 use leptos_router::{ActionForm, Redirect, Route, Router, Routes, A};
@@ -25,10 +25,33 @@ pub enum Tab {
     AccountSettings,
 }
 
+/// The name shown in the page title and header, configurable via `IDMAIL_BRAND_NAME`
+#[derive(Copy, Clone)]
+pub struct BrandName(pub Signal<String>);
+
+#[server]
+pub async fn get_brand_name() -> Result<String, ServerFnError> {
+    Ok(crate::database::ssr::config()?.brand_name.clone())
+}
+
+/// Appends the brand name to the title of each page
+fn title_formatter(brand_name: Signal<String>) -> impl Fn(String) -> String {
+    move |text| format!("{text} · {}", brand_name.get())
+}
+
 #[component]
 pub fn App() -> impl IntoView {
     provide_meta_context();
 
+    let brand_name = create_blocking_resource(|| (), |_| get_brand_name());
+    let brand_name = Signal::derive(move || {
+        brand_name
+            .get()
+            .and_then(|x| x.ok())
+            .unwrap_or_else(|| "idmail".to_string())
+    });
+    provide_context(BrandName(brand_name));
+
     let login = create_server_action::<Login>();
     let logout = create_server_action::<Logout>();
     let color_mode = use_color_mode_with_options(UseColorModeOptions::default().emit_auto(true));
@@ -37,7 +60,7 @@ pub fn App() -> impl IntoView {
         let dark = match (color_mode.mode)() {
             ColorMode::Light => "",
             ColorMode::Dark => "dark",
-            ColorMode::Auto | _ => {
+            ColorMode::Auto | ColorMode::Custom(_) => {
                 if prefers_dark() {
                     "dark"
                 } else {
@@ -51,7 +74,7 @@ pub fn App() -> impl IntoView {
     view! {
         <Link rel="shortcut icon" type_="image/ico" href="/favicon.ico"/>
         <Stylesheet id="leptos" href="/pkg/idmail.css"/>
-        <Title formatter=|text| format!("{text} · idmail")/>
+        <Title formatter=title_formatter(brand_name)/>
         <Body class=body_class/>
         <Router>
             <main>
@@ -76,6 +99,16 @@ pub fn App() -> impl IntoView {
                         }
                     />
 
+                    <Route
+                        path="/signup"
+                        view=move || {
+                            view! {
+                                <Title text="Sign up"/>
+                                <Signup color_mode=color_mode.mode set_color_mode=color_mode.set_mode/>
+                            }
+                        }
+                    />
+
                     <Route
                         path="/aliases"
                         view=move || {
@@ -174,6 +207,7 @@ pub fn Tab(
         move || (login.version().get(), logout.version().get()),
         move |_| get_user(),
     );
+    let BrandName(brand_name) = expect_context::<BrandName>();
 
     let class_for = move |t| {
         let a_class_inactive = "inline-flex flex-1 sm:flex-none items-center justify-ceter whitespace-nowrap font-medium text-base hover:text-indigo-700 dark:hover:text-indigo-300 py-2.5 px-4 transition-all rounded-lg focus-visible:ring-4 hover:bg-indigo-200 dark:hover:bg-indigo-900 focus-visible:ring-blue-300 dark:focus-visible:ring-blue-900".to_string();
@@ -197,20 +231,10 @@ pub fn Tab(
         OnClickOutsideOptions::default().ignore(["#account-button"]),
     );
 
-    let active_alias_count = create_resource(|| (), |_| async move { alias_count(Some(true), None).await });
-    let inactive_alias_count = create_resource(|| (), |_| async move { alias_count(Some(false), None).await });
-    let total_recv_via_aliases = create_resource(|| (), |_| async move { count_sent_or_received(false).await });
-    let total_sent_via_aliases = create_resource(|| (), |_| async move { count_sent_or_received(true).await });
-    let new_since_last_month = create_resource(
-        || (),
-        |_| async move { alias_count(None, Some(Utc::now() - Months::new(1))).await },
-    );
-    let reload_stats = Callback::new(move |_: ()| {
-        active_alias_count.refetch();
-        inactive_alias_count.refetch();
-        total_recv_via_aliases.refetch();
-        total_sent_via_aliases.refetch();
-    });
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let show_stats = move || capabilities.get().and_then(Result::ok).is_some_and(|x| x.stats);
+    let stats_trigger = create_trigger();
+    let reload_stats = Callback::new(move |_: ()| stats_trigger.notify());
 
     view! {
         <Transition fallback=move || {
@@ -226,7 +250,7 @@ pub fn Tab(
                                     <div class="flex-1 flex flex-col sm:flex-row items-center w-full sm:w-auto">
                                         <A href="/aliases" class="flex flex-row items-center mb-4 sm:mb-0 items-center">
                                             <img class="w-16 h-16 me-2" src="/logo.svg"/>
-                                            <h2 class="text-4xl leading-none font-bold inline-block">idmail</h2>
+                                            <h2 class="text-4xl leading-none font-bold inline-block">{brand_name}</h2>
                                         </A>
                                         <div class="flex flex-row w-full sm:w-auto items-center gap-4 sm:ml-12 mb-4 sm:mb-0">
                                             <A href="/aliases" class=class_for(Tab::Aliases)>
@@ -319,92 +343,8 @@ pub fn Tab(
                                     </div>
                                 </div>
                                 <div class="overflow-hidden px-4 md:px-12">
-                                    <Show when=move || tab == Tab::Aliases>
-                                        <div class="grid gap-4 lg:grid-cols-3">
-                                            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
-                                                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
-                                                    <h3 class="tracking-tight text-sm font-medium">Aliases</h3>
-                                                    <Icon icon=icondata::TbMailForward class="w-5 h-5"/>
-                                                </div>
-                                                <div class="p-4 pt-0">
-                                                    <div class="text-2xl font-bold">
-                                                        <Transition fallback=move || {
-                                                            view! { <p class="animate-pulse">"..."</p> }
-                                                        }>
-                                                            {move || match active_alias_count.get() {
-                                                                Some(Ok(count)) => view! { {count} }.into_view(),
-                                                                _ => view! {}.into_view(),
-                                                            }}
-                                                            " active"
-
-                                                        </Transition>
-                                                    </div>
-                                                    <p class="text-xs text-gray-500 dark:text-gray-400">
-                                                        <Transition fallback=move || {
-                                                            view! { <span class="animate-pulse">"..."</span> }
-                                                        }>
-                                                            {move || match inactive_alias_count.get() {
-                                                                Some(Ok(count)) => view! { {count} }.into_view(),
-                                                                _ => view! {}.into_view(),
-                                                            }}
-                                                            " inactive, "
-                                                        </Transition>
-                                                        <Transition fallback=move || {
-                                                            view! { <span class="animate-pulse">"..."</span> }
-                                                        }>
-                                                            "+"
-                                                            {move || match new_since_last_month.get() {
-                                                                Some(Ok(count)) => view! { {count} }.into_view(),
-                                                                _ => view! {}.into_view(),
-                                                            }}
-                                                            " new last month"
-                                                        </Transition>
-                                                    </p>
-                                                </div>
-                                            </div>
-                                            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
-                                                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
-                                                    <h3 class="tracking-tight text-sm font-medium">
-                                                        Total received via aliases
-                                                    </h3>
-                                                    <Icon icon=icondata::BsArrowDown class="w-5 h-5"/>
-                                                </div>
-                                                <div class="p-4 pt-0">
-                                                    <div class="text-2xl font-bold">
-                                                        <Transition fallback=move || {
-                                                            view! { <span class="animate-pulse">"..."</span> }
-                                                        }>
-                                                            {move || match total_recv_via_aliases.get() {
-                                                                Some(Ok(count)) => view! { {count} }.into_view(),
-                                                                _ => view! {}.into_view(),
-                                                            }}
-
-                                                        </Transition>
-                                                    </div>
-                                                </div>
-                                            </div>
-                                            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
-                                                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
-                                                    <h3 class="tracking-tight text-sm font-medium">
-                                                        Total sent via aliases
-                                                    </h3>
-                                                    <Icon icon=icondata::BsArrowUp class="w-5 h-5"/>
-                                                </div>
-                                                <div class="p-4 pt-0">
-                                                    <div class="text-2xl font-bold">
-                                                        <Transition fallback=move || {
-                                                            view! { <span class="animate-pulse">"..."</span> }
-                                                        }>
-                                                            {move || match total_sent_via_aliases.get() {
-                                                                Some(Ok(count)) => view! { {count} }.into_view(),
-                                                                _ => view! {}.into_view(),
-                                                            }}
-
-                                                        </Transition>
-                                                    </div>
-                                                </div>
-                                            </div>
-                                        </div>
+                                    <Show when=move || tab == Tab::Aliases && show_stats()>
+                                        <Stats reload=stats_trigger/>
                                     </Show>
 
                                     {match tab {
@@ -430,3 +370,210 @@ pub fn Tab(
         </Transition>
     }
 }
+
+/// The statistics cards shown above the alias list. This is only rendered if stats are
+/// enabled, so none of the count queries are issued otherwise.
+#[component]
+pub fn Stats(reload: Trigger) -> impl IntoView {
+    // The stats can be restricted to a single domain
+    let (stats_domains, set_stats_domains) = create_signal(vec!["all".to_string()]);
+    let (stats_domain_choice, set_stats_domain_choice) = create_signal("all".to_string());
+    let stats_domain = create_memo(move |_| Some(stats_domain_choice()).filter(|x| x != "all"));
+    let stats_source = move || (stats_domain(), reload.track());
+    if is_browser() {
+        spawn_local(async move {
+            match allowed_domains().await {
+                Err(e) => error!("Failed to load allowed domains: {}", e),
+                Ok(domains) => set_stats_domains(
+                    std::iter::once("all".to_string())
+                        .chain(domains.into_iter().map(|x| x.0))
+                        .collect(),
+                ),
+            }
+        });
+    }
+
+    let active_alias_count = create_resource(stats_source, |(domain, _)| async move {
+        alias_count(Some(true), None, domain).await
+    });
+    let inactive_alias_count = create_resource(stats_source, |(domain, _)| async move {
+        alias_count(Some(false), None, domain).await
+    });
+    let total_recv_via_aliases = create_resource(stats_source, |(domain, _)| async move {
+        count_sent_or_received(false, domain).await
+    });
+    let total_sent_via_aliases = create_resource(stats_source, |(domain, _)| async move {
+        count_sent_or_received(true, domain).await
+    });
+    let new_since_last_month = create_resource(stats_source, |(domain, _)| async move {
+        alias_count(None, Some(Utc::now() - Months::new(1)), domain).await
+    });
+    let recent = create_resource(move || reload.track(), |_| recent_aliases(5));
+
+    view! {
+        <div class="flex flex-row items-center justify-end gap-2 mb-4">
+            <label class="text-sm font-medium leading-none">
+                "Statistics for"
+            </label>
+            <Select
+                class="rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900"
+                choices=stats_domains
+                value=stats_domain_choice
+                set_value=set_stats_domain_choice
+            />
+        </div>
+        <div class="grid gap-4 lg:grid-cols-3">
+            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
+                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
+                    <h3 class="tracking-tight text-sm font-medium">Aliases</h3>
+                    <Icon icon=icondata::TbMailForward class="w-5 h-5"/>
+                </div>
+                <div class="p-4 pt-0">
+                    <div class="text-2xl font-bold">
+                        <Transition fallback=move || {
+                            view! { <p class="animate-pulse">"..."</p> }
+                        }>
+                            {move || match active_alias_count.get() {
+                                Some(Ok(count)) => view! { {count} }.into_view(),
+                                _ => view! {}.into_view(),
+                            }}
+                            " active"
+
+                        </Transition>
+                    </div>
+                    <p class="text-xs text-gray-500 dark:text-gray-400">
+                        <Transition fallback=move || {
+                            view! { <span class="animate-pulse">"..."</span> }
+                        }>
+                            {move || match inactive_alias_count.get() {
+                                Some(Ok(count)) => view! { {count} }.into_view(),
+                                _ => view! {}.into_view(),
+                            }}
+                            " inactive, "
+                        </Transition>
+                        <Transition fallback=move || {
+                            view! { <span class="animate-pulse">"..."</span> }
+                        }>
+                            "+"
+                            {move || match new_since_last_month.get() {
+                                Some(Ok(count)) => view! { {count} }.into_view(),
+                                _ => view! {}.into_view(),
+                            }}
+                            " new last month"
+                        </Transition>
+                    </p>
+                </div>
+            </div>
+            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
+                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
+                    <h3 class="tracking-tight text-sm font-medium">
+                        Total received via aliases
+                    </h3>
+                    <Icon icon=icondata::BsArrowDown class="w-5 h-5"/>
+                </div>
+                <div class="p-4 pt-0">
+                    <div class="text-2xl font-bold">
+                        <Transition fallback=move || {
+                            view! { <span class="animate-pulse">"..."</span> }
+                        }>
+                            {move || match total_recv_via_aliases.get() {
+                                Some(Ok(count)) => view! { {count} }.into_view(),
+                                _ => view! {}.into_view(),
+                            }}
+
+                        </Transition>
+                    </div>
+                </div>
+            </div>
+            <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800">
+                <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
+                    <h3 class="tracking-tight text-sm font-medium">
+                        Total sent via aliases
+                    </h3>
+                    <Icon icon=icondata::BsArrowUp class="w-5 h-5"/>
+                </div>
+                <div class="p-4 pt-0">
+                    <div class="text-2xl font-bold">
+                        <Transition fallback=move || {
+                            view! { <span class="animate-pulse">"..."</span> }
+                        }>
+                            {move || match total_sent_via_aliases.get() {
+                                Some(Ok(count)) => view! { {count} }.into_view(),
+                                _ => view! {}.into_view(),
+                            }}
+
+                        </Transition>
+                    </div>
+                </div>
+            </div>
+        </div>
+        <div class="rounded-xl border-[1.5px] border-gray-200 dark:border-zinc-800 mt-4">
+            <div class="p-4 flex flex-row items-center justify-between space-y-0 pb-2">
+                <h3 class="tracking-tight text-sm font-medium">Recently created</h3>
+                <Icon icon=icondata::FiPlus class="w-5 h-5"/>
+            </div>
+            <div class="p-4 pt-0">
+                <Transition fallback=move || {
+                    view! { <span class="animate-pulse">"..."</span> }
+                }>
+                    {move || match recent.get() {
+                        Some(Ok(aliases)) if aliases.is_empty() => {
+                            view! { <p class="text-sm text-gray-500 dark:text-gray-400">"No aliases yet."</p> }
+                                .into_view()
+                        }
+                        Some(Ok(aliases)) => {
+                            aliases
+                                .into_iter()
+                                .map(|alias| {
+                                    let created = chrono_humanize::HumanTime::from(alias.created_at - Utc::now()).to_string();
+                                    view! {
+                                        <div class="flex flex-row gap-3 items-center text-sm">
+                                            <span class="flex-1 min-w-0 truncate font-mono">{alias.address}</span>
+                                            <span class="text-gray-500 dark:text-gray-400 truncate">
+                                                "→ " {alias.target}
+                                            </span>
+                                            <span class="w-28 text-right text-xs text-gray-500 dark:text-gray-400">
+                                                {created}
+                                            </span>
+                                        </div>
+                                    }
+                                })
+                                .collect_view()
+                        }
+                        _ => view! {}.into_view(),
+                    }}
+
+                </Transition>
+            </div>
+        </div>
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn the_configured_brand_name_appears_in_the_title() {
+        let db = TestDb::new().await;
+        let config = crate::config::Config {
+            brand_name: "Acme Mail".to_string(),
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+        provide_meta_context();
+
+        let brand_name = get_brand_name().await.unwrap();
+        let brand_name = Signal::derive(move || brand_name.clone());
+        _ = view! {
+            <Title formatter=title_formatter(brand_name)/>
+            <Title text="Aliases"/>
+        }
+        .into_view()
+        .render_to_string();
+        let head = leptos_meta::use_head().dehydrate();
+        assert!(head.contains("<title>Aliases · Acme Mail</title>"), "{head}");
+        runtime.dispose();
+    }
+}
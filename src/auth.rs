@@ -1,10 +1,10 @@
 use leptos::*;
 use leptos_icons::Icon;
-use leptos_router::{ActionForm, Redirect};
+use leptos_router::{ActionForm, Redirect, A};
 use leptos_use::ColorMode;
 use serde::{Deserialize, Serialize};
 
-use crate::utils::ColorModeToggle;
+use crate::{app::BrandName, totp::TOTP_REQUIRED_ERROR, users::PasswordPolicy, utils::ColorModeToggle};
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
@@ -21,8 +21,49 @@ pub struct User {
     pub active: bool,
 }
 
+/// What an API token may be used for. Tokens without explicit scopes may do everything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ApiScope {
+    AliasCreate,
+    AliasRead,
+    AliasUpdate,
+    AliasDelete,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 4] = [
+        ApiScope::AliasCreate,
+        ApiScope::AliasRead,
+        ApiScope::AliasUpdate,
+        ApiScope::AliasDelete,
+    ];
+
+    /// The name under which the scope is stored
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiScope::AliasCreate => "alias:create",
+            ApiScope::AliasRead => "alias:read",
+            ApiScope::AliasUpdate => "alias:update",
+            ApiScope::AliasDelete => "alias:delete",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.as_str() == scope)
+    }
+
+    /// Parses the stored scopes of a token. Tokens from before scopes existed keep full access.
+    pub fn parse_stored(scopes: Option<&str>) -> Vec<Self> {
+        match scopes {
+            None => Self::ALL.to_vec(),
+            Some(scopes) => scopes.split(',').filter_map(Self::parse).collect(),
+        }
+    }
+}
+
 #[cfg(feature = "ssr")]
 pub mod ssr {
+    use super::ApiScope;
     pub use super::User;
     use anyhow::{anyhow, Context};
     pub use axum_session_auth::{Authentication, HasPermission};
@@ -32,6 +73,97 @@ pub mod ssr {
     pub type AuthSession = axum_session_auth::AuthSession<User, String, SessionSqlitePool, SqlitePool>;
     pub use async_trait::async_trait;
 
+    /// The configuration of the session store
+    pub fn session_config(config: &crate::config::Config) -> axum_session::SessionConfig {
+        let session_config = axum_session::SessionConfig::default().with_table_name("axum_sessions");
+        // Optionally expire sessions after a period of inactivity. Saving the session on
+        // every request moves its expiry forward, so only idle sessions run out.
+        match config.session_idle_timeout {
+            Some(timeout) => session_config
+                .with_lifetime(timeout)
+                .with_memory_lifetime(timeout.min(chrono::Duration::minutes(60)))
+                .with_always_save(true),
+            None => session_config,
+        }
+    }
+
+    /// Prefix of API tokens that are stored as a hash instead of the raw value
+    const HASHED_API_TOKEN_PREFIX: &str = "sha256:";
+
+    /// Hashes an API token for storage. API tokens are random and long enough,
+    /// so a plain unsalted hash still allows looking them up directly.
+    pub fn hash_api_token(api_token: &str) -> String {
+        use sha2::{Digest, Sha256};
+        format!(
+            "{HASHED_API_TOKEN_PREFIX}{}",
+            hex::encode(Sha256::digest(api_token.as_bytes()))
+        )
+    }
+
+    /// The name of the token that provisioning and admins store for a mailbox, which is also
+    /// the name of the single token mailboxes had before tokens could be named
+    pub const ACCOUNT_TOKEN_NAME: &str = "Account token";
+
+    /// The `account_kind` of the API tokens of the given account
+    pub fn api_token_account_kind(user: &User) -> &'static str {
+        if user.mailbox_owner.is_some() {
+            "mailbox"
+        } else {
+            "user"
+        }
+    }
+
+    /// Moves the tokens that were stored on mailboxes before tokens could be named into `api_tokens`,
+    /// hashing the plaintext ones. Runs once on startup.
+    pub async fn move_legacy_api_tokens(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query_as::<_, (String, String)>(
+            "SELECT address, api_token FROM mailboxes WHERE api_token IS NOT NULL",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for (address, api_token) in rows {
+            let token_hash = if api_token.starts_with(HASHED_API_TOKEN_PREFIX) {
+                api_token
+            } else {
+                hash_api_token(&api_token)
+            };
+            sqlx::query(
+                "INSERT INTO api_tokens (account, account_kind, name, token_hash) VALUES (?, 'mailbox', ?, ?) \
+                ON CONFLICT (token_hash) DO NOTHING",
+            )
+            .bind(&address)
+            .bind(ACCOUNT_TOKEN_NAME)
+            .bind(token_hash)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("UPDATE mailboxes SET api_token = NULL WHERE address = ?")
+                .bind(&address)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    /// The id and name of the token, the account and the scopes of an API token
+    type ApiTokenRow = (i64, String, String, String, Option<String>, bool, bool, Option<String>);
+
+    /// Records a successful login of the account. This happens in the background,
+    /// so auditing never slows down or fails the login.
+    pub fn record_login(pool: SqlitePool, user: &User) {
+        let sql = if user.mailbox_owner.is_some() {
+            "UPDATE mailboxes SET last_login_at = CURRENT_TIMESTAMP WHERE address = ?"
+        } else {
+            "UPDATE users SET last_login_at = CURRENT_TIMESTAMP WHERE username = ?"
+        };
+        let username = user.username.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sqlx::query(sql).bind(&username).execute(&pool).await {
+                log::error!("failed to record login of '{username}': {e}");
+            }
+        });
+    }
+
     impl User {
         pub async fn get(username: &str, pool: &SqlitePool) -> Option<Self> {
             let user = sqlx::query_as::<_, User>(
@@ -48,20 +180,39 @@ pub mod ssr {
             Some(user)
         }
 
-        pub async fn get_by_api_token(api_token: &str, pool: &SqlitePool) -> Option<Self> {
+        /// Returns the account the API token belongs to, together with the scopes and the name of the token.
+        pub async fn get_by_api_token(api_token: &str, pool: &SqlitePool) -> Option<(Self, Vec<ApiScope>, String)> {
             if api_token.len() < 16 {
                 // Disregard insecure API tokens directly
                 return None;
             }
 
-            let user = sqlx::query_as::<_, User>(
-                "SELECT address AS username, password_hash, owner AS mailbox_owner, FALSE AS admin, active \
-                FROM mailboxes WHERE api_token = $1",
+            // The account kind tells users and mailboxes apart, which could otherwise share a name
+            let (id, name, user, scopes) = sqlx::query_as::<_, ApiTokenRow>(
+                "SELECT t.id, t.name, u.username, u.password_hash, NULL AS mailbox_owner, u.admin, u.active, t.scopes \
+                FROM api_tokens t JOIN users u ON t.account_kind = 'user' AND u.username = t.account \
+                WHERE t.token_hash = $1 \
+                UNION ALL SELECT t.id, t.name, m.address AS username, m.password_hash, m.owner AS mailbox_owner, \
+                FALSE AS admin, m.active, t.scopes \
+                FROM api_tokens t JOIN mailboxes m ON t.account_kind = 'mailbox' AND m.address = t.account \
+                WHERE t.token_hash = $1",
             )
-            .bind(api_token)
+            .bind(hash_api_token(api_token))
             .fetch_one(pool)
             .await
-            .ok()?;
+            .ok()
+            .map(
+                |(id, name, username, password_hash, mailbox_owner, admin, active, scopes)| {
+                    let user = User {
+                        username,
+                        password_hash,
+                        mailbox_owner,
+                        admin,
+                        active,
+                    };
+                    (id, name, user, scopes)
+                },
+            )?;
 
             if !user.active {
                 log::warn!(
@@ -71,7 +222,19 @@ pub mod ssr {
                 return None;
             }
 
-            Some(user)
+            // Recorded in the background, so auditing never slows down or fails the request
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                if let Err(e) = sqlx::query("UPDATE api_tokens SET last_used_at = CURRENT_TIMESTAMP WHERE id = ?")
+                    .bind(id)
+                    .execute(&pool)
+                    .await
+                {
+                    log::error!("failed to record use of api token {id}: {e}");
+                }
+            });
+
+            Some((user, ApiScope::parse_stored(scopes.as_deref()), name))
         }
     }
 
@@ -106,10 +269,19 @@ pub mod ssr {
     }
 }
 
+/// Get the currently logged in user. Users that were deactivated
+/// while being logged in are logged out on their next request.
 #[server]
 pub async fn get_user() -> Result<Option<User>, ServerFnError> {
     let auth = crate::database::ssr::auth()?;
-    Ok(auth.current_user)
+    match auth.current_user.clone() {
+        Some(user) if !user.active => {
+            log::warn!("logging out user '{}' because it is inactive", user.username);
+            auth.logout_user();
+            Ok(None)
+        }
+        user => Ok(user),
+    }
 }
 
 /// Get the current user and ensure that it is an admin
@@ -140,41 +312,173 @@ pub async fn auth_any() -> Result<User, ServerFnError> {
     get_user().await?.ok_or_else(|| ServerFnError::new("Unauthorized"))
 }
 
+/// Whether sensitive actions (like creating API tokens) require
+/// the user to re-enter their current password.
 #[server]
-pub async fn authenticate_user(username: String, password: String) -> Result<User, ServerFnError> {
+pub async fn reauthentication_required() -> Result<bool, ServerFnError> {
+    Ok(crate::database::ssr::config()?.reauth_sensitive_actions)
+}
+
+/// Re-verifies the password of the given user before a sensitive action,
+/// if this is required by the configuration.
+#[cfg(feature = "ssr")]
+pub async fn reauthenticate_if_required(username: &str, current_password: String) -> Result<(), ServerFnError> {
+    if reauthentication_required().await? {
+        let _ = verify_current_password(username, &current_password).await?;
+    }
+    Ok(())
+}
+
+/// A generic error message to not leak information to the clients
+#[cfg(feature = "ssr")]
+const WRONG_CREDENTIALS_ERROR: &str = "Wrong password or invalid user.";
+/// Returned for password logins if only single sign-on is allowed
+#[cfg(feature = "ssr")]
+const PASSWORD_LOGIN_DISABLED_ERROR: &str = "Password login is disabled, please sign in with SSO.";
+
+/// Verifies only the password of the given user. This is enough to confirm the identity of
+/// a user that is already logged in, new logins must go through `authenticate_user`.
+#[cfg(feature = "ssr")]
+pub async fn verify_password(username: &str, password: &str) -> Result<User, ServerFnError> {
     use argon2::{
         password_hash::{PasswordHash, PasswordVerifier},
         Argon2,
     };
 
-    // A generic error message to not leak information to the clients
-    let generic_err = || ServerFnError::new("Wrong password or invalid user.");
+    let generic_err = || ServerFnError::new(WRONG_CREDENTIALS_ERROR);
 
     let pool = crate::database::ssr::pool()?;
-    let user = User::get(&username, &pool).await.ok_or_else(generic_err)?;
+    let user = User::get(username, &pool).await.ok_or_else(generic_err)?;
 
     let verify_result = PasswordHash::new(&user.password_hash)
         .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash));
-    if verify_result.is_ok() {
-        if !user.active {
-            log::warn!("denying successful login attempt because user '{username}' is inactive");
-            return Err(generic_err());
+    if let Err(e) = verify_result {
+        log::warn!("failed authentication of user '{username}': {e}");
+        return Err(generic_err());
+    }
+    if !user.active {
+        log::warn!("denying successful login attempt because user '{username}' is inactive");
+        return Err(generic_err());
+    }
+
+    Ok(user)
+}
+
+/// The address of the client of the current request, if known
+#[cfg(feature = "ssr")]
+async fn client_ip() -> Option<std::net::IpAddr> {
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    leptos_axum::extract::<ConnectInfo<SocketAddr>>()
+        .await
+        .ok()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Verifies the password of a logged in user before a sensitive action. Failures count
+/// towards the same lockout as failed logins, so this can't be used to guess passwords
+/// without the limits of the login form.
+#[cfg(feature = "ssr")]
+pub async fn verify_current_password(username: &str, password: &str) -> Result<User, ServerFnError> {
+    let started = std::time::Instant::now();
+    let limiter = crate::database::ssr::login_limiter()?;
+    let ip = client_ip().await;
+
+    if limiter.is_locked(username, ip) {
+        log::warn!("rejecting password confirmation of user '{username}': too many failed attempts");
+        limiter.delay_failure(started).await;
+        return Err(ServerFnError::new(WRONG_CREDENTIALS_ERROR));
+    }
+
+    match verify_password(username, password).await {
+        Ok(user) => {
+            limiter.reset(username, ip);
+            Ok(user)
+        }
+        Err(e) => {
+            if limiter.record_failure(username, ip) {
+                log::warn!("locking out user '{username}' after too many failed password confirmations");
+            }
+            limiter.delay_failure(started).await;
+            Err(e)
         }
+    }
+}
+
+/// Verifies the password of the given user and, if two-factor authentication
+/// is enabled for the account, the given TOTP code.
+#[server]
+pub async fn authenticate_user(
+    username: String,
+    password: String,
+    totp_code: Option<String>,
+) -> Result<User, ServerFnError> {
+    use crate::totp::{TOTP_INVALID_ERROR, TOTP_REQUIRED_ERROR};
+
+    let password_login_disabled = || {
+        log::warn!("rejecting password login of user '{username}': only single sign-on is allowed");
+        ServerFnError::new(PASSWORD_LOGIN_DISABLED_ERROR)
+    };
+    // Only the admin account that is recreated on startup can still use its password
+    let config = crate::database::ssr::config()?;
+    let break_glass_only = config.password_login_disabled();
+    if break_glass_only && username != config.admin_username {
+        return Err(password_login_disabled());
+    }
+
+    let started = std::time::Instant::now();
+    let limiter = crate::database::ssr::login_limiter()?;
+    let ip = client_ip().await;
+    let client = ip.map_or_else(|| "unknown address".to_string(), |x| x.to_string());
 
-        log::info!("login successful for user '{username}'");
-        Ok(user)
-    } else {
-        log::warn!(
-            "failed authentication of user '{username}': {}",
-            verify_result.unwrap_err()
-        );
-        Err(generic_err())
+    if limiter.is_locked(&username, ip) {
+        log::warn!("rejecting login attempt for user '{username}' from {client}: too many failed attempts");
+        limiter.delay_failure(started).await;
+        return Err(ServerFnError::new(WRONG_CREDENTIALS_ERROR));
     }
+    let record_failure = || {
+        if limiter.record_failure(&username, ip) {
+            log::warn!("locking out user '{username}' from {client} after too many failed login attempts");
+        }
+    };
+
+    let user = match verify_password(&username, &password).await {
+        Ok(user) => user,
+        Err(e) => {
+            record_failure();
+            limiter.delay_failure(started).await;
+            return Err(e);
+        }
+    };
+    if break_glass_only && !user.admin {
+        return Err(password_login_disabled());
+    }
+
+    let pool = crate::database::ssr::pool()?;
+    if crate::totp::ssr::enabled(&pool, &username).await? {
+        let Some(code) = totp_code.filter(|x| !x.trim().is_empty()) else {
+            return Err(ServerFnError::new(TOTP_REQUIRED_ERROR));
+        };
+        let key = crate::database::ssr::secret_key()?;
+        if !crate::totp::ssr::verify_code(&pool, &key, &user, &code, chrono::Utc::now()).await? {
+            log::warn!("failed authentication of user '{username}': invalid two-factor code");
+            record_failure();
+            limiter.delay_failure(started).await;
+            return Err(ServerFnError::new(TOTP_INVALID_ERROR));
+        }
+    }
+
+    limiter.reset(&username, ip);
+    log::info!("login successful for user '{username}'");
+
+    ssr::record_login(pool, &user);
+    Ok(user)
 }
 
 #[server]
-pub async fn login(username: String, password: String) -> Result<(), ServerFnError> {
-    let user = authenticate_user(username.clone(), password.clone()).await?;
+pub async fn login(username: String, password: String, totp_code: Option<String>) -> Result<(), ServerFnError> {
+    let user = authenticate_user(username.clone(), password.clone(), totp_code).await?;
     let auth = crate::database::ssr::auth()?;
 
     auth.login_user(user.username);
@@ -191,6 +495,178 @@ pub async fn logout() -> Result<(), ServerFnError> {
     Ok(())
 }
 
+/// What a public signup creates. Configured by IDMAIL_SIGNUP, which is
+/// either `user`, `mailbox` or unset to disable self-registration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignupMode {
+    Disabled,
+    /// Signups create a user, which can own domains and mailboxes
+    User,
+    /// Signups create a mailbox on one of the public domains
+    Mailbox,
+}
+
+/// Optional features enabled by the server configuration,
+/// so the frontend and API clients can adapt to them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// What a public signup creates, if enabled
+    pub signup: SignupMode,
+    /// Whether sensitive actions require re-entering the password
+    pub reauth_sensitive_actions: bool,
+    /// Whether non-admin users may create mailboxes
+    pub user_mailbox_creation: bool,
+    /// Whether mailboxes can opt in to weekly activity digests
+    pub digests: bool,
+    /// Whether entries managed by the provisioning file may be changed
+    pub edit_provisioned: bool,
+    /// Whether the statistics cards are shown above the alias list
+    pub stats: bool,
+    /// Additional requirements for new passwords
+    pub password_policy: PasswordPolicy,
+    /// For how many days deleted aliases can be restored
+    pub alias_trash_days: u32,
+    /// Whether users can sign in via OpenID Connect
+    pub oidc: bool,
+    /// Whether users can sign in with their password, which may be reserved to the break-glass
+    /// admin if single sign-on is mandatory
+    pub password_login: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl Capabilities {
+    pub fn new(config: &crate::config::Config) -> Self {
+        Self {
+            signup: config.signup,
+            reauth_sensitive_actions: config.reauth_sensitive_actions,
+            user_mailbox_creation: config.user_mailbox_creation,
+            digests: config.digest_from.is_some(),
+            edit_provisioned: config.allow_editing_provisioned,
+            stats: config.stats,
+            password_policy: config.password_policy,
+            alias_trash_days: config.alias_trash_days,
+            oidc: config.oidc.is_some(),
+            password_login: !config.password_login_disabled(),
+        }
+    }
+}
+
+#[server]
+pub async fn get_capabilities() -> Result<Capabilities, ServerFnError> {
+    Ok(Capabilities::new(&*crate::database::ssr::config()?))
+}
+
+#[server]
+pub async fn get_signup_mode() -> Result<SignupMode, ServerFnError> {
+    Ok(crate::database::ssr::config()?.signup)
+}
+
+/// The public domains on which mailboxes can be created by signing up.
+#[server]
+pub async fn signup_domains() -> Result<Vec<String>, ServerFnError> {
+    if get_signup_mode().await? != SignupMode::Mailbox {
+        return Ok(Vec::new());
+    }
+
+    let pool = crate::database::ssr::pool()?;
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT domain FROM domains WHERE public = TRUE AND active = TRUE ORDER BY domain",
+    )
+    .fetch_all(&pool)
+    .await?)
+}
+
+/// Creates the user or mailbox of a signup and returns the name to log in with
+#[cfg(feature = "ssr")]
+async fn create_signup_account(
+    pool: &sqlx::SqlitePool,
+    config: &crate::config::Config,
+    username: &str,
+    domain: &str,
+    password: &str,
+) -> Result<String, ServerFnError> {
+    use crate::aliases::check_globally_reserved;
+    use crate::users::mk_password_hash;
+    use crate::utils::ValidationErrors;
+
+    let taken = || ServerFnError::new(format!("'{username}' is already taken"));
+
+    let username = match config.signup {
+        SignupMode::Disabled => return Err(ServerFnError::new("Signup is disabled")),
+        SignupMode::User => {
+            if username.is_empty() || username.contains('@') {
+                return Err(ServerFnError::new("username must not be empty or contain an '@'"));
+            }
+            check_globally_reserved(config, username, false).map_err(ServerFnError::new)?;
+            if User::get(username, pool).await.is_some() {
+                return Err(taken());
+            }
+
+            let password_hash = mk_password_hash(password)?;
+            sqlx::query("INSERT INTO users (username, password_hash, admin, active) VALUES (?, ?, FALSE, TRUE)")
+                .bind(username)
+                .bind(password_hash)
+                .execute(pool)
+                .await
+                .map_err(|_| taken())?;
+            crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Users);
+
+            username.to_string()
+        }
+        SignupMode::Mailbox => {
+            if !signup_domains().await?.iter().any(|x| x == domain) {
+                return Err(ServerFnError::new("domain must be set to a public domain"));
+            }
+            // The same normalization and duplicate checks as for mailboxes created by their owner
+            let address = crate::mailboxes::check_new_address(pool, config, None, username, domain, false, false)
+                .await
+                .map_err(|e| match ValidationErrors::from_server_error(&e) {
+                    Some(errors) => {
+                        ServerFnError::new(errors.0.into_iter().map(|x| x.message).collect::<Vec<_>>().join(", "))
+                    }
+                    None => e,
+                })?;
+
+            // The mailbox belongs to the owner of the public domain
+            let password_hash = mk_password_hash(password)?;
+            let result = sqlx::query(
+                "INSERT INTO mailboxes (address, domain, password_hash, active, owner) \
+                SELECT ?1, domain, ?2, TRUE, owner FROM domains WHERE domain = ?3 \
+                AND NOT EXISTS (SELECT * FROM aliases WHERE address = ?1) \
+                AND NOT EXISTS (SELECT * FROM users WHERE username = ?1)",
+            )
+            .bind(&address)
+            .bind(password_hash)
+            .bind(domain)
+            .execute(pool)
+            .await
+            .map_err(|_| taken())?;
+            if result.rows_affected() == 0 {
+                return Err(taken());
+            }
+            crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Mailboxes);
+
+            address
+        }
+    };
+    Ok(username)
+}
+
+#[server]
+pub async fn signup(username: String, domain: String, password: String) -> Result<(), ServerFnError> {
+    let pool = crate::database::ssr::pool()?;
+    let config = crate::database::ssr::config()?;
+    let username = create_signup_account(&pool, &config, username.trim(), &domain, &password).await?;
+
+    log::info!("signup of '{username}' successful");
+    let auth = crate::database::ssr::auth()?;
+    auth.login_user(username);
+    auth.remember_user(false);
+    leptos_axum::redirect("/");
+    Ok(())
+}
+
 #[component]
 pub fn Login(
     action: Action<Login, Result<(), ServerFnError>>,
@@ -198,6 +674,17 @@ pub fn Login(
     set_color_mode: WriteSignal<ColorMode>,
 ) -> impl IntoView {
     let action_value = Signal::derive(move || action.value().get().unwrap_or(Ok(())));
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let BrandName(brand_name) = expect_context::<BrandName>();
+    // Ask for a TOTP code once the server reported that the account needs one
+    let needs_totp = create_memo(move |prev: Option<&bool>| {
+        prev.copied().unwrap_or(false)
+            || matches!(action.value().get(), Some(Err(e)) if e.to_string().contains(TOTP_REQUIRED_ERROR))
+    });
+    // If only single sign-on is allowed, the password form is only shown on request for the break-glass admin
+    let (break_glass, set_break_glass) = create_signal(false);
+    let password_login =
+        move || break_glass() || capabilities.get().and_then(|x| x.ok()).is_none_or(|x| x.password_login);
 
     view! {
         <div class="relative flex min-h-screen flex-col">
@@ -209,7 +696,7 @@ pub fn Login(
                 <div class="flex flex-col mx-auto">
                     <div class="mx-auto mb-4 flex flex-row items-center">
                         <img class="w-16 h-16 me-2" src="/logo.svg"/>
-                        <h2 class="text-4xl leading-none font-bold inline-block">idmail</h2>
+                        <h2 class="text-4xl leading-none font-bold inline-block">{brand_name}</h2>
                     </div>
                     <ActionForm
                         action
@@ -223,71 +710,119 @@ pub fn Login(
                         </div>
                         <div class="p-6 pt-0">
                             <div class="grid gap-4">
-                                <div class="grid gap-2">
-                                    <label
-                                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
-                                        for="username"
-                                    >
-                                        Email
-                                    </label>
-                                    <input
-                                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
-                                        type="text"
-                                        name="username"
-                                        placeholder="username@example.com"
-                                        required="required"
-                                    />
-                                </div>
-                                <div class="grid gap-2">
-                                    <div class="flex items-center">
+                                <Show when=password_login>
+                                    <div class="grid gap-2">
                                         <label
                                             class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
-                                            for="password"
+                                            for="username"
                                         >
-                                            Password
+                                            Email
                                         </label>
+                                        <input
+                                            class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                                            type="text"
+                                            name="username"
+                                            placeholder="username@example.com"
+                                            required="required"
+                                        />
                                     </div>
-                                    <input
-                                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
-                                        type="password"
-                                        name="password"
-                                        required="required"
-                                    />
-                                </div>
-                                <ErrorBoundary fallback=|errors| {
-                                    view! {
-                                        <div class="rounded-lg p-4 flex bg-red-100 dark:bg-red-800">
-                                            <div>
-                                                <Icon
-                                                    icon=icondata::BiXCircleSolid
-                                                    class="w-5 h-5 text-red-400 dark:text-red-300"
-                                                />
-                                            </div>
-                                            <div class="ml-3 text-red-700 dark:text-red-300">
-                                                <p>
-                                                    {move || {
-                                                        errors
-                                                            .get()
-                                                            .into_iter()
-                                                            .map(|(_, e)| view! { {e.to_string()} })
-                                                            .collect_view()
-                                                    }}
-
-                                                </p>
-                                            </div>
+                                    <div class="grid gap-2">
+                                        <div class="flex items-center">
+                                            <label
+                                                class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                                                for="password"
+                                            >
+                                                Password
+                                            </label>
                                         </div>
-                                    }
-                                }>
+                                        <input
+                                            class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                                            type="password"
+                                            name="password"
+                                            required="required"
+                                        />
+                                    </div>
+                                    <Show when=needs_totp>
+                                        <div class="grid gap-2">
+                                            <label
+                                                class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                                                for="totp_code"
+                                            >
+                                                "Two-factor code"
+                                            </label>
+                                            <input
+                                                class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                                                type="text"
+                                                name="totp_code"
+                                                inputmode="numeric"
+                                                autocomplete="one-time-code"
+                                                placeholder="123456"
+                                                maxlength="6"
+                                                required="required"
+                                            />
+                                        </div>
+                                    </Show>
+                                    <ErrorBoundary fallback=|errors| {
+                                        view! {
+                                            <div class="rounded-lg p-4 flex bg-red-100 dark:bg-red-800">
+                                                <div>
+                                                    <Icon
+                                                        icon=icondata::BiXCircleSolid
+                                                        class="w-5 h-5 text-red-400 dark:text-red-300"
+                                                    />
+                                                </div>
+                                                <div class="ml-3 text-red-700 dark:text-red-300">
+                                                    <p>
+                                                        {move || {
+                                                            errors
+                                                                .get()
+                                                                .into_iter()
+                                                                .map(|(_, e)| view! { {e.to_string()} })
+                                                                .collect_view()
+                                                        }}
+
+                                                    </p>
+                                                </div>
+                                            </div>
+                                        }
+                                    }>
 
-                                    {action_value}
-                                </ErrorBoundary>
-                                <button
-                                    type="submit"
-                                    tabindex="0"
-                                    class="inline-flex w-full justify-center mt-3 items-center rounded-lg transition-all p-2.5 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto"
-                                >
-                                    Login
-                                </button>
+                                        {action_value}
+                                    </ErrorBoundary>
+                                    <button
+                                        type="submit"
+                                        tabindex="0"
+                                        class="inline-flex w-full justify-center mt-3 items-center rounded-lg transition-all p-2.5 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto"
+                                    >
+                                        Login
+                                    </button>
+                                </Show>
+                                <Show when=move || capabilities.get().and_then(|x| x.ok()).is_some_and(|x| x.oidc)>
+                                    <a
+                                        href="/auth/oidc/login"
+                                        rel="external"
+                                        class="inline-flex w-full justify-center items-center rounded-lg transition-all p-2.5 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-800 font-semibold focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900"
+                                    >
+                                        <Icon icon=icondata::FiLogIn class="w-5 h-5 me-2"/>
+                                        "Sign in with SSO"
+                                    </a>
+                                </Show>
+                                <Show when=move || !password_login()>
+                                    <button
+                                        type="button"
+                                        class="text-sm text-center text-gray-500 dark:text-gray-400 hover:underline"
+                                        on:click=move |_| set_break_glass(true)
+                                    >
+                                        "Admin login with password"
+                                    </button>
+                                </Show>
+                                <Show when=move || {
+                                    capabilities.get().and_then(|x| x.ok()).is_some_and(|x| x.signup != SignupMode::Disabled)
+                                }>
+                                    <A href="/signup" class="text-sm text-center text-gray-500 dark:text-gray-400 hover:underline">
+                                        "No account yet? Sign up"
+                                    </A>
+                                </Show>
                             </div>
                         </div>
                     </ActionForm>
@@ -297,6 +832,150 @@ pub fn Login(
     }
 }
 
+#[component]
+pub fn Signup(color_mode: Signal<ColorMode>, set_color_mode: WriteSignal<ColorMode>) -> impl IntoView {
+    let action = create_server_action::<Signup>();
+    let action_value = Signal::derive(move || action.value().get().unwrap_or(Ok(())));
+    let mode = create_resource(|| (), |_| get_signup_mode());
+    let domains = create_resource(|| (), |_| signup_domains());
+    let BrandName(brand_name) = expect_context::<BrandName>();
+
+    view! {
+        <div class="relative flex min-h-screen flex-col">
+            <div class="absolute top-4 right-4">
+                <ColorModeToggle color_mode set_color_mode/>
+            </div>
+            <div class="w-full h-screen flex items-center justify-center px-4">
+
+                <div class="flex flex-col mx-auto">
+                    <div class="mx-auto mb-4 flex flex-row items-center">
+                        <img class="w-16 h-16 me-2" src="/logo.svg"/>
+                        <h2 class="text-4xl leading-none font-bold inline-block">{brand_name}</h2>
+                    </div>
+                    <Transition fallback=move || {
+                        view! { <span class="text-gray-300 dark:text-gray-600">"Loading..."</span> }
+                    }>
+                        {move || match mode.get() {
+                            None => view! {}.into_view(),
+                            Some(Ok(SignupMode::Disabled)) | Some(Err(_)) => view! { <Redirect path="/login"/> }.into_view(),
+                            Some(Ok(mode)) => {
+                                view! {
+                                    <ActionForm
+                                        action
+                                        class="rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 text-card-foreground max-w-sm"
+                                    >
+                                        <div class="flex flex-col space-y-1.5 p-6">
+                                            <h2 class="font-semibold tracking-tight text-2xl mb-2">Sign up</h2>
+                                            <p class="text-sm text-gray-500 dark:text-gray-400">
+                                                {if mode == SignupMode::Mailbox {
+                                                    "Choose an address and a password for your new mailbox"
+                                                } else {
+                                                    "Choose a username and a password for your new account"
+                                                }}
+
+                                            </p>
+                                        </div>
+                                        <div class="p-6 pt-0">
+                                            <div class="grid gap-4">
+                                                <div class="grid gap-2">
+                                                    <label class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70" for="username">
+                                                        {if mode == SignupMode::Mailbox { "Address" } else { "Username" }}
+                                                    </label>
+                                                    <div class="flex flex-row items-center">
+                                                        <input
+                                                            class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                                                            type="text"
+                                                            name="username"
+                                                            required="required"
+                                                        />
+                                                        <Show when=move || mode == SignupMode::Mailbox>
+                                                            <span class="inline-flex flex-none text-base items-center mx-2">@</span>
+                                                            <select
+                                                                class="rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900"
+                                                                name="domain"
+                                                            >
+                                                                {move || {
+                                                                    domains
+                                                                        .get()
+                                                                        .and_then(|x| x.ok())
+                                                                        .unwrap_or_default()
+                                                                        .into_iter()
+                                                                        .map(|x| view! { <option value=x.clone()>{x}</option> })
+                                                                        .collect_view()
+                                                                }}
+
+                                                            </select>
+                                                        </Show>
+                                                        <Show when=move || mode != SignupMode::Mailbox>
+                                                            <input type="hidden" name="domain" value=""/>
+                                                        </Show>
+                                                    </div>
+                                                </div>
+                                                <div class="grid gap-2">
+                                                    <label class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70" for="password">
+                                                        Password
+                                                    </label>
+                                                    <input
+                                                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                                                        type="password"
+                                                        name="password"
+                                                        required="required"
+                                                        minlength="12"
+                                                        maxlength="1024"
+                                                    />
+                                                </div>
+                                                <ErrorBoundary fallback=|errors| {
+                                                    view! {
+                                                        <div class="rounded-lg p-4 flex bg-red-100 dark:bg-red-800">
+                                                            <div>
+                                                                <Icon
+                                                                    icon=icondata::BiXCircleSolid
+                                                                    class="w-5 h-5 text-red-400 dark:text-red-300"
+                                                                />
+                                                            </div>
+                                                            <div class="ml-3 text-red-700 dark:text-red-300">
+                                                                <p>
+                                                                    {move || {
+                                                                        errors
+                                                                            .get()
+                                                                            .into_iter()
+                                                                            .map(|(_, e)| view! { {e.to_string()} })
+                                                                            .collect_view()
+                                                                    }}
+
+                                                                </p>
+                                                            </div>
+                                                        </div>
+                                                    }
+                                                }>
+
+                                                    {action_value}
+                                                </ErrorBoundary>
+                                                <button
+                                                    type="submit"
+                                                    tabindex="0"
+                                                    class="inline-flex w-full justify-center mt-3 items-center rounded-lg transition-all p-2.5 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto"
+                                                >
+                                                    Sign up
+                                                </button>
+                                                <A href="/login" class="text-sm text-center text-gray-500 dark:text-gray-400 hover:underline">
+                                                    "Already have an account? Login"
+                                                </A>
+                                            </div>
+                                        </div>
+                                    </ActionForm>
+                                }
+                                    .into_view()
+                            }
+                        }}
+
+                    </Transition>
+                </div>
+            </div>
+        </div>
+    }
+}
+
 #[component]
 pub fn LoginView(
     login: Action<Login, Result<(), ServerFnError>>,
@@ -333,3 +1012,304 @@ pub fn LoginView(
         </Transition>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::{config::Config, testing::*};
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    async fn set_password(pool: &sqlx::SqlitePool, username: &str) {
+        sqlx::query("UPDATE users SET password_hash = ? WHERE username = ?")
+            .bind(crate::users::hash_password(PASSWORD).unwrap())
+            .bind(username)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn sso_only_config() -> Config {
+        Config {
+            failed_login_delay: std::time::Duration::ZERO,
+            oidc: Some(crate::config::OidcConfig {
+                issuer_url: "https://sso.example.com".to_string(),
+                client_id: "idmail".to_string(),
+                client_secret: "secret".to_string(),
+                redirect_url: "https://idmail.example.com/auth/oidc/callback".to_string(),
+                auto_provision: false,
+                disable_password_login: true,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn password_login_can_be_reserved_to_the_break_glass_admin() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "other-admin", true).await;
+        insert_user(&db, "alice", false).await;
+        for username in ["admin", "other-admin", "alice"] {
+            set_password(&db, username).await;
+        }
+        let runtime = server_context(&app_state(&db, sso_only_config()));
+
+        let login = |username: &str| authenticate_user(username.to_string(), PASSWORD.to_string(), None);
+        let err = login("alice").await.unwrap_err();
+        assert!(err.to_string().contains(PASSWORD_LOGIN_DISABLED_ERROR), "{err}");
+        assert!(login("other-admin").await.is_err());
+        assert_eq!(login("admin").await.unwrap().username, "admin");
+
+        // The break-glass account loses its exception once it isn't an admin anymore
+        sqlx::query("UPDATE users SET admin = FALSE WHERE username = 'admin'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert!(login("admin").await.is_err());
+
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn password_login_stays_enabled_by_default() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        set_password(&db, "alice").await;
+        let mut config = sso_only_config();
+        config.oidc.as_mut().unwrap().disable_password_login = false;
+        let runtime = server_context(&app_state(&db, config));
+
+        let user = authenticate_user("alice".to_string(), PASSWORD.to_string(), None).await;
+        assert_eq!(user.unwrap().username, "alice");
+
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn password_confirmations_are_rate_limited() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        sqlx::query("UPDATE users SET password_hash = ? WHERE username = 'alice'")
+            .bind(crate::users::hash_password("correct horse battery staple").unwrap())
+            .execute(&*db)
+            .await
+            .unwrap();
+        let config = Config {
+            failed_login_delay: std::time::Duration::ZERO,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+
+        assert!(verify_current_password("alice", "correct horse battery staple")
+            .await
+            .is_ok());
+        for _ in 0..5 {
+            assert!(verify_current_password("alice", "wrong").await.is_err());
+        }
+        // Locked out, even with the right password
+        assert!(verify_current_password("alice", "correct horse battery staple")
+            .await
+            .is_err());
+
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn failed_logins_take_at_least_the_configured_delay() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        set_password(&db, "alice").await;
+        let delay = std::time::Duration::from_millis(100);
+        let config = Config {
+            failed_login_delay: delay,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+
+        // Unknown users and wrong passwords are delayed alike, so their timing doesn't differ
+        for (username, password) in [("alice", "wrong"), ("bob", PASSWORD)] {
+            let started = std::time::Instant::now();
+            assert!(authenticate_user(username.to_string(), password.to_string(), None)
+                .await
+                .is_err());
+            assert!(started.elapsed() >= delay, "{username}");
+        }
+
+        // The delay doesn't lock anyone out
+        assert!(authenticate_user("alice".to_string(), PASSWORD.to_string(), None)
+            .await
+            .is_ok());
+
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn tokens_belong_to_the_kind_of_account_they_were_created_for() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "me@example.com", "alice").await;
+        insert_api_token(&db, "me@example.com", "cli", "mailbox-token-0123456789").await;
+        // A user named like the mailbox, which can only be created by editing the database directly
+        sqlx::query("INSERT INTO users (username, password_hash, admin) VALUES ('me@example.com', '', TRUE)")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let (user, _, name) = User::get_by_api_token("mailbox-token-0123456789", &db).await.unwrap();
+        assert_eq!(user.mailbox_owner.as_deref(), Some("alice"));
+        assert!(!user.admin);
+        assert_eq!(name, "cli");
+    }
+
+    #[tokio::test]
+    async fn legacy_mailbox_tokens_are_moved_and_hashed() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "plain@example.com", "alice").await;
+        insert_mailbox(&db, "hashed@example.com", "alice").await;
+        for (address, stored) in [
+            ("plain@example.com", "plain-token-0123456789".to_string()),
+            ("hashed@example.com", ssr::hash_api_token("hashed-token-0123456789")),
+        ] {
+            sqlx::query("UPDATE mailboxes SET api_token = ? WHERE address = ?")
+                .bind(stored)
+                .bind(address)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        ssr::move_legacy_api_tokens(&db).await.unwrap();
+        let legacy = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM mailboxes WHERE api_token IS NOT NULL")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(legacy, 0);
+        let stored = sqlx::query_scalar::<_, String>("SELECT token_hash FROM api_tokens")
+            .fetch_all(&*db)
+            .await
+            .unwrap();
+        assert!(stored.iter().all(|x| x.starts_with("sha256:")));
+
+        for (address, token) in [
+            ("plain@example.com", "plain-token-0123456789"),
+            ("hashed@example.com", "hashed-token-0123456789"),
+        ] {
+            let (user, _, name) = User::get_by_api_token(token, &db).await.unwrap();
+            assert_eq!(user.username, address);
+            assert_eq!(name, ssr::ACCOUNT_TOKEN_NAME);
+        }
+        // The stored hash itself is no token
+        assert!(User::get_by_api_token(&stored[0], &db).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn user_signups_reject_addresses_and_taken_names() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        let config = Config {
+            signup: SignupMode::User,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config.clone()));
+
+        let signup = |username: &'static str| create_signup_account(&db, &config, username, "", PASSWORD);
+        assert_eq!(signup("bob").await.unwrap(), "bob");
+        assert!(User::get("bob", &db)
+            .await
+            .is_some_and(|x| !x.admin && x.mailbox_owner.is_none()));
+        assert!(signup("bob@example.com").await.is_err());
+        assert!(signup("").await.is_err());
+        assert!(signup("alice").await.unwrap_err().to_string().contains("already taken"));
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn mailbox_signups_are_checked_like_other_mailboxes() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "private.com", "alice").await;
+        sqlx::query("UPDATE domains SET public = TRUE WHERE domain = 'example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        insert_alias(&db, "taken@example.com", "someone@elsewhere.com", "alice").await;
+        let config = Config {
+            signup: SignupMode::Mailbox,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config.clone()));
+
+        let signup = |username: &'static str, domain: &'static str| {
+            create_signup_account(&db, &config, username, domain, PASSWORD)
+        };
+        // Localparts of case-insensitive domains are lowercased like for mailboxes created by their owner
+        assert_eq!(signup("Bob", "example.com").await.unwrap(), "bob@example.com");
+        let owner = sqlx::query_scalar::<_, String>("SELECT owner FROM mailboxes WHERE address = 'bob@example.com'")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(owner, "alice");
+        // Duplicates are found regardless of case, in aliases as well as mailboxes
+        assert!(signup("BOB", "example.com").await.is_err());
+        assert!(signup("Taken", "example.com").await.is_err());
+        assert!(signup("carol", "private.com").await.is_err());
+        assert!(signup("in valid", "example.com").await.is_err());
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_expire_unless_they_are_used() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        let config = Config {
+            session_idle_timeout: Some(chrono::Duration::seconds(2)),
+            ..Default::default()
+        };
+        let router = session_router(&app_state(&db, config)).await;
+        let idle = |millis| tokio::time::sleep(std::time::Duration::from_millis(millis));
+
+        let (_, mut cookies) = session_get(&router, "/login/alice", "").await;
+        // Each request extends the session, so it outlives the timeout while in use
+        for _ in 0..3 {
+            idle(1200).await;
+            let (username, next) = session_get(&router, "/user", &cookies).await;
+            assert_eq!(username, "alice");
+            cookies = next;
+        }
+
+        idle(3000).await;
+        let (username, _) = session_get(&router, "/user", &cookies).await;
+        assert_eq!(username, "");
+    }
+
+    #[tokio::test]
+    async fn deactivated_users_are_logged_out_on_their_next_request() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        let router = session_router(&app_state(&db, Config::default())).await;
+
+        let (_, cookies) = session_get(&router, "/login/alice", "").await;
+        let (username, cookies) = session_get(&router, "/user", &cookies).await;
+        assert_eq!(username, "alice");
+
+        sqlx::query("UPDATE users SET active = FALSE WHERE username = 'alice'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let (username, cookies) = session_get(&router, "/user", &cookies).await;
+        assert_eq!(username, "");
+
+        // The session stays logged out, even once the user is active again
+        sqlx::query("UPDATE users SET active = TRUE WHERE username = 'alice'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let (username, _) = session_get(&router, "/user", &cookies).await;
+        assert_eq!(username, "");
+    }
+}
@@ -0,0 +1,64 @@
+use std::{future::Future, time::Duration};
+
+use crate::ttl_cache::TtlCache;
+
+/// The maximum number of counts that are cached at once
+const MAX_ENTRIES: usize = 10000;
+
+/// The tables whose counts are cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CountTable {
+    Aliases,
+    Mailboxes,
+    Domains,
+    Users,
+}
+
+/// Identifies a count. Counts are scoped to what the user can see,
+/// so the user is always part of the key and counts never leak across owners.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    table: CountTable,
+    username: String,
+    /// All further filters of the count, in any unambiguous representation
+    filter: String,
+}
+
+/// Caches the row counts of the tables in the web interface for a few seconds. Each reload of
+/// a table needs its count in addition to the rows, and the dashboard shows several counts,
+/// so this saves many full scans on large databases. The cache is kept in memory and shared
+/// across requests via the `AppState`. Any change to a table must call [`CountCache::invalidate`].
+#[derive(Debug, Clone)]
+pub struct CountCache(TtlCache<Key, usize>);
+
+impl CountCache {
+    /// Creates a cache whose entries expire after the given duration. A zero duration disables caching.
+    pub fn new(ttl: Duration) -> Self {
+        Self(TtlCache::new(ttl, MAX_ENTRIES))
+    }
+
+    /// Returns the cached count, or runs the query and caches its result if there is none.
+    pub async fn get_or_load<F, E>(
+        &self,
+        table: CountTable,
+        username: &str,
+        filter: String,
+        load: F,
+    ) -> Result<usize, E>
+    where
+        F: Future<Output = Result<usize, E>>,
+    {
+        let key = Key {
+            table,
+            username: username.to_string(),
+            filter,
+        };
+        self.0.get_or_load(key, load).await
+    }
+
+    /// Forgets all cached counts of the table. Must be called after rows were added,
+    /// removed or changed in a way that affects any of the filters.
+    pub fn invalidate(&self, table: CountTable) {
+        self.0.invalidate_matching(|key| key.table == table);
+    }
+}
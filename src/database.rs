@@ -1,8 +1,19 @@
 #[cfg(feature = "ssr")]
 pub mod ssr {
-    use crate::auth::ssr::AuthSession;
+    use crate::{
+        auth::{ssr::AuthSession, User},
+        config::Config,
+        count_cache::CountCache,
+        domain_cache::AllowedDomainsCache,
+        rate_limit::LoginLimiter,
+        totp::ssr::SecretKey,
+    };
     use leptos::{use_context, ServerFnError};
-    use sqlx::SqlitePool;
+    use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
 
     pub fn pool() -> Result<SqlitePool, ServerFnError> {
         use_context::<SqlitePool>().ok_or_else(|| ServerFnError::ServerError("Pool missing.".into()))
@@ -11,4 +22,405 @@ pub mod ssr {
     pub fn auth() -> Result<AuthSession, ServerFnError> {
         use_context::<AuthSession>().ok_or_else(|| ServerFnError::ServerError("Auth session missing.".into()))
     }
+
+    pub fn login_limiter() -> Result<LoginLimiter, ServerFnError> {
+        use_context::<LoginLimiter>().ok_or_else(|| ServerFnError::ServerError("Login limiter missing.".into()))
+    }
+
+    pub fn allowed_domains_cache() -> Result<AllowedDomainsCache, ServerFnError> {
+        use_context::<AllowedDomainsCache>()
+            .ok_or_else(|| ServerFnError::ServerError("Allowed domains cache missing.".into()))
+    }
+
+    pub fn count_cache() -> Result<CountCache, ServerFnError> {
+        use_context::<CountCache>().ok_or_else(|| ServerFnError::ServerError("Count cache missing.".into()))
+    }
+
+    pub fn config() -> Result<Arc<Config>, ServerFnError> {
+        use_context::<Arc<Config>>().ok_or_else(|| ServerFnError::ServerError("Config missing.".into()))
+    }
+
+    pub fn secret_key() -> Result<SecretKey, ServerFnError> {
+        use_context::<SecretKey>().ok_or_else(|| ServerFnError::ServerError("Secret key missing.".into()))
+    }
+
+    /// Whether queries are logged, set once from [`Config::log_queries`] at startup.
+    /// Queries are also built outside of requests, where the configuration is not available as a context.
+    static LOG_QUERIES: AtomicBool = AtomicBool::new(false);
+
+    pub fn set_log_queries(config: &Config) {
+        LOG_QUERIES.store(config.log_queries, Ordering::Relaxed);
+    }
+
+    /// Logs the SQL of a query before it is built, if `IDMAIL_LOG_QUERIES` is enabled.
+    /// Only the statement with its placeholders is logged, so bound values like
+    /// password hashes or API tokens never end up in the log.
+    pub trait LogQuery {
+        fn logged(&mut self) -> &mut Self;
+    }
+
+    impl LogQuery for QueryBuilder<'_, Sqlite> {
+        fn logged(&mut self) -> &mut Self {
+            if LOG_QUERIES.load(Ordering::Relaxed) {
+                log::debug!("sql: {}", self.sql());
+            }
+            self
+        }
+    }
+
+    /// The kinds of entities that are scoped by their owner
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum OwnerScope {
+        Aliases,
+        Mailboxes,
+        Domains,
+    }
+
+    /// Restricts a query to the rows of the given entity kind that the user may access.
+    /// Admins can access everything. Aliases are also accessible through the mailboxes
+    /// a user owns, while mailbox accounts only ever see themselves (or their own aliases).
+    /// This appends an `AND` clause, so the query must already contain a `WHERE`.
+    pub fn push_owner_scope(query: &mut QueryBuilder<'_, Sqlite>, user: &User, scope: OwnerScope) {
+        if user.admin {
+            return;
+        }
+
+        match scope {
+            OwnerScope::Aliases => push_own_aliases_scope(query, user),
+            OwnerScope::Mailboxes if user.mailbox_owner.is_some() => {
+                query.push(" AND address = ");
+                query.push_bind(user.username.clone());
+            }
+            OwnerScope::Mailboxes | OwnerScope::Domains => {
+                query.push(" AND owner = ");
+                query.push_bind(user.username.clone());
+            }
+        }
+    }
+
+    /// Restricts a query to the aliases owned by the user directly or through one of their mailboxes.
+    /// Unlike [`push_owner_scope`], this also applies to admins, so bulk actions never reach into
+    /// the aliases of other users.
+    pub fn push_own_aliases_scope(query: &mut QueryBuilder<'_, Sqlite>, user: &User) {
+        query.push(" AND ( owner = ");
+        query.push_bind(user.username.clone());
+        query.push(" OR owner IN ( SELECT address from mailboxes WHERE owner = ");
+        query.push_bind(user.username.clone());
+        query.push(" ) )");
+    }
+
+    /// Appends the `ORDER BY` clause generated from the table sorting. The given unique key column is
+    /// always added as the last sort key, so that rows which compare equal on all sorted columns
+    /// still come in a stable order and don't shift between pages.
+    pub fn push_order_by(query: &mut QueryBuilder<'_, Sqlite>, order: Option<String>, key_column: &str) {
+        match order {
+            Some(order) => {
+                query.push(" ");
+                query.push(order);
+                query.push(", ");
+            }
+            None => {
+                query.push(" ORDER BY ");
+            }
+        }
+        query.push(key_column);
+    }
+
+    /// The kinds of entities that can be managed by the provisioning file
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Provisioned {
+        Users,
+        Mailboxes,
+        Domains,
+        Aliases,
+    }
+
+    /// Rejects changes to an entry that is managed by the provisioning file, because they would
+    /// silently be reverted the next time it is applied. Entries that don't exist or are not
+    /// accessible by the user are not rejected, the actual change will then affect nothing.
+    pub async fn ensure_not_provisioned(
+        pool: &SqlitePool,
+        user: &User,
+        kind: Provisioned,
+        key: &str,
+    ) -> Result<(), ServerFnError> {
+        if config()?.allow_editing_provisioned {
+            return Ok(());
+        }
+
+        let (table, key_column, scope) = match kind {
+            Provisioned::Users => ("users", "username", None),
+            Provisioned::Mailboxes => ("mailboxes", "address", Some(OwnerScope::Mailboxes)),
+            Provisioned::Domains => ("domains", "domain", Some(OwnerScope::Domains)),
+            Provisioned::Aliases => ("aliases", "address", Some(OwnerScope::Aliases)),
+        };
+        let mut query = QueryBuilder::new(format!("SELECT provisioned FROM {table} WHERE {key_column} = "));
+        query.push_bind(key.to_string());
+        if let Some(scope) = scope {
+            push_owner_scope(&mut query, user, scope);
+        }
+        let provisioned = query
+            .logged()
+            .build_query_scalar::<bool>()
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(false);
+
+        if provisioned {
+            return Err(ServerFnError::new(format!(
+                "'{key}' is managed by the provisioning file, please change it there instead"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Backs up the database next to the original file if the migrator has migrations
+    /// that weren't applied yet. Fresh databases are skipped. Returns the path of the backup.
+    pub async fn backup_if_migrations_pending(
+        pool: &SqlitePool,
+        migrator: &sqlx::migrate::Migrator,
+        database: &std::path::Path,
+    ) -> anyhow::Result<Option<String>> {
+        let initialized = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+        )
+        .fetch_one(pool)
+        .await?
+            > 0;
+        if !initialized {
+            return Ok(None);
+        }
+
+        let applied = sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        let pending = migrator
+            .iter()
+            .filter(|x| !x.migration_type.is_down_migration() && !applied.contains(&x.version))
+            .count();
+        if pending == 0 {
+            return Ok(None);
+        }
+
+        let path = format!(
+            "{}.{}.bak",
+            database.display(),
+            chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+        );
+        log::info!("{pending} pending migration(s), backing up database to '{path}'");
+        sqlx::query("VACUUM INTO ?").bind(&path).execute(pool).await?;
+        Ok(Some(path))
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::ssr::*;
+    use crate::testing::*;
+    use sqlx::{QueryBuilder, Sqlite};
+
+    /// Returns the details of the query plan, which name the indexes that are used.
+    /// All parameters of the query are bound to the given username.
+    async fn query_plan(pool: &sqlx::SqlitePool, query: QueryBuilder<'_, Sqlite>, username: &str) -> String {
+        let sql = format!("EXPLAIN QUERY PLAN {}", query.sql());
+        let mut plan = sqlx::query_as::<_, (i64, i64, i64, String)>(&sql);
+        for _ in 0..sql.matches('?').count() {
+            plan = plan.bind(username);
+        }
+        let plan = plan.fetch_all(pool).await.unwrap();
+        plan.into_iter()
+            .map(|(_, _, _, detail)| detail)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[tokio::test]
+    async fn owner_scopes_use_the_indexes() {
+        let db = TestDb::new().await;
+        let scoped = |table: &str, scope: OwnerScope| {
+            let mut query = QueryBuilder::new(format!("SELECT COUNT(*) FROM {table} WHERE TRUE"));
+            push_owner_scope(&mut query, &user("alice"), scope);
+            query
+        };
+
+        // The owner check of aliases also looks up the mailboxes of the user
+        let plan = query_plan(&db, scoped("aliases", OwnerScope::Aliases), "alice").await;
+        assert!(plan.contains("USING COVERING INDEX aliases_owner"), "{plan}");
+        assert!(plan.contains("USING COVERING INDEX mailboxes_owner"), "{plan}");
+        assert!(!plan.contains("SCAN"), "{plan}");
+
+        let plan = query_plan(&db, scoped("mailboxes", OwnerScope::Mailboxes), "alice").await;
+        assert!(plan.contains("INDEX mailboxes_owner"), "{plan}");
+        let plan = query_plan(&db, scoped("domains", OwnerScope::Domains), "alice").await;
+        assert!(plan.contains("INDEX domains_owner"), "{plan}");
+
+        let public = QueryBuilder::new("SELECT domain FROM domains WHERE active = TRUE AND public = TRUE");
+        let plan = query_plan(&db, public, "alice").await;
+        assert!(plan.contains("INDEX domains_active_public"), "{plan}");
+    }
+
+    #[tokio::test]
+    async fn owner_scopes_follow_the_same_rules_for_all_entities() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        for username in ["alice", "bob"] {
+            insert_user(&db, username, false).await;
+            let domain = format!("{username}.com");
+            let mailbox = format!("me@{domain}");
+            insert_domain(&db, &domain, username).await;
+            insert_mailbox(&db, &mailbox, username).await;
+            insert_alias(&db, &format!("user@{domain}"), &mailbox, username).await;
+            insert_alias(&db, &format!("mailbox@{domain}"), &mailbox, &mailbox).await;
+        }
+
+        let visible = |user: crate::auth::User, table: &'static str, key: &'static str, scope: OwnerScope| {
+            let db = &db;
+            async move {
+                let mut query = QueryBuilder::new(format!("SELECT {key} FROM {table} WHERE TRUE"));
+                push_owner_scope(&mut query, &user, scope);
+                query.push(format!(" ORDER BY {key}"));
+                query.build_query_scalar::<String>().fetch_all(&**db).await.unwrap()
+            }
+        };
+        let all = |user: fn() -> crate::auth::User| async move {
+            (
+                visible(user(), "aliases", "address", OwnerScope::Aliases).await,
+                visible(user(), "mailboxes", "address", OwnerScope::Mailboxes).await,
+                visible(user(), "domains", "domain", OwnerScope::Domains).await,
+            )
+        };
+
+        // Users see everything they own, including the aliases of their mailboxes
+        let (aliases, mailboxes, domains) = all(|| user("alice")).await;
+        assert_eq!(aliases, ["mailbox@alice.com", "user@alice.com"]);
+        assert_eq!(mailboxes, ["me@alice.com"]);
+        assert_eq!(domains, ["alice.com"]);
+        // Mailboxes only see themselves and their own aliases, never the entities of their owner
+        let (aliases, mailboxes, domains) = all(|| mailbox("me@alice.com", "alice")).await;
+        assert_eq!(aliases, ["mailbox@alice.com"]);
+        assert_eq!(mailboxes, ["me@alice.com"]);
+        assert!(domains.is_empty());
+        // Admins see everything
+        let (aliases, mailboxes, domains) = all(|| admin("admin")).await;
+        assert_eq!(aliases.len(), 4);
+        assert_eq!(mailboxes, ["me@alice.com", "me@bob.com"]);
+        assert_eq!(domains, ["alice.com", "bob.com"]);
+    }
+
+    /// The backup files next to the database of the test
+    fn backups(db: &TestDb) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(db.path().parent().unwrap())
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().is_some_and(|x| x == "bak"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn backups_are_only_made_if_migrations_are_pending() {
+        let db = TestDb::new().await;
+        let migrator = sqlx::migrate!();
+        let database = db.path();
+        let backup = || backup_if_migrations_pending(&db, &migrator, &database);
+        assert_eq!(backup().await.unwrap(), None);
+        assert!(backups(&db).is_empty());
+
+        // Pretend that the latest migration is new
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let path = backup().await.unwrap().unwrap();
+        assert_eq!(backups(&db), [std::path::PathBuf::from(&path)]);
+
+        // The backup contains the data as it was before migrating
+        let backup_pool = sqlx::SqlitePool::connect(&format!("sqlite://{path}")).await.unwrap();
+        let migrations: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations")
+            .fetch_one(&backup_pool)
+            .await
+            .unwrap();
+        assert_eq!(migrations as usize, migrator.iter().count() - 1);
+    }
+
+    #[tokio::test]
+    async fn fresh_databases_are_not_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("fresh.db");
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(&database)
+            .create_if_missing(true);
+        let pool = sqlx::SqlitePool::connect_with(options).await.unwrap();
+        let backup = backup_if_migrations_pending(&pool, &sqlx::migrate!(), &database).await;
+        assert_eq!(backup.unwrap(), None);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn provisioned_entries_are_protected_unless_allowed() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "managed.com", "alice").await;
+        insert_domain(&db, "manual.com", "alice").await;
+        sqlx::query("UPDATE domains SET provisioned = TRUE WHERE domain = 'managed.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let alice = user("alice");
+
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        let err = ensure_not_provisioned(&db, &alice, Provisioned::Domains, "managed.com")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("provisioning file"), "{err}");
+        assert!(ensure_not_provisioned(&db, &alice, Provisioned::Domains, "manual.com")
+            .await
+            .is_ok());
+        // Entries of other users are left to the scope of the actual change
+        assert!(
+            ensure_not_provisioned(&db, &user("bob"), Provisioned::Domains, "managed.com")
+                .await
+                .is_ok()
+        );
+        runtime.dispose();
+
+        let config = crate::config::Config {
+            allow_editing_provisioned: true,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config));
+        assert!(ensure_not_provisioned(&db, &alice, Provisioned::Domains, "managed.com")
+            .await
+            .is_ok());
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn password_binds_are_not_logged() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        let config = crate::config::Config {
+            log_queries: true,
+            ..Default::default()
+        };
+        let runtime = server_context(&app_state(&db, config.clone()));
+        capture_logs();
+        set_log_queries(&config);
+
+        let password_hash = crate::users::mk_password_hash("correct horse battery staple").unwrap();
+        let mut query = QueryBuilder::new("UPDATE users SET password_hash = ");
+        query.push_bind(&password_hash);
+        query.push(" WHERE username = ");
+        query.push_bind("alice");
+        query.logged().build().execute(&*db).await.unwrap();
+        set_log_queries(&Default::default());
+
+        let logs = captured_logs();
+        assert!(logs
+            .iter()
+            .any(|x| x == "sql: UPDATE users SET password_hash = ? WHERE username = ?"));
+        assert!(!logs.iter().any(|x| x.contains(&password_hash)));
+        runtime.dispose();
+    }
 }
@@ -0,0 +1,268 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use lettre::AsyncTransport;
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+
+/// How often a digest is sent to each opted-in mailbox
+const DIGEST_INTERVAL_DAYS: i64 = 7;
+/// The number of aliases listed as top receivers
+const TOP_RECEIVERS: usize = 5;
+
+/// The statistics a digest is generated from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DigestStats {
+    /// Aliases created since the last digest
+    pub new_aliases: Vec<String>,
+    /// Aliases that received their first mail since the last digest
+    pub newly_active: Vec<String>,
+    /// The aliases that received the most mail since the last digest, with their counts
+    pub top_receivers: Vec<(String, i64)>,
+}
+
+/// An alias of a mailbox that was created or received mail since the last digest
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AliasActivity {
+    pub address: String,
+    /// Whether the alias was created since the last digest
+    pub new: bool,
+    pub n_recv: i64,
+    /// The received counter as of the last digest
+    pub digest_n_recv: i64,
+}
+
+impl AliasActivity {
+    /// The number of mails received since the last digest
+    fn received(&self) -> i64 {
+        (self.n_recv - self.digest_n_recv).max(0)
+    }
+}
+
+impl DigestStats {
+    pub fn from_activity(activity: &[AliasActivity]) -> Self {
+        let new_aliases = activity.iter().filter(|x| x.new).map(|x| x.address.clone()).collect();
+        let newly_active = activity
+            .iter()
+            .filter(|x| x.digest_n_recv == 0 && x.n_recv > 0)
+            .map(|x| x.address.clone())
+            .collect();
+
+        let mut top_receivers: Vec<_> = activity
+            .iter()
+            .filter(|x| x.received() > 0)
+            .map(|x| (x.address.clone(), x.received()))
+            .collect();
+        top_receivers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_receivers.truncate(TOP_RECEIVERS);
+
+        Self {
+            new_aliases,
+            newly_active,
+            top_receivers,
+        }
+    }
+}
+
+/// Renders the plain-text body of a digest
+pub fn render_digest(mailbox: &str, stats: &DigestStats) -> String {
+    let mut body = format!("Weekly alias activity for {mailbox}\n");
+
+    let mut section = |title: &str, lines: Vec<String>| {
+        body.push_str(&format!("\n{title} ({}):\n", lines.len()));
+        if lines.is_empty() {
+            body.push_str("  none\n");
+        }
+        for line in lines {
+            body.push_str(&format!("  {line}\n"));
+        }
+    };
+    section("New aliases", stats.new_aliases.clone());
+    section("Newly active aliases", stats.newly_active.clone());
+    section(
+        "Top receivers",
+        stats
+            .top_receivers
+            .iter()
+            .map(|(alias, n_recv)| format!("{alias}: {n_recv}"))
+            .collect(),
+    );
+
+    body
+}
+
+/// Loads the aliases forwarding to the mailbox that were created or received mail since the given time
+async fn alias_activity(pool: &SqlitePool, mailbox: &str, since: DateTime<Utc>) -> Result<Vec<AliasActivity>> {
+    Ok(sqlx::query_as(
+        "SELECT address, datetime(created_at) >= datetime(?1) AS new, n_recv, digest_n_recv FROM aliases \
+        WHERE target = ?2 AND (datetime(created_at) >= datetime(?1) OR n_recv != digest_n_recv) ORDER BY address",
+    )
+    .bind(since)
+    .bind(mailbox)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Sends a digest to every opted-in mailbox whose last digest is older than the interval
+async fn send_due_digests<T>(pool: &SqlitePool, mailer: &T, from: &str) -> Result<()>
+where
+    T: AsyncTransport + Sync,
+    T::Error: std::error::Error + Send + Sync + 'static,
+{
+    let now = Utc::now();
+    let due_before = now - Duration::days(DIGEST_INTERVAL_DAYS);
+    let mailboxes: Vec<(String, Option<DateTime<Utc>>)> = sqlx::query_as(
+        "SELECT address, digest_last_sent FROM mailboxes \
+        WHERE digest = TRUE AND active = TRUE AND (digest_last_sent IS NULL OR digest_last_sent < ?)",
+    )
+    .bind(due_before)
+    .fetch_all(pool)
+    .await?;
+
+    for (mailbox, last_sent) in mailboxes {
+        let activity = alias_activity(pool, &mailbox, last_sent.unwrap_or(due_before)).await?;
+        let body = render_digest(&mailbox, &DigestStats::from_activity(&activity));
+        let message = crate::mail::message(from, &mailbox, "Your weekly alias digest", body)?;
+        if let Err(e) = mailer.send(message).await {
+            log::error!("failed to send digest to '{mailbox}': {e}");
+            continue;
+        }
+
+        // Only the counts that were reported are marked, so mails received meanwhile appear in the next digest
+        let mut tx = pool.begin().await?;
+        for alias in activity.iter().filter(|x| x.n_recv != x.digest_n_recv) {
+            sqlx::query("UPDATE aliases SET digest_n_recv = ? WHERE address = ?")
+                .bind(alias.n_recv)
+                .bind(&alias.address)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("UPDATE mailboxes SET digest_last_sent = ? WHERE address = ?")
+            .bind(now)
+            .bind(&mailbox)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        log::info!("sent weekly digest to '{mailbox}'");
+    }
+
+    Ok(())
+}
+
+/// Starts the background task sending weekly digests, if digests are configured
+pub fn spawn(pool: SqlitePool, config: &Config) {
+    let (Some(from), Some(smtp_url)) = (config.digest_from.clone(), config.smtp_url.as_deref()) else {
+        return;
+    };
+    let mailer = match crate::mail::mailer(smtp_url) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            log::error!("failed to create the mailer for digests: {e}");
+            return;
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = send_due_digests(&pool, &mailer, &from).await {
+                log::error!("failed to send digests: {e}");
+            }
+        }
+    });
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+    use lettre::transport::stub::AsyncStubTransport;
+
+    fn activity(address: &str, new: bool, n_recv: i64, digest_n_recv: i64) -> AliasActivity {
+        AliasActivity {
+            address: address.to_string(),
+            new,
+            n_recv,
+            digest_n_recv,
+        }
+    }
+
+    #[test]
+    fn digest_reports_the_activity_of_the_period() {
+        let stats = DigestStats::from_activity(&[
+            activity("fresh@example.com", true, 0, 0),
+            activity("first@example.com", false, 2, 0),
+            activity("busy@example.com", false, 1003, 1000),
+            activity("quiet@example.com", false, 50, 50),
+        ]);
+        assert_eq!(
+            stats,
+            DigestStats {
+                new_aliases: vec!["fresh@example.com".to_string()],
+                newly_active: vec!["first@example.com".to_string()],
+                top_receivers: vec![
+                    ("busy@example.com".to_string(), 3),
+                    ("first@example.com".to_string(), 2)
+                ],
+            }
+        );
+
+        let body = render_digest("bob@example.com", &stats);
+        assert_eq!(
+            body,
+            "Weekly alias activity for bob@example.com\n\
+            \nNew aliases (1):\n  fresh@example.com\n\
+            \nNewly active aliases (1):\n  first@example.com\n\
+            \nTop receivers (2):\n  busy@example.com: 3\n  first@example.com: 2\n"
+        );
+    }
+
+    #[test]
+    fn empty_digest_says_so() {
+        let body = render_digest("bob@example.com", &DigestStats::default());
+        assert_eq!(body.matches("  none\n").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn digests_report_each_mail_once() {
+        let db = TestDb::new().await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "bob").await;
+        insert_mailbox(&db, "bob@example.com", "bob").await;
+        insert_alias(&db, "old@example.com", "bob@example.com", "bob").await;
+        let set_n_recv = |n_recv: i64| {
+            sqlx::query("UPDATE aliases SET n_recv = ? WHERE address = 'old@example.com'")
+                .bind(n_recv)
+                .execute(&*db)
+        };
+        sqlx::query(
+            "UPDATE aliases SET created_at = datetime('now', '-30 days'), n_recv = 10, digest_n_recv = 10; \
+            UPDATE mailboxes SET digest = TRUE",
+        )
+        .execute(&*db)
+        .await
+        .unwrap();
+        set_n_recv(14).await.unwrap();
+
+        let mailer = AsyncStubTransport::new_ok();
+        send_due_digests(&db, &mailer, "idmail@example.com").await.unwrap();
+        let messages = mailer.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].1.contains("old@example.com: 4"));
+
+        // The next digest is only due in a week and starts counting from here
+        set_n_recv(15).await.unwrap();
+        send_due_digests(&db, &mailer, "idmail@example.com").await.unwrap();
+        assert_eq!(mailer.messages().await.len(), 1);
+        sqlx::query("UPDATE mailboxes SET digest_last_sent = ?")
+            .bind(Utc::now() - Duration::days(8))
+            .execute(&*db)
+            .await
+            .unwrap();
+        send_due_digests(&db, &mailer, "idmail@example.com").await.unwrap();
+        let messages = mailer.messages().await;
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].1.contains("old@example.com: 1"));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::utils::Modal;
+
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{push_owner_scope, LogQuery, OwnerScope};
+use leptos::{html::Dialog, *};
+use leptos_icons::Icon;
+use leptos_use::use_timeout_fn;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ssr")]
+use sqlx::QueryBuilder;
+
+/// A DNS record that is recommended for a domain
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsRecord {
+    /// The fully qualified name of the record
+    pub name: String,
+    /// The record type, such as `MX` or `TXT`
+    pub kind: String,
+    pub value: String,
+    /// Whether the value still has to be filled in by the user
+    pub placeholder: bool,
+}
+
+impl DnsRecord {
+    /// The record in zone file syntax
+    pub fn zone_line(&self) -> String {
+        format!("{}. IN {} {}", self.name, self.kind, self.value)
+    }
+}
+
+/// The result of looking up one of the records of a domain
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsCheck {
+    /// The record type, such as `MX` or `TXT`
+    pub kind: String,
+    pub expected: String,
+    /// The values that were actually found
+    pub found: Vec<String>,
+    /// Whether one of the found values matches the expected one
+    pub matches: bool,
+    /// Set if the lookup failed or timed out, in which case `found` is empty
+    pub error: Option<String>,
+}
+
+/// The live DNS state of a domain compared to the recommended records
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsVerification {
+    pub mx: DnsCheck,
+    pub spf: DnsCheck,
+}
+
+/// The SPF policy recommended for all domains
+#[cfg(feature = "ssr")]
+const SPF_POLICY: &str = "v=spf1 mx -all";
+
+/// How long all lookups of a verification may take in total
+#[cfg(feature = "ssr")]
+const VERIFY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// The hostname of the mailserver that should be the MX of the given domain
+#[cfg(feature = "ssr")]
+pub fn mail_hostname(config: &crate::config::Config, domain: &str) -> String {
+    config
+        .mail_hostname
+        .clone()
+        .unwrap_or_else(|| format!("mail.{domain}"))
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Builds the recommended records for a domain. The MX host and the DKIM selector and
+/// public key come from the configuration, since idmail cannot know the mailserver setup.
+#[cfg(feature = "ssr")]
+fn recommended_records(config: &crate::config::Config, domain: &str) -> Vec<DnsRecord> {
+    let mail_host = mail_hostname(config, domain);
+    let dkim_selector = &config.dkim_selector;
+    let dkim_public_key = &config.dkim_public_key;
+
+    vec![
+        DnsRecord {
+            name: domain.to_string(),
+            kind: "MX".to_string(),
+            value: format!("10 {mail_host}."),
+            placeholder: false,
+        },
+        DnsRecord {
+            name: domain.to_string(),
+            kind: "TXT".to_string(),
+            value: format!("\"{SPF_POLICY}\""),
+            placeholder: false,
+        },
+        DnsRecord {
+            name: format!("_dmarc.{domain}"),
+            kind: "TXT".to_string(),
+            value: format!("\"v=DMARC1; p=quarantine; rua=mailto:postmaster@{domain}\""),
+            placeholder: false,
+        },
+        DnsRecord {
+            name: format!("{dkim_selector}._domainkey.{domain}"),
+            kind: "TXT".to_string(),
+            value: format!(
+                "\"v=DKIM1; k=rsa; p={}\"",
+                dkim_public_key.as_deref().unwrap_or("<your DKIM public key>")
+            ),
+            placeholder: dkim_public_key.is_none(),
+        },
+    ]
+}
+
+/// Ensures that the given domain exists and is owned by the current user.
+#[cfg(feature = "ssr")]
+async fn auth_domain(domain: &str) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM domains WHERE domain = ");
+    query.push_bind(domain);
+    push_owner_scope(&mut query, &user, OwnerScope::Domains);
+    if query.logged().build_query_scalar::<i64>().fetch_one(&pool).await? == 0 {
+        return Err(ServerFnError::new(format!("Domain '{domain}' not found")));
+    }
+
+    Ok(())
+}
+
+/// Returns the recommended DNS records for the given domain, which must be owned by the user.
+#[server]
+pub async fn dns_records(domain: String) -> Result<Vec<DnsRecord>, ServerFnError> {
+    auth_domain(&domain).await?;
+    Ok(recommended_records(&*crate::database::ssr::config()?, &domain))
+}
+
+/// Builds a check from the outcome of a lookup. Failed lookups are reported
+/// on the check itself, so that the other checks can still succeed.
+#[cfg(feature = "ssr")]
+fn dns_check(
+    kind: &str,
+    expected: String,
+    lookup: Result<Result<Vec<String>, hickory_resolver::error::ResolveError>, tokio::time::error::Elapsed>,
+    matches: impl Fn(&str) -> bool,
+) -> DnsCheck {
+    use hickory_resolver::error::ResolveErrorKind;
+
+    let (found, error) = match lookup {
+        Ok(Ok(found)) => (found, None),
+        Ok(Err(e)) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => (Vec::new(), None),
+        Ok(Err(e)) => (Vec::new(), Some(e.to_string())),
+        Err(_) => (Vec::new(), Some("Lookup timed out".to_string())),
+    };
+    DnsCheck {
+        kind: kind.to_string(),
+        matches: found.iter().any(|x| matches(x)),
+        expected,
+        found,
+        error,
+    }
+}
+
+/// Looks up the MX and SPF records of the given domain, which must be owned by the user,
+/// and compares them to the recommended ones. Lookups that don't finish within
+/// five seconds are reported as timed out instead of failing the whole verification.
+#[server]
+pub async fn verify_domain_dns(domain: String) -> Result<DnsVerification, ServerFnError> {
+    use hickory_resolver::TokioAsyncResolver;
+
+    auth_domain(&domain).await?;
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+    // Query the domain as an absolute name, so no search domains are appended
+    let fqdn = format!("{domain}.");
+
+    let deadline = tokio::time::Instant::now() + VERIFY_TIMEOUT;
+    let mx_lookup = tokio::time::timeout_at(deadline, async {
+        resolver.mx_lookup(fqdn.as_str()).await.map(|mx| {
+            mx.iter()
+                .map(|x| format!("{} {}", x.preference(), x.exchange()))
+                .collect::<Vec<_>>()
+        })
+    });
+    let txt_lookup = tokio::time::timeout_at(deadline, async {
+        resolver.txt_lookup(fqdn.as_str()).await.map(|txt| {
+            txt.iter()
+                .map(|x| {
+                    x.txt_data()
+                        .iter()
+                        .map(|part| String::from_utf8_lossy(part))
+                        .collect::<String>()
+                })
+                .filter(|x| x.starts_with("v=spf1"))
+                .collect::<Vec<_>>()
+        })
+    });
+    let (mx_lookup, txt_lookup) = tokio::join!(mx_lookup, txt_lookup);
+
+    let mail_host = mail_hostname(&*crate::database::ssr::config()?, &domain);
+    let mx = dns_check("MX", format!("{mail_host}."), mx_lookup, |found| {
+        found
+            .split_whitespace()
+            .last()
+            .is_some_and(|exchange| exchange.trim_end_matches('.').eq_ignore_ascii_case(&mail_host))
+    });
+    let spf = dns_check("TXT", SPF_POLICY.to_string(), txt_lookup, |found| {
+        found.split_whitespace().eq(SPF_POLICY.split_whitespace())
+    });
+
+    Ok(DnsVerification { mx, spf })
+}
+
+#[component]
+fn CopyButton(text: String) -> impl IntoView {
+    let copied_timer = use_timeout_fn(|_: ()| (), 3000.0);
+    view! {
+        <button
+            class="text-gray-900 dark:text-gray-200 hover:bg-gray-100 dark:hover:bg-gray-700 rounded-lg py-2 px-2.5 inline-flex items-center justify-center bg-white dark:bg-black border-gray-200 border"
+            on:click=move |_ev| {
+                (copied_timer.start)(());
+                let clipboard = window().navigator().clipboard();
+                let _ = clipboard.write_text(&text);
+            }
+        >
+
+            <span class="inline-flex items-center" class=("hidden", copied_timer.is_pending)>
+                <Icon icon=icondata::RiFileCopy2DocumentFill class="w-3 h-3 me-1.5"/>
+                <span class="text-xs font-semibold">Copy</span>
+            </span>
+            <span class="hidden items-center" class=("!inline-flex", copied_timer.is_pending)>
+                <Icon icon=icondata::BiCheckRegular class="w-3 h-3 me-1.5 text-blue-700 dark:text-blue-300"/>
+                <span class="text-xs font-semibold text-blue-700 dark:text-blue-300">Copied</span>
+            </span>
+        </button>
+    }
+}
+
+#[component]
+fn DnsCheckResult(name: &'static str, check: DnsCheck) -> impl IntoView {
+    let (icon, class) = if check.matches {
+        (icondata::BiCheckRegular, "w-5 h-5 text-green-600 dark:text-green-400")
+    } else {
+        (icondata::BiXCircleSolid, "w-5 h-5 text-red-600 dark:text-red-400")
+    };
+    let details = match check.error {
+        Some(error) => error,
+        None if check.found.is_empty() => "No record found".to_string(),
+        None => format!("Found: {}", check.found.join(", ")),
+    };
+    view! {
+        <div class="flex flex-row gap-3 items-center text-sm">
+            <Icon icon class/>
+            <div class="flex-1 min-w-0 break-all">
+                <div class="font-semibold">{name} " (" {check.kind} " " {check.expected} ")"</div>
+                <div class="font-mono text-gray-500 dark:text-gray-400">{details}</div>
+            </div>
+        </div>
+    }
+}
+
+#[component]
+pub fn DnsRecordsModal(#[prop(into)] domain: RwSignal<Option<String>>) -> impl IntoView {
+    let modal_elem = create_node_ref::<Dialog>();
+    let open = Signal::derive(move || domain.get().is_some());
+
+    let records = create_resource(
+        move || domain.get(),
+        move |domain| async move {
+            match domain {
+                Some(domain) => dns_records(domain).await,
+                None => Ok(Vec::new()),
+            }
+        },
+    );
+
+    let (verification, set_verification) = create_signal(None::<Result<DnsVerification, String>>);
+    let (verifying, set_verifying) = create_signal(false);
+    create_effect(move |_| {
+        domain.track();
+        set_verification(None);
+    });
+    let on_verify = move |_ev| {
+        let Some(domain) = domain.get_untracked() else {
+            return;
+        };
+        if verifying.get_untracked() {
+            return;
+        }
+        set_verifying(true);
+        spawn_local(async move {
+            set_verification(Some(verify_domain_dns(domain).await.map_err(|e| e.to_string())));
+            set_verifying(false);
+        });
+    };
+
+    view! {
+        <Modal open dialog_el=modal_elem>
+            <div class="relative p-4 transform overflow-hidden rounded-lg bg-white text-black dark:bg-black dark:text-zinc-100 text-left transition-all w-full sm:min-w-[640px]">
+                <h3 class="text-2xl tracking-tight mt-2 mb-1 font-semibold text-gray-900 dark:text-gray-200">
+                    "DNS records for " {domain}
+                </h3>
+                <p class="text-sm text-gray-500 dark:text-gray-400 mb-4">
+                    "Add these records at your DNS provider so other mailservers accept mail from and deliver mail to this domain. Use Verify to check the MX and SPF records that are currently published."
+                </p>
+                <div class="flex flex-col gap-3">
+                    <Transition fallback=move || {
+                        view! { <span class="text-gray-300 dark:text-gray-600">"Loading..."</span> }
+                    }>
+                        {move || {
+                            records
+                                .get()
+                                .map(|records| match records {
+                                    Err(e) => view! { <p>{e.to_string()}</p> }.into_view(),
+                                    Ok(records) => {
+                                        records
+                                            .into_iter()
+                                            .map(|record| {
+                                                let zone_line = record.zone_line();
+                                                view! {
+                                                    <div class="flex flex-row gap-3 items-center text-sm">
+                                                        <span class="w-10 font-semibold">{record.kind}</span>
+                                                        <div class="flex-1 min-w-0 font-mono break-all">
+                                                            <div class="text-gray-500 dark:text-gray-400">{record.name}</div>
+                                                            <div>{record.value}</div>
+                                                            {record
+                                                                .placeholder
+                                                                .then(|| {
+                                                                    view! {
+                                                                        <div class="font-sans text-yellow-700 dark:text-yellow-200">
+                                                                            "Replace the placeholder with the public key of your mailserver"
+                                                                        </div>
+                                                                    }
+                                                                })}
+                                                        </div>
+                                                        <CopyButton text=zone_line/>
+                                                    </div>
+                                                }
+                                            })
+                                            .collect_view()
+                                    }
+                                })
+                        }}
+
+                    </Transition>
+                </div>
+                {move || {
+                    verification
+                        .get()
+                        .map(|verification| match verification {
+                            Err(e) => view! { <p class="text-sm text-red-600 dark:text-red-400 mt-4">{e}</p> }.into_view(),
+                            Ok(verification) => {
+                                view! {
+                                    <div class="flex flex-col gap-3 mt-4 pt-4 border-t-[1.5px] border-gray-200 dark:border-zinc-800">
+                                        <DnsCheckResult name="MX" check=verification.mx/>
+                                        <DnsCheckResult name="SPF" check=verification.spf/>
+                                    </div>
+                                }
+                                    .into_view()
+                            }
+                        })
+                }}
+
+                <div class="flex flex-col gap-3 sm:flex-row-reverse mt-4">
+                    <button
+                        type="button"
+                        disabled=verifying
+                        class="inline-flex w-full min-w-20 justify-center items-center rounded-lg transition-all px-3 py-2 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white dark:text-zinc-100 focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto"
+                        on:click=on_verify
+                    >
+                        <Show when=verifying>
+                            <Icon icon=icondata::CgSpinner class="inline w-5 h-5 me-2 text-blue-900 animate-spin"/>
+                        </Show>
+                        Verify
+                    </button>
+                    <button
+                        type="button"
+                        class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
+                        on:click=move |_ev| domain.set(None)
+                    >
+                        Close
+                    </button>
+                </div>
+            </div>
+        </Modal>
+    }
+}
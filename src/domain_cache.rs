@@ -0,0 +1,33 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::SqlitePool;
+
+use crate::{auth::User, domains::allowed_domains_for, ttl_cache::TtlCache};
+
+/// The maximum number of users for which domains are cached at once
+const MAX_ENTRIES: usize = 1000;
+
+/// Caches the domains each user may use, since they are suggested whenever an alias or
+/// mailbox is edited, but rarely change. Only used for suggestions, never for authorization.
+/// The cache is kept in memory and shared across requests via the `AppState`.
+/// Any change to the domains table must call [`AllowedDomainsCache::invalidate`].
+#[derive(Debug, Clone)]
+pub struct AllowedDomainsCache(TtlCache<String, Arc<Vec<(String, String)>>>);
+
+impl AllowedDomainsCache {
+    /// Creates a cache whose entries expire after the given duration. A zero duration disables caching.
+    pub fn new(ttl: Duration) -> Self {
+        Self(TtlCache::new(ttl, MAX_ENTRIES))
+    }
+
+    /// Returns the domains the user may use, loading them from the database if they aren't cached.
+    pub async fn get(&self, pool: &SqlitePool, user: &User) -> Result<Arc<Vec<(String, String)>>, sqlx::Error> {
+        let load = async { Ok(Arc::new(allowed_domains_for(pool, user).await?)) };
+        self.0.get_or_load(user.username.clone(), load).await
+    }
+
+    /// Forgets all cached domains. Must be called after domains were changed.
+    pub fn invalidate(&self) {
+        self.0.invalidate_all();
+    }
+}
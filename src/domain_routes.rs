@@ -0,0 +1,519 @@
+use crate::utils::Modal;
+
+#[cfg(feature = "ssr")]
+use crate::auth::User;
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{push_owner_scope, LogQuery, OwnerScope};
+use anyhow::bail;
+use leptos::{html::Dialog, logging::error, *};
+use leptos_icons::Icon;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ssr")]
+use sqlx::{QueryBuilder, SqlitePool};
+
+/// A pattern based route of a domain. Routes are matched in order of
+/// ascending priority before falling back to the catch-all of the domain.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct DomainRoute {
+    pub id: i64,
+    pub domain: String,
+    /// A localpart pattern in which `*` matches any sequence of characters
+    pub pattern: String,
+    pub target: String,
+    pub priority: i64,
+    pub active: bool,
+}
+
+/// Checks that a route pattern only consists of characters that are valid in a
+/// localpart, plus `*` as a wildcard. This also ensures that the pattern has the
+/// same meaning as a sqlite GLOB, which is used by the mailserver queries.
+pub(crate) fn validate_route_pattern(pattern: &str) -> anyhow::Result<()> {
+    if pattern.is_empty() {
+        bail!("pattern must not be empty");
+    }
+    if let Some(c) = pattern
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || "*._+-".contains(*c)))
+    {
+        bail!("pattern must not contain '{c}'");
+    }
+    Ok(())
+}
+
+/// Whether the localpart matches the given route pattern, in which `*`
+/// matches any (possibly empty) sequence of characters.
+#[cfg(feature = "ssr")]
+pub(crate) fn route_matches(pattern: &str, localpart: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = localpart.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Returns the target of the first active route of the address' domain that
+/// matches the address, or otherwise the catch-all of the domain.
+#[cfg(feature = "ssr")]
+pub async fn resolve_route(pool: &SqlitePool, address: &str) -> Result<Option<String>, sqlx::Error> {
+    let Some((localpart, domain)) = address.rsplit_once('@') else {
+        return Ok(None);
+    };
+
+    let routes = sqlx::query_as::<_, (String, String)>(
+        "SELECT pattern, target FROM domain_routes WHERE domain = ? AND active = TRUE ORDER BY priority, id",
+    )
+    .bind(domain)
+    .fetch_all(pool)
+    .await?;
+    if let Some((_, target)) = routes
+        .into_iter()
+        .find(|(pattern, _)| route_matches(pattern, localpart))
+    {
+        return Ok(Some(target));
+    }
+
+    Ok(
+        sqlx::query_scalar::<_, Option<String>>("SELECT catch_all FROM domains WHERE domain = ? AND active = TRUE")
+            .bind(domain)
+            .fetch_optional(pool)
+            .await?
+            .flatten(),
+    )
+}
+
+/// Ensures that the user may manage the routes of the given domain
+#[cfg(feature = "ssr")]
+async fn check_domain_access(pool: &SqlitePool, user: &User, domain: &str) -> Result<(), ServerFnError> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM domains WHERE domain = ");
+    query.push_bind(domain);
+    push_owner_scope(&mut query, user, OwnerScope::Domains);
+    if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? == 0 {
+        return Err(ServerFnError::new("Unauthorized"));
+    }
+    Ok(())
+}
+
+/// Restricts a query on domain_routes to the routes of domains the user owns
+#[cfg(feature = "ssr")]
+fn push_route_scope(query: &mut QueryBuilder<'_, sqlx::Sqlite>, user: &User) {
+    query.push(" AND domain IN (SELECT domain FROM domains WHERE 1=1");
+    push_owner_scope(query, user, OwnerScope::Domains);
+    query.push(")");
+}
+
+#[server]
+pub async fn list_domain_routes(domain: String) -> Result<Vec<DomainRoute>, ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let mut query =
+        QueryBuilder::new("SELECT id, domain, pattern, target, priority, active FROM domain_routes WHERE domain = ");
+    query.push_bind(domain);
+    push_route_scope(&mut query, &user);
+    query.push(" ORDER BY priority, id");
+
+    let pool = crate::database::ssr::pool()?;
+    Ok(query.logged().build_query_as::<DomainRoute>().fetch_all(&pool).await?)
+}
+
+#[server]
+pub async fn create_domain_route(
+    domain: String,
+    pattern: String,
+    target: String,
+    priority: i64,
+) -> Result<(), ServerFnError> {
+    use std::str::FromStr;
+
+    let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    check_domain_access(&pool, &user, &domain).await?;
+
+    let pattern = pattern.trim();
+    validate_route_pattern(pattern).map_err(ServerFnError::new)?;
+    let target = target.trim();
+    if email_address::EmailAddress::from_str(target).is_err() {
+        return Err(ServerFnError::new("target must be set to a valid email address"));
+    }
+
+    sqlx::query("INSERT INTO domain_routes (domain, pattern, target, priority) VALUES (?, ?, ?, ?)")
+        .bind(&domain)
+        .bind(pattern)
+        .bind(target)
+        .bind(priority)
+        .execute(&pool)
+        .await
+        .map_err(|e| -> ServerFnError {
+            match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    ServerFnError::new(format!("a route for '{pattern}' already exists"))
+                }
+                e => e.into(),
+            }
+        })?;
+
+    Ok(())
+}
+
+#[server]
+pub async fn update_domain_route_active(id: i64, active: bool) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let mut query = QueryBuilder::new("UPDATE domain_routes SET active = ");
+    query.push_bind(active);
+    query.push(" WHERE id = ");
+    query.push_bind(id);
+    push_route_scope(&mut query, &user);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+#[server]
+pub async fn delete_domain_route(id: i64) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let mut query = QueryBuilder::new("DELETE FROM domain_routes WHERE id = ");
+    query.push_bind(id);
+    push_route_scope(&mut query, &user);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+/// Returns where mail to the given address would be delivered: the mailbox itself,
+/// the target of an alias, the target of a matching route, or the catch-all.
+#[server]
+pub async fn resolve_address(address: String) -> Result<Option<String>, ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    let address = address.trim().to_lowercase();
+    let Some((_, domain)) = address.rsplit_once('@') else {
+        return Err(ServerFnError::new("address must contain an '@'"));
+    };
+    check_domain_access(&pool, &user, domain).await?;
+    Ok(resolve(&pool, &address).await?)
+}
+
+/// Returns the mailbox itself, the target of an alias, or otherwise the target
+/// of a matching route or the catch-all.
+#[cfg(feature = "ssr")]
+pub async fn resolve(pool: &SqlitePool, address: &str) -> Result<Option<String>, sqlx::Error> {
+    let direct = sqlx::query_scalar::<_, String>(
+        "SELECT address FROM mailboxes WHERE address = ?1 AND active = TRUE \
+        UNION ALL SELECT target FROM aliases WHERE address = ?1 AND active = TRUE",
+    )
+    .bind(address)
+    .fetch_optional(pool)
+    .await?;
+    if direct.is_some() {
+        return Ok(direct);
+    }
+
+    resolve_route(pool, address).await
+}
+
+#[component]
+pub fn DomainRoutesModal(#[prop(into)] domain: RwSignal<Option<String>>) -> impl IntoView {
+    let modal_elem = create_node_ref::<Dialog>();
+    let open = Signal::derive(move || domain.get().is_some());
+
+    let (version, set_version) = create_signal(0);
+    let routes = create_resource(
+        move || (domain.get(), version.get()),
+        move |(domain, _)| async move {
+            match domain {
+                Some(domain) => list_domain_routes(domain).await,
+                None => Ok(Vec::new()),
+            }
+        },
+    );
+    let reload = move || set_version.update(|x| *x += 1);
+
+    let (input_pattern, set_input_pattern) = create_signal("".to_string());
+    let (input_target, set_input_target) = create_signal("".to_string());
+    let (input_priority, set_input_priority) = create_signal("0".to_string());
+    let (server_error, set_server_error) = create_signal(None::<String>);
+    let (input_resolve, set_input_resolve) = create_signal("".to_string());
+    let (resolved, set_resolved) = create_signal(None::<String>);
+
+    create_effect(move |_| {
+        if !open() {
+            set_input_pattern("".to_string());
+            set_input_target("".to_string());
+            set_input_priority("0".to_string());
+            set_server_error(None);
+            set_input_resolve("".to_string());
+            set_resolved(None);
+        }
+    });
+
+    let has_invalid_pattern = Signal::derive(move || {
+        let pattern = input_pattern.get();
+        !pattern.is_empty() && validate_route_pattern(&pattern).is_err()
+    });
+
+    let on_add = move |_| {
+        let Some(domain) = domain.get_untracked() else {
+            return;
+        };
+        let Ok(priority) = input_priority.get_untracked().trim().parse::<i64>() else {
+            set_server_error(Some("priority must be an integer".to_string()));
+            return;
+        };
+        spawn_local(async move {
+            match create_domain_route(
+                domain,
+                input_pattern.get_untracked(),
+                input_target.get_untracked(),
+                priority,
+            )
+            .await
+            {
+                Err(e) => set_server_error(Some(e.to_string())),
+                Ok(()) => {
+                    set_server_error(None);
+                    set_input_pattern("".to_string());
+                    set_input_target("".to_string());
+                    reload();
+                }
+            }
+        });
+    };
+
+    let on_resolve = move |_| {
+        spawn_local(async move {
+            set_resolved(Some(match resolve_address(input_resolve.get_untracked()).await {
+                Ok(Some(target)) => format!("→ {target}"),
+                Ok(None) => "No route, mail would be rejected".to_string(),
+                Err(e) => e.to_string(),
+            }));
+        });
+    };
+
+    let input_class = "flex rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50";
+    let button_class = "inline-flex flex-none justify-center items-center rounded-lg transition-all px-3 py-2 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white dark:text-zinc-100 focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900";
+
+    view! {
+        <Modal open dialog_el=modal_elem>
+            <div class="relative p-4 transform overflow-hidden rounded-lg bg-white text-black dark:bg-black dark:text-zinc-100 text-left transition-all w-full sm:min-w-[640px]">
+                <h3 class="text-2xl tracking-tight mt-2 mb-1 font-semibold text-gray-900 dark:text-gray-200">
+                    "Routes for " {domain}
+                </h3>
+                <p class="text-sm text-gray-500 dark:text-gray-400 mb-4">
+                    "Routes are matched by ascending priority before falling back to the catch-all. Use "
+                    <code>"*"</code>
+                    " as a wildcard, e.g. "
+                    <code>"support-*"</code>
+                    "."
+                </p>
+                <div class="flex flex-col gap-3">
+                    <Transition fallback=move || {
+                        view! { <span class="text-gray-300 dark:text-gray-600">"Loading..."</span> }
+                    }>
+                        {move || {
+                            routes
+                                .get()
+                                .map(|routes| match routes {
+                                    Err(e) => view! { <p>{e.to_string()}</p> }.into_view(),
+                                    Ok(routes) if routes.is_empty() => {
+                                        view! { <p class="text-sm text-gray-500 dark:text-gray-400">"No routes yet."</p> }
+                                            .into_view()
+                                    }
+                                    Ok(routes) => {
+                                        routes
+                                            .into_iter()
+                                            .map(|route| {
+                                                let id = route.id;
+                                                view! {
+                                                    <div class="flex flex-row gap-3 items-center text-sm">
+                                                        <span class="w-8 text-right text-gray-500 dark:text-gray-400">
+                                                            {route.priority}
+                                                        </span>
+                                                        <span class="flex-1 font-mono">
+                                                            {route.pattern} "@" {route.domain} " → " {route.target}
+                                                        </span>
+                                                        <input
+                                                            class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                                                            type="checkbox"
+                                                            title="Active"
+                                                            prop:checked=route.active
+                                                            on:change=move |ev| {
+                                                                let active = event_target_checked(&ev);
+                                                                spawn_local(async move {
+                                                                    if let Err(e) = update_domain_route_active(id, active).await {
+                                                                        error!("Failed to update route: {}", e);
+                                                                    }
+                                                                    reload();
+                                                                });
+                                                            }
+                                                        />
+
+                                                        <button
+                                                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-lg font-medium px-2 py-1 inline-flex items-center"
+                                                            on:click=move |_| {
+                                                                spawn_local(async move {
+                                                                    if let Err(e) = delete_domain_route(id).await {
+                                                                        error!("Failed to delete route: {}", e);
+                                                                    }
+                                                                    reload();
+                                                                });
+                                                            }
+                                                        >
+
+                                                            <Icon icon=icondata::FiTrash2 class="w-4 h-4"/>
+                                                        </button>
+                                                    </div>
+                                                }
+                                            })
+                                            .collect_view()
+                                    }
+                                })
+                        }}
+
+                    </Transition>
+                    <div class="flex flex-col sm:flex-row gap-2 mt-2">
+                        <input
+                            class=format!("{input_class} w-20")
+                            type="number"
+                            title="Priority"
+                            on:input=move |ev| set_input_priority(event_target_value(&ev))
+                            prop:value=input_priority
+                        />
+                        <input
+                            class=format!("{input_class} flex-1")
+                            class=("!ring-4", has_invalid_pattern)
+                            class=("!ring-red-500", has_invalid_pattern)
+                            type="text"
+                            placeholder="support-*"
+                            on:input=move |ev| set_input_pattern(event_target_value(&ev))
+                            prop:value=input_pattern
+                        />
+                        <input
+                            class=format!("{input_class} flex-1")
+                            type="email"
+                            placeholder="target@example.com"
+                            on:input=move |ev| set_input_target(event_target_value(&ev))
+                            prop:value=input_target
+                        />
+                        <button type="button" class=button_class on:click=on_add>
+                            <Icon icon=icondata::FiPlus class="w-5 h-5"/>
+                        </button>
+                    </div>
+                    {move || {
+                        server_error()
+                            .map(|error| view! { <p class="text-sm text-red-600 dark:text-red-400">{error}</p> })
+                    }}
+
+                    <div class="flex flex-col sm:flex-row gap-2 mt-2">
+                        <input
+                            class=format!("{input_class} flex-1")
+                            type="email"
+                            placeholder="Test an address, e.g. support-team@example.com"
+                            on:input=move |ev| set_input_resolve(event_target_value(&ev))
+                            prop:value=input_resolve
+                        />
+                        <button type="button" class=button_class on:click=on_resolve>
+                            Resolve
+                        </button>
+                    </div>
+                    {move || resolved().map(|x| view! { <p class="text-sm font-mono">{x}</p> })}
+
+                    <div class="flex flex-col-reverse gap-3 sm:flex-row-reverse mt-2">
+                        <button
+                            type="button"
+                            class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
+                            on:click=move |_ev| domain.set(None)
+                        >
+                            Close
+                        </button>
+                    </div>
+                </div>
+            </div>
+        </Modal>
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn patterns_match_whole_localparts() {
+        assert!(route_matches("support", "support"));
+        assert!(!route_matches("support", "support-1"));
+        assert!(route_matches("support-*", "support-"));
+        assert!(route_matches("support-*", "support-billing"));
+        assert!(!route_matches("support-*", "my-support-billing"));
+        assert!(route_matches("*-ext", "team-ext"));
+        assert!(!route_matches("*-ext", "team-ext-2"));
+        assert!(route_matches("a*b*c", "abc"));
+        assert!(route_matches("a*b*c", "a-b-b-c"));
+        assert!(!route_matches("a*b*c", "a-c-b"));
+        // Overlapping parts must not share characters
+        assert!(!route_matches("ab*ba", "aba"));
+        assert!(route_matches("*", "anything"));
+    }
+
+    #[tokio::test]
+    async fn routes_are_matched_by_priority_before_the_catch_all() {
+        let db = TestDb::new().await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "bob").await;
+        insert_route(&db, "example.com", "support-*", "team@example.com", 10).await;
+        insert_route(&db, "example.com", "support-vip*", "vip@example.com", 5).await;
+        insert_route(&db, "example.com", "*-old", "old@example.com", 0).await;
+        sqlx::query(
+            "UPDATE domain_routes SET active = FALSE WHERE pattern = '*-old'; \
+            UPDATE domains SET catch_all = 'bob@example.com'",
+        )
+        .execute(&*db)
+        .await
+        .unwrap();
+
+        let resolve = |address: &'static str| resolve_route(&db, address);
+        assert_eq!(
+            resolve("support-vip1@example.com").await.unwrap().as_deref(),
+            Some("vip@example.com")
+        );
+        assert_eq!(
+            resolve("support-1@example.com").await.unwrap().as_deref(),
+            Some("team@example.com")
+        );
+        // Inactive routes are skipped
+        assert_eq!(
+            resolve("support-old@example.com").await.unwrap().as_deref(),
+            Some("team@example.com")
+        );
+        assert_eq!(
+            resolve("x-old@example.com").await.unwrap().as_deref(),
+            Some("bob@example.com")
+        );
+        assert_eq!(resolve("x@other.com").await.unwrap(), None);
+        assert_eq!(resolve("no-domain").await.unwrap(), None);
+    }
+
+    #[test]
+    fn route_patterns_are_validated() {
+        assert!(validate_route_pattern("support-*").is_ok());
+        assert!(validate_route_pattern("a.b+c_d*").is_ok());
+        assert!(validate_route_pattern("").is_err());
+        assert!(validate_route_pattern("Support").is_err());
+        assert!(validate_route_pattern("a?b").is_err());
+        assert!(validate_route_pattern("a[b]").is_err());
+    }
+}
@@ -1,10 +1,20 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::ops::Range;
 
-use crate::auth::User;
-use crate::utils::{DeleteModal, EditModal};
-use crate::utils::{SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer};
+use crate::auth::{get_capabilities, User};
+use crate::dns_records::DnsRecordsModal;
+use crate::domain_routes::DomainRoutesModal;
+use crate::users::OwnerInput;
+use crate::utils::{DeleteModal, EditModal, FieldErrorMessage};
+use crate::utils::{OptionTextRenderer, SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer};
+use crate::utils::{ProvisionedBadge, ProvisionedFilter, PROVISIONED_TOOLTIP};
 
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{
+    ensure_not_provisioned, push_order_by, push_owner_scope, LogQuery, OwnerScope, Provisioned,
+};
+#[cfg(feature = "ssr")]
+use crate::utils::{map_unique_violation, ValidationErrors};
 use chrono::{DateTime, Utc};
 use leptos::{ev::MouseEvent, logging::error, *};
 use leptos_icons::Icon;
@@ -20,6 +30,7 @@ use sqlx::QueryBuilder;
 pub struct Domain {
     #[table(class = "w-40")]
     pub domain: String,
+    #[table(renderer = "OptionTextRenderer")]
     pub catch_all: Option<String>,
     #[table(class = "w-1", renderer = "SliderRenderer")]
     pub public: bool,
@@ -29,6 +40,12 @@ pub struct Domain {
     pub owner: String,
     #[table(class = "w-1", title = "Created", renderer = "TimediffRenderer")]
     pub created_at: DateTime<Utc>,
+    /// Whether the mailserver treats localparts on this domain as case-sensitive
+    #[table(skip)]
+    pub localpart_case_sensitive: bool,
+    /// Whether the entry is managed by the provisioning file
+    #[table(skip)]
+    pub provisioned: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -37,36 +54,193 @@ pub struct DomainQuery {
     sort: VecDeque<(usize, ColumnSort)>,
     range: Range<usize>,
     search: String,
+    #[serde(default)]
+    provisioned: Option<bool>,
 }
 
-#[server]
-pub async fn allowed_domains() -> Result<Vec<(String, String)>, ServerFnError> {
-    let user = crate::auth::auth_any().await?;
+/// Whether localparts on the given domain are case-sensitive. Unless they are, new localparts
+/// are lowercased and duplicates are detected regardless of case, like most mailservers do.
+#[cfg(feature = "ssr")]
+pub(crate) async fn localpart_case_sensitive(pool: &sqlx::SqlitePool, domain: &str) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query_scalar::<_, bool>("SELECT localpart_case_sensitive FROM domains WHERE domain = ?")
+            .bind(domain)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(false),
+    )
+}
+
+/// The localparts of the aliases that RFC 2142 requires for every domain
+#[cfg(feature = "ssr")]
+pub(crate) const RFC2142_LOCALPARTS: [&str; 2] = ["postmaster", "abuse"];
 
+/// Where the RFC 2142 aliases of a new domain forward to: the catch-all if there is one,
+/// otherwise the oldest mailbox of the domain owner. None if neither exists.
+#[cfg(feature = "ssr")]
+async fn rfc2142_target(
+    conn: &mut sqlx::SqliteConnection,
+    owner: &str,
+    catch_all: Option<&str>,
+) -> Result<Option<String>, sqlx::Error> {
+    if let Some(catch_all) = catch_all {
+        return Ok(Some(catch_all.to_string()));
+    }
+    sqlx::query_scalar("SELECT address FROM mailboxes WHERE owner = ? ORDER BY created_at, address LIMIT 1")
+        .bind(owner)
+        .fetch_optional(conn)
+        .await
+}
+
+/// Creates the RFC 2142 aliases of a domain, unless a mailbox or alias already uses their address
+#[cfg(feature = "ssr")]
+async fn insert_rfc2142_aliases(
+    conn: &mut sqlx::SqliteConnection,
+    domain: &str,
+    owner: &str,
+    target: &str,
+) -> Result<(), sqlx::Error> {
+    for localpart in RFC2142_LOCALPARTS {
+        let address = format!("{localpart}@{domain}");
+        let mut query = QueryBuilder::new("INSERT INTO aliases (address, domain, target, comment, owner, created_via)");
+        query.push(" SELECT ");
+        query.push_bind(&address);
+        query.push(", ");
+        query.push_bind(domain);
+        query.push(", ");
+        query.push_bind(target);
+        query.push(", 'RFC 2142', ");
+        query.push_bind(owner);
+        query.push(", 'ui'");
+        // make sure that no mailbox exists with that address
+        query.push(" WHERE NOT EXISTS (SELECT * FROM mailboxes WHERE address = ");
+        query.push_bind(&address);
+        query.push(") ON CONFLICT (address) DO NOTHING");
+        query.logged().build().execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// The maximum number of domains offered to a single user. This only limits the suggestions,
+/// whether a domain may be used is always checked with [`allowed_domain_owner_for`].
+#[cfg(feature = "ssr")]
+const MAX_ALLOWED_DOMAINS: i64 = 1000;
+
+/// Returns the active domains (with their owner) that the given user may use for new
+/// addresses, up to [`MAX_ALLOWED_DOMAINS`]. These are all public domains, the domains owned
+/// by the user, and for mailboxes additionally the domains owned by the mailbox owner. Mailbox
+/// owners are always users, so this indirection is exactly one level deep.
+#[cfg(feature = "ssr")]
+pub async fn allowed_domains_for(pool: &sqlx::SqlitePool, user: &User) -> Result<Vec<(String, String)>, sqlx::Error> {
     let mut query = QueryBuilder::new("SELECT domain, owner FROM domains");
+    push_allowed_domains_filter(&mut query, user);
+    query.push(" ORDER BY domain LIMIT ");
+    query.push_bind(MAX_ALLOWED_DOMAINS);
+
+    query
+        .logged()
+        .build_query_as::<(String, String)>()
+        .fetch_all(pool)
+        .await
+}
+
+/// Restricts a query on the domains table to the domains the given user may use.
+#[cfg(feature = "ssr")]
+fn push_allowed_domains_filter<'a>(query: &mut QueryBuilder<'a, sqlx::Sqlite>, user: &'a User) {
     query.push(" WHERE active = TRUE AND (public = TRUE OR owner = ");
     query.push_bind(&user.username);
-    if let Some(mailbox_owner) = user.mailbox_owner {
+    if let Some(mailbox_owner) = &user.mailbox_owner {
         query.push(" OR owner = ");
-        query.push_bind(mailbox_owner.clone());
+        query.push_bind(mailbox_owner);
     }
     query.push(")");
+}
+
+/// Returns the owner of the given domain if the given user may use it for new addresses.
+/// This is shared by the web interface and the API so both always agree.
+#[cfg(feature = "ssr")]
+pub async fn allowed_domain_owner_for(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    domain: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT owner FROM domains");
+    push_allowed_domains_filter(&mut query, user);
+    query.push(" AND domain = ");
+    query.push_bind(domain);
+
+    query.logged().build_query_scalar::<String>().fetch_optional(pool).await
+}
+
+/// Picks a random domain that the given user may use, without loading all of them.
+#[cfg(feature = "ssr")]
+pub async fn random_allowed_domain_for(pool: &sqlx::SqlitePool, user: &User) -> Result<Option<String>, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT domain FROM domains");
+    push_allowed_domains_filter(&mut query, user);
+    query.push(" ORDER BY RANDOM() LIMIT 1");
+
+    query.logged().build_query_scalar::<String>().fetch_optional(pool).await
+}
+
+/// Returns the domains the current user may use, for suggestions in the web interface.
+#[server]
+pub async fn allowed_domains() -> Result<Vec<(String, String)>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    let cache = crate::database::ssr::allowed_domains_cache()?;
+    Ok(cache.get(&pool, &user).await?.to_vec())
+}
 
+/// Returns the catch-all address of the given domain, if the domain is usable
+/// by the current user and has a catch-all configured.
+#[server]
+pub async fn domain_catch_all(domain: String) -> Result<Option<String>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
     let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_as::<(String, String)>().fetch_all(&pool).await?)
+    Ok(catch_all_for(&pool, &user, &domain).await?)
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) async fn catch_all_for(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    domain: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    if allowed_domain_owner_for(pool, user, domain).await?.is_none() {
+        return Ok(None);
+    }
+
+    let mut query = QueryBuilder::new("SELECT catch_all FROM domains WHERE domain = ");
+    query.push_bind(domain);
+
+    let catch_all = query
+        .logged()
+        .build_query_scalar::<Option<String>>()
+        .fetch_optional(pool)
+        .await?
+        .flatten();
+
+    Ok(catch_all.filter(|x| !x.is_empty()))
 }
 
 #[server]
 pub async fn list_domains(query: DomainQuery) -> Result<Vec<Domain>, ServerFnError> {
     let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    Ok(query_domains(&pool, &user, query).await?)
+}
 
-    let DomainQuery { sort, range, search } = query;
+#[cfg(feature = "ssr")]
+async fn query_domains(pool: &sqlx::SqlitePool, user: &User, query: DomainQuery) -> Result<Vec<Domain>, sqlx::Error> {
+    let DomainQuery {
+        sort,
+        range,
+        search,
+        provisioned,
+    } = query;
 
     let mut query = QueryBuilder::new("SELECT * FROM domains WHERE 1=1");
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
-    }
+    push_owner_scope(&mut query, user, OwnerScope::Domains);
     if !search.is_empty() {
         query.push(" AND ( domain LIKE concat('%', ");
         query.push_bind(&search);
@@ -77,55 +251,85 @@ pub async fn list_domains(query: DomainQuery) -> Result<Vec<Domain>, ServerFnErr
         query.push(", '%') )");
     }
 
-    if let Some(order) = Domain::sorting_to_sql(&sort) {
-        query.push(" ");
-        query.push(order);
+    if let Some(provisioned) = provisioned {
+        query.push(" AND provisioned = ");
+        query.push_bind(provisioned);
     }
 
+    push_order_by(&mut query, Domain::sorting_to_sql(&sort), "domain");
+
     query.push(" LIMIT ");
     query.push_bind(range.len() as i64);
     query.push(" OFFSET ");
     query.push_bind(range.start as i64);
 
-    let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_as::<Domain>().fetch_all(&pool).await?)
+    query.logged().build_query_as::<Domain>().fetch_all(pool).await
 }
 
 #[server]
 pub async fn domain_count() -> Result<usize, ServerFnError> {
     let user = crate::auth::auth_user().await?;
 
-    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM domains");
-    if !user.admin {
-        query.push(" WHERE owner = ");
-        query.push_bind(&user.username);
-    }
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM domains WHERE 1=1");
+    push_owner_scope(&mut query, &user, OwnerScope::Domains);
 
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
-
-    Ok(count as usize)
+    let load = async {
+        let count = query.logged().build_query_scalar::<i64>().fetch_one(&pool).await?;
+        Ok::<_, ServerFnError>(count as usize)
+    };
+    crate::database::ssr::count_cache()?
+        .get_or_load(
+            crate::count_cache::CountTable::Domains,
+            &user.username,
+            String::new(),
+            load,
+        )
+        .await
 }
 
 #[server]
 pub async fn delete_domain(domain: String) -> Result<(), ServerFnError> {
     // Creating/Deleting only as admin!
     let user = crate::auth::auth_admin().await?;
+    let pool = crate::database::ssr::pool()?;
+    delete(&pool, &user, domain).await
+}
+
+#[cfg(feature = "ssr")]
+async fn delete(pool: &sqlx::SqlitePool, user: &User, domain: String) -> Result<(), ServerFnError> {
+    ensure_not_provisioned(pool, user, Provisioned::Domains, &domain).await?;
+
+    // Provisioned entries would otherwise be left behind without their domain,
+    // and the next provisioning run would fail to recreate them.
+    let provisioned_dependents = sqlx::query_scalar::<_, i64>(
+        "SELECT (SELECT COUNT(*) FROM mailboxes WHERE domain = ?1 AND provisioned = TRUE) \
+        + (SELECT COUNT(*) FROM aliases WHERE domain = ?1 AND provisioned = TRUE)",
+    )
+    .bind(&domain)
+    .fetch_one(pool)
+    .await?;
+    if provisioned_dependents > 0 {
+        return Err(ServerFnError::new(format!(
+            "'{domain}' is still used by {provisioned_dependents} mailboxes or aliases from the provisioning file, \
+            please remove them there first"
+        )));
+    }
 
     let mut query = QueryBuilder::new("DELETE FROM domains WHERE domain = ");
     query.push_bind(domain);
 
     // (Hypothetical) Non-admins can only delete their own domains
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
-    }
+    push_owner_scope(&mut query, user, OwnerScope::Domains);
 
-    let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    if query.logged().build().execute(pool).await?.rows_affected() > 0 {
+        crate::database::ssr::allowed_domains_cache()?.invalidate();
+        crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Domains);
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 #[server]
 pub async fn create_or_update_domain(
     old_domain: Option<String>,
@@ -134,6 +338,8 @@ pub async fn create_or_update_domain(
     public: bool,
     active: bool,
     owner: String,
+    rfc2142_aliases: bool,
+    localpart_case_sensitive: bool,
 ) -> Result<(), ServerFnError> {
     let user = if old_domain.is_some() {
         // Editing is allowed for some users
@@ -148,19 +354,28 @@ pub async fn create_or_update_domain(
     let owner = if user.admin { owner.trim() } else { &user.username };
     // Empty owner -> self owned
     let owner = if owner.is_empty() { &user.username } else { owner };
+    if user.admin {
+        crate::users::check_owner_exists(&pool, owner, false).await?;
+    }
     // Only admins may create public domains
     let public = public && user.admin;
     if domain.is_empty() {
-        return Err(ServerFnError::new("domain cannot be empty"));
+        return Err(ValidationErrors::field("domain", "domain cannot be empty").into());
     }
+    let reject_noncanonical = crate::database::ssr::config()?.reject_noncanonical_domains;
+    let domain = crate::aliases::normalize_domain(&domain, reject_noncanonical)
+        .map_err(|e| ValidationErrors::field("domain", e))?;
+    // Empty catch-all -> no catch-all
+    let catch_all = Some(catch_all.trim()).filter(|x| !x.is_empty());
 
     if let Some(old_domain) = old_domain {
+        ensure_not_provisioned(&pool, &user, Provisioned::Domains, &old_domain).await?;
         let mut query = QueryBuilder::new("UPDATE domains SET catch_all = ");
         query.push_bind(catch_all);
         if user.admin {
             // Only admins can edit the domain itself
             query.push(", domain = ");
-            query.push_bind(domain);
+            query.push_bind(&domain);
         }
         query.push(", public = ");
         query.push_bind(public);
@@ -168,24 +383,61 @@ pub async fn create_or_update_domain(
         query.push_bind(active);
         query.push(", owner = ");
         query.push_bind(owner);
+        if user.admin {
+            // This must match the mailserver, so only admins can change it
+            query.push(", localpart_case_sensitive = ");
+            query.push_bind(localpart_case_sensitive);
+        }
         query.push(" WHERE domain = ");
         query.push_bind(old_domain);
-        if !user.admin {
-            query.push(" AND owner = ");
-            query.push_bind(&user.username);
-        }
+        push_owner_scope(&mut query, &user, OwnerScope::Domains);
 
-        query.build().execute(&pool).await.map(|_| ())?;
-    } else {
-        sqlx::query("INSERT INTO domains (domain, catch_all, public, active, owner) VALUES (?, ?, ?, ?, ?)")
-            .bind(domain)
-            .bind(catch_all)
-            .bind(public)
-            .bind(active)
-            .bind(owner)
+        query
+            .logged()
+            .build()
             .execute(&pool)
             .await
-            .map(|_| ())?;
+            .map_err(map_unique_violation("domain", &domain))?;
+        crate::database::ssr::allowed_domains_cache()?.invalidate();
+    } else {
+        // The domain and its aliases are created together or not at all
+        let mut tx = pool.begin().await?;
+        let rfc2142_target = if rfc2142_aliases {
+            let Some(target) = rfc2142_target(&mut tx, owner, catch_all).await? else {
+                return Err(ValidationErrors::field(
+                    "catch_all",
+                    "postmaster and abuse aliases require a catch-all or a mailbox of the owner",
+                )
+                .into());
+            };
+            Some(target)
+        } else {
+            None
+        };
+
+        sqlx::query(
+            "INSERT INTO domains (domain, catch_all, public, active, owner, localpart_case_sensitive) \
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&domain)
+        .bind(catch_all)
+        .bind(public)
+        .bind(active)
+        .bind(owner)
+        .bind(localpart_case_sensitive)
+        .execute(&mut *tx)
+        .await
+        .map_err(map_unique_violation("domain", &domain))?;
+        if let Some(target) = &rfc2142_target {
+            insert_rfc2142_aliases(&mut tx, &domain, owner, target).await?;
+        }
+        tx.commit().await?;
+
+        crate::database::ssr::allowed_domains_cache()?.invalidate();
+        if rfc2142_target.is_some() {
+            crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+        }
+        crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Domains);
     }
 
     Ok(())
@@ -194,6 +446,19 @@ pub async fn create_or_update_domain(
 #[server]
 pub async fn update_domain_public_and_active(domain: String, public: bool, active: bool) -> Result<(), ServerFnError> {
     let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    set_public_and_active(&pool, &user, domain, public, active).await
+}
+
+#[cfg(feature = "ssr")]
+async fn set_public_and_active(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    domain: String,
+    public: bool,
+    active: bool,
+) -> Result<(), ServerFnError> {
+    ensure_not_provisioned(pool, user, Provisioned::Domains, &domain).await?;
 
     // Only admins may create public domains
     let public = public && user.admin;
@@ -206,26 +471,61 @@ pub async fn update_domain_public_and_active(domain: String, public: bool, activ
     query.push_bind(domain);
 
     // Non-admins can only change their own domains
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
-    }
+    push_owner_scope(&mut query, user, OwnerScope::Domains);
 
-    let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    query.logged().build().execute(pool).await.map(|_| ())?;
+    crate::database::ssr::allowed_domains_cache()?.invalidate();
     Ok(())
 }
 
+/// Sets the public status of several domains at once, which is only allowed for admins.
+/// Returns the number of updated domains.
+#[server]
+pub async fn set_domains_public(domains: Vec<String>, public: bool) -> Result<usize, ServerFnError> {
+    let user = crate::auth::auth_admin().await?;
+    let pool = crate::database::ssr::pool()?;
+    let updated = set_public(&pool, &user, domains, public).await?;
+    crate::database::ssr::allowed_domains_cache()?.invalidate();
+    Ok(updated)
+}
+
+/// Updates either all given domains or none, if any of them is provisioned
+#[cfg(feature = "ssr")]
+async fn set_public(
+    pool: &sqlx::SqlitePool,
+    user: &User,
+    domains: Vec<String>,
+    public: bool,
+) -> Result<usize, ServerFnError> {
+    for domain in &domains {
+        ensure_not_provisioned(pool, user, Provisioned::Domains, domain).await?;
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut updated = 0;
+    for domain in domains {
+        let mut query = QueryBuilder::new("UPDATE domains SET public = ");
+        query.push_bind(public);
+        query.push(" WHERE domain = ");
+        query.push_bind(domain);
+        updated += query.logged().build().execute(&mut *tx).await?.rows_affected() as usize;
+    }
+    tx.commit().await?;
+    Ok(updated)
+}
+
 #[derive(Default)]
 pub struct DomainTableDataProvider {
     sort: VecDeque<(usize, ColumnSort)>,
     pub search: RwSignal<String>,
+    pub provisioned: RwSignal<Option<bool>>,
 }
 
 impl TableDataProvider<Domain> for DomainTableDataProvider {
     async fn get_rows(&self, range: Range<usize>) -> Result<(Vec<Domain>, Range<usize>), String> {
         list_domains(DomainQuery {
             search: self.search.get_untracked().trim().to_string(),
+            provisioned: self.provisioned.get_untracked(),
             sort: self.sort.clone(),
             range: range.clone(),
         })
@@ -247,6 +547,7 @@ impl TableDataProvider<Domain> for DomainTableDataProvider {
 
     fn track(&self) {
         self.search.track();
+        self.provisioned.track();
     }
 }
 
@@ -263,12 +564,17 @@ pub fn Domains(user: User) -> impl IntoView {
 
     let delete_modal_domain = create_rw_signal(None);
     let edit_modal_domain = create_rw_signal(None);
+    let routes_modal_domain = create_rw_signal(None);
+    let dns_modal_domain = create_rw_signal(None);
+    let selected_domains = create_rw_signal(HashSet::<String>::new());
 
     let (edit_modal_input_domain, set_edit_modal_input_domain) = create_signal("".to_string());
     let (edit_modal_input_catchall, set_edit_modal_input_catchall) = create_signal("".to_string());
     let (edit_modal_input_public, set_edit_modal_input_public) = create_signal(true);
     let (edit_modal_input_active, set_edit_modal_input_active) = create_signal(true);
     let (edit_modal_input_owner, set_edit_modal_input_owner) = create_signal("".to_string());
+    let (edit_modal_input_rfc2142, set_edit_modal_input_rfc2142) = create_signal(false);
+    let (edit_modal_input_case_sensitive, set_edit_modal_input_case_sensitive) = create_signal(false);
     let edit_modal_open_with = Callback::new(move |edit_domain: Option<Domain>| {
         edit_modal_domain.set(Some(edit_domain.clone()));
 
@@ -278,18 +584,21 @@ pub fn Domains(user: User) -> impl IntoView {
             set_edit_modal_input_public(edit_domain.public);
             set_edit_modal_input_active(edit_domain.active);
             set_edit_modal_input_owner(edit_domain.owner.clone());
+            set_edit_modal_input_case_sensitive(edit_domain.localpart_case_sensitive);
         } else {
             set_edit_modal_input_domain("".to_string());
             set_edit_modal_input_catchall("".to_string());
             set_edit_modal_input_public(user.admin);
             set_edit_modal_input_active(true);
             set_edit_modal_input_owner("".to_string());
+            set_edit_modal_input_rfc2142(false);
+            set_edit_modal_input_case_sensitive(false);
         }
     });
 
     let errors = Vec::new;
 
-    let on_edit = move |(data, on_error): (Option<Domain>, Callback<String>)| {
+    let on_edit = move |(data, on_error): (Option<Domain>, Callback<ServerFnError>)| {
         spawn_local(async move {
             if let Err(e) = create_or_update_domain(
                 data.map(|x| x.domain),
@@ -298,10 +607,12 @@ pub fn Domains(user: User) -> impl IntoView {
                 edit_modal_input_public.get_untracked(),
                 edit_modal_input_active.get_untracked(),
                 edit_modal_input_owner.get_untracked(),
+                edit_modal_input_rfc2142.get_untracked(),
+                edit_modal_input_case_sensitive.get_untracked(),
             )
             .await
             {
-                on_error(e.to_string())
+                on_error(e)
             } else {
                 reload_controller.reload();
                 edit_modal_domain.set(None);
@@ -309,6 +620,18 @@ pub fn Domains(user: User) -> impl IntoView {
         });
     };
 
+    let set_selected_public = move |public: bool| {
+        let domains = selected_domains.get_untracked().into_iter().collect::<Vec<_>>();
+        spawn_local(async move {
+            if let Err(e) = set_domains_public(domains, public).await {
+                error!("Failed to update public status of domains: {}", e);
+            } else {
+                selected_domains.set(HashSet::new());
+            }
+            reload_controller.reload();
+        });
+    };
+
     let on_row_change = move |ev: ChangeEvent<Domain>| {
         spawn_local(async move {
             if let Err(e) = update_domain_public_and_active(
@@ -327,6 +650,14 @@ pub fn Domains(user: User) -> impl IntoView {
         });
     };
 
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let edit_provisioned = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|x| x.edit_provisioned)
+    };
+
     #[allow(unused_variables, non_snake_case)]
     let domain_row_renderer = move |class: Signal<String>,
                                     row: Domain,
@@ -335,25 +666,76 @@ pub fn Domains(user: User) -> impl IntoView {
                                     on_select: EventHandler<MouseEvent>,
                                     on_change: EventHandler<ChangeEvent<Domain>>| {
         let delete_domain = row.domain.clone();
+        let routes_domain = row.domain.clone();
+        let dns_domain = row.domain.clone();
+        let select_domain = row.domain.clone();
+        let is_selected = {
+            let domain = row.domain.clone();
+            move || selected_domains.with(|x| x.contains(&domain))
+        };
         let edit_domain = row.clone();
+        let provisioned = row.provisioned;
+        let locked = move || provisioned && !edit_provisioned();
         view! {
             <tr class=class on:click=move |mouse_event| on_select.run(mouse_event)>
                 {row.render_row(index, on_change)}
                 <td class="w-1 px-4 py-2 whitespace-nowrap text-ellipsis">
-                    <div class="inline-flex items-center rounded-md">
+                    <Show when=move || user.admin>
+                        <input
+                            class="w-4 h-4 me-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                            type="checkbox"
+                            title="Select"
+                            prop:checked=is_selected.clone()
+                            on:change={
+                                let select_domain = select_domain.clone();
+                                move |ev| {
+                                    let checked = event_target_checked(&ev);
+                                    let domain = select_domain.clone();
+                                    selected_domains
+                                        .update(|x| {
+                                            if checked {
+                                                x.insert(domain);
+                                            } else {
+                                                x.remove(&domain);
+                                            }
+                                        });
+                                }
+                            }
+                        />
+                    </Show>
+                    {provisioned.then(|| view! { <ProvisionedBadge/> })}
+                    <div
+                        class="inline-flex items-center rounded-md"
+                        title=move || locked().then_some(PROVISIONED_TOOLTIP)
+                    >
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| edit_modal_open_with(Some(edit_domain.clone()))
+                            disabled=locked
                         >
                             <Icon icon=icondata::FiEdit class="w-5 h-5"/>
                         </button>
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            title="Routes"
+                            on:click=move |_| routes_modal_domain.set(Some(routes_domain.clone()))
+                        >
+                            <Icon icon=icondata::FiShuffle class="w-5 h-5"/>
+                        </button>
+                        <button
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            title="DNS"
+                            on:click=move |_| dns_modal_domain.set(Some(dns_domain.clone()))
+                        >
+                            <Icon icon=icondata::FiGlobe class="w-5 h-5"/>
+                        </button>
+                        <button
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| {
                                 delete_modal_domain.set(Some(delete_domain.clone()));
                             }
 
-                            disabled=move || !user.admin
+                            disabled=move || !user.admin || locked()
                         >
 
                             <Icon icon=icondata::FiTrash2 class="w-5 h-5"/>
@@ -380,6 +762,7 @@ pub fn Domains(user: User) -> impl IntoView {
                             on_input(event_target_value(&e));
                         }
                     />
+                    <ProvisionedFilter value=rows.provisioned/>
 
                     <button
                         type="button"
@@ -389,6 +772,24 @@ pub fn Domains(user: User) -> impl IntoView {
                         <Icon icon=icondata::FiPlus class="w-6 h-6 me-2"/>
                         New
                     </button>
+                    <Show when=move || user.admin && selected_domains.with(|x| !x.is_empty())>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-white dark:bg-black border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            on:click=move |_| set_selected_public(true)
+                        >
+                            <Icon icon=icondata::FiGlobe class="w-6 h-6 me-2"/>
+                            "Make public (" {move || selected_domains.with(|x| x.len())} ")"
+                        </button>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-gray-800 dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-white dark:bg-black border-[1.5px] border-gray-200 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            on:click=move |_| set_selected_public(false)
+                        >
+                            <Icon icon=icondata::FiLock class="w-6 h-6 me-2"/>
+                            "Make private (" {move || selected_domains.with(|x| x.len())} ")"
+                        </button>
+                    </Show>
                     <div class="flex flex-1"></div>
                     <div class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-right px-4">
                         {count} " results"
@@ -401,7 +802,7 @@ pub fn Domains(user: User) -> impl IntoView {
                             <TableContent
                                 rows
                                 sorting=sorting
-                                sorting_mode=SortingMode::SingleColumn
+                                sorting_mode=SortingMode::MultiColumn
                                 row_renderer=domain_row_renderer
                                 reload_controller=reload_controller
                                 loading_row_display_limit=0
@@ -429,6 +830,9 @@ pub fn Domains(user: User) -> impl IntoView {
             }
         />
 
+        <DomainRoutesModal domain=routes_modal_domain/>
+        <DnsRecordsModal domain=dns_modal_domain/>
+
         <EditModal
             data=edit_modal_domain
             what="Domain".to_string()
@@ -451,6 +855,7 @@ pub fn Domains(user: User) -> impl IntoView {
                     prop:value=edit_modal_input_domain
                     disabled=move || !user.admin
                 />
+                <FieldErrorMessage field="domain"/>
             </div>
             <div class="flex flex-col gap-2">
                 <label
@@ -466,6 +871,7 @@ pub fn Domains(user: User) -> impl IntoView {
                     on:input=move |ev| set_edit_modal_input_catchall(event_target_value(&ev))
                     prop:value=edit_modal_input_catchall
                 />
+                <FieldErrorMessage field="catch_all"/>
             </div>
             <div class="flex flex-col gap-2">
                 <label
@@ -474,13 +880,13 @@ pub fn Domains(user: User) -> impl IntoView {
                 >
                     Owner
                 </label>
-                <input
-                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
-                    type="text"
-                    placeholder=move || user.username.clone()
-                    on:input=move |ev| set_edit_modal_input_owner(event_target_value(&ev))
-                    prop:value=edit_modal_input_owner
-                    disabled=move || !user.admin
+                <OwnerInput
+                    id="domain_owner"
+                    value=edit_modal_input_owner
+                    set_value=set_edit_modal_input_owner
+                    placeholder=user.username.clone()
+                    admin=user.admin
+                    allow_mailboxes=false
                 />
             </div>
             <Show when=move || user.admin>
@@ -499,6 +905,21 @@ pub fn Domains(user: User) -> impl IntoView {
                         Public
                     </label>
                 </div>
+                <div class="flex flex-row gap-2 mt-2 items-center">
+                    <input
+                        id="domains_case_sensitive"
+                        class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                        type="checkbox"
+                        on:change=move |ev| set_edit_modal_input_case_sensitive(event_target_checked(&ev))
+                        prop:checked=edit_modal_input_case_sensitive
+                    />
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="domains_case_sensitive"
+                    >
+                        Case-sensitive localparts
+                    </label>
+                </div>
             </Show>
             <div class="flex flex-row gap-2 mt-2 items-center">
                 <input
@@ -515,6 +936,384 @@ pub fn Domains(user: User) -> impl IntoView {
                     Active
                 </label>
             </div>
+            <Show when=move || matches!(edit_modal_domain.get(), Some(None))>
+                <div class="flex flex-row gap-2 mt-2 items-center">
+                    <input
+                        id="domains_rfc2142"
+                        class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                        type="checkbox"
+                        on:change=move |ev| set_edit_modal_input_rfc2142(event_target_checked(&ev))
+                        prop:checked=edit_modal_input_rfc2142
+                    />
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="domains_rfc2142"
+                    >
+                        Create postmaster and abuse aliases
+                    </label>
+                </div>
+            </Show>
         </EditModal>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn domain_changes_are_reflected_by_the_cache() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "alice.example", "alice").await;
+        insert_domain(&db, "public.example", "admin").await;
+        let state = app_state(
+            &db,
+            crate::config::Config {
+                allowed_domains_cache_ttl: std::time::Duration::from_secs(3600),
+                ..Default::default()
+            },
+        );
+        let runtime = server_context(&state);
+        let cache = &state.allowed_domains_cache;
+        let alice = user("alice");
+        let domains = || async {
+            cache
+                .get(&db, &alice)
+                .await
+                .unwrap()
+                .iter()
+                .map(|x| x.0.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(domains().await, ["alice.example"]);
+
+        let admin = admin("admin");
+        set_public_and_active(&db, &admin, "public.example".to_string(), true, true)
+            .await
+            .unwrap();
+        assert_eq!(domains().await, ["alice.example", "public.example"]);
+
+        set_public_and_active(&db, &alice, "alice.example".to_string(), false, false)
+            .await
+            .unwrap();
+        assert_eq!(domains().await, ["public.example"]);
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn domains_with_provisioned_dependents_are_not_deleted() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "admin").await;
+        insert_mailbox(&db, "me@example.com", "admin").await;
+        sqlx::query("UPDATE mailboxes SET provisioned = TRUE")
+            .execute(&*db)
+            .await
+            .unwrap();
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        let admin = admin("admin");
+
+        let err = delete(&db, &admin, "example.com".to_string()).await.unwrap_err();
+        assert!(
+            err.to_string().contains("still used by 1 mailboxes or aliases"),
+            "{err}"
+        );
+        assert_eq!(domain_count_of(&db).await, 1);
+
+        sqlx::query("UPDATE mailboxes SET provisioned = FALSE")
+            .execute(&*db)
+            .await
+            .unwrap();
+        delete(&db, &admin, "example.com".to_string()).await.unwrap();
+        assert_eq!(domain_count_of(&db).await, 0);
+        runtime.dispose();
+    }
+
+    async fn domain_count_of(pool: &sqlx::SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM domains")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn random_domains_are_picked_from_the_allowed_ones() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        for (domain, owner) in [
+            ("a.example", "alice"),
+            ("b.example", "alice"),
+            ("inactive.example", "alice"),
+            ("public.example", "admin"),
+            ("private.example", "admin"),
+            ("bob.example", "bob"),
+        ] {
+            insert_domain(&db, domain, owner).await;
+        }
+        sqlx::query("UPDATE domains SET active = FALSE WHERE domain = 'inactive.example'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE domains SET public = TRUE WHERE domain = 'public.example'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let alice = user("alice");
+        let mut picked = std::collections::BTreeSet::new();
+        for _ in 0..200 {
+            picked.insert(random_allowed_domain_for(&db, &alice).await.unwrap().unwrap());
+        }
+        assert_eq!(
+            picked.into_iter().collect::<Vec<_>>(),
+            ["a.example", "b.example", "public.example"]
+        );
+
+        // Mailboxes use the domains of their owner, and users without any get none
+        let mailbox = mailbox("me@a.example", "alice");
+        assert!(random_allowed_domain_for(&db, &mailbox).await.unwrap().is_some());
+        sqlx::query("UPDATE domains SET public = FALSE")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert_eq!(random_allowed_domain_for(&db, &user("carol")).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn allowed_domains_are_checked_in_the_database() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "admin.example", "admin").await;
+        // More domains than are suggested, the last one in order is only found by the check
+        sqlx::query(
+            "WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < ?) \
+            INSERT INTO domains (domain, owner) SELECT printf('d%04d.example', i), 'alice' FROM n",
+        )
+        .bind(MAX_ALLOWED_DOMAINS)
+        .execute(&*db)
+        .await
+        .unwrap();
+
+        let alice = user("alice");
+        let suggested = allowed_domains_for(&db, &alice).await.unwrap();
+        assert_eq!(suggested.len() as i64, MAX_ALLOWED_DOMAINS);
+        let last = format!("d{MAX_ALLOWED_DOMAINS:04}.example");
+        assert!(!suggested.iter().any(|x| x.0 == last));
+        let owner = allowed_domain_owner_for(&db, &alice, &last).await.unwrap();
+        assert_eq!(owner.as_deref(), Some("alice"));
+
+        // Private domains of others are not allowed, unless they belong to the mailbox owner
+        assert_eq!(
+            allowed_domain_owner_for(&db, &alice, "admin.example").await.unwrap(),
+            None
+        );
+        let owner = allowed_domain_owner_for(&db, &mailbox("me@admin.example", "admin"), "admin.example")
+            .await
+            .unwrap();
+        assert_eq!(owner.as_deref(), Some("admin"));
+    }
+
+    #[tokio::test]
+    async fn domain_routes_follow_their_domain() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "other.com", "alice").await;
+        for domain in ["example.com", "other.com"] {
+            sqlx::query("INSERT INTO domain_routes (domain, pattern, target) VALUES (?, 'shop-*', 'me@example.org')")
+                .bind(domain)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+        let routes = || async {
+            sqlx::query_scalar::<_, String>("SELECT domain FROM domain_routes ORDER BY domain")
+                .fetch_all(&*db)
+                .await
+                .unwrap()
+        };
+
+        sqlx::query("UPDATE domains SET domain = 'renamed.com' WHERE domain = 'example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert_eq!(routes().await, ["other.com", "renamed.com"]);
+
+        sqlx::query("DELETE FROM domains WHERE domain = 'renamed.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert_eq!(routes().await, ["other.com"]);
+    }
+
+    #[tokio::test]
+    async fn domains_without_catch_all_are_searched_and_sorted() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        for (domain, catch_all) in [
+            ("a.com", Some("zz-catch@example.org")),
+            ("b.com", None),
+            ("c.com", Some("aa-catch@example.org")),
+        ] {
+            insert_domain(&db, domain, "admin").await;
+            sqlx::query("UPDATE domains SET catch_all = ? WHERE domain = ?")
+                .bind(catch_all)
+                .bind(domain)
+                .execute(&*db)
+                .await
+                .unwrap();
+        }
+
+        let admin = admin("admin");
+        let domains = |search: &'static str, sort: Option<ColumnSort>| {
+            let (db, admin) = (&db, &admin);
+            async move {
+                let query = DomainQuery {
+                    sort: sort.map(|x| (1, x)).into_iter().collect(),
+                    range: 0..10,
+                    search: search.to_string(),
+                    provisioned: None,
+                };
+                query_domains(db, admin, query)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|x| x.domain)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Missing catch-alls never match, but the other columns still do
+        assert_eq!(domains("catch", None).await, ["a.com", "c.com"]);
+        assert_eq!(domains("b.com", None).await, ["b.com"]);
+        // Missing catch-alls come first in ascending order and last in descending order
+        assert_eq!(
+            domains("", Some(ColumnSort::Ascending)).await,
+            ["b.com", "c.com", "a.com"]
+        );
+        assert_eq!(
+            domains("", Some(ColumnSort::Descending)).await,
+            ["a.com", "c.com", "b.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn catch_alls_are_only_revealed_for_usable_domains() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "plain.com", "alice").await;
+        sqlx::query("UPDATE domains SET catch_all = 'all@example.org' WHERE domain = 'example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let catch_all = |username: &'static str, domain: &'static str| {
+            let db = &db;
+            async move { catch_all_for(db, &user(username), domain).await.unwrap() }
+        };
+        assert_eq!(
+            catch_all("alice", "example.com").await.as_deref(),
+            Some("all@example.org")
+        );
+        assert_eq!(catch_all("alice", "plain.com").await, None);
+        assert_eq!(catch_all("bob", "example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn rfc2142_aliases_forward_to_the_catch_all_or_a_mailbox() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "alice@example.com", "alice").await;
+        let mut conn = db.acquire().await.unwrap();
+
+        let target = rfc2142_target(&mut conn, "alice", Some("all@example.org"))
+            .await
+            .unwrap();
+        assert_eq!(target.as_deref(), Some("all@example.org"));
+        let target = rfc2142_target(&mut conn, "alice", None).await.unwrap();
+        assert_eq!(target.as_deref(), Some("alice@example.com"));
+        assert_eq!(rfc2142_target(&mut conn, "bob", None).await.unwrap(), None);
+
+        insert_domain(&db, "new.com", "alice").await;
+        insert_mailbox(&db, "abuse@new.com", "alice").await;
+        insert_rfc2142_aliases(&mut conn, "new.com", "alice", "alice@example.com")
+            .await
+            .unwrap();
+        let aliases: Vec<(String, String, String)> =
+            sqlx::query_as("SELECT address, target, owner FROM aliases ORDER BY address")
+                .fetch_all(&*db)
+                .await
+                .unwrap();
+        // The existing mailbox keeps receiving its mail
+        assert_eq!(
+            aliases,
+            [(
+                "postmaster@new.com".to_string(),
+                "alice@example.com".to_string(),
+                "alice".to_string()
+            )]
+        );
+    }
+
+    async fn public_domains(pool: &sqlx::SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT domain FROM domains WHERE public = TRUE ORDER BY domain")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn domains_are_made_public_together() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        for domain in ["a.com", "b.com", "c.com"] {
+            insert_domain(&db, domain, "admin").await;
+        }
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        let admin = admin("admin");
+
+        let domains = vec!["a.com".to_string(), "b.com".to_string(), "missing.com".to_string()];
+        assert_eq!(set_public(&db, &admin, domains.clone(), true).await.unwrap(), 2);
+        assert_eq!(public_domains(&db).await, ["a.com", "b.com"]);
+        assert_eq!(
+            set_public(&db, &admin, vec!["b.com".to_string()], false).await.unwrap(),
+            1
+        );
+        assert_eq!(public_domains(&db).await, ["a.com"]);
+
+        // A provisioned domain rejects the whole batch
+        sqlx::query("UPDATE domains SET provisioned = TRUE WHERE domain = 'c.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert!(
+            set_public(&db, &admin, vec!["b.com".to_string(), "c.com".to_string()], true)
+                .await
+                .is_err()
+        );
+        assert_eq!(public_domains(&db).await, ["a.com"]);
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_make_domains_public() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "a.com", "alice").await;
+        // Without an admin session, nothing is changed
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+        assert!(set_domains_public(vec!["a.com".to_string()], true).await.is_err());
+        assert!(public_domains(&db).await.is_empty());
+        runtime.dispose();
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::Utc;
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    count_cache::{CountCache, CountTable},
+};
+
+/// How often expired aliases are deactivated and the trash is purged
+const SWEEP_INTERVAL_SECS: u64 = 10 * 60;
+
+/// Deactivates all active aliases whose expiry date has passed.
+/// Returns the number of deactivated aliases.
+pub async fn deactivate_expired_aliases(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE aliases SET active = FALSE WHERE active = TRUE AND expires_at IS NOT NULL AND expires_at <= ?",
+    )
+    .bind(Utc::now())
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Permanently removes deleted aliases that are older than the given number of days.
+/// Returns the number of purged aliases.
+pub async fn purge_deleted_aliases(pool: &SqlitePool, trash_days: u32) -> Result<u64, sqlx::Error> {
+    // Compared in SQLite, so the format matches the CURRENT_TIMESTAMP of deleted_at
+    Ok(
+        sqlx::query("DELETE FROM deleted_aliases WHERE deleted_at <= datetime('now', '-' || ? || ' days')")
+            .bind(trash_days)
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
+
+/// Starts the background task deactivating expired aliases and purging the trash
+pub fn spawn(pool: SqlitePool, config: &Config, count_cache: CountCache) {
+    let trash_days = config.alias_trash_days;
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(SWEEP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match deactivate_expired_aliases(&pool).await {
+                Err(e) => log::error!("failed to deactivate expired aliases: {e}"),
+                Ok(0) => {}
+                Ok(n) => {
+                    // The counts of active aliases changed
+                    count_cache.invalidate(CountTable::Aliases);
+                    log::info!("deactivated {n} expired aliases");
+                }
+            }
+            match purge_deleted_aliases(&pool, trash_days).await {
+                Err(e) => log::error!("failed to purge deleted aliases: {e}"),
+                Ok(0) => {}
+                Ok(n) => log::info!("purged {n} deleted aliases"),
+            }
+        }
+    });
+}
@@ -1,4 +1,7 @@
-use crate::error_template::{AppError, ErrorTemplate};
+use crate::{
+    config::Config,
+    error_template::{AppError, ErrorTemplate},
+};
 use axum::{
     body::Body,
     extract::State,
@@ -6,16 +9,21 @@ use axum::{
     response::{IntoResponse, Response as AxumResponse},
 };
 use leptos::{view, Errors, LeptosOptions};
+use std::{path::Path, sync::Arc};
 use tower::ServiceExt;
-use tower_http::services::ServeDir;
+use tower_http::services::{ServeDir, ServeFile};
 
 pub async fn file_and_error_handler(
     uri: Uri,
     State(options): State<LeptosOptions>,
+    State(config): State<Arc<Config>>,
     req: Request<Body>,
 ) -> AxumResponse {
     let root = options.site_root.clone();
-    let res = get_static_file(uri.clone(), &root).await.unwrap();
+    let res = match branding_override(&config, uri.path()) {
+        Some(file) => get_branding_file(uri.clone(), file).await.unwrap(),
+        None => get_static_file(uri.clone(), &root).await.unwrap(),
+    };
 
     if res.status() == StatusCode::OK {
         res.into_response()
@@ -43,3 +51,23 @@ async fn get_static_file(uri: Uri, root: &str) -> Result<Response<Body>, (Status
         )),
     }
 }
+
+/// Returns the file configured to replace the given branding asset, if any.
+fn branding_override<'a>(config: &'a Config, path: &str) -> Option<&'a Path> {
+    match path {
+        "/logo.svg" => config.brand_logo.as_deref(),
+        "/favicon.ico" => config.brand_favicon.as_deref(),
+        _ => None,
+    }
+}
+
+async fn get_branding_file(uri: Uri, file: &Path) -> Result<Response<Body>, (StatusCode, String)> {
+    let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    match ServeFile::new(file).oneshot(req).await {
+        Ok(res) => Ok(res.into_response()),
+        Err(err) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Something went wrong: {err}"),
+        )),
+    }
+}
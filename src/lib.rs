@@ -3,18 +3,48 @@ pub mod aliases;
 pub mod api;
 pub mod app;
 pub mod auth;
+#[cfg(feature = "ssr")]
+pub mod config;
+#[cfg(feature = "ssr")]
+pub mod count_cache;
 pub mod database;
+#[cfg(feature = "ssr")]
+pub mod digest;
+pub mod dns_records;
+#[cfg(feature = "ssr")]
+pub mod domain_cache;
+pub mod domain_routes;
 pub mod domains;
 pub mod error_template;
 #[cfg(feature = "ssr")]
+pub mod expiry;
+#[cfg(feature = "ssr")]
 pub mod fileserv;
+#[cfg(feature = "ssr")]
+pub mod mail;
 pub mod mailboxes;
 #[cfg(feature = "ssr")]
+pub mod oidc;
+#[cfg(feature = "ssr")]
 pub mod provision;
 #[cfg(feature = "ssr")]
+pub mod rate_limit;
+pub mod recipients;
+#[cfg(feature = "ssr")]
+pub mod stalwart;
+#[cfg(feature = "ssr")]
 pub mod state;
+#[cfg(all(test, feature = "ssr"))]
+mod testing;
+#[cfg(feature = "ssr")]
+pub mod tls;
+pub mod totp;
+#[cfg(feature = "ssr")]
+pub mod ttl_cache;
 pub mod users;
 pub mod utils;
+#[cfg(feature = "ssr")]
+pub mod welcome;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -2,17 +2,27 @@ use std::collections::VecDeque;
 use std::ops::Range;
 
 use crate::aliases::validate_address;
-use crate::users::is_valid_pw;
-use crate::utils::{DeleteModal, EditModal, Select};
-use crate::utils::{SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer};
+use crate::users::{password_problems, regenerate_api_key_for, ApiTokenModal, OwnerInput};
+use crate::utils::{DeleteModal, EditModal, FieldErrorMessage, Select, ValidationErrors};
+use crate::utils::{
+    OptionTimediffRenderer, SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer,
+};
+use crate::utils::{ProvisionedBadge, ProvisionedFilter, PROVISIONED_TOOLTIP};
 
-use crate::auth::User;
+use crate::auth::{get_capabilities, User};
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{
+    ensure_not_provisioned, push_order_by, push_owner_scope, LogQuery, OwnerScope, Provisioned,
+};
+#[cfg(feature = "ssr")]
+use crate::utils::map_unique_violation;
 use chrono::{DateTime, Utc};
 use leptos::leptos_dom::is_browser;
 use leptos::{ev::MouseEvent, logging::error, *};
 use leptos_icons::Icon;
+use leptos_router::escape;
 use leptos_struct_table::*;
-use leptos_use::use_debounce_fn_with_arg;
+use leptos_use::{use_debounce_fn, use_debounce_fn_with_arg};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
 use sqlx::QueryBuilder;
@@ -30,6 +40,14 @@ pub struct Mailbox {
     pub owner: String,
     #[table(class = "w-1", title = "Created", renderer = "TimediffRenderer")]
     pub created_at: DateTime<Utc>,
+    #[table(class = "w-1", title = "Last login", renderer = "OptionTimediffRenderer")]
+    pub last_login_at: Option<DateTime<Utc>>,
+    /// The maximum number of aliases this mailbox may own, or None for unlimited
+    #[table(skip)]
+    pub alias_limit: Option<i64>,
+    /// Whether the entry is managed by the provisioning file
+    #[table(skip)]
+    pub provisioned: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -38,36 +56,89 @@ pub struct MailboxQuery {
     sort: VecDeque<(usize, ColumnSort)>,
     range: Range<usize>,
     search: String,
+    #[serde(default)]
+    provisioned: Option<bool>,
+}
+
+/// Parses the alias limit of a mailbox as entered by the user. An empty input means unlimited.
+fn parse_alias_limit(alias_limit: &str) -> anyhow::Result<Option<i64>> {
+    let alias_limit = alias_limit.trim();
+    if alias_limit.is_empty() {
+        return Ok(None);
+    }
+
+    match alias_limit.parse::<i64>() {
+        Ok(x) if x >= 0 => Ok(Some(x)),
+        _ => anyhow::bail!("alias limit must be a non-negative integer"),
+    }
+}
+
+/// Whether the given owner is a mailbox that already owns as many aliases as its limit allows.
+/// Owners that aren't mailboxes or have no limit are never limited.
+#[cfg(feature = "ssr")]
+pub async fn alias_limit_reached(conn: impl sqlx::SqliteExecutor<'_>, owner: &str) -> Result<bool, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT NOT ");
+    push_alias_limit_not_reached(&mut query, owner);
+    query.logged().build_query_scalar::<bool>().fetch_one(conn).await
+}
+
+/// Pushes a condition that holds unless the given owner is a mailbox that reached its alias limit.
+/// Statements that add aliases to an owner include it, so concurrent requests can't exceed the
+/// limit in between the check and the insert. See [`alias_limit_reached`] to report the reason.
+#[cfg(feature = "ssr")]
+pub fn push_alias_limit_not_reached<'a>(query: &mut QueryBuilder<'a, sqlx::Sqlite>, owner: &'a str) {
+    query.push("NOT EXISTS (SELECT * FROM mailboxes AS limited WHERE limited.address = ");
+    query.push_bind(owner);
+    query.push(
+        " AND limited.alias_limit <= (SELECT COUNT(*) FROM aliases AS owned WHERE owned.owner = limited.address))",
+    );
 }
 
 #[server]
 pub async fn allowed_targets() -> Result<Vec<String>, ServerFnError> {
     let user = crate::auth::auth_any().await?;
 
-    // Mailbox users can only target themselves
+    let pool = crate::database::ssr::pool()?;
+
+    // Mailbox users can only target themselves and their verified recipients
     if user.mailbox_owner.is_some() {
-        return Ok(vec![user.username]);
+        let recipients = crate::recipients::verified_recipients(&pool, &user.username).await?;
+        return Ok(std::iter::once(user.username).chain(recipients).collect());
     }
 
     let mut query = QueryBuilder::new("SELECT address FROM mailboxes");
     query.push(" WHERE owner = ");
     query.push_bind(&user.username);
 
-    let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_scalar::<String>().fetch_all(&pool).await?)
+    Ok(query.logged().build_query_scalar::<String>().fetch_all(&pool).await?)
+}
+
+/// Whether the current user may create new mailboxes. Admins always can,
+/// normal users only if `IDMAIL_USER_MAILBOX_CREATION` isn't disabled.
+#[server]
+pub async fn can_create_mailboxes() -> Result<bool, ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    Ok(may_create_mailboxes(&user, &*crate::database::ssr::config()?))
+}
+
+#[cfg(feature = "ssr")]
+fn may_create_mailboxes(user: &User, config: &crate::config::Config) -> bool {
+    user.admin || config.user_mailbox_creation
 }
 
 #[server]
 pub async fn list_mailboxes(query: MailboxQuery) -> Result<Vec<Mailbox>, ServerFnError> {
     let user = crate::auth::auth_user().await?;
 
-    let MailboxQuery { sort, range, search } = query;
+    let MailboxQuery {
+        sort,
+        range,
+        search,
+        provisioned,
+    } = query;
 
     let mut query = QueryBuilder::new("SELECT * FROM mailboxes WHERE 1=1");
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
-    }
+    push_owner_scope(&mut query, &user, OwnerScope::Mailboxes);
     if !search.is_empty() {
         query.push(" AND ( address LIKE concat('%', ");
         query.push_bind(&search);
@@ -76,54 +147,223 @@ pub async fn list_mailboxes(query: MailboxQuery) -> Result<Vec<Mailbox>, ServerF
         query.push(", '%') )");
     }
 
-    if let Some(order) = Mailbox::sorting_to_sql(&sort) {
-        query.push(" ");
-        query.push(order);
+    if let Some(provisioned) = provisioned {
+        query.push(" AND provisioned = ");
+        query.push_bind(provisioned);
     }
 
+    push_order_by(&mut query, Mailbox::sorting_to_sql(&sort), "address");
+
     query.push(" LIMIT ");
     query.push_bind(range.len() as i64);
     query.push(" OFFSET ");
     query.push_bind(range.start as i64);
 
     let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_as::<Mailbox>().fetch_all(&pool).await?)
+    Ok(query.logged().build_query_as::<Mailbox>().fetch_all(&pool).await?)
 }
 
 #[server]
 pub async fn mailbox_count() -> Result<usize, ServerFnError> {
     let user = crate::auth::auth_user().await?;
 
-    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM mailboxes");
-    if !user.admin {
-        query.push(" WHERE owner = ");
-        query.push_bind(&user.username);
-    }
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM mailboxes WHERE 1=1");
+    push_owner_scope(&mut query, &user, OwnerScope::Mailboxes);
 
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
-
-    Ok(count as usize)
+    let load = async {
+        let count = query.logged().build_query_scalar::<i64>().fetch_one(&pool).await?;
+        Ok::<_, ServerFnError>(count as usize)
+    };
+    crate::database::ssr::count_cache()?
+        .get_or_load(
+            crate::count_cache::CountTable::Mailboxes,
+            &user.username,
+            String::new(),
+            load,
+        )
+        .await
 }
 
 #[server]
 pub async fn delete_mailbox(address: String) -> Result<(), ServerFnError> {
     let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Mailboxes, &address).await?;
 
     let mut query = QueryBuilder::new("DELETE FROM mailboxes WHERE address = ");
     query.push_bind(address);
 
     // Non-admins can only delete their own mailboxes
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
+    push_owner_scope(&mut query, &user, OwnerScope::Mailboxes);
+
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Mailboxes);
+    Ok(())
+}
+
+/// The normalized and validated fields of a mailbox that is about to be created or updated
+#[cfg(feature = "ssr")]
+struct CheckedMailbox {
+    address: String,
+    domain: String,
+    owner: String,
+}
+
+/// Validates the inputs of [`create_or_update_mailbox`] without changing anything,
+/// so the same rules apply to the dry run in [`validate_mailbox`]. The password is
+/// only checked when saving, so it never has to be sent while typing.
+#[cfg(feature = "ssr")]
+async fn check_mailbox(
+    user: &User,
+    pool: &sqlx::SqlitePool,
+    old_address: Option<&str>,
+    localpart: &str,
+    domain: &str,
+    owner: &str,
+) -> Result<CheckedMailbox, ServerFnError> {
+    use crate::aliases::normalize_domain;
+    use crate::domains::allowed_domain_owner_for;
+
+    let config = crate::database::ssr::config()?;
+    if old_address.is_none() && !may_create_mailboxes(user, &config) {
+        return Err(ServerFnError::new("Mailbox creation is restricted to admins"));
     }
 
-    let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    // Only admins can assign other owners
+    let owner = if user.admin { owner.trim() } else { &user.username };
+    // Empty owner -> self owned
+    let owner = if owner.is_empty() { &user.username } else { owner };
+    if user.admin {
+        crate::users::check_owner_exists(pool, owner, false).await?;
+    }
+
+    // Check if address is valid
+    let domain = normalize_domain(domain, config.reject_noncanonical_domains)
+        .map_err(|e| ValidationErrors::field("domain", e))?;
+    let Some(domain_owner) = allowed_domain_owner_for(pool, user, &domain).await? else {
+        return Err(ValidationErrors::field("domain", "domain must be set to a valid domain").into());
+    };
+
+    let address = check_new_address(
+        pool,
+        &config,
+        old_address,
+        localpart,
+        &domain,
+        user.admin || domain_owner == user.username,
+        user.admin,
+    )
+    .await?;
+
+    Ok(CheckedMailbox {
+        address,
+        domain,
+        owner: owner.to_string(),
+    })
+}
+
+/// Normalizes the address of a new or renamed mailbox according to the case setting of its domain
+/// and makes sure that it is valid, not reserved and not in use yet. Reserved localparts are allowed
+/// for domain owners, globally reserved ones only for admins. Also used by signups.
+#[cfg(feature = "ssr")]
+pub(crate) async fn check_new_address(
+    pool: &sqlx::SqlitePool,
+    config: &crate::config::Config,
+    old_address: Option<&str>,
+    localpart: &str,
+    domain: &str,
+    allow_reserved: bool,
+    admin: bool,
+) -> Result<String, ServerFnError> {
+    use crate::aliases::check_globally_reserved;
+    use crate::domains::localpart_case_sensitive;
+
+    let case_sensitive = localpart_case_sensitive(pool, domain).await?;
+    let localpart = if case_sensitive {
+        localpart.to_string()
+    } else {
+        localpart.to_lowercase()
+    };
+    let address =
+        validate_address(&localpart, domain, allow_reserved).map_err(|e| ValidationErrors::field("localpart", e))?;
+    check_globally_reserved(config, &localpart, admin).map_err(|e| ValidationErrors::field("localpart", e))?;
+
+    // Mailboxes must never be shadowed by existing aliases
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM aliases WHERE address = ");
+    query.push_bind(&address);
+    if !case_sensitive {
+        query.push(" COLLATE NOCASE");
+    }
+    if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? > 0 {
+        return Err(ValidationErrors::field("localpart", format!("'{address}' is already in use by an alias!")).into());
+    }
+
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM mailboxes WHERE address = ");
+    query.push_bind(&address);
+    if !case_sensitive {
+        query.push(" COLLATE NOCASE");
+    }
+    if let Some(old_address) = old_address {
+        // Renaming a mailbox must not conflict with itself
+        query.push(" AND address != ");
+        query.push_bind(old_address);
+    }
+    if query.logged().build_query_scalar::<i64>().fetch_one(pool).await? > 0 {
+        return Err(ValidationErrors::field("localpart", format!("'{address}' already exists!")).into());
+    }
+
+    Ok(address)
+}
+
+/// Rejects a mailbox password that equals the password of the given owner
+#[cfg(feature = "ssr")]
+pub(crate) async fn reject_owner_password_reuse(
+    pool: &sqlx::SqlitePool,
+    owner: &str,
+    password: &str,
+) -> Result<(), ServerFnError> {
+    use argon2::{
+        password_hash::{PasswordHash, PasswordVerifier},
+        Argon2,
+    };
+
+    let owner_hash = sqlx::query_scalar::<_, String>("SELECT password_hash FROM users WHERE username = ?")
+        .bind(owner)
+        .fetch_optional(pool)
+        .await?;
+    if let Some(owner_hash) = owner_hash {
+        let reused = PasswordHash::new(&owner_hash)
+            .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+            .is_ok();
+        if reused {
+            return Err(ValidationErrors::field(
+                "password",
+                "The mailbox password must differ from the password of its owner",
+            )
+            .into());
+        }
+    }
     Ok(())
 }
 
+/// Runs the validation of [`create_or_update_mailbox`] without creating or updating
+/// anything, and returns the errors that saving would currently result in.
+#[server]
+pub async fn validate_mailbox(
+    old_address: Option<String>,
+    localpart: String,
+    domain: String,
+    owner: String,
+) -> Result<ValidationErrors, ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    match check_mailbox(&user, &pool, old_address.as_deref(), &localpart, &domain, &owner).await {
+        Ok(_) => Ok(ValidationErrors::default()),
+        Err(e) => ValidationErrors::from_server_error(&e).ok_or(e),
+    }
+}
+
 #[server]
 pub async fn create_or_update_mailbox(
     old_address: Option<String>,
@@ -132,28 +372,30 @@ pub async fn create_or_update_mailbox(
     password: String,
     active: bool,
     owner: String,
+    alias_limit: Option<i64>,
 ) -> Result<(), ServerFnError> {
-    use crate::domains::allowed_domains;
     use crate::users::mk_password_hash;
 
     let user = crate::auth::auth_user().await?;
     let pool = crate::database::ssr::pool()?;
+    if alias_limit.is_some_and(|x| x < 0) {
+        return Err(ValidationErrors::field("alias_limit", "alias limit must be a non-negative integer").into());
+    }
+    // Only admins can limit mailboxes, so users can't lift limits that were imposed on them
+    let alias_limit = if user.admin { alias_limit } else { None };
+    let CheckedMailbox { address, domain, owner } =
+        check_mailbox(&user, &pool, old_address.as_deref(), &localpart, &domain, &owner).await?;
 
-    // Only admins can assign other owners
-    let owner = if user.admin { owner.trim() } else { &user.username };
-    // Empty owner -> self owned
-    let owner = if owner.is_empty() { &user.username } else { owner };
-
-    // Check if address is valid
-    let allowed_domains = allowed_domains().await?;
-    let Some((_, domain_owner)) = allowed_domains.iter().find(|x| x.0 == domain) else {
-        return Err(ServerFnError::new("domain must be set to a valid domain"));
-    };
+    // Optionally make sure that the mailbox doesn't reuse the password of its owner
+    if !password.is_empty() && crate::database::ssr::config()?.reject_owner_password_reuse {
+        reject_owner_password_reuse(&pool, &owner, &password).await?;
+    }
 
-    let address = validate_address(&localpart, &domain, user.admin || *domain_owner == user.username)
-        .map_err(ServerFnError::new)?;
+    // New active mailboxes are greeted once they were created
+    let welcome = (old_address.is_none() && active).then(|| (address.clone(), domain.clone(), owner.clone()));
 
     let mut query = if let Some(old_address) = old_address {
+        ensure_not_provisioned(&pool, &user, Provisioned::Mailboxes, &old_address).await?;
         let mut query = QueryBuilder::new("UPDATE mailboxes SET address = ");
         query.push_bind(&address);
         query.push(", domain = ");
@@ -167,12 +409,13 @@ pub async fn create_or_update_mailbox(
         query.push_bind(active);
         query.push(", owner = ");
         query.push_bind(owner);
+        if user.admin {
+            query.push(", alias_limit = ");
+            query.push_bind(alias_limit);
+        }
         query.push(" WHERE address = ");
         query.push_bind(old_address);
-        if !user.admin {
-            query.push(" AND owner = ");
-            query.push_bind(&user.username);
-        }
+        push_owner_scope(&mut query, &user, OwnerScope::Mailboxes);
         // make sure that no alias exists with that address
         query.push(" AND NOT EXISTS (SELECT * FROM aliases WHERE address = ");
         query.push_bind(&address);
@@ -181,7 +424,8 @@ pub async fn create_or_update_mailbox(
         query
     } else {
         let password_hash = mk_password_hash(&password)?;
-        let mut query = QueryBuilder::new("INSERT INTO mailboxes (address, domain, password_hash, active, owner)");
+        let mut query =
+            QueryBuilder::new("INSERT INTO mailboxes (address, domain, password_hash, active, owner, alias_limit)");
         query.push("SELECT ");
         query.push_bind(&address);
         query.push(", ");
@@ -192,6 +436,8 @@ pub async fn create_or_update_mailbox(
         query.push_bind(active);
         query.push(", ");
         query.push_bind(owner);
+        query.push(", ");
+        query.push_bind(alias_limit);
         // make sure that no alias exists with that address
         query.push(" WHERE NOT EXISTS (SELECT * FROM aliases WHERE address = ");
         query.push_bind(&address);
@@ -200,8 +446,21 @@ pub async fn create_or_update_mailbox(
         query
     };
 
-    if query.build().execute(&pool).await?.rows_affected() == 0 {
-        return Err(ServerFnError::new("This address is already in use by an alias!"));
+    let result = query
+        .logged()
+        .build()
+        .execute(&pool)
+        .await
+        .map_err(map_unique_violation("localpart", &address))?;
+    if result.rows_affected() == 0 {
+        return Err(ValidationErrors::field("localpart", "This address is already in use by an alias!").into());
+    }
+    // Renaming a mailbox also moves the aliases it owns
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Mailboxes);
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Aliases);
+
+    if let Some((address, domain, owner)) = welcome {
+        crate::welcome::spawn_welcome(&*crate::database::ssr::config()?, address, domain, owner);
     }
 
     Ok(())
@@ -210,19 +469,17 @@ pub async fn create_or_update_mailbox(
 #[server]
 pub async fn update_mailbox_active(address: String, active: bool) -> Result<(), ServerFnError> {
     let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Mailboxes, &address).await?;
     let mut query = QueryBuilder::new("UPDATE mailboxes SET active = ");
     query.push_bind(active);
     query.push(" WHERE address = ");
     query.push_bind(address);
 
     // Non-admins can only change their own domains
-    if !user.admin {
-        query.push(" AND owner = ");
-        query.push_bind(&user.username);
-    }
+    push_owner_scope(&mut query, &user, OwnerScope::Mailboxes);
 
-    let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
     Ok(())
 }
 
@@ -230,12 +487,14 @@ pub async fn update_mailbox_active(address: String, active: bool) -> Result<(),
 pub struct MailboxTableDataProvider {
     sort: VecDeque<(usize, ColumnSort)>,
     pub search: RwSignal<String>,
+    pub provisioned: RwSignal<Option<bool>>,
 }
 
 impl TableDataProvider<Mailbox> for MailboxTableDataProvider {
     async fn get_rows(&self, range: Range<usize>) -> Result<(Vec<Mailbox>, Range<usize>), String> {
         list_mailboxes(MailboxQuery {
             search: self.search.get_untracked().trim().to_string(),
+            provisioned: self.provisioned.get_untracked(),
             sort: self.sort.clone(),
             range: range.clone(),
         })
@@ -257,6 +516,7 @@ impl TableDataProvider<Mailbox> for MailboxTableDataProvider {
 
     fn track(&self) {
         self.search.track();
+        self.provisioned.track();
     }
 }
 
@@ -289,19 +549,47 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
         });
     };
 
+    let (can_create, set_can_create) = create_signal(user.admin);
     if is_browser() {
         refresh_domains();
+        spawn_local(async move {
+            match can_create_mailboxes().await {
+                Err(e) => error!("Failed to check whether mailboxes can be created: {}", e),
+                Ok(allowed) => set_can_create(allowed),
+            }
+        });
     }
 
     let delete_modal_mailbox = create_rw_signal(None);
     let edit_modal_mailbox = create_rw_signal(None);
 
+    let api_token_modal_open = create_rw_signal(false);
+    let api_token_modal_token = create_rw_signal("".to_string());
+    // Regenerating revokes all other tokens of the mailbox, so this always needs confirmation
+    let regenerate_modal_mailbox = create_rw_signal(None);
+    let on_regenerate = move |(data, on_error): (Option<String>, Callback<ServerFnError>)| {
+        let Some(address) = data else {
+            return;
+        };
+        spawn_local(async move {
+            match regenerate_api_key_for(address).await {
+                Err(e) => on_error(e),
+                Ok(api_token) => {
+                    regenerate_modal_mailbox.set(None);
+                    api_token_modal_token.set(api_token);
+                    api_token_modal_open.set(true);
+                }
+            }
+        });
+    };
+
     let (edit_modal_input_localpart, set_edit_modal_input_localpart) = create_signal("".to_string());
     let (edit_modal_input_domain, set_edit_modal_input_domain) = create_signal("".to_string());
     let (edit_modal_input_password, set_edit_modal_input_password) = create_signal("".to_string());
     let (edit_modal_input_password_repeat, set_edit_modal_input_password_repeat) = create_signal("".to_string());
     let (edit_modal_input_active, set_edit_modal_input_active) = create_signal(true);
     let (edit_modal_input_owner, set_edit_modal_input_owner) = create_signal("".to_string());
+    let (edit_modal_input_alias_limit, set_edit_modal_input_alias_limit) = create_signal("".to_string());
     let edit_modal_open_with = Callback::new(move |edit_mailbox: Option<Mailbox>| {
         refresh_domains();
         edit_modal_mailbox.set(Some(edit_mailbox.clone()));
@@ -322,6 +610,7 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
             }
             set_edit_modal_input_active(edit_mailbox.active);
             set_edit_modal_input_owner(edit_mailbox.owner.clone());
+            set_edit_modal_input_alias_limit(edit_mailbox.alias_limit.map(|x| x.to_string()).unwrap_or_default());
         } else {
             // Only set the input domain if the current one is not in the list
             // of allowed domains. This allows users to keep the old value
@@ -333,10 +622,11 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
             }
             set_edit_modal_input_active(true);
             set_edit_modal_input_owner("".to_string());
+            set_edit_modal_input_alias_limit("".to_string());
         }
     });
 
-    let on_edit = move |(data, on_error): (Option<Mailbox>, Callback<String>)| {
+    let on_edit = move |(data, on_error): (Option<Mailbox>, Callback<ServerFnError>)| {
         spawn_local(async move {
             if let Err(e) = create_or_update_mailbox(
                 data.map(|x| x.address),
@@ -345,10 +635,11 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                 edit_modal_input_password.get_untracked(),
                 edit_modal_input_active.get_untracked(),
                 edit_modal_input_owner.get_untracked(),
+                parse_alias_limit(&edit_modal_input_alias_limit.get_untracked()).unwrap_or_default(),
             )
             .await
             {
-                on_error(e.to_string())
+                on_error(e)
             } else {
                 reload.notify();
                 edit_modal_mailbox.set(None);
@@ -365,6 +656,14 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
         });
     };
 
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let edit_provisioned = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|x| x.edit_provisioned)
+    };
+
     #[allow(unused_variables, non_snake_case)]
     let mailbox_row_renderer = move |class: Signal<String>,
                                      row: Mailbox,
@@ -373,23 +672,55 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                                      on_select: EventHandler<MouseEvent>,
                                      on_change: EventHandler<ChangeEvent<Mailbox>>| {
         let delete_address = row.address.clone();
+        let regenerate_address = row.address.clone();
         let edit_mailbox = row.clone();
+        let provisioned = row.provisioned;
+        let locked = move || provisioned && !edit_provisioned();
         view! {
             <tr class=class on:click=move |mouse_event| on_select.run(mouse_event)>
                 {row.render_row(index, on_change)}
                 <td class="w-1 px-4 py-2 whitespace-nowrap text-ellipsis">
-                    <div class="inline-flex items-center rounded-md">
+                    {provisioned.then(|| view! { <ProvisionedBadge/> })}
+                    <div
+                        class="inline-flex items-center rounded-md"
+                        title=move || locked().then_some(PROVISIONED_TOOLTIP)
+                    >
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| edit_modal_open_with(Some(edit_mailbox.clone()))
+                            disabled=locked
                         >
                             <Icon icon=icondata::FiEdit class="w-5 h-5"/>
                         </button>
+                        <a
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            title="Export aliases targeting this mailbox"
+                            href=format!("/api/export/aliases?target={}&format=csv", escape(&row.address))
+                            rel="external"
+                            download
+                        >
+                            <Icon icon=icondata::FiDownload class="w-5 h-5"/>
+                        </a>
+                        <Show when=move || user.admin>
+                            <button
+                                class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
+                                title="Regenerate the API token of this mailbox"
+                                on:click={
+                                    let regenerate_address = regenerate_address.clone();
+                                    move |_| regenerate_modal_mailbox.set(Some(Some(regenerate_address.clone())))
+                                }
+
+                                disabled=locked
+                            >
+                                <Icon icon=icondata::FiKey class="w-5 h-5"/>
+                            </button>
+                        </Show>
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| {
                                 delete_modal_mailbox.set(Some(delete_address.clone()));
                             }
+                            disabled=locked
                         >
 
                             <Icon icon=icondata::FiTrash2 class="w-5 h-5"/>
@@ -401,14 +732,23 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
     };
 
     let has_password_mismatch = move || edit_modal_input_password() != edit_modal_input_password_repeat();
-    let has_invalid_password = create_memo(move |_| {
+    let password_policy = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .map(|x| x.password_policy)
+            .unwrap_or_default()
+    };
+    let password_errors = create_memo(move |_| {
         // Either we edit an existing mailbox (in which case an empty password means no change)
-        // or the password is of correct length.
+        // or the password must satisfy the policy.
         let is_new = matches!(edit_modal_mailbox.get(), Some(None));
-        let is_valid_pw = is_valid_pw(&edit_modal_input_password());
-        let valid = is_valid_pw || (!is_new && edit_modal_input_password().is_empty());
-        !valid
+        if !is_new && edit_modal_input_password().is_empty() {
+            return Vec::new();
+        }
+        password_problems(&edit_modal_input_password(), &password_policy())
     });
+    let has_invalid_password = move || password_errors.with(|x| !x.is_empty());
     let has_invalid_address = create_memo(move |_| {
         validate_address(
             &edit_modal_input_localpart(),
@@ -417,6 +757,8 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
         )
         .is_err()
     });
+    let has_invalid_alias_limit = create_memo(move |_| parse_alias_limit(&edit_modal_input_alias_limit()).is_err());
+
     let errors = create_memo(move |_| {
         let mut errors = Vec::new();
         if let Err(e) = validate_address(
@@ -429,12 +771,50 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
         if has_password_mismatch() {
             errors.push("Passwords don't match".to_string());
         }
-        if has_invalid_password() {
-            errors.push("Password must be between 12 and 512 characters".to_string());
+        errors.extend(password_errors());
+        if let Err(e) = parse_alias_limit(&edit_modal_input_alias_limit()) {
+            errors.push(e.to_string());
         }
         errors
     });
 
+    // Validate the inputs on the server while typing, so problems show up before saving
+    let field_errors = create_rw_signal(ValidationErrors::default());
+    let validate = use_debounce_fn(
+        move || {
+            let Some(data) = edit_modal_mailbox.get_untracked() else {
+                return;
+            };
+            if !errors.get_untracked().is_empty() {
+                field_errors.set(ValidationErrors::default());
+                return;
+            }
+            spawn_local(async move {
+                let result = validate_mailbox(
+                    data.map(|x| x.address),
+                    edit_modal_input_localpart.get_untracked(),
+                    edit_modal_input_domain.get_untracked(),
+                    edit_modal_input_owner.get_untracked(),
+                )
+                .await;
+                match result {
+                    Ok(errors) if edit_modal_mailbox.with_untracked(|x| x.is_some()) => field_errors.set(errors),
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to validate mailbox: {}", e),
+                }
+            });
+        },
+        500.0,
+    );
+    create_effect(move |_| {
+        edit_modal_input_localpart.track();
+        edit_modal_input_domain.track();
+        edit_modal_input_owner.track();
+        if edit_modal_mailbox.with(|x| x.is_some()) {
+            validate();
+        }
+    });
+
     view! {
         <div class="h-full flex-1 flex-col mt-12">
             <div class="flex items-center justify-between space-y-2 mb-4">
@@ -451,15 +831,18 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                             on_input(event_target_value(&e));
                         }
                     />
+                    <ProvisionedFilter value=rows.provisioned/>
 
-                    <button
-                        type="button"
-                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-700 hover:bg-blue-500 dark:hover:bg-blue-600 focus:ring-blue-300 dark:focus:ring-blue-900"
-                        on:click=move |_| edit_modal_open_with(None)
-                    >
-                        <Icon icon=icondata::FiPlus class="w-6 h-6 me-2"/>
-                        New
-                    </button>
+                    <Show when=can_create>
+                        <button
+                            type="button"
+                            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 me-2 mb-2 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-700 hover:bg-blue-500 dark:hover:bg-blue-600 focus:ring-blue-300 dark:focus:ring-blue-900"
+                            on:click=move |_| edit_modal_open_with(None)
+                        >
+                            <Icon icon=icondata::FiPlus class="w-6 h-6 me-2"/>
+                            New
+                        </button>
+                    </Show>
                     <div class="flex flex-1"></div>
                     <div class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-right px-4">
                         {count} " results"
@@ -472,7 +855,7 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                             <TableContent
                                 rows
                                 sorting=sorting
-                                sorting_mode=SortingMode::SingleColumn
+                                sorting_mode=SortingMode::MultiColumn
                                 row_renderer=mailbox_row_renderer
                                 reload_controller=reload_controller
                                 loading_row_display_limit=0
@@ -500,12 +883,28 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
             }
         />
 
+        <EditModal
+            data=regenerate_modal_mailbox
+            what="API Token".to_string()
+            get_title=move |x| { x }
+            on_confirm=on_regenerate
+            errors=Signal::derive(Vec::new)
+        >
+            <p class="text-sm text-gray-500 dark:text-gray-400">
+                "Regenerating the API token of this mailbox immediately revokes all of its current tokens. "
+                "Any integration that still uses one of them will stop working until it is updated with the new token."
+            </p>
+        </EditModal>
+
+        <ApiTokenModal open=api_token_modal_open token=api_token_modal_token/>
+
         <EditModal
             data=edit_modal_mailbox
             what="Mailbox".to_string()
             get_title=move |x| { &x.address }
             on_confirm=on_edit
             errors
+            field_errors
         >
             <div class="flex flex-col sm:flex-row">
                 <div class="flex flex-1 flex-col gap-2">
@@ -543,6 +942,8 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     />
                 </div>
             </div>
+            <FieldErrorMessage field="localpart"/>
+            <FieldErrorMessage field="domain"/>
             <div class="flex flex-col gap-2">
                 <label
                     class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
@@ -567,6 +968,7 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                     on:input=move |ev| set_edit_modal_input_password(event_target_value(&ev))
                     prop:value=edit_modal_input_password
                 />
+                <FieldErrorMessage field="password"/>
             </div>
             <div class="flex flex-col gap-2">
                 <label
@@ -593,15 +995,36 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
                 >
                     Owner
                 </label>
-                <input
-                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
-                    type="text"
+                <OwnerInput
+                    id="mailbox_owner"
+                    value=edit_modal_input_owner
+                    set_value=set_edit_modal_input_owner
                     placeholder=user.username.clone()
-                    on:input=move |ev| set_edit_modal_input_owner(event_target_value(&ev))
-                    prop:value=edit_modal_input_owner
-                    disabled=!user.admin
+                    admin=user.admin
+                    allow_mailboxes=false
                 />
             </div>
+            <Show when=move || user.admin>
+                <div class="flex flex-col gap-2">
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="alias_limit"
+                    >
+                        Alias Limit
+                    </label>
+                    <input
+                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                        class=("!ring-4", has_invalid_alias_limit)
+                        class=("!ring-red-500", has_invalid_alias_limit)
+                        type="number"
+                        min="0"
+                        placeholder="Unlimited"
+                        on:input=move |ev| set_edit_modal_input_alias_limit(event_target_value(&ev))
+                        prop:value=edit_modal_input_alias_limit
+                    />
+                    <FieldErrorMessage field="alias_limit"/>
+                </div>
+            </Show>
             <div class="flex flex-row gap-2 mt-2 items-center">
                 <input
                     id="mailboxes_active"
@@ -620,3 +1043,244 @@ pub fn Mailboxes(user: User, reload_stats: Callback<()>) -> impl IntoView {
         </EditModal>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn check_mailbox_rejects_conflicting_renames() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "one@example.com", "alice").await;
+        insert_mailbox(&db, "two@example.com", "alice").await;
+        insert_alias(&db, "alias@example.com", "one@example.com", "alice").await;
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+
+        let alice = user("alice");
+        let rename = |localpart: &'static str| {
+            let (db, alice) = (&db, &alice);
+            async move { check_mailbox(alice, db, Some("one@example.com"), localpart, "example.com", "").await }
+        };
+        assert!(rename("one").await.is_ok());
+        assert!(rename("three").await.is_ok());
+        let error = rename("two").await.err().unwrap();
+        assert_eq!(
+            field_error(error, "localpart").unwrap(),
+            "'two@example.com' already exists!"
+        );
+        let error = rename("alias").await.err().unwrap();
+        assert!(field_error(error, "localpart").unwrap().contains("in use by an alias"));
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn mailbox_duplicates_follow_the_case_setting_of_the_domain() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "strict.com", "alice").await;
+        sqlx::query("UPDATE domains SET localpart_case_sensitive = TRUE WHERE domain = 'strict.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        insert_mailbox(&db, "one@example.com", "alice").await;
+        insert_mailbox(&db, "Taken@example.com", "alice").await;
+        insert_mailbox(&db, "taken@strict.com", "alice").await;
+        insert_alias(&db, "Alias@example.com", "one@example.com", "alice").await;
+        insert_alias(&db, "alias@strict.com", "one@example.com", "alice").await;
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+
+        let alice = user("alice");
+        let rename = |localpart: &'static str, domain: &'static str| {
+            let (db, alice) = (&db, &alice);
+            async move {
+                check_mailbox(alice, db, Some("one@example.com"), localpart, domain, "")
+                    .await
+                    .map(|x| x.address)
+                    .map_err(|e| field_error(e, "localpart").unwrap())
+            }
+        };
+        // Case-insensitive domains lowercase the localpart and find duplicates regardless of case
+        assert_eq!(rename("NEW", "example.com").await.unwrap(), "new@example.com");
+        assert_eq!(
+            rename("TAKEN", "example.com").await.unwrap_err(),
+            "'taken@example.com' already exists!"
+        );
+        assert!(rename("ALIAS", "example.com")
+            .await
+            .unwrap_err()
+            .contains("in use by an alias"));
+        // Case-sensitive domains keep the localpart and only find exact duplicates
+        assert_eq!(rename("Taken", "strict.com").await.unwrap(), "Taken@strict.com");
+        assert_eq!(
+            rename("taken", "strict.com").await.unwrap_err(),
+            "'taken@strict.com' already exists!"
+        );
+        assert_eq!(rename("Alias", "strict.com").await.unwrap(), "Alias@strict.com");
+        assert!(rename("alias", "strict.com")
+            .await
+            .unwrap_err()
+            .contains("in use by an alias"));
+        runtime.dispose();
+    }
+
+    #[tokio::test]
+    async fn mailbox_creation_can_be_restricted_to_admins() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_domain(&db, "admin.example", "admin").await;
+        insert_mailbox(&db, "one@example.com", "alice").await;
+        let (alice, admin) = (user("alice"), admin("admin"));
+
+        for user_mailbox_creation in [true, false] {
+            let config = crate::config::Config {
+                user_mailbox_creation,
+                ..Default::default()
+            };
+            assert!(may_create_mailboxes(&admin, &config));
+            assert_eq!(may_create_mailboxes(&alice, &config), user_mailbox_creation);
+
+            let runtime = server_context(&app_state(&db, config));
+            let created = check_mailbox(&alice, &db, None, "two", "example.com", "").await;
+            assert_eq!(created.is_ok(), user_mailbox_creation);
+            assert!(check_mailbox(&admin, &db, None, "two", "admin.example", "alice")
+                .await
+                .is_ok());
+            // Existing mailboxes can still be edited
+            assert!(
+                check_mailbox(&alice, &db, Some("one@example.com"), "one", "example.com", "")
+                    .await
+                    .is_ok()
+            );
+            runtime.dispose();
+        }
+    }
+
+    #[tokio::test]
+    async fn owner_password_reuse_is_rejected() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        sqlx::query("UPDATE users SET password_hash = ? WHERE username = 'alice'")
+            .bind(crate::users::hash_password("correct horse battery staple").unwrap())
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let error = reject_owner_password_reuse(&db, "alice", "correct horse battery staple")
+            .await
+            .err()
+            .unwrap();
+        assert!(field_error(error, "password").is_some());
+        assert!(reject_owner_password_reuse(&db, "alice", "another password")
+            .await
+            .is_ok());
+    }
+
+    async fn set_alias_limit(pool: &sqlx::SqlitePool, address: &str, alias_limit: Option<i64>) {
+        sqlx::query("UPDATE mailboxes SET alias_limit = ? WHERE address = ?")
+            .bind(alias_limit)
+            .bind(address)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn alias_limits_apply_to_mailboxes_only() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "box@example.com", "alice").await;
+        insert_alias(&db, "one@example.com", "box@example.com", "box@example.com").await;
+        insert_alias(&db, "two@example.com", "box@example.com", "alice").await;
+
+        assert!(!alias_limit_reached(&*db, "box@example.com").await.unwrap());
+        set_alias_limit(&db, "box@example.com", Some(2)).await;
+        assert!(!alias_limit_reached(&*db, "box@example.com").await.unwrap());
+        set_alias_limit(&db, "box@example.com", Some(1)).await;
+        assert!(alias_limit_reached(&*db, "box@example.com").await.unwrap());
+        assert!(!alias_limit_reached(&*db, "alice").await.unwrap());
+
+        // Moving an alias to a mailbox at its limit changes nothing
+        let mut query =
+            QueryBuilder::new("UPDATE aliases SET owner = 'box@example.com' WHERE address = 'two@example.com' AND ");
+        push_alias_limit_not_reached(&mut query, "box@example.com");
+        assert_eq!(query.build().execute(&*db).await.unwrap().rows_affected(), 0);
+    }
+
+    #[tokio::test]
+    async fn account_data_follows_its_account() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "me@example.com", "alice").await;
+        insert_api_token(&db, "me@example.com", "mailbox", "token-1").await;
+        insert_api_token(&db, "alice", "user", "token-2").await;
+        sqlx::query(
+            "INSERT INTO recipients (address, mailbox) VALUES ('ext@example.org', 'me@example.com'); \
+            INSERT INTO quick_alias_nonces (mailbox, nonce, address) VALUES \
+            ('me@example.com', 'n1', 'a@example.com'), ('alice', 'n2', 'b@example.com')",
+        )
+        .execute(&*db)
+        .await
+        .unwrap();
+        let accounts = || async {
+            sqlx::query_scalar::<_, String>(
+                "SELECT mailbox FROM recipients UNION ALL SELECT account FROM api_tokens \
+                UNION ALL SELECT mailbox FROM quick_alias_nonces ORDER BY 1",
+            )
+            .fetch_all(&*db)
+            .await
+            .unwrap()
+        };
+
+        sqlx::query("UPDATE mailboxes SET address = 'new@example.com' WHERE address = 'me@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert_eq!(
+            accounts().await,
+            [
+                "alice",
+                "alice",
+                "new@example.com",
+                "new@example.com",
+                "new@example.com"
+            ]
+        );
+
+        sqlx::query("DELETE FROM mailboxes; DELETE FROM users")
+            .execute(&*db)
+            .await
+            .unwrap();
+        assert!(accounts().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mailboxes_cannot_be_owned_by_mailboxes() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "box@example.com", "alice").await;
+
+        let insert = sqlx::query(
+            "INSERT INTO mailboxes (address, domain, password_hash, owner) \
+            VALUES ('nested@example.com', 'example.com', '', 'box@example.com')",
+        )
+        .execute(&*db)
+        .await
+        .unwrap_err();
+        assert!(insert.to_string().contains("mailbox owner must be a user"), "{insert}");
+
+        let update = sqlx::query("UPDATE mailboxes SET owner = 'box@example.com' WHERE address = 'box@example.com'")
+            .execute(&*db)
+            .await
+            .unwrap_err();
+        assert!(update.to_string().contains("mailbox owner must be a user"), "{update}");
+    }
+}
@@ -4,23 +4,33 @@ use axum::{
     extract::{Path, State},
     http::Request,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
-use axum_session::{SessionConfig, SessionLayer, SessionStore};
+use axum_session::{SessionLayer, SessionStore};
 use axum_session_auth::{AuthConfig, AuthSessionLayer};
 use axum_session_sqlx::SessionSqlitePool;
 use idmail::{
     app::App,
-    auth::{ssr::AuthSession, User},
+    auth::{
+        ssr::{move_legacy_api_tokens, AuthSession},
+        User,
+    },
+    config::Config,
+    count_cache::CountCache,
+    database::ssr::{backup_if_migrations_pending, LogQuery},
+    domain_cache::AllowedDomainsCache,
     fileserv::file_and_error_handler,
     provision::provision,
+    rate_limit::LoginLimiter,
     state::AppState,
+    totp::ssr::{encrypt_plaintext_secrets, SecretKey},
 };
 use leptos::{get_configuration, provide_context};
 use leptos_axum::{generate_route_list, handle_server_fns_with_context, LeptosRoutes};
-use log::{info, warn};
+use log::{error, info, warn};
 use sqlx::{sqlite::SqliteConnectOptions, QueryBuilder, SqlitePool};
+use std::{net::SocketAddr, sync::Arc};
 
 async fn server_fn_handler(
     State(app_state): State<AppState>,
@@ -32,6 +42,11 @@ async fn server_fn_handler(
         move || {
             provide_context(auth_session.clone());
             provide_context(app_state.pool.clone());
+            provide_context(app_state.login_limiter.clone());
+            provide_context(app_state.allowed_domains_cache.clone());
+            provide_context(app_state.count_cache.clone());
+            provide_context(app_state.secret_key.clone());
+            provide_context(app_state.config.clone());
         },
         request,
     )
@@ -49,6 +64,10 @@ async fn leptos_routes_handler(
         move || {
             provide_context(auth_session.clone());
             provide_context(app_state.pool.clone());
+            provide_context(app_state.allowed_domains_cache.clone());
+            provide_context(app_state.count_cache.clone());
+            provide_context(app_state.secret_key.clone());
+            provide_context(app_state.config.clone());
         },
         App,
     );
@@ -65,44 +84,72 @@ async fn connect(filename: impl AsRef<std::path::Path>) -> Result<sqlx::Pool<sql
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt().without_time().init();
+    let config = Config::from_env()?;
 
-    let pool = connect("idmail.db").await?;
+    // Query logging happens at debug level, so it needs a more verbose subscriber
+    let max_level = if config.log_queries {
+        tracing_subscriber::filter::LevelFilter::DEBUG
+    } else {
+        tracing_subscriber::filter::LevelFilter::INFO
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(max_level)
+        .without_time()
+        .init();
+    idmail::database::ssr::set_log_queries(&config);
+    info!("effective configuration: {config:?}");
+
+    let pool = connect(&config.database).await?;
 
     // Auth section
-    let session_config = SessionConfig::default().with_table_name("axum_sessions");
+    let session_config = idmail::auth::ssr::session_config(&config);
     // Disable user caching
     let auth_config = AuthConfig::<String>::default().set_cache(false);
     let session_store =
         SessionStore::<SessionSqlitePool>::new(Some(SessionSqlitePool::from(pool.clone())), session_config).await?;
 
-    sqlx::migrate!().run(&pool).await?;
+    let migrator = sqlx::migrate!();
+    if config.backup_before_migrate {
+        backup_if_migrations_pending(&pool, &migrator, &config.database).await?;
+    }
+    migrator.run(&pool).await?;
+
+    // Secrets that were stored before they were encrypted are encrypted once
+    let secret_key = SecretKey::load_or_create(&config.secret_key_file)?;
+    encrypt_plaintext_secrets(&pool, &secret_key).await?;
+    // Likewise, the API tokens stored on mailboxes before tokens could be named are hashed once
+    move_legacy_api_tokens(&pool).await?;
 
     // Provisioning
-    provision(&pool).await?;
+    provision(&pool, &config).await?;
 
     // Create admin user if none exist
-    let admin_user_exists = QueryBuilder::new("SELECT COUNT(*) FROM users WHERE username = 'admin'")
-        .build_query_scalar::<i64>()
-        .fetch_one(&pool)
-        .await?
-        > 0;
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM users WHERE username = ");
+    query.push_bind(&config.admin_username);
+    let admin_user_exists = query.logged().build_query_scalar::<i64>().fetch_one(&pool).await? > 0;
     if !admin_user_exists {
-        warn!("admin user doesn't exist in database, recovering...");
+        warn!(
+            "admin user '{}' doesn't exist in database, recovering...",
+            config.admin_username
+        );
 
         let mut buf = [0u8; 24];
         getrandom::getrandom(&mut buf)?;
         let password = hex::encode(buf);
 
-        let password_hash = idmail::users::mk_password_hash(&password)
+        let password_hash = idmail::users::hash_password(&password)
             .map_err(|e| anyhow!("failed to hash password for admin user: {e}"))?;
-        sqlx::query("INSERT INTO users (username, password_hash, admin) VALUES ('admin', ?, TRUE)")
+        sqlx::query("INSERT INTO users (username, password_hash, admin) VALUES (?, ?, TRUE)")
+            .bind(&config.admin_username)
             .bind(password_hash)
             .execute(&pool)
             .await
             .map(|_| ())?;
 
-        warn!("created admin user with password '{password}'");
+        warn!(
+            "created admin user '{}' with password '{password}'",
+            config.admin_username
+        );
     }
 
     // Setting this to None means we'll be using cargo-leptos and its env vars
@@ -111,17 +158,61 @@ async fn main() -> Result<()> {
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(App);
 
+    // Single sign-on stays unavailable if the provider can't be reached, password logins still work
+    let oidc = match &config.oidc {
+        None => None,
+        Some(oidc_config) => match idmail::oidc::discover(oidc_config).await {
+            Ok(client) => Some(Arc::new(client)),
+            Err(e) => {
+                error!("single sign-on is unavailable: {e:#}");
+                None
+            }
+        },
+    };
+
+    let tls = config.tls.clone();
     let app_state = AppState {
         leptos_options,
         pool: pool.clone(),
         routes: routes.clone(),
+        login_limiter: LoginLimiter::new(config.failed_login_delay),
+        allowed_domains_cache: AllowedDomainsCache::new(config.allowed_domains_cache_ttl),
+        count_cache: CountCache::new(config.count_cache_ttl),
+        config: Arc::new(config),
+        secret_key,
+        oidc,
     };
 
+    // Weekly activity digests
+    idmail::digest::spawn(pool.clone(), &app_state.config);
+    // Deactivation of expired aliases
+    idmail::expiry::spawn(pool.clone(), &app_state.config, app_state.count_cache.clone());
+
     // build our application with a route
     let app = Router::new()
         .route("/api/*fn_name", get(server_fn_handler).post(server_fn_handler))
         .route("/api/alias/random/new", post(idmail::api::create_simple_login))
-        .route("/api/v1/aliases", post(idmail::api::create_addy_io))
+        .route(
+            "/api/v1/aliases",
+            get(idmail::api::list_aliases).post(idmail::api::create_addy_io),
+        )
+        .route("/api/v1/aliases/unused", delete(idmail::api::delete_unused_aliases))
+        .route(
+            "/api/v1/aliases/:id",
+            get(idmail::api::get_alias)
+                .delete(idmail::api::delete_alias)
+                .patch(idmail::api::update_alias),
+        )
+        .route("/api/v1/aliases/:id/stat", post(idmail::api::increment_alias_stat))
+        .route("/api/v1/ping", get(idmail::api::ping))
+        .route("/api/v1/capabilities", get(idmail::api::capabilities))
+        .route("/api/v1/quick-alias", get(idmail::api::quick_alias))
+        .route("/api/backup", get(idmail::api::download_backup))
+        .route("/api/export/aliases", get(idmail::api::export_aliases_by_target))
+        .route("/api/export/stalwart", get(idmail::api::export_stalwart_snapshot))
+        .route("/api/usage", get(idmail::api::owner_usage))
+        .route("/auth/oidc/login", get(idmail::oidc::login))
+        .route("/auth/oidc/callback", get(idmail::oidc::callback))
         .leptos_routes_with_handler(routes, get(leptos_routes_handler))
         .fallback(file_and_error_handler)
         .layer(
@@ -131,10 +222,19 @@ async fn main() -> Result<()> {
         .layer(SessionLayer::new(session_store))
         .with_state(app_state);
 
-    // run our app with hyper
-    // `axum::Server` is a re-export of `hyper::Server`
-    info!("listening on http://{addr}");
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app.into_make_service()).await?;
+    // run our app with hyper, or serve HTTPS directly if a certificate was configured
+    if let Some((cert, key)) = tls {
+        let tls_config = idmail::tls::load(&cert, &key).await?;
+        idmail::tls::spawn_reload(tls_config.clone(), cert, key);
+
+        info!("listening on https://{addr}");
+        axum_server::bind_rustls(addr, tls_config)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await?;
+    } else {
+        info!("listening on http://{addr}");
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+    }
     Ok(())
 }
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    response::Redirect,
+};
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreIdTokenClaims, CoreProviderMetadata},
+    reqwest::async_http_client,
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope, TokenResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::ApiError,
+    auth::{ssr::AuthSession, User},
+    config::OidcConfig,
+    count_cache::CountTable,
+    state::AppState,
+};
+
+/// The session key under which the state of a pending login is kept until the callback
+const PENDING_LOGIN_KEY: &str = "oidc_pending_login";
+
+/// What the callback needs to verify that it belongs to the login started in this session
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    csrf_token: String,
+    nonce: String,
+    pkce_verifier: String,
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: String,
+    state: String,
+}
+
+/// Discovers the provider and creates the client used for all logins
+pub async fn discover(config: &OidcConfig) -> Result<CoreClient> {
+    let issuer_url = IssuerUrl::new(config.issuer_url.clone()).context("invalid IDMAIL_OIDC_ISSUER")?;
+    let metadata = CoreProviderMetadata::discover_async(issuer_url, async_http_client)
+        .await
+        .with_context(|| format!("failed to discover OIDC provider {}", config.issuer_url))?;
+
+    Ok(CoreClient::from_provider_metadata(
+        metadata,
+        ClientId::new(config.client_id.clone()),
+        Some(ClientSecret::new(config.client_secret.clone())),
+    )
+    .set_redirect_uri(RedirectUrl::new(config.redirect_url.clone()).context("invalid IDMAIL_OIDC_REDIRECT_URL")?))
+}
+
+fn client(app_state: &AppState) -> Result<&CoreClient, ApiError> {
+    app_state
+        .oidc
+        .as_deref()
+        .ok_or_else(|| ApiError::NotFound("Single sign-on is not available".to_string()))
+}
+
+/// Redirects to the provider to start a login
+pub async fn login(State(app_state): State<AppState>, auth_session: AuthSession) -> Result<Redirect, ApiError> {
+    let client = client(&app_state)?;
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (auth_url, csrf_token, nonce) = client
+        .authorize_url(
+            CoreAuthenticationFlow::AuthorizationCode,
+            CsrfToken::new_random,
+            Nonce::new_random,
+        )
+        .add_scope(Scope::new("email".to_string()))
+        .add_scope(Scope::new("profile".to_string()))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    auth_session.session.set(
+        PENDING_LOGIN_KEY,
+        PendingLogin {
+            csrf_token: csrf_token.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        },
+    );
+    Ok(Redirect::to(auth_url.as_str()))
+}
+
+/// Finds the account belonging to the verified claims. The verified email is matched against
+/// mailbox addresses, and the preferred username against users, which are created if
+/// IDMAIL_OIDC_AUTO_PROVISION is enabled.
+async fn find_account(app_state: &AppState, claims: &CoreIdTokenClaims, auto_provision: bool) -> Result<Option<User>> {
+    let pool = &app_state.pool;
+    if let Some(email) = claims.email().filter(|_| claims.email_verified() == Some(true)) {
+        if let Some(user) = User::get(email.as_str(), pool)
+            .await
+            .filter(|x| x.mailbox_owner.is_some())
+        {
+            return Ok(Some(user));
+        }
+    }
+
+    let Some(username) = claims.preferred_username().map(|x| x.as_str().trim()) else {
+        return Ok(None);
+    };
+    if username.is_empty() || username.contains('@') {
+        return Ok(None);
+    }
+    if let Some(user) = User::get(username, pool).await {
+        return Ok(Some(user));
+    }
+
+    if !auto_provision || crate::aliases::check_globally_reserved(&app_state.config, username, false).is_err() {
+        return Ok(None);
+    }
+
+    // The user can only sign in via the provider until an admin sets a password
+    let mut buf = [0u8; 24];
+    getrandom::getrandom(&mut buf)?;
+    let password_hash =
+        crate::users::hash_password(&hex::encode(buf)).map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))?;
+    sqlx::query("INSERT INTO users (username, password_hash, admin, active) VALUES (?, ?, FALSE, TRUE)")
+        .bind(username)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+    app_state.count_cache.invalidate(CountTable::Users);
+    log::info!("created user '{username}' on first single sign-on");
+
+    Ok(User::get(username, pool).await)
+}
+
+/// Completes a login after the provider redirected back, establishing the same session as a password login
+pub async fn callback(
+    State(app_state): State<AppState>,
+    auth_session: AuthSession,
+    Query(query): Query<CallbackQuery>,
+) -> Result<Redirect, ApiError> {
+    let client = client(&app_state)?;
+    let Some(pending) = auth_session.session.get::<PendingLogin>(PENDING_LOGIN_KEY) else {
+        return Err(ApiError::BadRequest("No login in progress".to_string()));
+    };
+    auth_session.session.remove(PENDING_LOGIN_KEY);
+    if query.state != pending.csrf_token {
+        return Err(ApiError::BadRequest("Invalid login state".to_string()));
+    }
+
+    let failed = || ApiError::Unauthorized("Single sign-on failed".to_string());
+    let token_response = client
+        .exchange_code(AuthorizationCode::new(query.code))
+        .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| {
+            log::warn!("failed to exchange OIDC authorization code: {e}");
+            failed()
+        })?;
+    let id_token = token_response.id_token().ok_or_else(|| {
+        log::warn!("OIDC provider returned no id token");
+        failed()
+    })?;
+    let claims = id_token
+        .claims(&client.id_token_verifier(), &Nonce::new(pending.nonce))
+        .map_err(|e| {
+            log::warn!("failed to verify OIDC id token: {e}");
+            failed()
+        })?;
+
+    let auto_provision = app_state.config.oidc.as_ref().is_some_and(|x| x.auto_provision);
+    let user = find_account(&app_state, claims, auto_provision)
+        .await
+        .map_err(|e| {
+            log::error!("failed to look up account for OIDC login: {e}");
+            ApiError::ServerError("Single sign-on failed".to_string())
+        })?
+        .ok_or_else(|| {
+            log::warn!(
+                "rejecting OIDC login of subject '{}': no matching account",
+                claims.subject().as_str()
+            );
+            ApiError::Unauthorized("No account belongs to this identity".to_string())
+        })?;
+    if !user.active {
+        log::warn!("rejecting OIDC login of user '{}': inactive", user.username);
+        return Err(ApiError::Unauthorized("Account is inactive".to_string()));
+    }
+
+    log::info!("login successful for user '{}' via OIDC", user.username);
+    crate::auth::ssr::record_login(app_state.pool.clone(), &user);
+    auth_session.login_user(user.username);
+    auth_session.remember_user(false);
+    Ok(Redirect::to("/"))
+}
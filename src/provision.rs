@@ -1,9 +1,10 @@
 use std::collections::HashSet;
 
 use self::state::State;
+use crate::{config::Config, database::ssr::LogQuery};
 use anyhow::{bail, Context, Result};
 use owo_colors::OwoColorize;
-use sqlx::{QueryBuilder, SqlitePool};
+use sqlx::{QueryBuilder, SqliteConnection, SqlitePool};
 
 mod state {
     use serde::Deserialize;
@@ -27,6 +28,10 @@ mod state {
         #[serde(default = "default_true")]
         pub active: bool,
         pub owner: String,
+        #[serde(default = "default_false")]
+        pub rfc2142_aliases: bool,
+        #[serde(default = "default_false")]
+        pub localpart_case_sensitive: bool,
     }
 
     #[derive(Debug, Deserialize)]
@@ -43,7 +48,11 @@ mod state {
     pub struct Alias {
         pub target: String,
         #[serde(default)]
+        pub name: Option<String>,
+        #[serde(default)]
         pub comment: Option<String>,
+        #[serde(default)]
+        pub daily_limit: Option<u32>,
         #[serde(default = "default_true")]
         pub active: bool,
         pub owner: String,
@@ -77,41 +86,103 @@ fn value_or_file(value: String) -> Result<String> {
     }
 }
 
-pub async fn select_provisioned(pool: &SqlitePool, table: &str, index_column: &str) -> Result<HashSet<String>> {
+pub async fn select_provisioned(
+    conn: &mut SqliteConnection,
+    table: &str,
+    index_column: &str,
+) -> Result<HashSet<String>> {
     let ret = sqlx::query_scalar(&format!("SELECT {index_column} FROM {table} WHERE provisioned = TRUE"))
-        .fetch_all(pool)
+        .fetch_all(&mut *conn)
         .await?;
     Ok(ret.into_iter().collect())
 }
 
 pub async fn delete_orphans(
-    pool: &SqlitePool,
+    conn: &mut SqliteConnection,
     table: &str,
     index_column: &str,
     orphans: &HashSet<String>,
 ) -> Result<()> {
     for orphan in orphans {
-        let mut query = QueryBuilder::new(&format!("DELETE FROM {table} WHERE {index_column} = "));
+        let mut query = QueryBuilder::new(format!("DELETE FROM {table} WHERE {index_column} = "));
         query.push_bind(orphan);
-        query.build().execute(pool).await?;
+        query.logged().build().execute(&mut *conn).await?;
     }
     Ok(())
 }
 
-pub async fn provision_users(pool: &SqlitePool, state: &State) -> Result<()> {
-    let known_users = select_provisioned(pool, "users", "username").await?;
-    let orphaned_users = &known_users - &state.users.keys().cloned().collect::<HashSet<_>>();
+/// Logs the changes that provisioning the given table makes. In dry-run mode,
+/// the affected keys are listed individually instead of just being counted.
+fn log_changes(
+    table: &str,
+    wanted: &HashSet<String>,
+    known: &HashSet<String>,
+    orphans: &HashSet<String>,
+    dry_run: bool,
+) {
+    let mut created = (wanted - known).into_iter().collect::<Vec<_>>();
+    let mut updated = wanted.intersection(known).cloned().collect::<Vec<_>>();
+    let mut deleted = orphans.iter().cloned().collect::<Vec<_>>();
 
     log::info!(
-        "Provisioning {} users ({}, {})",
-        state.users.len().yellow(),
-        format!("-{}", orphaned_users.len()).red(),
-        format!("+{}", state.users.len() - known_users.len() + orphaned_users.len()).green(),
+        "{} {} {table} ({}, {})",
+        if dry_run { "Would provision" } else { "Provisioning" },
+        wanted.len().yellow(),
+        format!("-{}", deleted.len()).red(),
+        format!("+{}", created.len()).green(),
     );
-    delete_orphans(pool, "users", "username", &orphaned_users).await?;
+    if !dry_run {
+        return;
+    }
+
+    created.sort();
+    updated.sort();
+    deleted.sort();
+    for key in deleted {
+        log::info!("  {}", format!("- {key}").red());
+    }
+    for key in created {
+        log::info!("  {}", format!("+ {key}").green());
+    }
+    for key in updated {
+        log::info!("  {}", format!("~ {key}").yellow());
+    }
+}
+
+/// Checks whether an entry with the given address exists in the table. Provisioned entries that are
+/// no longer part of the state don't count, since they are deleted later in the same transaction.
+async fn address_in_use(
+    conn: &mut SqliteConnection,
+    table: &str,
+    address: &str,
+    still_provisioned: bool,
+) -> Result<bool> {
+    let mut query = QueryBuilder::new(format!("SELECT provisioned FROM {table} WHERE address = "));
+    query.push_bind(address);
+    let provisioned = query
+        .logged()
+        .build_query_scalar::<bool>()
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(provisioned.is_some_and(|provisioned| !provisioned || still_provisioned))
+}
+
+pub async fn provision_users(conn: &mut SqliteConnection, state: &State, dry_run: bool) -> Result<()> {
+    let known_users = select_provisioned(conn, "users", "username").await?;
+    let wanted_users = state.users.keys().cloned().collect::<HashSet<_>>();
+    let orphaned_users = &known_users - &wanted_users;
+
+    log_changes("users", &wanted_users, &known_users, &orphaned_users, dry_run);
+    if !dry_run {
+        delete_orphans(conn, "users", "username", &orphaned_users).await?;
+    }
 
     for (name, user) in &state.users {
         let password_hash = value_or_file(user.password_hash.clone())?;
+        if dry_run {
+            continue;
+        }
+
         let mut query = QueryBuilder::new("INSERT INTO users (username, password_hash, admin, active, provisioned)");
         query.push(" VALUES (");
         query.push_bind(name);
@@ -132,39 +203,40 @@ pub async fn provision_users(pool: &SqlitePool, state: &State) -> Result<()> {
         query.push_bind(user.active);
         query.push(", provisioned = TRUE");
 
-        query.build().execute(pool).await?;
+        query.logged().build().execute(&mut *conn).await?;
     }
 
     Ok(())
 }
 
-pub async fn provision_domains(pool: &SqlitePool, state: &State) -> Result<()> {
-    let known_domains = select_provisioned(pool, "domains", "domain").await?;
-    let orphaned_domains = &known_domains - &state.domains.keys().cloned().collect::<HashSet<_>>();
+pub async fn provision_domains(conn: &mut SqliteConnection, state: &State, dry_run: bool) -> Result<()> {
+    let known_domains = select_provisioned(conn, "domains", "domain").await?;
+    let wanted_domains = state.domains.keys().cloned().collect::<HashSet<_>>();
+    let orphaned_domains = &known_domains - &wanted_domains;
 
-    log::info!(
-        "Provisioning {} domains ({}, {})",
-        state.domains.len().yellow(),
-        format!("-{}", orphaned_domains.len()).red(),
-        format!(
-            "+{}",
-            state.domains.len() - known_domains.len() + orphaned_domains.len()
-        )
-        .green(),
-    );
-    delete_orphans(pool, "domains", "domain", &orphaned_domains).await?;
+    log_changes("domains", &wanted_domains, &known_domains, &orphaned_domains, dry_run);
+    if !dry_run {
+        delete_orphans(conn, "domains", "domain", &orphaned_domains).await?;
+    }
 
     for (name, domain) in &state.domains {
-        if !state.users.contains_key(&domain.owner) {
-            bail!(
-                "Failed to provision domain '{name}': Owner '{}' must be a provisioned user",
+        // Like in the UI, only admins decide which domains are public. Owning one doesn't require
+        // being an admin, but since that is usually a mistake it's worth pointing out.
+        if domain.public && state.users.get(&domain.owner).is_some_and(|x| !x.admin) {
+            log::warn!(
+                "Provisioned domain '{name}' is public but owned by the non-admin user '{}'",
                 domain.owner
             );
         }
 
-        let catch_all = domain.catch_all.as_deref().unwrap_or("");
-        let mut query =
-            QueryBuilder::new("INSERT INTO domains (domain, catch_all, public, active, owner, provisioned)");
+        if dry_run {
+            continue;
+        }
+
+        let catch_all = domain.catch_all.as_deref().filter(|x| !x.is_empty());
+        let mut query = QueryBuilder::new(
+            "INSERT INTO domains (domain, catch_all, public, active, owner, localpart_case_sensitive, provisioned)",
+        );
 
         query.push(" VALUES (");
         query.push_bind(name);
@@ -176,6 +248,8 @@ pub async fn provision_domains(pool: &SqlitePool, state: &State) -> Result<()> {
         query.push_bind(domain.active);
         query.push(", ");
         query.push_bind(&domain.owner);
+        query.push(", ");
+        query.push_bind(domain.localpart_case_sensitive);
         query.push(", TRUE)");
 
         query.push(" ON CONFLICT (domain) DO UPDATE SET");
@@ -187,53 +261,50 @@ pub async fn provision_domains(pool: &SqlitePool, state: &State) -> Result<()> {
         query.push_bind(domain.active);
         query.push(", owner = ");
         query.push_bind(&domain.owner);
+        query.push(", localpart_case_sensitive = ");
+        query.push_bind(domain.localpart_case_sensitive);
         query.push(", provisioned = TRUE");
 
-        query.build().execute(pool).await?;
+        query.logged().build().execute(&mut *conn).await?;
     }
 
     Ok(())
 }
 
-pub async fn provision_mailboxes(pool: &SqlitePool, state: &State) -> Result<()> {
-    let known_mailboxes = select_provisioned(pool, "mailboxes", "address").await?;
-    let orphaned_mailboxes = &known_mailboxes - &state.mailboxes.keys().cloned().collect::<HashSet<_>>();
-
-    log::info!(
-        "Provisioning {} mailboxes ({}, {})",
-        state.mailboxes.len().yellow(),
-        format!("-{}", orphaned_mailboxes.len()).red(),
-        format!(
-            "+{}",
-            state.mailboxes.len() - known_mailboxes.len() + orphaned_mailboxes.len()
-        )
-        .green(),
+pub async fn provision_mailboxes(conn: &mut SqliteConnection, state: &State, dry_run: bool) -> Result<()> {
+    let known_mailboxes = select_provisioned(conn, "mailboxes", "address").await?;
+    let wanted_mailboxes = state.mailboxes.keys().cloned().collect::<HashSet<_>>();
+    let orphaned_mailboxes = &known_mailboxes - &wanted_mailboxes;
+
+    log_changes(
+        "mailboxes",
+        &wanted_mailboxes,
+        &known_mailboxes,
+        &orphaned_mailboxes,
+        dry_run,
     );
-    delete_orphans(pool, "mailboxes", "address", &orphaned_mailboxes).await?;
+    if !dry_run {
+        delete_orphans(conn, "mailboxes", "address", &orphaned_mailboxes).await?;
+    }
 
     for (name, mailbox) in &state.mailboxes {
+        // Checked by validate() already
         let Some((_localpart, domain)) = name.split_once('@') else {
             bail!("Failed to provision mailbox '{name}': Invalid address");
         };
 
-        if !state.domains.contains_key(domain) {
-            bail!("Failed to provision mailbox '{name}': Domain '{domain}' must be a provisioned domain");
-        }
-        if !state.users.contains_key(&mailbox.owner) {
-            bail!(
-                "Failed to provision mailbox '{name}': Owner '{}' must be a provisioned user",
-                mailbox.owner
-            );
+        if address_in_use(conn, "aliases", name, state.aliases.contains_key(name)).await? {
+            bail!("Failed to provision mailbox '{name}': Address is already in use by an alias");
         }
 
         let password_hash = value_or_file(mailbox.password_hash.clone())?;
         let api_token = mailbox.api_token.clone().map(value_or_file).transpose()?;
-        if api_token.as_ref().is_some_and(|x| x.len() < 16) {
-            bail!("Failed to provision mailbox '{name}': API tokens must be at least 16 characters long");
+        if dry_run {
+            continue;
         }
-        let mut query = QueryBuilder::new(
-            "INSERT INTO mailboxes (address, domain, password_hash, api_token, active, owner, provisioned)",
-        );
+
+        let mut query =
+            QueryBuilder::new("INSERT INTO mailboxes (address, domain, password_hash, active, owner, provisioned)");
         query.push(" VALUES (");
         query.push_bind(name);
         query.push(", ");
@@ -241,8 +312,6 @@ pub async fn provision_mailboxes(pool: &SqlitePool, state: &State) -> Result<()>
         query.push(", ");
         query.push_bind(&password_hash);
         query.push(", ");
-        query.push_bind(&api_token);
-        query.push(", ");
         query.push_bind(mailbox.active);
         query.push(", ");
         query.push_bind(&mailbox.owner);
@@ -251,54 +320,71 @@ pub async fn provision_mailboxes(pool: &SqlitePool, state: &State) -> Result<()>
         query.push(" ON CONFLICT (address) DO UPDATE SET");
         query.push(" password_hash = ");
         query.push_bind(&password_hash);
-        query.push(", api_token = ");
-        query.push_bind(&api_token);
         query.push(", active = ");
         query.push_bind(mailbox.active);
         query.push(", owner = ");
         query.push_bind(&mailbox.owner);
         query.push(", provisioned = TRUE");
 
-        query.build().execute(pool).await?;
+        query.logged().build().execute(&mut *conn).await?;
+
+        // The provisioning file contains the raw token, the database only contains its hash.
+        // An unchanged token is kept, so its last use stays recorded.
+        let token_hash = api_token.as_deref().map(crate::auth::ssr::hash_api_token);
+        sqlx::query(
+            "DELETE FROM api_tokens WHERE account_kind = 'mailbox' AND account = ? AND name = ? AND token_hash IS NOT ?",
+        )
+        .bind(name)
+        .bind(crate::auth::ssr::ACCOUNT_TOKEN_NAME)
+        .bind(&token_hash)
+        .execute(&mut *conn)
+        .await?;
+        if let Some(token_hash) = &token_hash {
+            sqlx::query(
+                "INSERT INTO api_tokens (account, account_kind, name, token_hash) SELECT ?1, 'mailbox', ?2, ?3 \
+                WHERE NOT EXISTS (SELECT * FROM api_tokens WHERE account_kind = 'mailbox' AND account = ?1 \
+                AND token_hash = ?3)",
+            )
+            .bind(name)
+            .bind(crate::auth::ssr::ACCOUNT_TOKEN_NAME)
+            .bind(token_hash)
+            .execute(&mut *conn)
+            .await?;
+        }
     }
 
     Ok(())
 }
 
-pub async fn provision_aliases(pool: &SqlitePool, state: &State) -> Result<()> {
-    let known_aliases = select_provisioned(pool, "aliases", "address").await?;
-    let orphaned_aliases = &known_aliases - &state.aliases.keys().cloned().collect::<HashSet<_>>();
+pub async fn provision_aliases(conn: &mut SqliteConnection, state: &State, dry_run: bool) -> Result<()> {
+    let known_aliases = select_provisioned(conn, "aliases", "address").await?;
+    let wanted_aliases = state.aliases.keys().cloned().collect::<HashSet<_>>();
+    let orphaned_aliases = &known_aliases - &wanted_aliases;
 
-    log::info!(
-        "Provisioning {} aliases ({}, {})",
-        state.aliases.len().yellow(),
-        format!("-{}", orphaned_aliases.len()).red(),
-        format!(
-            "+{}",
-            state.aliases.len() - known_aliases.len() + orphaned_aliases.len()
-        )
-        .green(),
-    );
-    delete_orphans(pool, "aliases", "address", &orphaned_aliases).await?;
+    log_changes("aliases", &wanted_aliases, &known_aliases, &orphaned_aliases, dry_run);
+    if !dry_run {
+        delete_orphans(conn, "aliases", "address", &orphaned_aliases).await?;
+    }
 
     for (name, alias) in &state.aliases {
+        // Checked by validate() already
         let Some((_localpart, domain)) = name.split_once('@') else {
             bail!("Failed to provision alias '{name}': Invalid address");
         };
 
-        if !state.domains.contains_key(domain) {
-            bail!("Failed to provision alias '{name}': Domain '{domain}' must be a provisioned domain");
+        if address_in_use(conn, "mailboxes", name, state.mailboxes.contains_key(name)).await? {
+            bail!("Failed to provision alias '{name}': Address is already in use by a mailbox");
         }
-        if !state.users.contains_key(&alias.owner) && !state.mailboxes.contains_key(&alias.owner) {
-            bail!(
-                "Failed to provision alias '{name}': Owner '{}' must be a provisioned user or mailbox",
-                alias.owner
-            );
+
+        if dry_run {
+            continue;
         }
 
         let comment = alias.comment.as_deref().unwrap_or("");
-        let mut query =
-            QueryBuilder::new("INSERT INTO aliases (address, domain, target, comment, active, owner, provisioned)");
+        let alias_name = alias.name.as_deref().filter(|x| !x.is_empty());
+        let mut query = QueryBuilder::new(
+            "INSERT INTO aliases (address, domain, target, name, comment, daily_limit, active, owner, provisioned)",
+        );
 
         query.push(" VALUES (");
         query.push_bind(name);
@@ -307,8 +393,12 @@ pub async fn provision_aliases(pool: &SqlitePool, state: &State) -> Result<()> {
         query.push(", ");
         query.push_bind(&alias.target);
         query.push(", ");
+        query.push_bind(alias_name);
+        query.push(", ");
         query.push_bind(comment);
         query.push(", ");
+        query.push_bind(alias.daily_limit);
+        query.push(", ");
         query.push_bind(alias.active);
         query.push(", ");
         query.push_bind(&alias.owner);
@@ -317,34 +407,452 @@ pub async fn provision_aliases(pool: &SqlitePool, state: &State) -> Result<()> {
         query.push(" ON CONFLICT (address) DO UPDATE SET");
         query.push(" target = ");
         query.push_bind(&alias.target);
+        query.push(", name = ");
+        query.push_bind(alias_name);
         query.push(", comment = ");
         query.push_bind(comment);
+        query.push(", daily_limit = ");
+        query.push_bind(alias.daily_limit);
         query.push(", active = ");
         query.push_bind(alias.active);
         query.push(", owner = ");
         query.push_bind(&alias.owner);
         query.push(", provisioned = TRUE");
 
-        query.build().execute(pool).await?;
+        query.logged().build().execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Adds the postmaster and abuse aliases for all domains that request them,
+/// unless they are already provisioned explicitly. They forward to the catch-all,
+/// or to the first provisioned mailbox of the domain owner if there is none.
+fn add_rfc2142_aliases(state: &mut State) -> Result<()> {
+    for (name, domain) in &state.domains {
+        if !domain.rfc2142_aliases {
+            continue;
+        }
+
+        let owner_mailbox = || {
+            state
+                .mailboxes
+                .iter()
+                .filter(|(_, mailbox)| mailbox.owner == domain.owner)
+                .map(|(address, _)| address)
+                .min()
+                .cloned()
+        };
+        let Some(target) = domain
+            .catch_all
+            .clone()
+            .filter(|x| !x.is_empty())
+            .or_else(owner_mailbox)
+        else {
+            bail!(
+                "domain '{name}': RFC 2142 aliases require a catch-all or a provisioned mailbox of '{}'",
+                domain.owner
+            );
+        };
+
+        for localpart in crate::domains::RFC2142_LOCALPARTS {
+            let address = format!("{localpart}@{name}");
+            // A provisioned mailbox already receives this mail
+            if state.mailboxes.contains_key(&address) {
+                continue;
+            }
+            state.aliases.entry(address).or_insert_with(|| state::Alias {
+                target: target.clone(),
+                name: None,
+                comment: Some("RFC 2142".to_string()),
+                daily_limit: None,
+                active: true,
+                owner: domain.owner.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the address of a provisioned mailbox or alias is valid and on a provisioned domain
+fn validate_address(errors: &mut Vec<String>, state: &State, kind: &str, address: &str) {
+    use std::str::FromStr;
+
+    if email_address::EmailAddress::from_str(address).is_err() {
+        errors.push(format!("{kind} '{address}': Invalid address"));
+    } else if let Some((_localpart, domain)) = address.split_once('@') {
+        if !state.domains.contains_key(domain) {
+            errors.push(format!(
+                "{kind} '{address}': Domain '{domain}' must be a provisioned domain"
+            ));
+        }
+    }
+}
+
+/// Checks everything about the state that doesn't depend on the database, so that
+/// provisioning fails before anything is changed. Reports all problems at once.
+fn validate(state: &State) -> Result<()> {
+    use std::str::FromStr;
+
+    let mut errors = Vec::new();
+
+    for name in state.users.keys() {
+        if name.is_empty() || name.contains('@') {
+            errors.push(format!("user '{name}': Usernames must not be empty or contain an '@'"));
+        }
+    }
+
+    for (name, domain) in &state.domains {
+        if !state.users.contains_key(&domain.owner) {
+            errors.push(format!(
+                "domain '{name}': Owner '{}' must be a provisioned user",
+                domain.owner
+            ));
+        }
+    }
+
+    for (name, mailbox) in &state.mailboxes {
+        validate_address(&mut errors, state, "mailbox", name);
+        if state.mailboxes.contains_key(&mailbox.owner) {
+            errors.push(format!(
+                "mailbox '{name}': Owner '{}' is a mailbox, but mailboxes must be owned by users",
+                mailbox.owner
+            ));
+        } else if !state.users.contains_key(&mailbox.owner) {
+            errors.push(format!(
+                "mailbox '{name}': Owner '{}' must be a provisioned user",
+                mailbox.owner
+            ));
+        }
+        match mailbox.api_token.clone().map(value_or_file).transpose() {
+            Err(e) => errors.push(format!("mailbox '{name}': Failed to read API token: {e}")),
+            Ok(Some(api_token)) if api_token.len() < 16 => errors.push(format!(
+                "mailbox '{name}': API tokens must be at least 16 characters long"
+            )),
+            Ok(_) => {}
+        }
+    }
+
+    for (name, alias) in &state.aliases {
+        validate_address(&mut errors, state, "alias", name);
+        if state.mailboxes.contains_key(name) {
+            errors.push(format!("alias '{name}': Address is also provisioned as a mailbox"));
+        }
+        // External targets are fine, but local ones should point to an existing mailbox
+        if email_address::EmailAddress::from_str(&alias.target).is_err() {
+            errors.push(format!("alias '{name}': Invalid target '{}'", alias.target));
+        } else if alias
+            .target
+            .split_once('@')
+            .is_some_and(|(_, domain)| state.domains.contains_key(domain))
+            && !state.mailboxes.contains_key(&alias.target)
+            && !state.aliases.contains_key(&alias.target)
+        {
+            log::warn!(
+                "Provisioned alias '{name}' targets '{}', which is neither a provisioned mailbox nor alias",
+                alias.target
+            );
+        }
+        if !state.users.contains_key(&alias.owner) && !state.mailboxes.contains_key(&alias.owner) {
+            errors.push(format!(
+                "alias '{name}': Owner '{}' must be a provisioned user or mailbox",
+                alias.owner
+            ));
+        }
     }
 
+    if !errors.is_empty() {
+        errors.sort();
+        bail!("Invalid provisioning state:\n  {}", errors.join("\n  "));
+    }
     Ok(())
 }
 
-pub async fn provision(pool: &SqlitePool) -> Result<()> {
-    let Ok(provision_file) = std::env::var("IDMAIL_PROVISION") else {
+pub async fn provision(pool: &SqlitePool, config: &Config) -> Result<()> {
+    let Some(provision_file) = &config.provision else {
         // No provisioning desired
         return Ok(());
     };
 
-    let file_content = std::fs::read_to_string(&provision_file)
-        .context(format!("Failed to read provision file: {}", provision_file))?;
-    let state: State = toml::from_str(&file_content).context("Failed to parse provision state")?;
+    let file_content = std::fs::read_to_string(provision_file)
+        .context(format!("Failed to read provision file: {}", provision_file.display()))?;
+    let mut state: State = toml::from_str(&file_content).context("Failed to parse provision state")?;
+    // The generated RFC 2142 aliases are validated like the ones from the file
+    add_rfc2142_aliases(&mut state)?;
+    validate(&state)?;
+
+    // Only validate the state and report what would change, without touching the database
+    let dry_run = config.provision_dry_run;
+    // Either the whole state is applied or nothing at all. The write lock is taken right away,
+    // so overlapping runs are serialized, even from several processes sharing the database.
+    let mut conn = pool.acquire().await?;
+    sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+    let result = async {
+        provision_users(&mut conn, &state, dry_run).await?;
+        provision_domains(&mut conn, &state, dry_run).await?;
+        provision_mailboxes(&mut conn, &state, dry_run).await?;
+        provision_aliases(&mut conn, &state, dry_run).await
+    }
+    .await;
+    let end = if result.is_ok() && !dry_run {
+        "COMMIT"
+    } else {
+        "ROLLBACK"
+    };
+    sqlx::query(end).execute(&mut *conn).await?;
+
+    result
+}
 
-    provision_users(pool, &state).await?;
-    provision_domains(pool, &state).await?;
-    provision_mailboxes(pool, &state).await?;
-    provision_aliases(pool, &state).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const BASE: &str = r#"
+        [users.admin]
+        password_hash = "x"
+        admin = true
+
+        [domains."example.com"]
+        owner = "admin"
+    "#;
+
+    async fn provision_str(pool: &SqlitePool, state: &str) -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("state.toml");
+        std::fs::write(&file, format!("{BASE}\n{state}")).unwrap();
+        let config = Config {
+            provision: Some(file),
+            ..Default::default()
+        };
+        provision(pool, &config).await
+    }
 
-    Ok(())
+    const MAILBOX: &str = r#"
+        [mailboxes."me@example.com"]
+        password_hash = "x"
+        owner = "admin"
+    "#;
+
+    #[tokio::test]
+    async fn mailboxes_must_not_shadow_aliases() {
+        let db = TestDb::new().await;
+        provision_str(&db, "").await.unwrap();
+        insert_alias(&db, "me@example.com", "someone@example.org", "admin").await;
+
+        let err = provision_str(&db, MAILBOX).await.unwrap_err();
+        assert!(err.to_string().contains("already in use by an alias"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn removed_aliases_can_become_mailboxes() {
+        let db = TestDb::new().await;
+        let alias = r#"
+            [aliases."me@example.com"]
+            target = "someone@example.org"
+            owner = "admin"
+        "#;
+        provision_str(&db, alias).await.unwrap();
+        provision_str(&db, MAILBOX).await.unwrap();
+
+        let aliases: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM aliases WHERE address = 'me@example.com'")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(aliases, 0);
+    }
+
+    #[tokio::test]
+    async fn the_same_address_cannot_be_provisioned_twice() {
+        let db = TestDb::new().await;
+        let both = format!(
+            r#"{MAILBOX}
+            [aliases."me@example.com"]
+            target = "someone@example.org"
+            owner = "admin"
+            "#
+        );
+        let err = provision_str(&db, &both).await.unwrap_err();
+        assert!(err.to_string().contains("also provisioned as a mailbox"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn overlapping_runs_are_serialized() {
+        let db = TestDb::new().await;
+        let (a, b) = tokio::join!(provision_str(&db, MAILBOX), provision_str(&db, MAILBOX));
+        a.unwrap();
+        b.unwrap();
+
+        // A failed run leaves no transaction open on the pooled connection
+        sqlx::query("DELETE FROM mailboxes").execute(&*db).await.unwrap();
+        insert_alias(&db, "me@example.com", "someone@example.org", "admin").await;
+        provision_str(&db, MAILBOX).await.unwrap_err();
+        sqlx::query("DELETE FROM aliases").execute(&*db).await.unwrap();
+        provision_str(&db, MAILBOX).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn usernames_must_not_look_like_addresses() {
+        let db = TestDb::new().await;
+        let user = r#"
+            [users."me@example.com"]
+            password_hash = "x"
+        "#;
+        let err = provision_str(&db, user).await.unwrap_err();
+        assert!(err.to_string().contains("must not be empty or contain an '@'"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn public_domains_of_non_admins_are_provisioned_with_a_warning() {
+        let db = TestDb::new().await;
+        capture_logs();
+        let state = r#"
+            [users.alice]
+            password_hash = "x"
+
+            [domains."public-of-alice.example"]
+            owner = "alice"
+            public = true
+
+            [domains."public-of-admin.example"]
+            owner = "admin"
+            public = true
+        "#;
+        provision_str(&db, state).await.unwrap();
+
+        let public: Vec<String> = sqlx::query_scalar("SELECT domain FROM domains WHERE public ORDER BY domain")
+            .fetch_all(&*db)
+            .await
+            .unwrap();
+        assert_eq!(public, ["public-of-admin.example", "public-of-alice.example"]);
+        let warnings: Vec<_> = captured_logs()
+            .into_iter()
+            .filter(|x| x.contains("is public but owned by the non-admin user"))
+            .collect();
+        assert_eq!(
+            warnings,
+            ["Provisioned domain 'public-of-alice.example' is public but owned by the non-admin user 'alice'"]
+        );
+    }
+
+    #[tokio::test]
+    async fn alias_targets_must_be_addresses() {
+        let db = TestDb::new().await;
+        let alias = |target: &str| {
+            format!(
+                r#"
+                [aliases."me@example.com"]
+                target = "{target}"
+                owner = "admin"
+                "#
+            )
+        };
+        let err = provision_str(&db, &alias("not an address")).await.unwrap_err();
+        assert!(err.to_string().contains("Invalid target 'not an address'"), "{err}");
+
+        // Targets outside of the provisioned domains are fine
+        provision_str(&db, &alias("someone@example.org")).await.unwrap();
+        let target: String = sqlx::query_scalar("SELECT target FROM aliases WHERE address = 'me@example.com'")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(target, "someone@example.org");
+    }
+
+    #[tokio::test]
+    async fn all_problems_are_reported_at_once() {
+        let db = TestDb::new().await;
+        let state = r#"
+            [users."me@example.com"]
+            password_hash = "x"
+
+            [domains."other.example"]
+            owner = "nobody"
+
+            [aliases."me@example.com"]
+            target = "not an address"
+            owner = "admin"
+        "#;
+        let err = provision_str(&db, state).await.unwrap_err().to_string();
+        for problem in [
+            "user 'me@example.com': Usernames must not be empty",
+            "domain 'other.example': Owner 'nobody' must be a provisioned user",
+            "alias 'me@example.com': Invalid target 'not an address'",
+        ] {
+            assert!(err.contains(problem), "{err}");
+        }
+        assert_eq!(err.matches("Invalid provisioning state").count(), 1, "{err}");
+    }
+
+    #[tokio::test]
+    async fn mailboxes_must_be_owned_by_users() {
+        let db = TestDb::new().await;
+        let nested = format!(
+            r#"{MAILBOX}
+            [mailboxes."nested@example.com"]
+            password_hash = "x"
+            owner = "me@example.com"
+            "#
+        );
+        let err = provision_str(&db, &nested).await.unwrap_err();
+        assert!(err.to_string().contains("Owner 'me@example.com' is a mailbox"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn provisioned_tokens_are_hashed_and_authenticate() {
+        let db = TestDb::new().await;
+        let token = |token: &str| format!("{MAILBOX}\napi_token = \"{token}\"\n");
+        provision_str(&db, &token("provisioned-token-0123456789"))
+            .await
+            .unwrap();
+
+        let stored: Vec<String> = sqlx::query_scalar("SELECT token_hash FROM api_tokens")
+            .fetch_all(&*db)
+            .await
+            .unwrap();
+        assert_eq!(
+            stored,
+            [crate::auth::ssr::hash_api_token("provisioned-token-0123456789")]
+        );
+        let (user, ..) = crate::auth::User::get_by_api_token("provisioned-token-0123456789", &db)
+            .await
+            .unwrap();
+        assert_eq!(user.username, "me@example.com");
+
+        // A changed token replaces the old one
+        provision_str(&db, &token("rotated-token-0123456789")).await.unwrap();
+        assert!(crate::auth::User::get_by_api_token("provisioned-token-0123456789", &db)
+            .await
+            .is_none());
+        assert!(crate::auth::User::get_by_api_token("rotated-token-0123456789", &db)
+            .await
+            .is_some());
+        let tokens: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_tokens")
+            .fetch_one(&*db)
+            .await
+            .unwrap();
+        assert_eq!(tokens, 1);
+    }
+
+    #[tokio::test]
+    async fn rfc2142_aliases_forward_to_a_mailbox_of_the_owner() {
+        let db = TestDb::new().await;
+        let domain = r#"
+            [domains."rfc.example"]
+            owner = "admin"
+            rfc2142_aliases = true
+        "#;
+        let err = provision_str(&db, domain).await.unwrap_err();
+        assert!(err.to_string().contains("require a catch-all"), "{err}");
+
+        provision_str(&db, &format!("{MAILBOX}\n{domain}")).await.unwrap();
+        let aliases: Vec<(String, String, String)> =
+            sqlx::query_as("SELECT address, target, owner FROM aliases ORDER BY address")
+                .fetch_all(&*db)
+                .await
+                .unwrap();
+        let alias = |address: &str| (address.to_string(), "me@example.com".to_string(), "admin".to_string());
+        assert_eq!(aliases, [alias("abuse@rfc.example"), alias("postmaster@rfc.example")]);
+    }
 }
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+/// The number of failed logins within [`FAILURE_WINDOW`] after which further attempts are rejected
+const MAX_FAILURES: u32 = 5;
+/// The window in which failed logins are counted
+const FAILURE_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How long further attempts are rejected once the limit was reached
+const LOCKOUT_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Failed logins are tracked per lowercased username and client address
+type Key = (String, Option<IpAddr>);
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    first_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_expired(&self, now: Instant) -> bool {
+        match self.locked_until {
+            Some(until) => until <= now,
+            None => now.duration_since(self.first_failure) >= FAILURE_WINDOW,
+        }
+    }
+}
+
+/// Tracks failed logins per username and client address to slow down online password guessing.
+/// The state is kept in memory and shared across requests via the `AppState`.
+#[derive(Debug, Clone)]
+pub struct LoginLimiter {
+    failures: Arc<Mutex<HashMap<Key, Failures>>>,
+    /// The minimum time a failed login takes, see [`LoginLimiter::delay_failure`]
+    failure_delay: Duration,
+}
+
+impl LoginLimiter {
+    /// Creates a limiter whose failed logins take at least the given duration. A zero duration disables the delay.
+    pub fn new(failure_delay: Duration) -> Self {
+        Self {
+            failures: Default::default(),
+            failure_delay,
+        }
+    }
+
+    fn key(username: &str, ip: Option<IpAddr>) -> Key {
+        (username.to_lowercase(), ip)
+    }
+
+    /// Whether logins for this username from this address are currently locked out.
+    pub fn is_locked(&self, username: &str, ip: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        let failures = self.failures.lock().expect("login limiter mutex poisoned");
+        failures
+            .get(&Self::key(username, ip))
+            .and_then(|x| x.locked_until)
+            .is_some_and(|until| until > now)
+    }
+
+    /// Records a failed login. Returns true if this failure started a lockout.
+    pub fn record_failure(&self, username: &str, ip: Option<IpAddr>) -> bool {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().expect("login limiter mutex poisoned");
+        // Forget stale entries here, so the map can't grow without bounds
+        failures.retain(|_, x| !x.is_expired(now));
+
+        let entry = failures.entry(Self::key(username, ip)).or_insert(Failures {
+            count: 0,
+            first_failure: now,
+            locked_until: None,
+        });
+        entry.count += 1;
+        if entry.count >= MAX_FAILURES && entry.locked_until.is_none() {
+            entry.locked_until = Some(now + LOCKOUT_DURATION);
+            return true;
+        }
+        false
+    }
+
+    /// Waits until a failed login that started at the given time took at least the configured delay,
+    /// plus a random jitter of up to the same amount. This limits the throughput of guessing even
+    /// before the lockout kicks in, and hides whether a login failed because the user doesn't exist
+    /// or because the password hash didn't match, which take very different amounts of time.
+    pub async fn delay_failure(&self, started: Instant) {
+        if self.failure_delay.is_zero() {
+            return;
+        }
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.failure_delay);
+        tokio::time::sleep_until((started + self.failure_delay + jitter).into()).await;
+    }
+
+    /// Forgets all failed logins after a successful login.
+    pub fn reset(&self, username: &str, ip: Option<IpAddr>) {
+        let mut failures = self.failures.lock().expect("login limiter mutex poisoned");
+        failures.remove(&Self::key(username, ip));
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(feature = "ssr")]
+use crate::auth::User;
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{push_owner_scope, LogQuery, OwnerScope};
+use leptos::{logging::error, *};
+use leptos_icons::Icon;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "ssr")]
+use sqlx::{QueryBuilder, SqlitePool};
+
+/// An external address that a mailbox may forward its aliases to, once it was verified
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Recipient {
+    pub address: String,
+    pub mailbox: String,
+    pub verified: bool,
+}
+
+/// Returns the verified recipients of the given mailbox
+#[cfg(feature = "ssr")]
+pub async fn verified_recipients(pool: &SqlitePool, mailbox: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT address FROM recipients WHERE mailbox = ? AND verified = TRUE ORDER BY address")
+        .bind(mailbox)
+        .fetch_all(pool)
+        .await
+}
+
+/// Restricts a query on recipients to those of mailboxes the user can access
+#[cfg(feature = "ssr")]
+fn push_recipient_scope(query: &mut QueryBuilder<'_, sqlx::Sqlite>, user: &User) {
+    query.push(" AND mailbox IN (SELECT address FROM mailboxes WHERE 1=1");
+    push_owner_scope(query, user, OwnerScope::Mailboxes);
+    query.push(")");
+}
+
+/// Lists the recipients of the current mailbox, or of all mailboxes the user owns
+#[server]
+pub async fn list_recipients() -> Result<Vec<Recipient>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let mut query = QueryBuilder::new("SELECT address, mailbox, verified FROM recipients WHERE 1=1");
+    push_recipient_scope(&mut query, &user);
+    query.push(" ORDER BY mailbox, address");
+
+    let pool = crate::database::ssr::pool()?;
+    Ok(query.logged().build_query_as::<Recipient>().fetch_all(&pool).await?)
+}
+
+/// Records a new recipient for the current mailbox. Nothing is sent to the address,
+/// it can only be used as a target after the owner of the mailbox verified it.
+#[server]
+pub async fn add_recipient(address: String) -> Result<(), ServerFnError> {
+    use std::str::FromStr;
+
+    let user = crate::auth::auth_any().await?;
+    if user.mailbox_owner.is_none() {
+        return Err(ServerFnError::new("Only mailboxes can add recipients"));
+    }
+
+    let address = address.trim();
+    if email_address::EmailAddress::from_str(address).is_err() {
+        return Err(ServerFnError::new("recipient must be a valid email address"));
+    }
+    if address.eq_ignore_ascii_case(&user.username) {
+        return Err(ServerFnError::new("aliases can always forward to the mailbox itself"));
+    }
+
+    let pool = crate::database::ssr::pool()?;
+    sqlx::query("INSERT INTO recipients (address, mailbox) VALUES (?, ?)")
+        .bind(address)
+        .bind(&user.username)
+        .execute(&pool)
+        .await
+        .map_err(|e| -> ServerFnError {
+            match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    ServerFnError::new(format!("'{address}' was already added"))
+                }
+                e => e.into(),
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Marks a recipient as verified or not, which only the owner of the mailbox can do
+#[server]
+pub async fn set_recipient_verified(mailbox: String, address: String, verified: bool) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let mut query = QueryBuilder::new("UPDATE recipients SET verified = ");
+    query.push_bind(verified);
+    query.push(" WHERE mailbox = ");
+    query.push_bind(mailbox);
+    query.push(" AND address = ");
+    query.push_bind(address);
+    push_recipient_scope(&mut query, &user);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+/// Removes a recipient. Aliases that already forward to it are not changed.
+#[server]
+pub async fn delete_recipient(mailbox: String, address: String) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let mut query = QueryBuilder::new("DELETE FROM recipients WHERE mailbox = ");
+    query.push_bind(mailbox);
+    query.push(" AND address = ");
+    query.push_bind(address);
+    push_recipient_scope(&mut query, &user);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+/// The recipients section of the account settings. Mailboxes can add recipients here,
+/// while owners of mailboxes see the recipients of all their mailboxes to verify them.
+#[component]
+pub fn Recipients(is_mailbox: bool) -> impl IntoView {
+    let (version, set_version) = create_signal(0);
+    let recipients = create_resource(move || version.get(), move |_| list_recipients());
+    let reload = move || set_version.update(|x| *x += 1);
+
+    let (input_address, set_input_address) = create_signal("".to_string());
+    let (server_error, set_server_error) = create_signal(None::<String>);
+
+    let on_add = move |_| {
+        spawn_local(async move {
+            match add_recipient(input_address.get_untracked()).await {
+                Err(e) => set_server_error(Some(e.to_string())),
+                Ok(()) => {
+                    set_server_error(None);
+                    set_input_address("".to_string());
+                    reload();
+                }
+            }
+        });
+    };
+
+    let input_class = "flex flex-1 rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50";
+    let button_class = "inline-flex flex-none justify-center items-center rounded-lg transition-all px-3 py-2 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white dark:text-zinc-100 focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900";
+
+    view! {
+        <div class="flex flex-col gap-3 mt-2">
+            <h3 class="text-lg font-semibold">"Recipients"</h3>
+            <p class="text-sm text-gray-500 dark:text-gray-400">
+                {if is_mailbox {
+                    "Aliases can forward to these external addresses once the owner of this mailbox verified them."
+                } else {
+                    "External addresses that your mailboxes want to forward aliases to. Verify them to allow this."
+                }}
+
+            </p>
+            <Transition fallback=move || {
+                view! { <span class="text-gray-300 dark:text-gray-600">"Loading..."</span> }
+            }>
+                {move || {
+                    recipients
+                        .get()
+                        .map(|recipients| match recipients {
+                            Err(e) => view! { <p>{e.to_string()}</p> }.into_view(),
+                            Ok(recipients) if recipients.is_empty() => {
+                                view! { <p class="text-sm text-gray-500 dark:text-gray-400">"No recipients yet."</p> }
+                                    .into_view()
+                            }
+                            Ok(recipients) => {
+                                recipients
+                                    .into_iter()
+                                    .map(|recipient| {
+                                        let Recipient { address, mailbox, verified } = recipient;
+                                        let (verify_mailbox, verify_address) = (mailbox.clone(), address.clone());
+                                        let (delete_mailbox, delete_address) = (mailbox.clone(), address.clone());
+                                        view! {
+                                            <div class="flex flex-row gap-3 items-center text-sm">
+                                                <span class="flex-1 min-w-0 font-mono break-all">
+                                                    {(!is_mailbox).then(|| format!("{mailbox} → "))} {address}
+                                                </span>
+                                                <span
+                                                    class="text-yellow-700 dark:text-yellow-200"
+                                                    class=("!text-green-600", verified)
+                                                    class=("dark:!text-green-400", verified)
+                                                >
+                                                    {if verified { "verified" } else { "pending" }}
+                                                </span>
+                                                <Show when=move || !is_mailbox>
+                                                    <input
+                                                        class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                                                        type="checkbox"
+                                                        title="Verified"
+                                                        prop:checked=verified
+                                                        on:change={
+                                                            let mailbox = verify_mailbox.clone();
+                                                            let address = verify_address.clone();
+                                                            move |ev| {
+                                                                let verified = event_target_checked(&ev);
+                                                                let mailbox = mailbox.clone();
+                                                                let address = address.clone();
+                                                                spawn_local(async move {
+                                                                    if let Err(e) = set_recipient_verified(mailbox, address, verified).await {
+                                                                        error!("Failed to update recipient: {}", e);
+                                                                    }
+                                                                    reload();
+                                                                });
+                                                            }
+                                                        }
+                                                    />
+                                                </Show>
+                                                <button
+                                                    class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-lg font-medium px-2 py-1 inline-flex items-center"
+                                                    on:click=move |_| {
+                                                        let mailbox = delete_mailbox.clone();
+                                                        let address = delete_address.clone();
+                                                        spawn_local(async move {
+                                                            if let Err(e) = delete_recipient(mailbox, address).await {
+                                                                error!("Failed to delete recipient: {}", e);
+                                                            }
+                                                            reload();
+                                                        });
+                                                    }
+                                                >
+
+                                                    <Icon icon=icondata::FiTrash2 class="w-4 h-4"/>
+                                                </button>
+                                            </div>
+                                        }
+                                    })
+                                    .collect_view()
+                            }
+                        })
+                }}
+
+            </Transition>
+            <Show when=move || is_mailbox>
+                <div class="flex flex-row gap-2">
+                    <input
+                        class=input_class
+                        type="email"
+                        placeholder="me@example.org"
+                        on:input=move |ev| set_input_address(event_target_value(&ev))
+                        prop:value=input_address
+                    />
+                    <button type="button" class=button_class on:click=on_add>
+                        <Icon icon=icondata::FiPlus class="w-5 h-5"/>
+                    </button>
+                </div>
+            </Show>
+            {move || {
+                server_error().map(|error| view! { <p class="text-sm text-red-600 dark:text-red-400">{error}</p> })
+            }}
+        </div>
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::domain_routes::route_matches;
+
+/// The name of the directory in the generated snapshot
+const DIRECTORY_NAME: &str = "idmail-snapshot";
+
+/// How many aliases, routes and catch-alls are followed before an address is considered a routing loop
+const MAX_HOPS: usize = 16;
+
+/// Aliases only count if their owner is an active user, or an active mailbox of an active user
+const ACTIVE_OWNERS: &str = "SELECT username FROM users WHERE active = true \
+    UNION SELECT m.address AS username FROM mailboxes AS m \
+    JOIN users AS u ON m.owner = u.username \
+    WHERE m.active = true AND u.active = true";
+
+#[derive(Debug, Serialize)]
+struct Principal {
+    name: String,
+    class: &'static str,
+    description: String,
+    secret: String,
+    email: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct MemoryDirectory {
+    r#type: &'static str,
+    domains: Vec<String>,
+    principals: Vec<Principal>,
+}
+
+/// A recipient rewrite for routing that the directory itself cannot express,
+/// which are domain routes and everything that forwards to other mailservers.
+#[derive(Debug, Clone, PartialEq)]
+struct Rewrite {
+    /// The matched address, in which `*` matches any sequence of characters
+    pattern: String,
+    /// Addresses which match the pattern but are delivered by the directory
+    except: Vec<String>,
+    target: String,
+}
+
+impl Rewrite {
+    /// Whether the rewrite can be written as a stalwart expression without escaping
+    fn is_expressible(&self) -> bool {
+        std::iter::once(&self.pattern)
+            .chain(&self.except)
+            .chain(std::iter::once(&self.target))
+            .all(|x| !x.contains(['\'', '\\']))
+    }
+
+    /// Renders the rewrite as a rule of stalwart's `session.rcpt.rewrite` expression
+    fn rule(&self) -> RewriteRule {
+        let mut condition = if self.pattern.contains('*') {
+            // Patterns only consist of localpart and domain characters, so this is all that needs escaping
+            let regex = self.pattern.replace('.', "[.]").replace('+', "[+]").replace('*', ".*");
+            format!("matches('^{regex}$', rcpt)")
+        } else {
+            format!("rcpt == '{}'", self.pattern)
+        };
+        for address in &self.except {
+            condition.push_str(&format!(" && rcpt != '{address}'"));
+        }
+
+        RewriteRule::Rewrite {
+            r#if: condition,
+            then: format!("'{}'", self.target),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum RewriteRule {
+    Rewrite { r#if: String, then: String },
+    Keep { r#else: bool },
+}
+
+#[derive(Debug, Serialize)]
+struct Rcpt {
+    rewrite: Vec<RewriteRule>,
+}
+
+#[derive(Debug, Serialize)]
+struct Session {
+    rcpt: Rcpt,
+}
+
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    directory: BTreeMap<&'static str, MemoryDirectory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<Session>,
+}
+
+/// Everything that decides where mail to an address is delivered
+#[derive(Debug, Default)]
+struct Routing {
+    mailboxes: BTreeSet<String>,
+    aliases: BTreeMap<String, String>,
+    /// The routes of each domain, in the order they are matched
+    routes: BTreeMap<String, Vec<(String, String)>>,
+    catch_alls: BTreeMap<String, String>,
+}
+
+impl Routing {
+    /// Returns where mail to the address is forwarded next, or nothing if it is a mailbox or unknown.
+    /// This matches the order of the live resolver in [`crate::domain_routes::resolve`].
+    fn next_hop(&self, address: &str) -> Option<&str> {
+        if self.mailboxes.contains(address) {
+            return None;
+        }
+        if let Some(target) = self.aliases.get(address) {
+            return Some(target);
+        }
+
+        let (localpart, domain) = address.rsplit_once('@')?;
+        self.routes
+            .get(domain)
+            .and_then(|routes| routes.iter().find(|(pattern, _)| route_matches(pattern, localpart)))
+            .map(|(_, target)| target.as_str())
+            .or_else(|| self.catch_alls.get(domain).map(String::as_str))
+    }
+
+    /// Follows aliases, routes and catch-alls until reaching a mailbox or an address
+    /// of another mailserver. Returns nothing for routing loops.
+    fn deliver_to(&self, address: &str) -> Option<String> {
+        let mut address = address;
+        for _ in 0..MAX_HOPS {
+            match self.next_hop(address) {
+                Some(next) => address = next,
+                None => return Some(address.to_string()),
+            }
+        }
+        None
+    }
+
+    /// The addresses of the domain which match the pattern and are delivered without a rewrite
+    fn explicit_addresses(&self, domain: &str, pattern: &str) -> Vec<String> {
+        self.mailboxes
+            .iter()
+            .chain(self.aliases.keys())
+            .filter(|address| {
+                address
+                    .rsplit_once('@')
+                    .is_some_and(|(localpart, x)| x == domain && route_matches(pattern, localpart))
+            })
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Loads the routing with the same `active` rules as the SQL queries from the README
+async fn load_routing(pool: &SqlitePool) -> Result<(Routing, Vec<(String, String)>), sqlx::Error> {
+    let mailboxes = sqlx::query_as::<_, (String, String)>(
+        "SELECT m.address, m.password_hash FROM mailboxes AS m \
+        JOIN domains AS d ON m.domain = d.domain \
+        JOIN users AS u ON m.owner = u.username \
+        WHERE m.active = true AND d.active = true AND u.active = true \
+        ORDER BY m.address",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let aliases = sqlx::query_as::<_, (String, String)>(&format!(
+        "SELECT a.address, a.target FROM aliases AS a \
+        JOIN domains AS d ON a.domain = d.domain \
+        JOIN ( {ACTIVE_OWNERS} ) AS u ON a.owner = u.username \
+        WHERE a.active = true AND d.active = true \
+        ORDER BY a.address"
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let routes = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT r.domain, r.pattern, r.target FROM domain_routes AS r \
+        JOIN domains AS d ON r.domain = d.domain \
+        WHERE r.active = true AND d.active = true \
+        ORDER BY r.domain, r.priority, r.id",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let catch_alls = sqlx::query_as::<_, (String, String)>(
+        "SELECT domain, catch_all FROM domains \
+        WHERE active = true AND catch_all IS NOT NULL AND catch_all != '' \
+        ORDER BY domain",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut routing = Routing {
+        mailboxes: mailboxes.iter().map(|(address, _)| address.clone()).collect(),
+        aliases: aliases.into_iter().collect(),
+        routes: BTreeMap::new(),
+        catch_alls: catch_alls.into_iter().collect(),
+    };
+    for (domain, pattern, target) in routes {
+        routing.routes.entry(domain).or_default().push((pattern, target));
+    }
+    Ok((routing, mailboxes))
+}
+
+/// Builds the directory and the rewrites for everything it cannot express.
+/// Returns them together with the number of skipped routing loops and addresses that need escaping.
+async fn build_snapshot(pool: &SqlitePool) -> Result<(MemoryDirectory, Vec<Rewrite>, usize), anyhow::Error> {
+    let domains = sqlx::query_scalar::<_, String>("SELECT domain FROM domains WHERE active = true ORDER BY domain")
+        .fetch_all(pool)
+        .await?;
+    let (routing, mailboxes) = load_routing(pool).await?;
+
+    let mut principals = mailboxes
+        .into_iter()
+        .map(|(address, password_hash)| {
+            (
+                address.clone(),
+                Principal {
+                    name: address.clone(),
+                    class: "individual",
+                    description: address.clone(),
+                    secret: password_hash,
+                    email: vec![address],
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut rewrites = Vec::new();
+    let mut skipped = 0;
+    let mut add_rewrite = |rewrite: Rewrite| {
+        if rewrite.is_expressible() {
+            rewrites.push(rewrite);
+        } else {
+            skipped += 1;
+        }
+    };
+
+    // Aliases that (eventually) deliver to a mailbox become addresses of its principal
+    let mut loops = 0;
+    for address in routing.aliases.keys() {
+        match routing.deliver_to(address) {
+            Some(target) => match principals.get_mut(&target) {
+                Some(principal) => principal.email.push(address.clone()),
+                None => add_rewrite(Rewrite {
+                    pattern: address.clone(),
+                    except: vec![],
+                    target,
+                }),
+            },
+            None => loops += 1,
+        }
+    }
+
+    // Routes are matched after the directory, but before the catch-all
+    for (domain, routes) in &routing.routes {
+        for (pattern, target) in routes {
+            match routing.deliver_to(target) {
+                Some(target) => add_rewrite(Rewrite {
+                    pattern: format!("{pattern}@{domain}"),
+                    except: routing.explicit_addresses(domain, pattern),
+                    target,
+                }),
+                None => loops += 1,
+            }
+        }
+    }
+
+    for (domain, catch_all) in &routing.catch_alls {
+        match routing.deliver_to(catch_all) {
+            Some(target) => match principals.get_mut(&target) {
+                Some(principal) => principal.email.push(format!("@{domain}")),
+                None => add_rewrite(Rewrite {
+                    pattern: format!("*@{domain}"),
+                    except: routing.explicit_addresses(domain, "*"),
+                    target,
+                }),
+            },
+            None => loops += 1,
+        }
+    }
+
+    let directory = MemoryDirectory {
+        r#type: "memory",
+        domains,
+        principals: principals.into_values().collect(),
+    };
+    Ok((directory, rewrites, skipped + loops))
+}
+
+/// Renders the current routing as a static in-memory directory for stalwart, together
+/// with recipient rewrites for domain routes and forwards to other mailservers.
+/// It applies the same `active` rules as the SQL queries from the README, so it
+/// resolves mailboxes, aliases, routes and catch-alls like the live resolver would.
+/// Returns the TOML and the number of routes that cannot be expressed, which are
+/// routing loops and addresses that would need escaping.
+pub async fn render_snapshot(pool: &SqlitePool) -> Result<(String, usize), anyhow::Error> {
+    let (directory, rewrites, skipped) = build_snapshot(pool).await?;
+
+    let session = (!rewrites.is_empty()).then(|| Session {
+        rcpt: Rcpt {
+            rewrite: rewrites
+                .iter()
+                .map(Rewrite::rule)
+                .chain(std::iter::once(RewriteRule::Keep { r#else: false }))
+                .collect(),
+        },
+    });
+    let snapshot = Snapshot {
+        directory: BTreeMap::from([(DIRECTORY_NAME, directory)]),
+        session,
+    };
+
+    let toml = format!(
+        "# Static directory snapshot generated by idmail at {}\n\
+        # Not included are routing loops and addresses that would need escaping ({skipped} skipped).\n\n{}",
+        chrono::Utc::now().to_rfc3339(),
+        toml::to_string(&snapshot)?,
+    );
+    Ok((toml, skipped))
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    /// Resolves an address like stalwart would with the snapshot: rewrites first, then the directory
+    fn resolve_in_snapshot(directory: &MemoryDirectory, rewrites: &[Rewrite], address: &str) -> Option<String> {
+        let address = rewrites
+            .iter()
+            .find(|x| route_matches(&x.pattern, address) && !x.except.iter().any(|x| x == address))
+            .map_or(address, |x| x.target.as_str());
+        let (_, domain) = address.rsplit_once('@')?;
+
+        let principal = |email: &str| directory.principals.iter().find(|x| x.email.iter().any(|x| x == email));
+        match principal(address).or_else(|| principal(&format!("@{domain}"))) {
+            Some(principal) => Some(principal.name.clone()),
+            None if directory.domains.iter().any(|x| x == domain) => None,
+            None => Some(address.to_string()),
+        }
+    }
+
+    /// Follows the live resolver until it reaches a mailbox or an address of another mailserver
+    async fn resolve_live(pool: &SqlitePool, address: &str) -> Option<String> {
+        let mut address = crate::domain_routes::resolve(pool, address).await.unwrap()?;
+        loop {
+            match crate::domain_routes::resolve(pool, &address).await.unwrap() {
+                Some(next) if next != address => address = next,
+                _ => return Some(address),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_resolves_like_the_live_resolver() {
+        let db = TestDb::new().await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "bob").await;
+        insert_domain(&db, "other.com", "bob").await;
+        insert_mailbox(&db, "bob@example.com", "bob").await;
+        insert_mailbox(&db, "team@example.com", "bob").await;
+        insert_alias(&db, "shop@example.com", "bob@example.com", "bob").await;
+        insert_alias(&db, "chain@example.com", "shop@example.com", "bob").await;
+        insert_alias(&db, "away@example.com", "bob@elsewhere.org", "bob").await;
+        insert_alias(&db, "support-vip@example.com", "bob@example.com", "bob").await;
+        insert_route(&db, "example.com", "support-*", "team@example.com", 0).await;
+        insert_route(&db, "example.com", "*-ext", "help@elsewhere.org", 1).await;
+        insert_route(&db, "other.com", "*", "shop@example.com", 0).await;
+        sqlx::query("UPDATE domains SET catch_all = 'bob@example.com' WHERE domain = 'example.com'")
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        let (directory, rewrites, skipped) = build_snapshot(&db).await.unwrap();
+        assert_eq!(skipped, 0);
+        for address in [
+            "bob@example.com",
+            "team@example.com",
+            "shop@example.com",
+            "chain@example.com",
+            "away@example.com",
+            "support-vip@example.com",
+            "support-1@example.com",
+            "support-ext@example.com",
+            "x-ext@example.com",
+            "random@example.com",
+            "anything@other.com",
+        ] {
+            assert_eq!(
+                resolve_in_snapshot(&directory, &rewrites, address),
+                resolve_live(&db, address).await,
+                "{address}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn routing_loops_are_skipped() {
+        let db = TestDb::new().await;
+        insert_user(&db, "bob", false).await;
+        insert_domain(&db, "example.com", "bob").await;
+        insert_alias(&db, "a@example.com", "b@example.com", "bob").await;
+        insert_alias(&db, "b@example.com", "a@example.com", "bob").await;
+
+        let (toml, skipped) = render_snapshot(&db).await.unwrap();
+        assert_eq!(skipped, 2);
+        assert!(!toml.contains("[session"));
+    }
+
+    #[test]
+    fn rewrites_become_stalwart_expressions() {
+        let rewrite = Rewrite {
+            pattern: "support-*@example.com".to_string(),
+            except: vec!["support-vip@example.com".to_string()],
+            target: "team@example.com".to_string(),
+        };
+        let RewriteRule::Rewrite { r#if, then } = rewrite.rule() else {
+            panic!("expected a rewrite");
+        };
+        assert_eq!(
+            r#if,
+            "matches('^support-.*@example[.]com$', rcpt) && rcpt != 'support-vip@example.com'"
+        );
+        assert_eq!(then, "'team@example.com'");
+
+        let quoted = Rewrite {
+            pattern: "o'neil@example.com".to_string(),
+            except: vec![],
+            target: "team@example.com".to_string(),
+        };
+        assert!(!quoted.is_expressible());
+    }
+}
@@ -2,6 +2,9 @@ use axum::extract::FromRef;
 use leptos::LeptosOptions;
 use leptos_router::RouteListing;
 use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::{config::Config, count_cache::CountCache, domain_cache::AllowedDomainsCache, rate_limit::LoginLimiter};
 
 /// This takes advantage of Axum's SubStates feature by deriving FromRef. This is the only way to have more than one
 /// item in Axum's State. Leptos requires you to have leptosOptions in your State struct for the leptos route handlers
@@ -10,4 +13,12 @@ pub struct AppState {
     pub leptos_options: LeptosOptions,
     pub pool: SqlitePool,
     pub routes: Vec<RouteListing>,
+    pub login_limiter: LoginLimiter,
+    pub allowed_domains_cache: AllowedDomainsCache,
+    pub count_cache: CountCache,
+    pub config: Arc<Config>,
+    /// The key that encrypts the TOTP secrets in the database
+    pub secret_key: crate::totp::ssr::SecretKey,
+    /// The OpenID Connect client, if single sign-on is configured and the provider was discovered
+    pub oidc: Option<Arc<openidconnect::core::CoreClient>>,
 }
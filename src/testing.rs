@@ -0,0 +1,269 @@
+//! Helpers shared by the unit tests. Each [`TestDb`] is a fresh, fully migrated database
+//! in a temporary directory, so tests can run in parallel without seeing each other.
+
+use crate::{
+    auth::User, config::Config, count_cache::CountCache, domain_cache::AllowedDomainsCache, rate_limit::LoginLimiter,
+    state::AppState,
+};
+use leptos::LeptosOptions;
+use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
+use std::{ops::Deref, sync::Arc};
+
+pub struct TestDb {
+    pool: SqlitePool,
+    // Keeps the database file alive until the test ends
+    dir: tempfile::TempDir,
+}
+
+impl TestDb {
+    pub async fn new() -> Self {
+        let dir = tempfile::tempdir().expect("failed to create temporary directory");
+        let options = SqliteConnectOptions::new()
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
+            .filename(dir.path().join("idmail.db"))
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .expect("failed to open database");
+        sqlx::migrate!().run(&pool).await.expect("failed to migrate database");
+        Self { pool, dir }
+    }
+
+    /// The path of the database file
+    pub fn path(&self) -> std::path::PathBuf {
+        self.dir.path().join("idmail.db")
+    }
+}
+
+impl Deref for TestDb {
+    type Target = SqlitePool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+pub fn user(username: &str) -> User {
+    User {
+        username: username.to_string(),
+        password_hash: String::new(),
+        mailbox_owner: None,
+        admin: false,
+        active: true,
+    }
+}
+
+pub fn admin(username: &str) -> User {
+    User {
+        admin: true,
+        ..user(username)
+    }
+}
+
+pub fn mailbox(address: &str, owner: &str) -> User {
+    User {
+        mailbox_owner: Some(owner.to_string()),
+        ..user(address)
+    }
+}
+
+pub async fn insert_user(pool: &SqlitePool, username: &str, admin: bool) {
+    sqlx::query("INSERT INTO users (username, password_hash, admin) VALUES (?, '', ?)")
+        .bind(username)
+        .bind(admin)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+pub async fn insert_domain(pool: &SqlitePool, domain: &str, owner: &str) {
+    sqlx::query("INSERT INTO domains (domain, owner) VALUES (?, ?)")
+        .bind(domain)
+        .bind(owner)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+pub async fn insert_mailbox(pool: &SqlitePool, address: &str, owner: &str) {
+    let domain = address.split_once('@').unwrap().1;
+    sqlx::query("INSERT INTO mailboxes (address, domain, password_hash, owner) VALUES (?, ?, '', ?)")
+        .bind(address)
+        .bind(domain)
+        .bind(owner)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+pub async fn insert_alias(pool: &SqlitePool, address: &str, target: &str, owner: &str) {
+    let domain = address.split_once('@').unwrap().1;
+    sqlx::query("INSERT INTO aliases (address, domain, target, comment, owner) VALUES (?, ?, ?, '', ?)")
+        .bind(address)
+        .bind(domain)
+        .bind(target)
+        .bind(owner)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+pub async fn insert_route(pool: &SqlitePool, domain: &str, pattern: &str, target: &str, priority: i64) {
+    sqlx::query("INSERT INTO domain_routes (domain, pattern, target, priority) VALUES (?, ?, ?, ?)")
+        .bind(domain)
+        .bind(pattern)
+        .bind(target)
+        .bind(priority)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+pub fn app_state(pool: &SqlitePool, config: Config) -> AppState {
+    AppState {
+        leptos_options: LeptosOptions::builder().output_name("idmail").build(),
+        pool: pool.clone(),
+        routes: vec![],
+        login_limiter: LoginLimiter::new(config.failed_login_delay),
+        allowed_domains_cache: AllowedDomainsCache::new(config.allowed_domains_cache_ttl),
+        count_cache: CountCache::new(config.count_cache_ttl),
+        config: Arc::new(config),
+        secret_key: crate::totp::ssr::SecretKey::generate(),
+        oidc: None,
+    }
+}
+
+/// Adds a named API token with all scopes for the given user or mailbox
+pub async fn insert_api_token(pool: &SqlitePool, account: &str, name: &str, token: &str) {
+    let account_kind = if account.contains('@') { "mailbox" } else { "user" };
+    sqlx::query("INSERT INTO api_tokens (account, account_kind, name, token_hash) VALUES (?, ?, ?, ?)")
+        .bind(account)
+        .bind(account_kind)
+        .bind(name)
+        .bind(crate::auth::ssr::hash_api_token(token))
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+/// Collects the messages logged by all tests, since only one logger can be installed per process
+struct CapturingLogger(std::sync::Mutex<Vec<String>>);
+
+static LOGGER: CapturingLogger = CapturingLogger(std::sync::Mutex::new(Vec::new()));
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Starts capturing the log messages of all levels, see [`captured_logs`]
+pub fn capture_logs() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+/// The messages logged since [`capture_logs`] was first called, by any test
+pub fn captured_logs() -> Vec<String> {
+    LOGGER.0.lock().unwrap().clone()
+}
+
+/// The message of the validation error of the given field, if the error carries one
+pub fn field_error(error: leptos::ServerFnError, field: &str) -> Option<String> {
+    crate::utils::ValidationErrors::from_server_error(&error).and_then(|x| x.get(field).map(str::to_string))
+}
+
+/// Headers that authenticate an API request with the given token
+pub fn bearer(token: &str) -> http::HeaderMap {
+    let mut headers = http::HeaderMap::new();
+    headers.insert(http::header::AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+    headers
+}
+
+/// Splits a response into its status and JSON body, which is `null` for empty or non-JSON bodies
+pub async fn response_json(response: impl axum::response::IntoResponse) -> (http::StatusCode, serde_json::Value) {
+    let response = response.into_response();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+/// Creates a reactive runtime on the current thread that provides the same contexts as the
+/// server function handler, except for the auth session. Dispose it at the end of the test.
+pub fn server_context(state: &AppState) -> leptos::RuntimeId {
+    let runtime = leptos::create_runtime();
+    leptos::provide_context(state.pool.clone());
+    leptos::provide_context(state.login_limiter.clone());
+    leptos::provide_context(state.allowed_domains_cache.clone());
+    leptos::provide_context(state.count_cache.clone());
+    leptos::provide_context(state.secret_key.clone());
+    leptos::provide_context(state.config.clone());
+    runtime
+}
+
+/// A router with the session layers of the server, configured like it, and two routes: `/login/{username}` logs the
+/// account in and `/user` answers with the name of the account that [`crate::auth::get_user`] sees.
+pub async fn session_router(state: &AppState) -> axum::Router {
+    use crate::auth::ssr::AuthSession;
+    use axum::{extract::Path, routing::get};
+    use axum_session::{SessionLayer, SessionStore};
+    use axum_session_auth::{AuthConfig, AuthSessionLayer};
+    use axum_session_sqlx::SessionSqlitePool;
+
+    async fn login(auth: AuthSession, Path(username): Path<String>) {
+        auth.login_user(username);
+    }
+
+    async fn current_user(auth: AuthSession) -> String {
+        let runtime = leptos::create_runtime();
+        leptos::provide_context(auth);
+        let user = crate::auth::get_user().await.unwrap();
+        runtime.dispose();
+        user.map(|x| x.username).unwrap_or_default()
+    }
+
+    let session_config = crate::auth::ssr::session_config(&state.config);
+    let session_store = SessionStore::<SessionSqlitePool>::new(Some(state.pool.clone().into()), session_config)
+        .await
+        .unwrap();
+    axum::Router::new()
+        .route("/login/:username", get(login))
+        .route("/user", get(current_user))
+        .layer(
+            AuthSessionLayer::<User, String, SessionSqlitePool, SqlitePool>::new(Some(state.pool.clone()))
+                .with_config(AuthConfig::<String>::default().set_cache(false)),
+        )
+        .layer(SessionLayer::new(session_store))
+}
+
+/// Sends a GET request with the given cookies. Returns the body and the cookies to send next,
+/// which are the ones the response set, if any.
+pub async fn session_get(router: &axum::Router, uri: &str, cookies: &str) -> (String, String) {
+    use tower::ServiceExt;
+
+    let request = http::Request::get(uri)
+        .header(http::header::COOKIE, cookies)
+        .body(axum::body::Body::empty())
+        .unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    let set_cookies = response
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok()?.split(';').next().map(str::to_string))
+        .collect::<Vec<_>>();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let cookies = if set_cookies.is_empty() {
+        cookies.to_string()
+    } else {
+        set_cookies.join("; ")
+    };
+    (String::from_utf8(body.to_vec()).unwrap(), cookies)
+}
@@ -0,0 +1,53 @@
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+
+/// How often the certificate and key are checked for changes
+const RELOAD_CHECK_INTERVAL_SECS: u64 = 60;
+
+/// The modification times of the certificate and key, to detect renewals
+fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(cert).and_then(|x| x.modified()).ok()?;
+    let key = std::fs::metadata(key).and_then(|x| x.modified()).ok()?;
+    Some((cert, key))
+}
+
+/// Loads the PEM encoded certificate chain and private key for serving HTTPS directly
+pub async fn load(cert: &Path, key: &Path) -> Result<RustlsConfig> {
+    // Both sqlx and axum-server use rustls, so the crypto provider must be chosen explicitly.
+    // This fails if a provider was already installed, which is fine.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .with_context(|| format!("failed to load tls certificate {cert:?} with key {key:?}"))
+}
+
+/// Starts the background task reloading the certificate and key whenever they change on disk,
+/// for example after a renewal by certbot. New connections use the new certificate right away.
+pub fn spawn_reload(tls_config: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    tokio::spawn(async move {
+        let mut last_modified = modified(&cert, &key);
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(RELOAD_CHECK_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let current = modified(&cert, &key);
+            if current.is_none() || current == last_modified {
+                continue;
+            }
+
+            // Only remember the change once it was loaded, so a half-written renewal is retried
+            match tls_config.reload_from_pem_file(&cert, &key).await {
+                Err(e) => log::error!("failed to reload tls certificate {cert:?}: {e}"),
+                Ok(()) => {
+                    log::info!("reloaded tls certificate {cert:?}");
+                    last_modified = current;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,672 @@
+use leptos::{logging::error, *};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::EditModal;
+
+/// Returned by a login with the correct password when the account additionally
+/// requires a TOTP code, so the login form can ask for it.
+pub const TOTP_REQUIRED_ERROR: &str = "Two-factor code required.";
+/// Returned when a TOTP code is given but doesn't match
+pub const TOTP_INVALID_ERROR: &str = "Invalid two-factor code.";
+
+/// A newly generated TOTP secret, which is only stored once it was confirmed with a valid code
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    /// The base32 encoded secret, for manual entry into an authenticator app
+    pub secret: String,
+    /// The otpauth:// URI, which authenticator apps can import
+    pub uri: String,
+    /// The otpauth:// URI as a QR code in SVG format, for scanning with an authenticator app
+    pub qr_svg: String,
+}
+
+#[cfg(feature = "ssr")]
+pub mod ssr {
+    use crate::auth::User;
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng},
+        Aes256Gcm, Key, Nonce,
+    };
+    use anyhow::{bail, Context};
+    use chrono::{DateTime, Utc};
+    use sqlx::SqlitePool;
+    use std::{fmt, path::Path, sync::Arc};
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    /// The number of digits of a code
+    const DIGITS: usize = 6;
+    /// The time in seconds for which a single code is valid
+    const STEP_SECS: i64 = 30;
+    /// Codes of this many steps before or after the current one are accepted to allow for clock drift
+    const SKEW_STEPS: i64 = 1;
+    /// The length of generated secrets in bytes, as recommended by RFC 4226
+    const SECRET_LEN: usize = 20;
+    /// Marks stored secrets that are encrypted, older databases may still contain plaintext ones
+    const ENCRYPTED_PREFIX: &str = "enc:";
+    const NONCE_LEN: usize = 12;
+
+    /// The key that encrypts the TOTP secrets in the database. It is kept in a separate file, so
+    /// a leaked database or backup alone isn't enough to generate codes.
+    #[derive(Clone)]
+    pub struct SecretKey(Arc<Aes256Gcm>);
+
+    impl fmt::Debug for SecretKey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("SecretKey(<redacted>)")
+        }
+    }
+
+    impl SecretKey {
+        /// Generates a new random key, which is not persisted
+        pub fn generate() -> Self {
+            Self(Arc::new(Aes256Gcm::new(&Aes256Gcm::generate_key(OsRng))))
+        }
+
+        /// Reads the hex encoded key from the given file, or creates the file with a new key
+        /// that only the current user can read if it doesn't exist yet.
+        pub fn load_or_create(path: &Path) -> anyhow::Result<Self> {
+            use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+            let key = match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    let key = hex::decode(content.trim()).context("The secret key file must contain hex")?;
+                    if key.len() != 32 {
+                        bail!("The secret key in '{}' must be 32 bytes long", path.display());
+                    }
+                    key
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!(
+                        "secret key file '{}' doesn't exist, generating a new key",
+                        path.display()
+                    );
+                    let key = Aes256Gcm::generate_key(OsRng).to_vec();
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .mode(0o600)
+                        .open(path)
+                        .and_then(|mut file| file.write_all(hex::encode(&key).as_bytes()))
+                        .with_context(|| format!("Failed to create secret key file '{}'", path.display()))?;
+                    key
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read secret key file '{}'", path.display()))
+                }
+            };
+
+            Ok(Self(Arc::new(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))))
+        }
+
+        pub(crate) fn encrypt(&self, secret: &str) -> String {
+            let nonce = Aes256Gcm::generate_nonce(OsRng);
+            let ciphertext = self
+                .0
+                .encrypt(&nonce, secret.as_bytes())
+                .expect("encrypting a short secret cannot fail");
+            format!("{ENCRYPTED_PREFIX}{}{}", hex::encode(nonce), hex::encode(ciphertext))
+        }
+
+        pub(crate) fn decrypt(&self, stored: &str) -> Option<String> {
+            let Some(data) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+                return Some(stored.to_string());
+            };
+            let data = hex::decode(data).ok()?;
+            if data.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+            let secret = self.0.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+            String::from_utf8(secret).ok()
+        }
+    }
+
+    /// Encrypts the secrets that were stored before they were encrypted. Runs once on startup.
+    pub async fn encrypt_plaintext_secrets(pool: &SqlitePool, key: &SecretKey) -> Result<(), sqlx::Error> {
+        for (table, column) in [("users", "username"), ("mailboxes", "address")] {
+            let rows = sqlx::query_as::<_, (String, String)>(&format!(
+                "SELECT {column}, totp_secret FROM {table} WHERE totp_secret NOT LIKE '{ENCRYPTED_PREFIX}%'"
+            ))
+            .fetch_all(pool)
+            .await?;
+            for (account, secret) in rows {
+                sqlx::query(&format!("UPDATE {table} SET totp_secret = ? WHERE {column} = ?"))
+                    .bind(key.encrypt(&secret))
+                    .bind(account)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates a new random secret, base32 encoded
+    pub fn generate_secret() -> Result<String, getrandom::Error> {
+        let mut buf = [0u8; SECRET_LEN];
+        getrandom::getrandom(&mut buf)?;
+        Ok(Secret::Raw(buf.to_vec()).to_encoded().to_string())
+    }
+
+    /// Builds the generator for the given base32 secret. Colons are reserved in otpauth:// URIs.
+    fn totp(secret: &str, issuer: &str, account: &str) -> Option<TOTP> {
+        TOTP::new(
+            Algorithm::SHA1,
+            DIGITS,
+            0,
+            STEP_SECS as u64,
+            Secret::Encoded(secret.to_string()).to_bytes().ok()?,
+            Some(issuer.replace(':', "")),
+            account.replace(':', ""),
+        )
+        .ok()
+    }
+
+    /// Builds the otpauth:// URI that authenticator apps use to import a secret
+    pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> Option<String> {
+        totp(secret, issuer, account).map(|x| x.get_url())
+    }
+
+    /// Renders the given text as a QR code in SVG format
+    pub fn qr_svg(text: &str) -> Option<String> {
+        use qrcodegen::{QrCode, QrCodeEcc};
+
+        const BORDER: i32 = 4;
+        let qr = QrCode::encode_text(text, QrCodeEcc::Medium).ok()?;
+        let size = qr.size() + 2 * BORDER;
+        let mut path = String::new();
+        for y in 0..qr.size() {
+            for x in 0..qr.size() {
+                if qr.get_module(x, y) {
+                    path += &format!("M{},{}h1v1h-1z", x + BORDER, y + BORDER);
+                }
+            }
+        }
+        Some(format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {size} {size}\" stroke=\"none\">\
+            <rect width=\"100%\" height=\"100%\" fill=\"#FFFFFF\"/><path d=\"{path}\" fill=\"#000000\"/></svg>"
+        ))
+    }
+
+    /// Returns the time step whose code (RFC 6238) matches the given one, checking the steps around
+    /// the given time to allow for clock drift
+    pub fn matching_step(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+        let code = code.trim();
+        if code.len() != DIGITS || !code.bytes().all(|x| x.is_ascii_digit()) {
+            return None;
+        }
+        let totp = totp(secret, "", "")?;
+        let step = now.timestamp().div_euclid(STEP_SECS);
+        (step - SKEW_STEPS..=step + SKEW_STEPS).find(|x| totp.check(code, (x * STEP_SECS) as u64))
+    }
+
+    /// Whether two-factor authentication is enabled for the given user or mailbox
+    pub async fn enabled(pool: &SqlitePool, username: &str) -> Result<bool, sqlx::Error> {
+        let enabled = sqlx::query_scalar::<_, bool>(
+            "SELECT totp_secret IS NOT NULL FROM users WHERE username = $1 \
+            UNION ALL SELECT totp_secret IS NOT NULL FROM mailboxes WHERE address = $1",
+        )
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(enabled.unwrap_or(false))
+    }
+
+    fn table_of(user: &User) -> (&'static str, &'static str) {
+        if user.mailbox_owner.is_some() {
+            ("mailboxes", "address")
+        } else {
+            ("users", "username")
+        }
+    }
+
+    /// Checks the code against the TOTP secret of the given user or mailbox. Each code is only
+    /// accepted once, later codes of the same or an earlier time step are rejected to prevent
+    /// replaying an observed code. Accounts without a secret never match.
+    pub async fn verify_code(
+        pool: &SqlitePool,
+        key: &SecretKey,
+        user: &User,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        let (table, column) = table_of(user);
+        let stored =
+            sqlx::query_scalar::<_, Option<String>>(&format!("SELECT totp_secret FROM {table} WHERE {column} = ?"))
+                .bind(&user.username)
+                .fetch_optional(pool)
+                .await?
+                .flatten();
+        let Some(secret) = stored.and_then(|x| key.decrypt(&x)) else {
+            return Ok(false);
+        };
+        let Some(step) = matching_step(&secret, code, now) else {
+            return Ok(false);
+        };
+
+        // Only one of concurrent requests with the same code can advance the step
+        let result = sqlx::query(&format!(
+            "UPDATE {table} SET totp_last_step = ? WHERE {column} = ? \
+            AND (totp_last_step IS NULL OR totp_last_step < ?)"
+        ))
+        .bind(step)
+        .bind(&user.username)
+        .bind(step)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores the TOTP secret of the given user or mailbox encrypted, together with the step of the
+    /// code that confirmed it. `None` disables two-factor authentication.
+    pub async fn set_secret(
+        pool: &SqlitePool,
+        key: &SecretKey,
+        user: &User,
+        secret: Option<(&str, i64)>,
+    ) -> Result<(), sqlx::Error> {
+        let (table, column) = table_of(user);
+        sqlx::query(&format!(
+            "UPDATE {table} SET totp_secret = ?, totp_last_step = ? WHERE {column} = ?"
+        ))
+        .bind(secret.map(|(secret, _)| key.encrypt(secret)))
+        .bind(secret.map(|(_, step)| step))
+        .bind(&user.username)
+        .execute(pool)
+        .await
+        .map(|_| ())
+    }
+}
+
+/// Whether two-factor authentication is enabled for the current account
+#[server]
+pub async fn totp_enabled() -> Result<bool, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    Ok(ssr::enabled(&pool, &user.username).await?)
+}
+
+/// Generates a new secret for the current account. It only takes effect
+/// after it was confirmed with `confirm_totp`, so nobody gets locked out by
+/// an enrollment that didn't reach their authenticator app.
+#[server]
+pub async fn enroll_totp() -> Result<TotpEnrollment, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let secret = ssr::generate_secret()?;
+    let issuer = crate::app::get_brand_name().await?;
+    let uri = ssr::otpauth_uri(&issuer, &user.username, &secret)
+        .ok_or_else(|| ServerFnError::new("Failed to generate two-factor secret"))?;
+    let qr_svg = ssr::qr_svg(&uri).ok_or_else(|| ServerFnError::new("Failed to generate QR code"))?;
+
+    Ok(TotpEnrollment { secret, uri, qr_svg })
+}
+
+/// Enables two-factor authentication with the given secret, if the code matches it
+#[server]
+pub async fn confirm_totp(current_password: String, secret: String, code: String) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    crate::auth::reauthenticate_if_required(&user.username, current_password).await?;
+    let Some(step) = ssr::matching_step(&secret, &code, chrono::Utc::now()) else {
+        return Err(ServerFnError::new(TOTP_INVALID_ERROR));
+    };
+
+    let pool = crate::database::ssr::pool()?;
+    let key = crate::database::ssr::secret_key()?;
+    ssr::set_secret(&pool, &key, &user, Some((&secret, step))).await?;
+    log::info!("enabled two-factor authentication for '{}'", user.username);
+    Ok(())
+}
+
+/// Disables two-factor authentication, which requires a current code
+#[server]
+pub async fn disable_totp(current_password: String, code: String) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    crate::auth::reauthenticate_if_required(&user.username, current_password).await?;
+    let pool = crate::database::ssr::pool()?;
+    if !ssr::enabled(&pool, &user.username).await? {
+        return Ok(());
+    }
+    let key = crate::database::ssr::secret_key()?;
+    if !ssr::verify_code(&pool, &key, &user, &code, chrono::Utc::now()).await? {
+        return Err(ServerFnError::new(TOTP_INVALID_ERROR));
+    }
+
+    ssr::set_secret(&pool, &key, &user, None).await?;
+    log::info!("disabled two-factor authentication for '{}'", user.username);
+    Ok(())
+}
+
+/// Enables or disables two-factor authentication of the current account
+/// Enables or disables two-factor authentication of the current account. Both need the current
+/// password if reauthentication is required for sensitive actions.
+#[component]
+pub fn TotpSettings(reauth_required: ReadSignal<bool>) -> impl IntoView {
+    let (enabled, set_enabled) = create_signal(false);
+    if leptos::leptos_dom::is_browser() {
+        spawn_local(async move {
+            match totp_enabled().await {
+                Err(e) => error!("Failed to check whether two-factor authentication is enabled: {}", e),
+                Ok(x) => set_enabled(x),
+            }
+        });
+    }
+
+    let (input_code, set_input_code) = create_signal("".to_string());
+    let (input_password, set_input_password) = create_signal("".to_string());
+    let has_invalid_code = move || {
+        let code = input_code();
+        code.len() != 6 || !code.bytes().all(|x| x.is_ascii_digit())
+    };
+    let errors = Signal::derive(move || {
+        if has_invalid_code() {
+            vec!["Enter the 6-digit code from your authenticator app".to_string()]
+        } else {
+            Vec::new()
+        }
+    });
+
+    let enroll_modal = create_rw_signal(None);
+    let enroll = move || {
+        spawn_local(async move {
+            match enroll_totp().await {
+                Err(e) => error!("Failed to generate two-factor secret: {}", e),
+                Ok(enrollment) => {
+                    set_input_code("".to_string());
+                    set_input_password("".to_string());
+                    enroll_modal.set(Some(Some(enrollment)));
+                }
+            }
+        });
+    };
+    let on_enroll = move |(data, on_error): (Option<TotpEnrollment>, Callback<ServerFnError>)| {
+        let Some(enrollment) = data else {
+            return;
+        };
+        spawn_local(async move {
+            match confirm_totp(
+                input_password.get_untracked(),
+                enrollment.secret,
+                input_code.get_untracked(),
+            )
+            .await
+            {
+                Err(e) => on_error(e),
+                Ok(()) => {
+                    enroll_modal.set(None);
+                    set_enabled(true);
+                }
+            }
+        });
+    };
+
+    let disable_modal = create_rw_signal(None);
+    let disable = move || {
+        set_input_code("".to_string());
+        set_input_password("".to_string());
+        disable_modal.set(Some(Some(())));
+    };
+    let on_disable = move |(_data, on_error): (Option<()>, Callback<ServerFnError>)| {
+        spawn_local(async move {
+            match disable_totp(input_password.get_untracked(), input_code.get_untracked()).await {
+                Err(e) => on_error(e),
+                Ok(()) => {
+                    disable_modal.set(None);
+                    set_enabled(false);
+                }
+            }
+        });
+    };
+
+    let code_input = move || {
+        view! {
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="totp_code"
+                >
+                    "Code"
+                </label>
+                <input
+                    id="totp_code"
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    type="text"
+                    inputmode="numeric"
+                    autocomplete="one-time-code"
+                    placeholder="123456"
+                    maxlength="6"
+                    on:input=move |ev| set_input_code(event_target_value(&ev))
+                    prop:value=input_code
+                />
+            </div>
+            <Show when=reauth_required>
+                <div class="flex flex-col gap-2">
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="totp_reauth_password"
+                    >
+                        "Current Password"
+                    </label>
+                    <input
+                        id="totp_reauth_password"
+                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                        type="password"
+                        required="required"
+                        maxlength="1024"
+                        on:input=move |ev| set_input_password(event_target_value(&ev))
+                        prop:value=input_password
+                    />
+                </div>
+            </Show>
+        }
+    };
+
+    view! {
+        <button
+            type="button"
+            class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 focus:ring-blue-300 dark:focus:ring-blue-900"
+            on:click=move |_| if enabled() { disable() } else { enroll() }
+        >
+            {move || {
+                if enabled() {
+                    "Disable two-factor authentication"
+                } else {
+                    "Enable two-factor authentication"
+                }
+            }}
+        </button>
+
+        <EditModal
+            data=enroll_modal
+            what="two-factor authentication".to_string()
+            get_title=move |_| { "two-factor authentication" }
+            on_confirm=on_enroll
+            errors
+        >
+            <p class="text-sm text-gray-500 dark:text-gray-400">
+                "Scan the QR code with your authenticator app, or add the secret below manually. "
+                "Then enter the current code to confirm."
+            </p>
+            <div
+                class="self-center w-48 h-48"
+                inner_html=move || enroll_modal.get().flatten().map(|x| x.qr_svg).unwrap_or_default()
+            ></div>
+            <div class="flex flex-col gap-2">
+                <label class="text-sm font-medium leading-none">"Secret"</label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-gray-50 dark:bg-zinc-900 text-sm font-mono p-2.5"
+                    type="text"
+                    readonly
+                    prop:value=move || enroll_modal.get().flatten().map(|x| x.secret).unwrap_or_default()
+                />
+            </div>
+            <div class="flex flex-col gap-2">
+                <label class="text-sm font-medium leading-none">"Link"</label>
+                <input
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-gray-50 dark:bg-zinc-900 text-sm font-mono p-2.5"
+                    type="text"
+                    readonly
+                    prop:value=move || enroll_modal.get().flatten().map(|x| x.uri).unwrap_or_default()
+                />
+            </div>
+            {code_input}
+        </EditModal>
+
+        <EditModal
+            data=disable_modal
+            what="two-factor authentication".to_string()
+            get_title=move |_| { "two-factor authentication" }
+            on_confirm=on_disable
+            errors
+        >
+            <p class="text-sm text-gray-500 dark:text-gray-400">
+                "Enter a current code from your authenticator app to disable two-factor authentication."
+            </p>
+            {code_input}
+        </EditModal>
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::ssr::*;
+    use crate::testing::*;
+    use chrono::{DateTime, Utc};
+    use totp_rs::{Algorithm, Secret, TOTP};
+
+    fn code_at(secret: &str, time: DateTime<Utc>) -> String {
+        let bytes = Secret::Encoded(secret.to_string()).to_bytes().unwrap();
+        TOTP::new(Algorithm::SHA1, 6, 0, 30, bytes, None, String::new())
+            .unwrap()
+            .generate(time.timestamp() as u64)
+    }
+
+    async fn stored_secret(pool: &sqlx::SqlitePool, username: &str) -> Option<String> {
+        sqlx::query_scalar("SELECT totp_secret FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn matching_step_allows_one_step_of_drift() {
+        let secret = generate_secret().unwrap();
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let step = now.timestamp() / 30;
+
+        assert_eq!(matching_step(&secret, &code_at(&secret, now), now), Some(step));
+        let previous = now - chrono::Duration::seconds(30);
+        assert_eq!(matching_step(&secret, &code_at(&secret, previous), now), Some(step - 1));
+        let stale = now - chrono::Duration::seconds(90);
+        assert_eq!(matching_step(&secret, &code_at(&secret, stale), now), None);
+        assert_eq!(matching_step(&secret, "12345", now), None);
+    }
+
+    #[test]
+    fn enrollment_uri_and_qr_code() {
+        let secret = generate_secret().unwrap();
+        let uri = otpauth_uri("id:mail", "alice", &secret).unwrap();
+        assert!(uri.starts_with("otpauth://totp/idmail:alice?"));
+        assert!(uri.contains(&format!("secret={secret}")));
+        assert!(qr_svg(&uri).unwrap().starts_with("<svg"));
+    }
+
+    #[tokio::test]
+    async fn secrets_are_stored_encrypted() {
+        let db = TestDb::new().await;
+        let key = SecretKey::generate();
+        insert_user(&db, "alice", false).await;
+        let secret = generate_secret().unwrap();
+        set_secret(&db, &key, &user("alice"), Some((&secret, 0))).await.unwrap();
+
+        let stored = stored_secret(&db, "alice").await.unwrap();
+        assert!(!stored.contains(&secret));
+        let now = Utc::now();
+        assert!(verify_code(&db, &key, &user("alice"), &code_at(&secret, now), now)
+            .await
+            .unwrap());
+        // Another key can't decrypt the secret
+        let now = now + chrono::Duration::seconds(30);
+        let code = code_at(&secret, now);
+        assert!(!verify_code(&db, &SecretKey::generate(), &user("alice"), &code, now)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn plaintext_secrets_are_encrypted_on_startup() {
+        let db = TestDb::new().await;
+        let key = SecretKey::generate();
+        insert_user(&db, "alice", false).await;
+        let secret = generate_secret().unwrap();
+        sqlx::query("UPDATE users SET totp_secret = ? WHERE username = 'alice'")
+            .bind(&secret)
+            .execute(&*db)
+            .await
+            .unwrap();
+
+        // Legacy plaintext secrets still work until they are encrypted
+        let now = Utc::now();
+        assert!(verify_code(&db, &key, &user("alice"), &code_at(&secret, now), now)
+            .await
+            .unwrap());
+        encrypt_plaintext_secrets(&db, &key).await.unwrap();
+        assert_ne!(stored_secret(&db, "alice").await.unwrap(), secret);
+        let later = now + chrono::Duration::seconds(30);
+        assert!(verify_code(&db, &key, &user("alice"), &code_at(&secret, later), later)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn codes_cannot_be_replayed() {
+        let db = TestDb::new().await;
+        let key = SecretKey::generate();
+        insert_user(&db, "alice", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "box@example.com", "alice").await;
+        let mailbox = mailbox("box@example.com", "alice");
+        let secret = generate_secret().unwrap();
+        set_secret(&db, &key, &mailbox, Some((&secret, 0))).await.unwrap();
+
+        let now = Utc::now();
+        let code = code_at(&secret, now);
+        assert!(verify_code(&db, &key, &mailbox, &code, now).await.unwrap());
+        assert!(!verify_code(&db, &key, &mailbox, &code, now).await.unwrap());
+        // Codes of an earlier step are rejected once a later one was used
+        let previous = code_at(&secret, now - chrono::Duration::seconds(30));
+        assert!(!verify_code(&db, &key, &mailbox, &previous, now).await.unwrap());
+        assert!(!verify_code(&db, &key, &mailbox, "000000", now).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn the_confirming_code_cannot_be_reused() {
+        let db = TestDb::new().await;
+        let key = SecretKey::generate();
+        insert_user(&db, "alice", false).await;
+        let secret = generate_secret().unwrap();
+        let now = Utc::now();
+        let code = code_at(&secret, now);
+        let step = matching_step(&secret, &code, now).unwrap();
+        set_secret(&db, &key, &user("alice"), Some((&secret, step)))
+            .await
+            .unwrap();
+
+        assert!(!verify_code(&db, &key, &user("alice"), &code, now).await.unwrap());
+    }
+
+    #[test]
+    fn key_file_is_created_private_and_reloaded() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("idmail.key");
+        let key = SecretKey::load_or_create(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let encrypted = key.encrypt("secret");
+        let reloaded = SecretKey::load_or_create(&path).unwrap();
+        assert_eq!(reloaded.decrypt(&encrypted).as_deref(), Some("secret"));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Entry<V> {
+    loaded_at: Instant,
+    value: V,
+}
+
+#[derive(Debug)]
+struct Entries<K, V> {
+    /// Incremented on each invalidation, so loads that raced with it aren't cached
+    generation: u64,
+    by_key: HashMap<K, Entry<V>>,
+}
+
+/// An in-memory cache whose entries expire after a fixed duration. Cloning it is cheap
+/// and shares the entries, so it can be kept in the `AppState`. It holds at most a
+/// fixed number of entries and forgets the stale ones (or all of them) when it is full.
+#[derive(Debug)]
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Arc<Mutex<Entries<K, V>>>,
+}
+
+impl<K, V> Clone for TtlCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    /// Creates a cache whose entries expire after the given duration. A zero duration disables caching.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Arc::new(Mutex::new(Entries {
+                generation: 0,
+                by_key: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns the cached value, or runs the load and caches its result if there is none.
+    pub async fn get_or_load<F, E>(&self, key: K, load: F) -> Result<V, E>
+    where
+        F: Future<Output = Result<V, E>>,
+    {
+        let now = Instant::now();
+        let generation = {
+            let entries = self.entries();
+            if let Some(entry) = entries.by_key.get(&key) {
+                if now.duration_since(entry.loaded_at) < self.ttl {
+                    return Ok(entry.value.clone());
+                }
+            }
+            entries.generation
+        };
+
+        let value = load.await?;
+        let mut entries = self.entries();
+        if !self.ttl.is_zero() && entries.generation == generation {
+            let by_key = &mut entries.by_key;
+            if by_key.len() >= self.max_entries {
+                // Forget stale entries here, and everything if that wasn't enough
+                by_key.retain(|_, x| now.duration_since(x.loaded_at) < self.ttl);
+                if by_key.len() >= self.max_entries {
+                    by_key.clear();
+                }
+            }
+            by_key.insert(
+                key,
+                Entry {
+                    loaded_at: now,
+                    value: value.clone(),
+                },
+            );
+        }
+        Ok(value)
+    }
+
+    /// Forgets all entries whose key matches
+    pub fn invalidate_matching(&self, matches: impl Fn(&K) -> bool) {
+        let mut entries = self.entries();
+        entries.generation += 1;
+        entries.by_key.retain(|key, _| !matches(key));
+    }
+
+    /// Forgets all entries
+    pub fn invalidate_all(&self) {
+        self.invalidate_matching(|_| true);
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Entries<K, V>> {
+        self.entries.lock().expect("cache mutex poisoned")
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    async fn load(cache: &TtlCache<&'static str, u32>, key: &'static str, value: u32) -> u32 {
+        cache.get_or_load(key, async { Ok::<_, ()>(value) }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn entries_are_cached_until_invalidated() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        assert_eq!(load(&cache, "a", 1).await, 1);
+        assert_eq!(load(&cache, "a", 2).await, 1);
+        assert_eq!(load(&cache, "b", 3).await, 3);
+
+        cache.invalidate_matching(|key| *key == "a");
+        assert_eq!(load(&cache, "a", 4).await, 4);
+        assert_eq!(load(&cache, "b", 5).await, 3);
+        cache.invalidate_all();
+        assert_eq!(load(&cache, "b", 6).await, 6);
+    }
+
+    #[tokio::test]
+    async fn zero_ttl_disables_caching() {
+        let cache = TtlCache::new(Duration::ZERO, 10);
+        assert_eq!(load(&cache, "a", 1).await, 1);
+        assert_eq!(load(&cache, "a", 2).await, 2);
+    }
+
+    #[tokio::test]
+    async fn loads_racing_with_an_invalidation_are_not_cached() {
+        let cache = TtlCache::new(Duration::from_secs(60), 10);
+        let racing = cache.get_or_load("a", async {
+            cache.invalidate_all();
+            Ok::<_, ()>(1)
+        });
+        assert_eq!(racing.await.unwrap(), 1);
+        assert_eq!(load(&cache, "a", 2).await, 2);
+    }
+
+    #[tokio::test]
+    async fn full_caches_forget_their_entries() {
+        let cache = TtlCache::new(Duration::from_secs(60), 2);
+        load(&cache, "a", 1).await;
+        load(&cache, "b", 2).await;
+        load(&cache, "c", 3).await;
+        assert_eq!(load(&cache, "a", 4).await, 4);
+    }
+}
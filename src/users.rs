@@ -1,15 +1,25 @@
 use std::collections::VecDeque;
 use std::ops::Range;
 
-use crate::utils::{DeleteModal, EditModal, Modal};
-use crate::utils::{SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer};
+use crate::auth::{get_capabilities, ApiScope};
+use crate::recipients::Recipients;
+use crate::totp::TotpSettings;
+use crate::utils::{DeleteModal, EditModal, FieldErrorMessage, Modal};
+use crate::utils::{
+    OptionTimediffRenderer, SliderRenderer, THeadCellRenderer, TailwindClassesPreset, TimediffRenderer,
+};
+use crate::utils::{ProvisionedBadge, ProvisionedFilter, PROVISIONED_TOOLTIP};
 
+#[cfg(feature = "ssr")]
+use crate::database::ssr::{ensure_not_provisioned, push_order_by, LogQuery, Provisioned};
+#[cfg(feature = "ssr")]
+use crate::utils::{map_unique_violation, ValidationErrors};
 use chrono::{DateTime, Utc};
 use leptos::html::Dialog;
 use leptos::{ev::MouseEvent, logging::error, *};
 use leptos_icons::Icon;
 use leptos_struct_table::*;
-use leptos_use::{use_debounce_fn_with_arg, use_timeout_fn};
+use leptos_use::{signal_debounced, use_debounce_fn_with_arg, use_timeout_fn};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
 use sqlx::QueryBuilder;
@@ -27,10 +37,69 @@ pub struct User {
     pub active: bool,
     #[table(class = "w-1", title = "Created", renderer = "TimediffRenderer")]
     pub created_at: DateTime<Utc>,
+    #[table(class = "w-1", title = "Last login", renderer = "OptionTimediffRenderer")]
+    pub last_login_at: Option<DateTime<Utc>>,
+    /// Whether the entry is managed by the provisioning file
+    #[table(skip)]
+    pub provisioned: bool,
+}
+
+/// Additional requirements for new passwords beyond the length bound,
+/// configured through `IDMAIL_PASSWORD_POLICY`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    /// Require both lowercase and uppercase letters
+    pub mixed_case: bool,
+    /// Require at least one digit
+    pub digit: bool,
+    /// Require at least one character that is neither a letter nor a digit
+    pub symbol: bool,
+    /// Reject passwords from the bundled list of common passwords
+    pub common: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl PasswordPolicy {
+    /// Parses a comma separated list of requirements, e.g. `mixed-case,digit,symbol,common`
+    pub fn parse(requirements: &str) -> anyhow::Result<Self> {
+        let mut policy = Self::default();
+        for requirement in requirements.split(',') {
+            match requirement.trim() {
+                "" => {}
+                "mixed-case" => policy.mixed_case = true,
+                "digit" => policy.digit = true,
+                "symbol" => policy.symbol = true,
+                "common" => policy.common = true,
+                x => anyhow::bail!("unknown password requirement '{x}'"),
+            }
+        }
+        Ok(policy)
+    }
 }
 
-pub(crate) fn is_valid_pw(password: &str) -> bool {
-    (12..=1024).contains(&password.len())
+/// Commonly used passwords, one per line. Only entries that satisfy the
+/// length bound are listed, since shorter ones are rejected anyway.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// Returns the reasons why the given password violates the policy, if any.
+pub(crate) fn password_problems(password: &str, policy: &PasswordPolicy) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !(12..=1024).contains(&password.len()) {
+        problems.push("Password must be between 12 and 1024 characters".to_string());
+    }
+    if policy.mixed_case && !(password.chars().any(char::is_lowercase) && password.chars().any(char::is_uppercase)) {
+        problems.push("Password must contain both lowercase and uppercase letters".to_string());
+    }
+    if policy.digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        problems.push("Password must contain a digit".to_string());
+    }
+    if policy.symbol && password.chars().all(char::is_alphanumeric) {
+        problems.push("Password must contain a symbol".to_string());
+    }
+    if policy.common && COMMON_PASSWORDS.lines().any(|x| x.eq_ignore_ascii_case(password)) {
+        problems.push("Password is too common".to_string());
+    }
+    problems
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -39,100 +108,512 @@ pub struct UserQuery {
     sort: VecDeque<(usize, ColumnSort)>,
     range: Range<usize>,
     search: String,
+    #[serde(default)]
+    provisioned: Option<bool>,
 }
 
 #[server]
 pub async fn list_users(query: UserQuery) -> Result<Vec<User>, ServerFnError> {
     let _user = crate::auth::auth_admin().await?;
-    let UserQuery { sort, range, search } = query;
-
-    let mut query = QueryBuilder::new("SELECT * FROM users");
+    let UserQuery {
+        sort,
+        range,
+        search,
+        provisioned,
+    } = query;
+
+    let mut query = QueryBuilder::new("SELECT * FROM users WHERE 1=1");
     if !search.is_empty() {
-        query.push(" WHERE username LIKE concat('%', ");
+        query.push(" AND username LIKE concat('%', ");
         query.push_bind(&search);
         query.push(", '%')");
     }
 
-    if let Some(order) = User::sorting_to_sql(&sort) {
-        query.push(" ");
-        query.push(order);
+    if let Some(provisioned) = provisioned {
+        query.push(" AND provisioned = ");
+        query.push_bind(provisioned);
     }
 
+    push_order_by(&mut query, User::sorting_to_sql(&sort), "username");
+
     query.push(" LIMIT ");
     query.push_bind(range.len() as i64);
     query.push(" OFFSET ");
     query.push_bind(range.start as i64);
 
     let pool = crate::database::ssr::pool()?;
-    Ok(query.build_query_as::<User>().fetch_all(&pool).await?)
+    Ok(query.logged().build_query_as::<User>().fetch_all(&pool).await?)
 }
 
+/// Whether the current mailbox is opted in to the weekly activity digest
 #[server]
-pub async fn regenerate_api_key() -> Result<String, ServerFnError> {
+pub async fn digest_enabled() -> Result<bool, ServerFnError> {
     let user = crate::auth::auth_any().await?;
     if user.mailbox_owner.is_none() {
         return Err(ServerFnError::new("Must be a mailbox user."));
     }
 
+    let pool = crate::database::ssr::pool()?;
+    Ok(sqlx::query_scalar("SELECT digest FROM mailboxes WHERE address = ?")
+        .bind(&user.username)
+        .fetch_one(&pool)
+        .await?)
+}
+
+#[server]
+pub async fn set_digest_enabled(enabled: bool) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    if user.mailbox_owner.is_none() {
+        return Err(ServerFnError::new("Must be a mailbox user."));
+    }
+
+    let mut query = QueryBuilder::new("UPDATE mailboxes SET digest = ");
+    query.push_bind(enabled);
+    query.push(" WHERE address = ");
+    query.push_bind(&user.username);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+/// Whether aliases created by the given user or mailbox start out active
+#[cfg(feature = "ssr")]
+pub async fn new_aliases_active_of(pool: &sqlx::SqlitePool, username: &str) -> Result<bool, sqlx::Error> {
+    let active = sqlx::query_scalar::<_, bool>(
+        "SELECT new_aliases_active FROM users WHERE username = $1 \
+        UNION ALL SELECT new_aliases_active FROM mailboxes WHERE address = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(active.unwrap_or(true))
+}
+
+/// Whether aliases created by the current account start out active
+#[server]
+pub async fn new_aliases_active() -> Result<bool, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+    Ok(new_aliases_active_of(&pool, &user.username).await?)
+}
+
+#[server]
+pub async fn set_new_aliases_active(active: bool) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+
+    let mut query = if user.mailbox_owner.is_some() {
+        QueryBuilder::new("UPDATE mailboxes SET new_aliases_active = ")
+    } else {
+        QueryBuilder::new("UPDATE users SET new_aliases_active = ")
+    };
+    query.push_bind(active);
+    if user.mailbox_owner.is_some() {
+        query.push(" WHERE address = ");
+    } else {
+        query.push(" WHERE username = ");
+    }
+    query.push_bind(&user.username);
+
+    let pool = crate::database::ssr::pool()?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
+    Ok(())
+}
+
+/// A named API token of the current account. The token itself is only shown once when it is created.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The maximum number of named API tokens of a single account
+#[cfg(feature = "ssr")]
+const MAX_API_TOKENS: i64 = 32;
+
+/// Names that would be confused with the other sources aliases record in `created_via`
+#[cfg(feature = "ssr")]
+const RESERVED_API_TOKEN_NAMES: [&str; 3] = ["ui", "import", crate::auth::ssr::ACCOUNT_TOKEN_NAME];
+
+/// Trims the name of a new API token and makes sure it is usable
+#[cfg(feature = "ssr")]
+fn check_api_token_name(name: &str) -> Result<&str, ServerFnError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(ServerFnError::new("The name must be between 1 and 64 characters."));
+    }
+    if RESERVED_API_TOKEN_NAMES.iter().any(|x| name.eq_ignore_ascii_case(x)) {
+        return Err(ServerFnError::new(format!("The name '{name}' is reserved.")));
+    }
+    Ok(name)
+}
+
+/// Lists the API tokens of the current account
+#[server]
+pub async fn list_api_tokens() -> Result<Vec<ApiToken>, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let pool = crate::database::ssr::pool()?;
+
+    let tokens = sqlx::query_as::<_, (i64, String, Option<String>, DateTime<Utc>, Option<DateTime<Utc>>)>(
+        "SELECT id, name, scopes, created_at, last_used_at FROM api_tokens \
+        WHERE account_kind = ? AND account = ? ORDER BY created_at, id",
+    )
+    .bind(crate::auth::ssr::api_token_account_kind(&user))
+    .bind(&user.username)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(tokens
+        .into_iter()
+        .map(|(id, name, scopes, created_at, last_used_at)| ApiToken {
+            id,
+            name,
+            scopes: ApiScope::parse_stored(scopes.as_deref()),
+            created_at,
+            last_used_at,
+        })
+        .collect())
+}
+
+/// Creates a new named API token for the current account with the given scopes. Tokens of users
+/// act with the permissions of the user, just like tokens of mailboxes. Only the hash of the token
+/// is stored, so the returned token cannot be retrieved again later.
+#[server]
+pub async fn create_api_token(
+    current_password: String,
+    name: String,
+    scopes: Vec<ApiScope>,
+) -> Result<String, ServerFnError> {
+    let user = crate::auth::auth_any().await?;
+    let name = check_api_token_name(&name)?;
+    if scopes.is_empty() {
+        return Err(ServerFnError::new("The API token needs at least one scope."));
+    }
+    // Tokens with all scopes are stored without explicit scopes, so they also gain future ones
+    let scopes = (!ApiScope::ALL.iter().all(|x| scopes.contains(x)))
+        .then(|| scopes.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(","));
+
+    crate::auth::reauthenticate_if_required(&user.username, current_password).await?;
+    let pool = crate::database::ssr::pool()?;
+    let account_kind = crate::auth::ssr::api_token_account_kind(&user);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM api_tokens WHERE account_kind = ? AND account = ?")
+        .bind(account_kind)
+        .bind(&user.username)
+        .fetch_one(&pool)
+        .await?;
+    if count >= MAX_API_TOKENS {
+        return Err(ServerFnError::new(format!(
+            "An account can have at most {MAX_API_TOKENS} API tokens."
+        )));
+    }
+
     let mut buf = [0u8; 24];
     getrandom::getrandom(&mut buf)?;
     let api_token = hex::encode(buf);
 
-    let mut query = QueryBuilder::new("UPDATE mailboxes SET api_token = ");
-    query.push_bind(&api_token);
-    query.push(" WHERE address = ");
-    query.push_bind(&user.username);
+    sqlx::query("INSERT INTO api_tokens (account, account_kind, name, token_hash, scopes) VALUES (?, ?, ?, ?, ?)")
+        .bind(&user.username)
+        .bind(account_kind)
+        .bind(name)
+        .bind(crate::auth::ssr::hash_api_token(&api_token))
+        .bind(scopes)
+        .execute(&pool)
+        .await?;
+
+    Ok(api_token)
+}
 
+/// Revokes an API token of the current account. The account token of provisioned
+/// mailboxes is managed by the provisioning file.
+#[server]
+pub async fn delete_api_token(id: i64) -> Result<(), ServerFnError> {
+    let user = crate::auth::auth_any().await?;
     let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    let account_kind = crate::auth::ssr::api_token_account_kind(&user);
+
+    let name = sqlx::query_scalar::<_, String>(
+        "SELECT name FROM api_tokens WHERE id = ? AND account_kind = ? AND account = ?",
+    )
+    .bind(id)
+    .bind(account_kind)
+    .bind(&user.username)
+    .fetch_optional(&pool)
+    .await?;
+    if name.as_deref() == Some(crate::auth::ssr::ACCOUNT_TOKEN_NAME) && user.mailbox_owner.is_some() {
+        ensure_not_provisioned(&pool, &user, Provisioned::Mailboxes, &user.username).await?;
+    }
+
+    sqlx::query("DELETE FROM api_tokens WHERE id = ? AND account_kind = ? AND account = ?")
+        .bind(id)
+        .bind(account_kind)
+        .bind(&user.username)
+        .execute(&pool)
+        .await?;
+    Ok(())
+}
+
+/// Replaces all API tokens of the given mailbox with a new account token and returns it.
+/// Returns None if the mailbox doesn't exist.
+#[cfg(feature = "ssr")]
+async fn replace_mailbox_api_tokens(pool: &sqlx::SqlitePool, address: &str) -> Result<Option<String>, ServerFnError> {
+    let mut tx = pool.begin().await?;
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM mailboxes WHERE address = ?")
+        .bind(address)
+        .fetch_one(&mut *tx)
+        .await?
+        > 0;
+    if !exists {
+        return Ok(None);
+    }
+
+    let mut buf = [0u8; 24];
+    getrandom::getrandom(&mut buf)?;
+    let api_token = hex::encode(buf);
 
+    sqlx::query("DELETE FROM api_tokens WHERE account_kind = 'mailbox' AND account = ?")
+        .bind(address)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO api_tokens (account, account_kind, name, token_hash) VALUES (?, 'mailbox', ?, ?)")
+        .bind(address)
+        .bind(crate::auth::ssr::ACCOUNT_TOKEN_NAME)
+        .bind(crate::auth::ssr::hash_api_token(&api_token))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(Some(api_token))
+}
+
+/// Regenerates the API token of any mailbox on behalf of its owner. All other tokens of the mailbox
+/// are revoked, so a leaked token can't be used anymore. The new token is only returned once and
+/// cannot be retrieved later.
+#[server]
+pub async fn regenerate_api_key_for(address: String) -> Result<String, ServerFnError> {
+    let user = crate::auth::auth_user().await?;
+    let pool = crate::database::ssr::pool()?;
+    regenerate_mailbox_api_token(&pool, &user, &address).await
+}
+
+#[cfg(feature = "ssr")]
+async fn regenerate_mailbox_api_token(
+    pool: &sqlx::SqlitePool,
+    user: &crate::auth::User,
+    address: &str,
+) -> Result<String, ServerFnError> {
+    if !user.admin {
+        return Err(ServerFnError::new("Unauthorized"));
+    }
+    ensure_not_provisioned(pool, user, Provisioned::Mailboxes, address).await?;
+
+    let api_token = replace_mailbox_api_tokens(pool, address)
+        .await?
+        .ok_or_else(|| ServerFnError::new("Mailbox does not exist."))?;
+
+    log::info!(
+        "admin '{}' regenerated the api tokens of mailbox '{address}'",
+        user.username
+    );
     Ok(api_token)
 }
 
 #[server]
 pub async fn admin_count() -> Result<usize, ServerFnError> {
-    let _user = crate::auth::auth_admin().await?;
+    let user = crate::auth::auth_admin().await?;
     let mut query = QueryBuilder::new("SELECT COUNT(*) FROM users WHERE admin = TRUE");
 
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
-
-    Ok(count as usize)
+    let load = async {
+        let count = query.logged().build_query_scalar::<i64>().fetch_one(&pool).await?;
+        Ok::<_, ServerFnError>(count as usize)
+    };
+    crate::database::ssr::count_cache()?
+        .get_or_load(
+            crate::count_cache::CountTable::Users,
+            &user.username,
+            "admins".to_string(),
+            load,
+        )
+        .await
 }
 
 #[server]
 pub async fn user_count() -> Result<usize, ServerFnError> {
-    let _user = crate::auth::auth_admin().await?;
+    let user = crate::auth::auth_admin().await?;
     let mut query = QueryBuilder::new("SELECT COUNT(*) FROM users");
 
     let pool = crate::database::ssr::pool()?;
-    let count = query.build_query_scalar::<i64>().fetch_one(&pool).await?;
+    let load = async {
+        let count = query.logged().build_query_scalar::<i64>().fetch_one(&pool).await?;
+        Ok::<_, ServerFnError>(count as usize)
+    };
+    crate::database::ssr::count_cache()?
+        .get_or_load(
+            crate::count_cache::CountTable::Users,
+            &user.username,
+            String::new(),
+            load,
+        )
+        .await
+}
+
+/// Restricts a statement that changes or deletes the given user to the case where an active
+/// admin remains afterwards, since nobody could manage the instance anymore otherwise.
+/// `remains_admin` is whether the user is still an active admin after the change. Being part
+/// of the statement itself, the check can't be raced by concurrent changes to other admins.
+#[cfg(feature = "ssr")]
+fn push_admin_remains_guard<'a>(query: &mut QueryBuilder<'a, sqlx::Sqlite>, username: &'a str, remains_admin: bool) {
+    if remains_admin {
+        return;
+    }
+    query.push(" AND (NOT (admin = TRUE AND active = TRUE) OR EXISTS (SELECT * FROM users AS other");
+    query.push(" WHERE other.admin = TRUE AND other.active = TRUE AND other.username != ");
+    query.push_bind(username);
+    query.push("))");
+}
+
+/// Turns a statement with [`push_admin_remains_guard`] that changed nothing into an error,
+/// unless the user doesn't exist at all.
+#[cfg(feature = "ssr")]
+async fn ensure_admin_remained(
+    pool: &sqlx::SqlitePool,
+    username: &str,
+    result: sqlx::sqlite::SqliteQueryResult,
+) -> Result<(), ServerFnError> {
+    if result.rows_affected() == 0 {
+        let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_one(pool)
+            .await?
+            > 0;
+        if exists {
+            return Err(ServerFnError::new("Cannot remove the last admin"));
+        }
+    }
+    Ok(())
+}
+
+/// Changes whether the given user is an admin and active, see [`update_user_admin_or_active`]
+#[cfg(feature = "ssr")]
+async fn set_user_admin_or_active(
+    pool: &sqlx::SqlitePool,
+    username: &str,
+    admin: bool,
+    active: bool,
+) -> Result<(), ServerFnError> {
+    let mut query = QueryBuilder::new("UPDATE users SET admin = ");
+    query.push_bind(admin);
+    query.push(", active = ");
+    query.push_bind(active);
+    query.push(" WHERE username = ");
+    query.push_bind(username);
+    push_admin_remains_guard(&mut query, username, admin && active);
 
-    Ok(count as usize)
+    let result = query.logged().build().execute(pool).await?;
+    ensure_admin_remained(pool, username, result).await
 }
 
 #[server]
 pub async fn delete_user(username: String) -> Result<(), ServerFnError> {
-    let _user = crate::auth::auth_admin().await?;
+    let user = crate::auth::auth_admin().await?;
+    let pool = crate::database::ssr::pool()?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Users, &username).await?;
 
     // Force user reload on next request
     let auth = crate::database::ssr::auth()?;
     auth.cache_clear_user(username.clone());
 
     let mut query = QueryBuilder::new("DELETE FROM users WHERE username = ");
-    query.push_bind(username);
+    query.push_bind(&username);
+    push_admin_remains_guard(&mut query, &username, false);
+
+    let result = query.logged().build().execute(&pool).await?;
+    ensure_admin_remained(&pool, &username, result).await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Users);
+    Ok(())
+}
 
+/// The maximum number of owner suggestions shown to admins
+#[cfg(feature = "ssr")]
+const MAX_OWNER_CANDIDATES: i64 = 20;
+
+/// Lists the users matching the filter, and also the mailboxes if they may own the entity.
+/// Only available to admins, since it reveals all accounts.
+#[server]
+pub async fn list_owner_candidates(filter: String, allow_mailboxes: bool) -> Result<Vec<String>, ServerFnError> {
+    let _user = crate::auth::auth_admin().await?;
     let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    Ok(owner_candidates(&pool, &filter, allow_mailboxes).await?)
+}
+
+#[cfg(feature = "ssr")]
+async fn owner_candidates(pool: &sqlx::SqlitePool, filter: &str, allow_mailboxes: bool) -> sqlx::Result<Vec<String>> {
+    let query = if allow_mailboxes {
+        "SELECT username FROM users WHERE username LIKE ?1 ESCAPE '\\' \
+        UNION SELECT address FROM mailboxes WHERE address LIKE ?1 ESCAPE '\\' \
+        ORDER BY 1 LIMIT ?2"
+    } else {
+        "SELECT username FROM users WHERE username LIKE ?1 ESCAPE '\\' ORDER BY 1 LIMIT ?2"
+    };
+    sqlx::query_scalar::<_, String>(query)
+        .bind(format!("%{}%", escape_like(filter.trim())))
+        .bind(MAX_OWNER_CANDIDATES)
+        .fetch_all(pool)
+        .await
+}
+
+/// Escapes the wildcards of a LIKE pattern, so the text only matches literally
+#[cfg(feature = "ssr")]
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Ensures that an owner chosen by an admin exists, so typos can't create rows with
+/// orphaned owners. Mailboxes may only own aliases, everything else is owned by users.
+#[cfg(feature = "ssr")]
+pub(crate) async fn check_owner_exists(
+    pool: &sqlx::SqlitePool,
+    owner: &str,
+    allow_mailboxes: bool,
+) -> Result<(), ServerFnError> {
+    let query = if allow_mailboxes {
+        "SELECT COUNT(*) FROM users WHERE username = ?1 OR EXISTS (SELECT * FROM mailboxes WHERE address = ?1)"
+    } else {
+        "SELECT COUNT(*) FROM users WHERE username = ?1"
+    };
+    if sqlx::query_scalar::<_, i64>(query).bind(owner).fetch_one(pool).await? == 0 {
+        let is_mailbox = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM mailboxes WHERE address = ?")
+            .bind(owner)
+            .fetch_one(pool)
+            .await?
+            > 0;
+        let message = if is_mailbox {
+            format!("owner '{owner}' is a mailbox, but only users can own this")
+        } else {
+            format!("owner '{owner}' does not exist")
+        };
+        return Err(ValidationErrors::field("owner", message).into());
+    }
     Ok(())
 }
 
+/// Hashes a new password that was chosen by a user, which must satisfy the configured password policy
 #[cfg(feature = "ssr")]
 pub fn mk_password_hash(password: &str) -> Result<String, ServerFnError> {
-    if !is_valid_pw(password) {
-        return Err(ServerFnError::new("Password is invalid."));
+    let problems = password_problems(password, &crate::database::ssr::config()?.password_policy);
+    if !problems.is_empty() {
+        return Err(ServerFnError::new(problems.join(", ")));
     }
+    hash_password(password)
+}
 
+/// Hashes a password without checking it against the password policy, which is only meant for generated passwords
+#[cfg(feature = "ssr")]
+pub fn hash_password(password: &str) -> Result<String, ServerFnError> {
     use argon2::{
         password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
         Argon2,
@@ -155,20 +636,34 @@ pub async fn change_password(current_password: String, new_password: String) ->
     let user = crate::auth::auth_any().await?;
 
     // Reauthenticate
-    let _ = crate::auth::authenticate_user(user.username.clone(), current_password.clone()).await?;
+    let _ = crate::auth::verify_current_password(&user.username, &current_password).await?;
+    if new_password == current_password {
+        return Err(ServerFnError::new("The new password must differ from the current one"));
+    }
+    let pool = crate::database::ssr::pool()?;
+    let (table, column, provisioned) = match &user.mailbox_owner {
+        Some(_) => ("mailboxes", "address", Provisioned::Mailboxes),
+        None => ("users", "username", Provisioned::Users),
+    };
+    ensure_not_provisioned(&pool, &user, provisioned, &user.username).await?;
+    // Optionally make sure that a mailbox doesn't reuse the password of its owner
+    if let Some(owner) = &user.mailbox_owner {
+        if crate::database::ssr::config()?.reject_owner_password_reuse {
+            crate::mailboxes::reject_owner_password_reuse(&pool, owner, &new_password).await?;
+        }
+    }
     let password_hash = mk_password_hash(&new_password)?;
 
     // Force user reload on next request
     let auth = crate::database::ssr::auth()?;
     auth.cache_clear_user(user.username.clone());
 
-    let mut query = QueryBuilder::new("UPDATE users SET password_hash = ");
+    let mut query = QueryBuilder::new(format!("UPDATE {table} SET password_hash = "));
     query.push_bind(password_hash);
-    query.push(" WHERE username = ");
+    query.push(format!(" WHERE {column} = "));
     query.push_bind(&user.username);
 
-    let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    query.logged().build().execute(&pool).await.map(|_| ())?;
 
     Ok(())
 }
@@ -181,10 +676,11 @@ pub async fn create_or_update_user(
     admin: bool,
     active: bool,
 ) -> Result<(), ServerFnError> {
-    let _user = crate::auth::auth_admin().await?;
+    let user = crate::auth::auth_admin().await?;
     let pool = crate::database::ssr::pool()?;
 
     if let Some(old_username) = old_username {
+        ensure_not_provisioned(&pool, &user, Provisioned::Users, &old_username).await?;
         // Force user reload on next request
         let auth = crate::database::ssr::auth()?;
         auth.cache_clear_user(username.clone());
@@ -199,36 +695,38 @@ pub async fn create_or_update_user(
         query.push(", active = ");
         query.push_bind(active);
         query.push(" WHERE username = ");
-        query.push_bind(old_username);
+        query.push_bind(&old_username);
+        push_admin_remains_guard(&mut query, &old_username, admin && active);
 
-        query.build().execute(&pool).await.map(|_| ())?;
+        let result = query.logged().build().execute(&pool).await?;
+        ensure_admin_remained(&pool, &old_username, result).await?;
     } else {
+        // Usernames and mailbox addresses share a namespace, for example when logging in
+        if username.is_empty() || username.contains('@') {
+            return Err(ValidationErrors::field("username", "username must not be empty or contain an '@'").into());
+        }
         let password_hash = mk_password_hash(&password)?;
         sqlx::query("INSERT INTO users (username, password_hash, admin, active) VALUES (?, ?, ?, ?)")
-            .bind(username)
+            .bind(&username)
             .bind(password_hash)
             .bind(admin)
             .bind(active)
             .execute(&pool)
             .await
-            .map(|_| ())?;
+            .map_err(map_unique_violation("username", &username))?;
     }
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Users);
 
     Ok(())
 }
 
 #[server]
 pub async fn update_user_admin_or_active(username: String, admin: bool, active: bool) -> Result<(), ServerFnError> {
-    let _user = crate::auth::auth_admin().await?;
-    let mut query = QueryBuilder::new("UPDATE users SET admin = ");
-    query.push_bind(admin);
-    query.push(", active = ");
-    query.push_bind(active);
-    query.push(" WHERE username = ");
-    query.push_bind(username);
-
+    let user = crate::auth::auth_admin().await?;
     let pool = crate::database::ssr::pool()?;
-    query.build().execute(&pool).await.map(|_| ())?;
+    ensure_not_provisioned(&pool, &user, Provisioned::Users, &username).await?;
+    set_user_admin_or_active(&pool, &username, admin, active).await?;
+    crate::database::ssr::count_cache()?.invalidate(crate::count_cache::CountTable::Users);
     Ok(())
 }
 
@@ -236,12 +734,14 @@ pub async fn update_user_admin_or_active(username: String, admin: bool, active:
 pub struct UserTableDataProvider {
     sort: VecDeque<(usize, ColumnSort)>,
     pub search: RwSignal<String>,
+    pub provisioned: RwSignal<Option<bool>>,
 }
 
 impl TableDataProvider<User> for UserTableDataProvider {
     async fn get_rows(&self, range: Range<usize>) -> Result<(Vec<User>, Range<usize>), String> {
         list_users(UserQuery {
             search: self.search.get_untracked().trim().to_string(),
+            provisioned: self.provisioned.get_untracked(),
             sort: self.sort.clone(),
             range: range.clone(),
         })
@@ -263,6 +763,61 @@ impl TableDataProvider<User> for UserTableDataProvider {
 
     fn track(&self) {
         self.search.track();
+        self.provisioned.track();
+    }
+}
+
+/// Input for the owner of an entity, which only admins may change. Admins get suggestions of
+/// existing users while typing, and of mailboxes if they may own the entity (see `check_owner_exists`).
+#[component]
+pub fn OwnerInput(
+    id: &'static str,
+    value: ReadSignal<String>,
+    set_value: WriteSignal<String>,
+    #[prop(into)] placeholder: MaybeSignal<String>,
+    admin: bool,
+    allow_mailboxes: bool,
+) -> impl IntoView {
+    let filter = signal_debounced(value, 300.0);
+    let candidates = create_local_resource(
+        move || filter.get(),
+        move |filter| async move {
+            if !admin {
+                return Vec::new();
+            }
+            list_owner_candidates(filter, allow_mailboxes)
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Failed to list owner candidates: {}", e);
+                    Vec::new()
+                })
+        },
+    );
+    let list_id = format!("{id}_candidates");
+
+    view! {
+        <input
+            id=id
+            class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+            type="text"
+            list=list_id.clone()
+            placeholder=placeholder
+            on:input=move |ev| set_value(event_target_value(&ev))
+            prop:value=value
+            disabled=!admin
+        />
+        <datalist id=list_id>
+            {move || {
+                candidates
+                    .get()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| view! { <option value=x></option> })
+                    .collect_view()
+            }}
+
+        </datalist>
+        <FieldErrorMessage field="owner"/>
     }
 }
 
@@ -301,7 +856,7 @@ pub fn Users() -> impl IntoView {
         }
     });
 
-    let on_edit = move |(data, on_error): (Option<User>, Callback<String>)| {
+    let on_edit = move |(data, on_error): (Option<User>, Callback<ServerFnError>)| {
         spawn_local(async move {
             if let Err(e) = create_or_update_user(
                 data.map(|x| x.username),
@@ -312,7 +867,7 @@ pub fn Users() -> impl IntoView {
             )
             .await
             {
-                on_error(e.to_string())
+                on_error(e)
             } else {
                 reload_controller.reload();
                 edit_modal_user.set(None);
@@ -338,6 +893,14 @@ pub fn Users() -> impl IntoView {
         });
     };
 
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let edit_provisioned = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|x| x.edit_provisioned)
+    };
+
     #[allow(unused_variables, non_snake_case)]
     let user_row_renderer = move |class: Signal<String>,
                                   row: User,
@@ -347,22 +910,30 @@ pub fn Users() -> impl IntoView {
                                   on_change: EventHandler<ChangeEvent<User>>| {
         let delete_username = row.username.clone();
         let edit_user = row.clone();
+        let provisioned = row.provisioned;
+        let locked = move || provisioned && !edit_provisioned();
         view! {
             <tr class=class on:click=move |mouse_event| on_select.run(mouse_event)>
                 {row.render_row(index, on_change)}
                 <td class="w-1 px-4 py-2 whitespace-nowrap text-ellipsis">
-                    <div class="inline-flex items-center rounded-md">
+                    {provisioned.then(|| view! { <ProvisionedBadge/> })}
+                    <div
+                        class="inline-flex items-center rounded-md"
+                        title=move || locked().then_some(PROVISIONED_TOOLTIP)
+                    >
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-blue-600 dark:hover:bg-blue-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-l-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| edit_modal_open_with(Some(edit_user.clone()))
+                            disabled=locked
                         >
                             <Icon icon=icondata::FiEdit class="w-5 h-5"/>
                         </button>
                         <button
-                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center"
+                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-l-0 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-r-lg font-medium px-4 py-2 inline-flex space-x-1 items-center disabled:opacity-50 disabled:pointer-events-none"
                             on:click=move |_| {
                                 delete_modal_user.set(Some(delete_username.clone()));
                             }
+                            disabled=locked
                         >
 
                             <Icon icon=icondata::FiTrash2 class="w-5 h-5"/>
@@ -374,22 +945,29 @@ pub fn Users() -> impl IntoView {
     };
 
     let has_password_mismatch = move || edit_modal_input_password() != edit_modal_input_password_repeat();
-    let has_invalid_password = create_memo(move |_| {
+    let password_policy = move || {
+        capabilities
+            .get()
+            .and_then(Result::ok)
+            .map(|x| x.password_policy)
+            .unwrap_or_default()
+    };
+    let password_errors = create_memo(move |_| {
         // Either we edit an existing user (in which case an empty password means no change)
-        // or the password is of correct length.
+        // or the password must satisfy the policy.
         let is_new = matches!(edit_modal_user.get(), Some(None));
-        let is_valid_pw = is_valid_pw(&edit_modal_input_password());
-        let valid = is_valid_pw || (!is_new && edit_modal_input_password().is_empty());
-        !valid
+        if !is_new && edit_modal_input_password().is_empty() {
+            return Vec::new();
+        }
+        password_problems(&edit_modal_input_password(), &password_policy())
     });
+    let has_invalid_password = move || password_errors.with(|x| !x.is_empty());
     let errors = create_memo(move |_| {
         let mut errors = Vec::new();
         if has_password_mismatch() {
             errors.push("Passwords don't match".to_string());
         }
-        if has_invalid_password() {
-            errors.push("Password must be between 12 and 512 characters".to_string());
-        }
+        errors.extend(password_errors());
         errors
     });
 
@@ -409,6 +987,7 @@ pub fn Users() -> impl IntoView {
                             on_input(event_target_value(&e));
                         }
                     />
+                    <ProvisionedFilter value=rows.provisioned/>
 
                     <button
                         type="button"
@@ -430,7 +1009,7 @@ pub fn Users() -> impl IntoView {
                             <TableContent
                                 rows
                                 sorting=sorting
-                                sorting_mode=SortingMode::SingleColumn
+                                sorting_mode=SortingMode::MultiColumn
                                 row_renderer=user_row_renderer
                                 reload_controller=reload_controller
                                 loading_row_display_limit=0
@@ -481,6 +1060,7 @@ pub fn Users() -> impl IntoView {
                     prop:value=edit_modal_input_username
                     disabled=move || !matches!(edit_modal_user.get(), Some(None))
                 />
+                <FieldErrorMessage field="username"/>
             </div>
             <div class="flex flex-col gap-2">
                 <label
@@ -573,7 +1153,7 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
         set_edit_modal_input_password_repeat("".to_string());
     };
 
-    let on_edit = move |(_data, on_error): (Option<()>, Callback<String>)| {
+    let on_edit = move |(_data, on_error): (Option<()>, Callback<ServerFnError>)| {
         spawn_local(async move {
             if let Err(e) = change_password(
                 edit_modal_input_current_password.get_untracked(),
@@ -581,7 +1161,7 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
             )
             .await
             {
-                on_error(e.to_string())
+                on_error(e)
             } else {
                 edit_modal_password.set(None);
             }
@@ -589,31 +1169,114 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
     };
 
     let has_password_mismatch = move || edit_modal_input_password() != edit_modal_input_password_repeat();
-    let has_invalid_password = create_memo(move |_| !is_valid_pw(&edit_modal_input_password()));
+    let capabilities = create_resource(|| (), |_| get_capabilities());
+    let password_errors = create_memo(move |_| {
+        let policy = capabilities
+            .get()
+            .and_then(Result::ok)
+            .map(|x| x.password_policy)
+            .unwrap_or_default();
+        password_problems(&edit_modal_input_password(), &policy)
+    });
+    let has_invalid_password = move || password_errors.with(|x| !x.is_empty());
     let errors = create_memo(move |_| {
         let mut errors = Vec::new();
         if has_password_mismatch() {
             errors.push("Passwords don't match".to_string());
         }
-        if has_invalid_password() {
-            errors.push("Password must be between 12 and 512 characters".to_string());
-        }
+        errors.extend(password_errors());
         errors
     });
 
-    let api_token_modal = create_node_ref::<Dialog>();
+    let (reauth_required, set_reauth_required) = create_signal(false);
+    if leptos::leptos_dom::is_browser() {
+        spawn_local(async move {
+            use crate::auth::reauthentication_required;
+            match reauthentication_required().await {
+                Err(e) => error!("Failed to check whether reauthentication is required: {}", e),
+                Ok(required) => set_reauth_required(required),
+            }
+        });
+    }
+
+    let is_mailbox = user.mailbox_owner.is_some();
+    let (digest, set_digest) = create_signal(false);
+    if leptos::leptos_dom::is_browser() && is_mailbox {
+        spawn_local(async move {
+            match digest_enabled().await {
+                Err(e) => error!("Failed to load digest setting: {}", e),
+                Ok(enabled) => set_digest(enabled),
+            }
+        });
+    }
+    let on_digest_change = move |enabled: bool| {
+        set_digest(enabled);
+        spawn_local(async move {
+            if let Err(e) = set_digest_enabled(enabled).await {
+                error!("Failed to update digest setting: {}", e);
+                set_digest(!enabled);
+            }
+        });
+    };
+
+    let (aliases_active, set_aliases_active) = create_signal(true);
+    if leptos::leptos_dom::is_browser() {
+        spawn_local(async move {
+            match new_aliases_active().await {
+                Err(e) => error!("Failed to load new alias setting: {}", e),
+                Ok(active) => set_aliases_active(active),
+            }
+        });
+    }
+    let on_aliases_active_change = move |active: bool| {
+        set_aliases_active(active);
+        spawn_local(async move {
+            if let Err(e) = set_new_aliases_active(active).await {
+                error!("Failed to update new alias setting: {}", e);
+                set_aliases_active(!active);
+            }
+        });
+    };
+
     let api_token_modal_open = create_rw_signal(false);
     let api_token_modal_token = create_rw_signal("".to_string());
-    let api_token_modal_copied_timer = use_timeout_fn(|_: ()| (), 3000.0);
-    let (api_token_modal_server_error, api_token_modal_set_server_error) = create_signal(None);
-    create_effect(move |_| {
-        // Clear API token when dialog closes in any way
-        if !api_token_modal_open() {
-            api_token_modal_token.set("".to_string());
-            (api_token_modal_copied_timer.stop)();
-            api_token_modal_set_server_error(None);
-        }
-    });
+
+    let (api_tokens_version, set_api_tokens_version) = create_signal(0);
+    let api_tokens = create_resource(move || api_tokens_version.get(), move |_| list_api_tokens());
+    let reload_api_tokens = move || set_api_tokens_version.update(|x| *x += 1);
+
+    let create_token_modal = create_rw_signal(None);
+    let (create_token_modal_input_password, set_create_token_modal_input_password) = create_signal("".to_string());
+    let (create_token_modal_input_name, set_create_token_modal_input_name) = create_signal("".to_string());
+    let create_token_modal_scopes = create_rw_signal(ApiScope::ALL.to_vec());
+    let create_token = move || {
+        set_create_token_modal_input_password("".to_string());
+        set_create_token_modal_input_name("".to_string());
+        create_token_modal_scopes.set(ApiScope::ALL.to_vec());
+        create_token_modal.set(Some(Some(())));
+    };
+    let on_create_token = move |(_data, on_error): (Option<()>, Callback<ServerFnError>)| {
+        spawn_local(async move {
+            match create_api_token(
+                create_token_modal_input_password.get_untracked(),
+                create_token_modal_input_name.get_untracked(),
+                create_token_modal_scopes.get_untracked(),
+            )
+            .await
+            {
+                Err(e) => on_error(e),
+                Ok(api_token) => {
+                    create_token_modal.set(None);
+                    reload_api_tokens();
+                    api_token_modal_token.set(api_token);
+                    api_token_modal_open.set(true)
+                }
+            }
+        });
+    };
+
+    let delete_token_modal = create_rw_signal(None);
+    let delete_token_id = create_rw_signal(0);
 
     view! {
         <div class="h-full flex-1 flex-col mt-12">
@@ -628,29 +1291,132 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
                 >
                     "Change password"
                 </button>
-                <button
-                    type="button"
-                    class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 focus:ring-blue-300 dark:focus:ring-blue-900 disabled:pointer-events-none disabled:cursor-not-allowed disabled:opacity-50"
-                    on:click=move |_| {
-                        spawn_local(async move {
-                            match regenerate_api_key().await {
-                                Err(e) => api_token_modal_set_server_error(Some(e.to_string())),
-                                Ok(api_token) => api_token_modal_token.set(api_token),
-                            }
-                            api_token_modal_open.set(true)
-                        });
-                    }
-
-                    disabled=user.mailbox_owner.is_none()
-                >
-
-                    {if user.mailbox_owner.is_none() {
-                        "Regenerate API Token (login as a mailbox first)"
-                    } else {
-                        "Regenerate API Token"
-                    }}
+                <TotpSettings reauth_required/>
+                <div class="flex flex-col gap-3 mt-2">
+                    <h3 class="text-lg font-semibold">"API Tokens"</h3>
+                    <p class="text-sm text-gray-500 dark:text-gray-400">
+                        "Give each integration its own token, so it can be revoked without affecting the others."
+                    </p>
+                    <Transition fallback=move || {
+                        view! { <span class="text-gray-300 dark:text-gray-600">"Loading..."</span> }
+                    }>
+                        {move || {
+                            api_tokens
+                                .get()
+                                .map(|api_tokens| match api_tokens {
+                                    Err(e) => view! { <p>{e.to_string()}</p> }.into_view(),
+                                    Ok(api_tokens) if api_tokens.is_empty() => {
+                                        view! {
+                                            <p class="text-sm text-gray-500 dark:text-gray-400">"No API tokens yet."</p>
+                                        }
+                                            .into_view()
+                                    }
+                                    Ok(api_tokens) => {
+                                        api_tokens
+                                            .into_iter()
+                                            .map(|api_token| {
+                                                let ApiToken { id, name, scopes, created_at, last_used_at } = api_token;
+                                                let scopes = if scopes.len() == ApiScope::ALL.len() {
+                                                    "all scopes".to_string()
+                                                } else {
+                                                    scopes.iter().map(|x| x.as_str()).collect::<Vec<_>>().join(", ")
+                                                };
+                                                let created = format!(", created {}", created_at.format("%Y-%m-%d"));
+                                                let last_used = last_used_at
+                                                    .map_or(", never used".to_string(), |x| {
+                                                        format!(", last used {}", x.format("%Y-%m-%d"))
+                                                    });
+                                                let delete_name = name.clone();
+                                                view! {
+                                                    <div class="flex flex-row gap-3 items-center text-sm">
+                                                        <div class="flex flex-col flex-1 min-w-0">
+                                                            <span class="font-medium break-all">{name}</span>
+                                                            <span class="text-gray-500 dark:text-gray-400">
+                                                                {scopes} {created} {last_used}
+                                                            </span>
+                                                        </div>
+                                                        <button
+                                                            class="text-gray-800 dark:text-zinc-100 hover:text-white dark:hover:text-black bg-white dark:bg-black hover:bg-red-600 dark:hover:bg-red-500 transition-all border-[1.5px] border-gray-200 dark:border-zinc-800 rounded-lg font-medium px-2 py-1 inline-flex items-center"
+                                                            title="Revoke"
+                                                            on:click=move |_| {
+                                                                delete_token_id.set(id);
+                                                                delete_token_modal.set(Some(delete_name.clone()));
+                                                            }
+                                                        >
+
+                                                            <Icon icon=icondata::FiTrash2 class="w-4 h-4"/>
+                                                        </button>
+                                                    </div>
+                                                }
+                                            })
+                                            .collect_view()
+                                    }
+                                })
+                        }}
 
-                </button>
+                    </Transition>
+                    <button
+                        type="button"
+                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 focus:ring-blue-300 dark:focus:ring-blue-900"
+                        on:click=move |_| create_token()
+                    >
+                        <Icon icon=icondata::FiPlus class="w-5 h-5 me-2"/>
+                        "New API Token"
+                    </button>
+                </div>
+                <div class="flex flex-row gap-2 mt-2 items-center">
+                    <input
+                        id="new_aliases_active"
+                        class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                        type="checkbox"
+                        on:change=move |ev| on_aliases_active_change(event_target_checked(&ev))
+                        prop:checked=aliases_active
+                    />
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="new_aliases_active"
+                    >
+                        "New aliases start out active"
+                    </label>
+                </div>
+                <Show when=move || is_mailbox>
+                    <div class="flex flex-row gap-2 mt-2 items-center">
+                        <input
+                            id="digest"
+                            class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                            type="checkbox"
+                            on:change=move |ev| on_digest_change(event_target_checked(&ev))
+                            prop:checked=digest
+                        />
+                        <label
+                            class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                            for="digest"
+                        >
+                            "Receive a weekly digest of alias activity"
+                        </label>
+                    </div>
+                </Show>
+                <Recipients is_mailbox/>
+                <Show when=move || user.admin>
+                    <a
+                        href="/api/backup"
+                        rel="external"
+                        download
+                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 focus:ring-blue-300 dark:focus:ring-blue-900"
+                    >
+                        <Icon icon=icondata::FiDownload class="w-5 h-5 me-2"/>
+                        "Download database backup"
+                    </a>
+                    <a
+                        href="/api/export/stalwart"
+                        rel="external"
+                        download
+                        class="inline-flex flex-none items-center justify-center whitespace-nowrap font-medium text-base text-white dark:text-zinc-100 py-2.5 px-4 transition-all rounded-lg focus:ring-4 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 focus:ring-blue-300 dark:focus:ring-blue-900"
+                    >
+                        <Icon icon=icondata::FiDownload class="w-5 h-5 me-2"/>
+                        "Download stalwart snapshot"
+                    </a>
+                </Show>
             </div>
         </div>
 
@@ -715,42 +1481,162 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
             </div>
         </EditModal>
 
-        <Modal open=api_token_modal_open dialog_el=api_token_modal>
+        <EditModal
+            data=create_token_modal
+            what="API Token".to_string()
+            get_title=move |_| { "API token" }
+            on_confirm=on_create_token
+            errors=Signal::derive(move || {
+                let mut errors = Vec::new();
+                if create_token_modal_input_name.with(|x| x.trim().is_empty()) {
+                    errors.push("Enter a name for the token".to_string());
+                }
+                if create_token_modal_scopes.with(|x| x.is_empty()) {
+                    errors.push("Select at least one scope".to_string());
+                }
+                errors
+            })
+        >
+            <div class="flex flex-col gap-2">
+                <label
+                    class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                    for="token_name"
+                >
+                    "Name"
+                </label>
+                <input
+                    id="token_name"
+                    class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                    type="text"
+                    placeholder="Bitwarden"
+                    required="required"
+                    maxlength="64"
+                    on:input=move |ev| set_create_token_modal_input_name(event_target_value(&ev))
+                    prop:value=create_token_modal_input_name
+                />
+            </div>
+            {ApiScope::ALL
+                .into_iter()
+                .map(|scope| {
+                    let id = format!("scope_{}", scope.as_str().replace(':', "_"));
+                    view! {
+                        <div class="flex flex-row gap-2 items-center">
+                            <input
+                                id=id.clone()
+                                class="w-4 h-4 bg-transparent dark:bg-transparent text-blue-600 border-[1.5px] border-gray-200 dark:border-zinc-800 rounded checked:bg-blue-600 dark:checked:bg-blue-600 dark:bg-blue-600 focus:ring-ring focus:ring-4 transition-all"
+                                type="checkbox"
+                                on:change=move |ev| {
+                                    let checked = event_target_checked(&ev);
+                                    create_token_modal_scopes
+                                        .update(|x| {
+                                            x.retain(|x| *x != scope);
+                                            if checked {
+                                                x.push(scope);
+                                            }
+                                        });
+                                }
+                                prop:checked=move || create_token_modal_scopes.with(|x| x.contains(&scope))
+                            />
+                            <label
+                                class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                                for=id
+                            >
+                                {scope.as_str()}
+                            </label>
+                        </div>
+                    }
+                })
+                .collect_view()}
+            <Show when=reauth_required>
+                <div class="flex flex-col gap-2">
+                    <label
+                        class="text-sm font-medium leading-none peer-disabled:cursor-not-allowed peer-disabled:opacity-70"
+                        for="reauth_password"
+                    >
+                        "Current Password"
+                    </label>
+                    <input
+                        class="flex flex-none w-full rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-sm p-2.5 transition-all placeholder:text-gray-500 dark:placeholder:text-zinc-500 focus-visible:outline-none focus-visible:ring-4 focus-visible:ring-ring disabled:cursor-not-allowed disabled:opacity-50"
+                        type="password"
+                        required="required"
+                        maxlength="1024"
+                        on:input=move |ev| set_create_token_modal_input_password(event_target_value(&ev))
+                        prop:value=create_token_modal_input_password
+                    />
+                </div>
+            </Show>
+        </EditModal>
+
+        <DeleteModal
+            data=delete_token_modal
+            text="Are you sure you want to revoke this API token? Integrations using it stop working immediately."
+                .into_view()
+            on_confirm=move |_| {
+                spawn_local(async move {
+                    if let Err(e) = delete_api_token(delete_token_id.get_untracked()).await {
+                        error!("Failed to revoke API token: {}", e);
+                    } else {
+                        reload_api_tokens();
+                    }
+                    delete_token_modal.set(None);
+                });
+            }
+        />
+
+        <ApiTokenModal open=api_token_modal_open token=api_token_modal_token/>
+    }
+}
+
+/// Shows a newly created API token once, so it can be copied
+#[component]
+pub fn ApiTokenModal(open: RwSignal<bool>, token: RwSignal<String>) -> impl IntoView {
+    let modal_elem = create_node_ref::<Dialog>();
+    let copied_timer = use_timeout_fn(|_: ()| (), 3000.0);
+    create_effect(move |_| {
+        // Clear API token when dialog closes in any way
+        if !open() {
+            token.set("".to_string());
+            (copied_timer.stop)();
+        }
+    });
+
+    view! {
+        <Modal open dialog_el=modal_elem>
             <div class="relative p-4 transform overflow-hidden rounded-lg bg-white dark:bg-black text-left transition-all sm:w-full sm:max-w-xl">
                 <h3 class="text-2xl tracking-tight mt-2 mb-2 font-semibold text-gray-900 dark:text-gray-200">
                     "API Token"
                 </h3>
                 <div class="pb-3 space-y-3">
                     <p class="text-sm text-gray-500 dark:text-gray-400">
-                        "Your new API Token is displayed below. Make sure to save it now, as it will not be displayed again."
+                        "The new API token is displayed below. Make sure to save it now, as it will not be displayed again."
                     </p>
                     <div class="w-full relative">
                         <input
                             type="text"
                             class="col-span-6 bg-gray-50 dark:bg-gray-900 dark:bg-black border border-gray-300 text-gray-500 dark:text-gray-400 text-sm rounded-lg focus:ring-blue-500 focus:border-blue-500 block w-full px-2.5 py-4"
-                            value=move || api_token_modal_token
+                            value=move || token
                             disabled
                             readonly
                         />
                         <button
                             class="absolute end-2.5 top-1/2 -translate-y-1/2 text-gray-900 dark:text-gray-200 hover:bg-gray-100 dark:hover:bg-gray-700 rounded-lg py-2 px-2.5 inline-flex items-center justify-center bg-white dark:bg-black border-gray-200 border"
                             on:click=move |_ev| {
-                                (api_token_modal_copied_timer.start)(());
+                                (copied_timer.start)(());
                                 let clipboard = window().navigator().clipboard();
-                                let _ = clipboard.write_text(&api_token_modal_token.get());
+                                let _ = clipboard.write_text(&token.get());
                             }
                         >
 
                             <span
                                 class="inline-flex items-center"
-                                class=("hidden", api_token_modal_copied_timer.is_pending)
+                                class=("hidden", copied_timer.is_pending)
                             >
                                 <Icon icon=icondata::RiFileCopy2DocumentFill class="w-3 h-3 me-1.5"/>
                                 <span class="text-xs font-semibold">Copy</span>
                             </span>
                             <span
                                 class="hidden items-center"
-                                class=("!inline-flex", api_token_modal_copied_timer.is_pending)
+                                class=("!inline-flex", copied_timer.is_pending)
                             >
                                 <Icon
                                     icon=icondata::BiCheckRegular
@@ -760,29 +1646,13 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
                             </span>
                         </button>
                     </div>
-                    <Show when=move || api_token_modal_server_error().is_some()>
-                        <div class="rounded-lg p-4 flex bg-red-100 dark:bg-red-900 mt-2">
-                            <div>
-                                <Icon icon=icondata::BiXCircleSolid class="w-5 h-5 text-red-400 dark:text-red-200"/>
-                            </div>
-                            <div class="ml-3 text-red-700 dark:text-red-200">
-                                {move || {
-                                    match api_token_modal_server_error() {
-                                        None => view! {}.into_view(),
-                                        Some(error) => view! { <p>{error}</p> }.into_view(),
-                                    }
-                                }}
-
-                            </div>
-                        </div>
-                    </Show>
                 </div>
                 <div class="flex flex-col gap-3 sm:flex-row-reverse">
                     <button
                         type="button"
                         class="inline-flex w-full min-w-20 justify-center rounded-lg transition-all bg-white dark:bg-black px-3 py-2 font-semibold text-gray-900 dark:text-gray-200 focus:ring-4 dark:focus:ring-zinc-800 border-[1.5px] border-gray-300 dark:border-zinc-800 hover:bg-gray-100 dark:hover:bg-zinc-900 sm:w-auto"
                         on:click=move |_ev| {
-                            api_token_modal_open.set(false);
+                            open.set(false);
                         }
                     >
 
@@ -793,3 +1663,190 @@ pub fn AccountSettings(user: crate::auth::User) -> impl IntoView {
         </Modal>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    async fn active_admins(pool: &sqlx::SqlitePool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE admin = TRUE AND active = TRUE")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_last_admin_cannot_be_demoted() {
+        let db = TestDb::new().await;
+        insert_user(&db, "admin", true).await;
+        insert_user(&db, "alice", false).await;
+
+        assert!(set_user_admin_or_active(&db, "admin", false, true).await.is_err());
+        assert!(set_user_admin_or_active(&db, "admin", true, false).await.is_err());
+        assert!(set_user_admin_or_active(&db, "alice", false, false).await.is_ok());
+        assert!(set_user_admin_or_active(&db, "nobody", false, false).await.is_ok());
+        assert_eq!(active_admins(&db).await, 1);
+
+        set_user_admin_or_active(&db, "alice", true, true).await.unwrap();
+        set_user_admin_or_active(&db, "admin", false, true).await.unwrap();
+        assert_eq!(active_admins(&db).await, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_demotions_keep_one_admin() {
+        let db = TestDb::new().await;
+        insert_user(&db, "one", true).await;
+        insert_user(&db, "two", true).await;
+
+        let (a, b) = tokio::join!(
+            set_user_admin_or_active(&db, "one", false, true),
+            set_user_admin_or_active(&db, "two", false, true)
+        );
+        assert!(a.is_ok() != b.is_ok());
+        assert_eq!(active_admins(&db).await, 1);
+    }
+
+    #[test]
+    fn api_token_names_cannot_be_other_alias_sources() {
+        assert_eq!(check_api_token_name("  Bitwarden ").unwrap(), "Bitwarden");
+        assert!(check_api_token_name(" ").is_err());
+        assert!(check_api_token_name(&"x".repeat(65)).is_err());
+        for name in ["ui", "UI", "import", "account token"] {
+            assert!(check_api_token_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn owner_candidates_match_the_filter_literally() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "a_b", false).await;
+        insert_user(&db, "axb", false).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "alice@example.com", "alice").await;
+
+        let candidates = |filter: &'static str, allow_mailboxes| owner_candidates(&db, filter, allow_mailboxes);
+        assert_eq!(candidates("alice", true).await.unwrap(), ["alice", "alice@example.com"]);
+        assert_eq!(candidates(" alice ", false).await.unwrap(), ["alice"]);
+        assert_eq!(candidates("_", false).await.unwrap(), ["a_b"]);
+        assert!(candidates("%", true).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn passwords_are_checked_against_each_policy_flag() {
+        let all = PasswordPolicy {
+            mixed_case: true,
+            digit: true,
+            symbol: true,
+            common: true,
+        };
+        assert!(password_problems("Correct-horse-battery-7", &all).is_empty());
+
+        for (password, policy, problem) in [
+            (
+                "correct-horse-battery-7",
+                PasswordPolicy {
+                    mixed_case: true,
+                    ..Default::default()
+                },
+                "lowercase and uppercase",
+            ),
+            (
+                "Correct-horse-battery",
+                PasswordPolicy {
+                    digit: true,
+                    ..Default::default()
+                },
+                "digit",
+            ),
+            (
+                "Correcthorsebattery7",
+                PasswordPolicy {
+                    symbol: true,
+                    ..Default::default()
+                },
+                "symbol",
+            ),
+            (
+                "1q2w3e4r5t6y",
+                PasswordPolicy {
+                    common: true,
+                    ..Default::default()
+                },
+                "too common",
+            ),
+            // The common password list ignores case
+            (
+                "1Q2W3E4R5T6Y",
+                PasswordPolicy {
+                    common: true,
+                    ..Default::default()
+                },
+                "too common",
+            ),
+        ] {
+            let problems = password_problems(password, &policy);
+            assert_eq!(problems.len(), 1, "{password}: {problems:?}");
+            assert!(problems[0].contains(problem), "{password}: {problems:?}");
+            // Without the flag, the same password is fine
+            assert!(
+                password_problems(password, &PasswordPolicy::default()).is_empty(),
+                "{password}"
+            );
+        }
+    }
+
+    #[test]
+    fn passwords_must_be_within_the_length_bound() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(password_problems(&"a".repeat(11), &policy).len(), 1);
+        assert!(password_problems(&"a".repeat(12), &policy).is_empty());
+        assert!(password_problems(&"a".repeat(1024), &policy).is_empty());
+        assert_eq!(password_problems(&"a".repeat(1025), &policy).len(), 1);
+        // Problems are reported together
+        let all = PasswordPolicy {
+            mixed_case: true,
+            digit: true,
+            symbol: true,
+            common: true,
+        };
+        assert_eq!(password_problems("short", &all).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn only_admins_can_regenerate_mailbox_tokens() {
+        let db = TestDb::new().await;
+        insert_user(&db, "alice", false).await;
+        insert_user(&db, "admin", true).await;
+        insert_domain(&db, "example.com", "alice").await;
+        insert_mailbox(&db, "box@example.com", "alice").await;
+        insert_api_token(&db, "box@example.com", "ci", "named-token-0123456789").await;
+        let runtime = server_context(&app_state(&db, crate::config::Config::default()));
+
+        let err = regenerate_mailbox_api_token(&db, &user("alice"), "box@example.com")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+        assert!(crate::auth::User::get_by_api_token("named-token-0123456789", &db)
+            .await
+            .is_some());
+        assert!(
+            regenerate_mailbox_api_token(&db, &admin("admin"), "missing@example.com")
+                .await
+                .is_err()
+        );
+
+        let api_token = regenerate_mailbox_api_token(&db, &admin("admin"), "box@example.com")
+            .await
+            .unwrap();
+        let (account, _, name) = crate::auth::User::get_by_api_token(&api_token, &db).await.unwrap();
+        assert_eq!(account.username, "box@example.com");
+        assert_eq!(name, crate::auth::ssr::ACCOUNT_TOKEN_NAME);
+        // All other tokens of the mailbox are revoked
+        assert!(crate::auth::User::get_by_api_token("named-token-0123456789", &db)
+            .await
+            .is_none());
+        runtime.dispose();
+    }
+}
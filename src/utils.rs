@@ -5,7 +5,177 @@ use leptos::{
 };
 use leptos_icons::Icon;
 use leptos_struct_table::*;
-use leptos_use::ColorMode;
+use leptos_use::{use_timeout_fn, ColorMode};
+use serde::{Deserialize, Serialize};
+
+/// Quotes a CSV field if necessary
+#[cfg(feature = "ssr")]
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits CSV data into records of fields, the inverse of [`csv_field`]
+#[cfg(feature = "ssr")]
+pub fn parse_csv(data: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.next_if_eq(&'"').is_some() {
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Lets the browser download the given content as a file, without a round trip to the server.
+pub fn download_file(filename: &str, content_type: &str, content: &str) -> Result<(), wasm_bindgen::JsValue> {
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::of1(&content.into());
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(content_type);
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)?;
+
+    let link = document()
+        .create_element("a")?
+        .unchecked_into::<web_sys::HtmlAnchorElement>();
+    link.set_href(&url);
+    link.set_download(filename);
+    link.click();
+    web_sys::Url::revoke_object_url(&url)
+}
+
+/// Wraps a typed error into the message of a [`ServerFnError`], tagged with the given prefix, so the
+/// client can recover it with [`decode_server_error`]. Server functions can only transport errors
+/// as strings, so this keeps structured errors working without a custom error type.
+pub fn encode_server_error<T: Serialize>(prefix: &str, error: &T) -> ServerFnError {
+    let json = serde_json::to_string(error).unwrap_or_default();
+    ServerFnError::ServerError(format!("{prefix}{json}"))
+}
+
+/// Recovers an error that was wrapped by [`encode_server_error`] with the same prefix.
+/// Returns `None` for any other error, which should then be shown as a plain string.
+pub fn decode_server_error<T: serde::de::DeserializeOwned>(prefix: &str, error: &ServerFnError) -> Option<T> {
+    match error {
+        ServerFnError::ServerError(message) => message.strip_prefix(prefix).and_then(|x| serde_json::from_str(x).ok()),
+        _ => None,
+    }
+}
+
+/// A server-side validation failure that refers to a specific input field.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// The name of the offending field, as used by [`FieldErrorMessage`]
+    pub field: String,
+    /// A human readable description of the problem
+    pub message: String,
+}
+
+/// A list of validation errors returned by the create_or_update server functions.
+/// They are transported inside the message of a [`ServerFnError`], so any other
+/// failure keeps being reported as a plain error string.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+const VALIDATION_ERRORS_PREFIX: &str = "validation errors: ";
+
+impl ValidationErrors {
+    /// Creates a validation error for a single field.
+    pub fn field(field: &str, message: impl ToString) -> Self {
+        Self(vec![FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }])
+    }
+
+    /// Recovers the structured errors from a server function error, if it carries any.
+    pub fn from_server_error(error: &ServerFnError) -> Option<Self> {
+        decode_server_error(VALIDATION_ERRORS_PREFIX, error)
+    }
+
+    /// Returns the message for the given field, if any.
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.iter().find(|x| x.field == field).map(|x| x.message.as_str())
+    }
+}
+
+impl From<ValidationErrors> for ServerFnError {
+    fn from(errors: ValidationErrors) -> Self {
+        encode_server_error(VALIDATION_ERRORS_PREFIX, &errors)
+    }
+}
+
+/// Reports a unique constraint violation as an "already exists" error of the given field,
+/// since the checks before an insert or rename can race with a concurrent request.
+#[cfg(feature = "ssr")]
+pub fn map_unique_violation<'a>(field: &'a str, value: &'a str) -> impl FnOnce(sqlx::Error) -> ServerFnError + 'a {
+    move |e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            ValidationErrors::field(field, format!("'{value}' already exists!")).into()
+        }
+        e => e.into(),
+    }
+}
+
+/// Shows the server-side validation error of the given field of the surrounding [`EditModal`].
+#[component]
+pub fn FieldErrorMessage(field: &'static str) -> impl IntoView {
+    let errors = use_context::<ReadSignal<ValidationErrors>>();
+    let message = move || errors.and_then(|x| x.with(|x| x.get(field).map(str::to_string)));
+
+    view! {
+        {move || {
+            message().map(|message| view! { <p class="text-sm text-red-600 dark:text-red-400">{message}</p> })
+        }}
+    }
+}
+
+/// Describes the time relative to `now`, like "3 days ago" for past or "in 3 days" for future times
+fn relative_time(utc_time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    chrono_humanize::HumanTime::from(utc_time - now).to_string()
+}
+
+/// Shows the time relative to now, with the precise local time as a tooltip
+fn timediff_view(utc_time: DateTime<Utc>) -> impl IntoView {
+    let local_time: DateTime<Local> = DateTime::from(utc_time);
+    let approximate_time = relative_time(utc_time, Utc::now());
+    let precise_time = local_time.format("%c").to_string();
+
+    view! {
+        <div class="group relative w-max">
+            <span class="pointer-events-none absolute -top-10 -left-10 w-max rounded-lg bg-gray-900 dark:bg-black dark:border-[1.5px] dark:border-zinc-800 px-2 py-1 font-medium text-gray-50 opacity-0 shadow transition-opacity group-hover:opacity-100 z-50">
+                {precise_time}
+            </span>
+            {approximate_time}
+        </div>
+    }
+}
 
 #[component]
 #[allow(unused_variables, non_snake_case)]
@@ -18,26 +188,97 @@ pub fn TimediffRenderer<F>(
 where
     F: Fn(DateTime<Utc>) + 'static,
 {
-    let time_tooltip = move || {
-        let utc_time = value();
-        let dt = utc_time - Utc::now();
-        let human_time = chrono_humanize::HumanTime::from(dt);
-
-        let local_time: DateTime<Local> = DateTime::from(utc_time);
-        let approximate_time = human_time.to_string();
-        let precise_time = local_time.format("%c").to_string();
-
-        view! {
-            <div class="group relative w-max">
-                <span class="pointer-events-none absolute -top-10 -left-10 w-max rounded-lg bg-gray-900 dark:bg-black dark:border-[1.5px] dark:border-zinc-800 px-2 py-1 font-medium text-gray-50 opacity-0 shadow transition-opacity group-hover:opacity-100 z-50">
-                    {precise_time}
-                </span>
-                {approximate_time}
-            </div>
-        }
-    };
+    view! { <td class=class>{move || timediff_view(value())}</td> }
+}
+
+/// Like [`TimediffRenderer`], but for optional times such as the last login or an expiry date. Shows "never" if unset
+#[component]
+#[allow(unused_variables, non_snake_case)]
+pub fn OptionTimediffRenderer<F>(
+    class: String,
+    #[prop(into)] value: MaybeSignal<Option<DateTime<Utc>>>,
+    on_change: F,
+    index: usize,
+) -> impl IntoView
+where
+    F: Fn(Option<DateTime<Utc>>) + 'static,
+{
+    view! {
+        <td class=class>
+            {move || match value() {
+                Some(utc_time) => timediff_view(utc_time).into_view(),
+                None => view! { <span class="text-gray-400 dark:text-zinc-500">"never"</span> }.into_view(),
+            }}
+
+        </td>
+    }
+}
+
+/// Renders optional text such as a catch-all address. Shows "-" if unset
+#[component]
+#[allow(unused_variables, non_snake_case)]
+pub fn OptionTextRenderer<F>(
+    class: String,
+    #[prop(into)] value: MaybeSignal<Option<String>>,
+    on_change: F,
+    index: usize,
+) -> impl IntoView
+where
+    F: Fn(Option<String>) + 'static,
+{
+    view! {
+        <td class=class>
+            {move || match value() {
+                Some(text) => text.into_view(),
+                None => view! { <span class="text-gray-400 dark:text-zinc-500">"-"</span> }.into_view(),
+            }}
+
+        </td>
+    }
+}
+
+#[component]
+#[allow(unused_variables, non_snake_case)]
+pub fn CopyableRenderer<F>(
+    class: String,
+    #[prop(into)] value: MaybeSignal<String>,
+    on_change: F,
+    index: usize,
+) -> impl IntoView
+where
+    F: Fn(String) + 'static,
+{
+    let copied_timer = use_timeout_fn(|_: ()| (), 2000.0);
+    let text = value.clone();
 
-    view! { <td class=class>{time_tooltip}</td> }
+    view! {
+        <td class=class>
+            <div class="group inline-flex items-center">
+                {text}
+                <button
+                    class="ms-1.5 p-1 rounded-md text-gray-500 dark:text-gray-400 hover:bg-gray-100 dark:hover:bg-zinc-800 opacity-0 group-hover:opacity-100 focus:opacity-100 transition-all"
+                    class=("opacity-100", copied_timer.is_pending)
+                    title="Copy to clipboard"
+                    on:click=move |ev| {
+                        // Don't toggle the row selection
+                        ev.stop_propagation();
+                        (copied_timer.start)(());
+                        let clipboard = window().navigator().clipboard();
+                        let _ = clipboard.write_text(&value.get_untracked());
+                    }
+                >
+
+                    <span class=("hidden", copied_timer.is_pending)>
+                        <Icon icon=icondata::RiFileCopy2DocumentFill class="w-3 h-3"/>
+                    </span>
+                    <span class="hidden items-center" class=("!inline-flex", copied_timer.is_pending)>
+                        <Icon icon=icondata::BiCheckRegular class="w-3 h-3 me-1 text-blue-700 dark:text-blue-300"/>
+                        <span class="text-xs font-semibold text-blue-700 dark:text-blue-300">Copied</span>
+                    </span>
+                </button>
+            </div>
+        </td>
+    }
 }
 
 #[component]
@@ -106,7 +347,7 @@ where
                 class="inline-flex items-center justify-center whitespace-nowrap px-2 -ml-2 h-12 text-gray-900 dark:text-gray-200 bg-white dark:bg-black focus:outline-none hover:bg-gray-100 dark:hover:bg-zinc-900 focus-visible:ring-4 focus-visible:ring-ring rounded-lg"
             >
                 <span class=inner_class>{children()}</span>
-                <span class="ml-2 w-3">
+                <span class="ml-2 w-6 text-left">
                     {move || {
                         match (sort_priority(), sort_direction()) {
                             (_, ColumnSort::Ascending) => view! { "↑" },
@@ -114,6 +355,12 @@ where
                             _ => view! { "" },
                         }
                     }}
+                    // Secondary sort columns show their position in the sort order
+                    {move || {
+                        sort_priority()
+                            .filter(|priority| *priority > 0)
+                            .map(|priority| view! { <sup class="text-xs">{priority + 1}</sup> })
+                    }}
 
                 </span>
             </button>
@@ -245,29 +492,93 @@ pub fn SelectOption(
     }
 }
 
+/// Explains why provisioned entries can't be changed in the web interface
+pub const PROVISIONED_TOOLTIP: &str =
+    "Managed by the provisioning file, changes would be overwritten when it is applied again";
+
+/// Marks an entry that is managed by the provisioning file
+#[component]
+pub fn ProvisionedBadge() -> impl IntoView {
+    view! {
+        <span
+            class="inline-flex items-center rounded-md bg-gray-100 dark:bg-zinc-800 text-xs font-medium px-2 py-1 me-2"
+            title=PROVISIONED_TOOLTIP
+        >
+            Provisioned
+        </span>
+    }
+}
+
+/// Filters a table by provisioned (`Some(true)`) or manually created (`Some(false)`) entries
+#[component]
+pub fn ProvisionedFilter(value: RwSignal<Option<bool>>) -> impl IntoView {
+    view! {
+        <select
+            class="flex-none rounded-lg border-[1.5px] border-gray-200 dark:border-zinc-800 bg-transparent dark:bg-transparent text-base p-2.5 me-2 mb-2 transition-all focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900"
+            on:change=move |ev| {
+                value
+                    .set(
+                        match event_target_value(&ev).as_str() {
+                            "provisioned" => Some(true),
+                            "manual" => Some(false),
+                            _ => None,
+                        },
+                    )
+            }
+        >
+            <option value="all" selected=move || value.get().is_none()>
+                All entries
+            </option>
+            <option value="provisioned" selected=move || value.get() == Some(true)>
+                Provisioned
+            </option>
+            <option value="manual" selected=move || value.get() == Some(false)>
+                Manual
+            </option>
+        </select>
+    }
+}
+
 #[component]
 pub fn EditModal<T: Clone + 'static, F: Fn(&T) -> &str + 'static>(
     #[prop(into)] data: RwSignal<Option<Option<T>>>,
     #[prop(into)] errors: Signal<Vec<String>>,
     what: String,
     get_title: F,
-    #[prop(into)] on_confirm: Callback<(Option<T>, Callback<String>)>,
+    #[prop(into)] on_confirm: Callback<(Option<T>, Callback<ServerFnError>)>,
+    /// Allows the caller to report validation errors of individual fields,
+    /// for example from a server-side dry run while the user is typing.
+    #[prop(optional)]
+    field_errors: Option<RwSignal<ValidationErrors>>,
     children: Children,
 ) -> impl IntoView {
     let (server_error, set_server_error) = create_signal(None);
+    let field_errors = field_errors.unwrap_or_else(|| create_rw_signal(ValidationErrors::default()));
+    let (field_errors, set_field_errors) = field_errors.split();
+    provide_context(field_errors);
     let (modal_waiting, set_modal_waiting) = create_signal(false);
     let modal_elem = create_node_ref::<Dialog>();
     let open = Signal::derive(move || data.get().is_some());
 
-    let on_error = Callback::new(move |error: String| {
+    let on_error = Callback::new(move |error: ServerFnError| {
         set_modal_waiting(false);
-        set_server_error(Some(error));
+        match ValidationErrors::from_server_error(&error) {
+            Some(errors) => {
+                set_server_error(None);
+                set_field_errors(errors);
+            }
+            None => {
+                set_server_error(Some(error.to_string()));
+                set_field_errors(ValidationErrors::default());
+            }
+        }
     });
 
     create_effect(move |_| {
         if !open() {
             set_modal_waiting(false);
             set_server_error(None);
+            set_field_errors(ValidationErrors::default());
         }
     });
 
@@ -285,7 +596,7 @@ pub fn EditModal<T: Clone + 'static, F: Fn(&T) -> &str + 'static>(
 
                 </h3>
                 <div class="flex flex-col gap-3">
-                    {children()} <Show when=move || (!errors.get().is_empty() || server_error().is_some())>
+                    {children()} <Show when=move || !errors.get().is_empty() || server_error().is_some()>
                         <div class="rounded-lg p-4 flex bg-red-100 dark:bg-red-900 mt-2">
                             <div>
                                 <Icon icon=icondata::BiXCircleSolid class="w-5 h-5 text-red-400 dark:text-red-200"/>
@@ -316,9 +627,9 @@ pub fn EditModal<T: Clone + 'static, F: Fn(&T) -> &str + 'static>(
                         </button>
                         <button
                             type="button"
-                            disabled=move || (modal_waiting() || !errors.get().is_empty())
+                            disabled=move || modal_waiting() || !errors.get().is_empty()
                             class="inline-flex w-full min-w-20 justify-center items-center rounded-lg transition-all px-3 py-2 bg-blue-600 dark:bg-blue-600 hover:bg-blue-500 dark:hover:bg-blue-500 font-semibold text-white dark:text-zinc-100 focus:ring-4 focus:ring-blue-300 dark:focus:ring-blue-900 sm:w-auto disabled:cursor-not-allowed disabled:opacity-50"
-                            class=("!bg-blue-500", move || (modal_waiting() || !errors.get().is_empty()))
+                            class=("!bg-blue-500", move || modal_waiting() || !errors.get().is_empty())
                             on:click=move |_ev| {
                                 if let Some(data) = data.get() {
                                     if !modal_waiting() && errors.get().is_empty() {
@@ -440,3 +751,131 @@ pub fn ColorModeToggle(color_mode: Signal<ColorMode>, set_color_mode: WriteSigna
         </button>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use server_fn::error::ServerFnErrorSerde;
+
+    #[test]
+    fn validation_errors_survive_the_transport() {
+        let errors = ValidationErrors(vec![
+            FieldError {
+                field: "alias".to_string(),
+                message: "'a@example.com' already exists!".to_string(),
+            },
+            FieldError {
+                field: "catch_all".to_string(),
+                message: "catch-all must be a valid address".to_string(),
+            },
+        ]);
+        let error: ServerFnError = errors.clone().into();
+        let received: ServerFnError = ServerFnErrorSerde::de(&ServerFnErrorSerde::ser(&error).unwrap());
+
+        let decoded = ValidationErrors::from_server_error(&received).unwrap();
+        assert_eq!(decoded, errors);
+        assert_eq!(decoded.get("catch_all"), Some("catch-all must be a valid address"));
+        assert_eq!(decoded.get("owner"), None);
+    }
+
+    #[test]
+    fn other_errors_are_not_decoded() {
+        assert_eq!(
+            ValidationErrors::from_server_error(&ServerFnError::new("Alias limit reached")),
+            None
+        );
+        let error = encode_server_error("other: ", &ValidationErrors::field("alias", "taken"));
+        assert_eq!(ValidationErrors::from_server_error(&error), None);
+        assert!(decode_server_error::<ValidationErrors>("other: ", &error).is_some());
+    }
+
+    #[tokio::test]
+    async fn unique_violations_become_field_errors() {
+        let db = crate::testing::TestDb::new().await;
+        crate::testing::insert_user(&db, "alice", false).await;
+        crate::testing::insert_domain(&db, "example.com", "alice").await;
+        crate::testing::insert_domain(&db, "example.org", "alice").await;
+        crate::testing::insert_mailbox(&db, "me@example.com", "alice").await;
+        crate::testing::insert_alias(&db, "a@example.com", "me@example.com", "alice").await;
+
+        let duplicate = |sql: &'static str, field: &'static str, value: &'static str| {
+            let db = &db;
+            async move {
+                let error = sqlx::query(sql)
+                    .execute(&**db)
+                    .await
+                    .map_err(map_unique_violation(field, value));
+                ValidationErrors::from_server_error(&error.unwrap_err()).and_then(|x| x.get(field).map(str::to_string))
+            }
+        };
+        assert_eq!(
+            duplicate(
+                "INSERT INTO users (username, password_hash) VALUES ('alice', '')",
+                "username",
+                "alice"
+            )
+            .await,
+            Some("'alice' already exists!".to_string())
+        );
+        assert_eq!(
+            duplicate(
+                "UPDATE domains SET domain = 'example.com' WHERE domain = 'example.org'",
+                "domain",
+                "example.com"
+            )
+            .await,
+            Some("'example.com' already exists!".to_string())
+        );
+        assert_eq!(
+            duplicate(
+                "INSERT INTO mailboxes (address, domain, password_hash, owner) \
+                VALUES ('me@example.com', 'example.com', '', 'alice')",
+                "localpart",
+                "me@example.com"
+            )
+            .await,
+            Some("'me@example.com' already exists!".to_string())
+        );
+        assert_eq!(
+            duplicate(
+                "INSERT INTO aliases (address, domain, target, comment, owner) \
+                VALUES ('a@example.com', 'example.com', 'me@example.com', '', 'alice')",
+                "alias",
+                "a@example.com"
+            )
+            .await,
+            Some("'a@example.com' already exists!".to_string())
+        );
+
+        // Other errors are passed on unchanged
+        assert_eq!(duplicate("SELECT * FROM missing", "alias", "a@example.com").await, None);
+    }
+
+    #[test]
+    fn relative_times_in_the_past_and_future() {
+        let now = Utc::now();
+        let days = chrono::Duration::days;
+        assert_eq!(relative_time(now - days(3), now), "3 days ago");
+        assert_eq!(relative_time(now + days(3), now), "in 3 days");
+        assert_eq!(relative_time(now + days(1), now), "in a day");
+        assert_eq!(relative_time(now - days(1), now), "a day ago");
+    }
+
+    #[test]
+    fn relative_times_around_now() {
+        let now = Utc::now();
+        let seconds = chrono::Duration::seconds;
+        assert_eq!(relative_time(now, now), "now");
+        assert_eq!(relative_time(now + seconds(5), now), "now");
+        assert_eq!(relative_time(now - seconds(5), now), "now");
+        assert_eq!(relative_time(now + seconds(50), now), "in a minute");
+        assert_eq!(relative_time(now - seconds(50), now), "a minute ago");
+    }
+
+    #[test]
+    fn provisioned_badge_explains_itself() {
+        let html = leptos::ssr::render_to_string(|| view! { <ProvisionedBadge/> });
+        assert!(html.contains("Provisioned"), "{html}");
+        assert!(html.contains(&format!("title=\"{PROVISIONED_TOOLTIP}\"")), "{html}");
+    }
+}
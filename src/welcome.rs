@@ -0,0 +1,135 @@
+use crate::config::{Config, WelcomeConfig};
+use anyhow::{Context, Result};
+use lettre::{AsyncTransport, Message};
+
+pub const DEFAULT_SUBJECT: &str = "Welcome to your new mailbox";
+
+const DEFAULT_TEMPLATE: &str = "\
+Hello,
+
+the mailbox {address} has been created for you.
+
+You can log in to your mail client with the following settings:
+
+  Username:    {address}
+  IMAP server: {mail_host} (port 993, SSL/TLS)
+  SMTP server: {mail_host} (port 465, SSL/TLS)
+
+Your password is not part of this mail, please ask {owner} for it
+and change it after your first login.
+";
+
+/// Fills in the placeholders `{address}`, `{domain}`, `{owner}` and `{mail_host}` of a template
+pub fn render_welcome(template: &str, address: &str, domain: &str, owner: &str, mail_host: &str) -> String {
+    template
+        .replace("{address}", address)
+        .replace("{domain}", domain)
+        .replace("{owner}", owner)
+        .replace("{mail_host}", mail_host)
+}
+
+/// Builds the welcome mail of a new mailbox from the configured or the built-in template
+async fn welcome_message(
+    welcome: &WelcomeConfig,
+    address: &str,
+    domain: &str,
+    owner: &str,
+    mail_host: &str,
+) -> Result<Message> {
+    let template = match &welcome.template {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .context(format!("Failed to read welcome template {}", path.display()))?,
+        None => DEFAULT_TEMPLATE.to_string(),
+    };
+    let body = render_welcome(&template, address, domain, owner, mail_host);
+
+    crate::mail::message(&welcome.from, address, &welcome.subject, body)
+}
+
+/// Sends the welcome mail to a newly created mailbox in the background, if welcome mails are configured.
+/// Failures are only logged, since the mailbox itself was created successfully.
+pub fn spawn_welcome(config: &Config, address: String, domain: String, owner: String) {
+    let (Some(welcome), Some(smtp_url)) = (config.welcome.clone(), config.smtp_url.clone()) else {
+        return;
+    };
+    let mail_host = crate::dns_records::mail_hostname(config, &domain);
+
+    tokio::spawn(async move {
+        let result = async {
+            let message = welcome_message(&welcome, &address, &domain, &owner, &mail_host).await?;
+            crate::mail::mailer(&smtp_url)?.send(message).await?;
+            anyhow::Ok(())
+        };
+        match result.await {
+            Ok(()) => log::info!("sent welcome mail to '{address}'"),
+            Err(e) => log::error!("failed to send welcome mail to '{address}': {e}"),
+        }
+    });
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn welcome(template: Option<std::path::PathBuf>) -> WelcomeConfig {
+        WelcomeConfig {
+            from: "idmail@example.com".to_string(),
+            subject: DEFAULT_SUBJECT.to_string(),
+            template,
+        }
+    }
+
+    fn formatted(message: Message) -> String {
+        String::from_utf8(message.formatted()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn welcome_mail_contains_address_and_server() {
+        let message = welcome_message(
+            &welcome(None),
+            "bob@example.com",
+            "example.com",
+            "alice",
+            "mail.example.com",
+        )
+        .await
+        .unwrap();
+        let envelope = message.envelope();
+        assert_eq!(envelope.from().unwrap().to_string(), "idmail@example.com");
+        assert_eq!(envelope.to()[0].to_string(), "bob@example.com");
+
+        let mail = formatted(message);
+        assert!(mail.contains("IMAP server: mail.example.com (port 993, SSL/TLS)"));
+        assert!(mail.contains("Subject: Welcome to your new mailbox\r\n"));
+        assert!(mail.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(mail.contains("the mailbox bob@example.com has been created for you."));
+        assert!(mail.contains("ask alice for it"));
+        assert!(!mail.contains('{'));
+    }
+
+    #[tokio::test]
+    async fn welcome_mail_uses_the_configured_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("welcome.txt");
+        std::fs::write(&path, "Hi {address}, your domain is {domain}.").unwrap();
+
+        let message = welcome_message(
+            &welcome(Some(path)),
+            "bob@example.com",
+            "example.com",
+            "alice",
+            "mail.example.com",
+        )
+        .await
+        .unwrap();
+        assert!(formatted(message).ends_with("\r\n\r\nHi bob@example.com, your domain is example.com."));
+
+        let missing = welcome(Some(dir.path().join("missing.txt")));
+        assert!(
+            welcome_message(&missing, "bob@example.com", "example.com", "alice", "mail.example.com")
+                .await
+                .is_err()
+        );
+    }
+}